@@ -29,7 +29,7 @@ fn impl_sequence(ast: &syn::DeriveInput) -> proc_macro2::TokenStream {
                     self.#name.write_asn1(sequence.next())?;
                 });
                 read_asn1.extend(quote! {
-                    self.#name.read_asn1(sequence.next())?;
+                    failure.keep(::asn1::read_field(&mut self.#name, stringify!(#name), sequence))?;
                 });
             }
         }
@@ -45,11 +45,16 @@ fn impl_sequence(ast: &syn::DeriveInput) -> proc_macro2::TokenStream {
                 }
 
                 fn read_asn1(&mut self, reader: BERReader) -> KerlabResult<()> {
-                    reader.read_sequence(|sequence| {
+                    let mut failure = ::asn1::Failure::default();
+                    let result = reader.read_sequence(|sequence| {
                         #read_asn1
                         Ok(())
-                    })?;
-                    Ok(())
+                    });
+                    failure.result(result).map_err(|e| e.within_structure(stringify!(#name)))
+                }
+
+                fn tag() -> Option<::yasna::Tag> {
+                    Some(::yasna::tags::TAG_SEQUENCE)
                 }
             }
         }
@@ -104,4 +109,84 @@ fn impl_component(ast: &syn::DeriveInput) -> proc_macro2::TokenStream {
     else {
         panic!("error");
     }
-}
\ No newline at end of file
+}
+
+/// CHOICE between tagged alternatives
+///
+/// Each variant holds a single type with a known tag,
+/// the tag read ahead selects the variant.
+/// The first variant is the default one
+#[proc_macro_derive(Choice)]
+pub fn choice_derive(input: TokenStream) -> TokenStream {
+    let ast = syn::parse(input).unwrap();
+    impl_choice(&ast).into()
+}
+
+fn impl_choice(ast: &syn::DeriveInput) -> proc_macro2::TokenStream {
+    let name = &ast.ident;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+
+    if let syn::Data::Enum(data) = &ast.data {
+        let mut write_asn1 = quote!();
+        let mut read_asn1 = quote!();
+        let mut accepts = quote!(false);
+        let mut default = None;
+
+        for variant in data.variants.iter() {
+            let ident = &variant.ident;
+            let ty = match &variant.fields {
+                Fields::Unnamed(unnamed) if unnamed.unnamed.len() == 1 => &unnamed.unnamed[0].ty,
+                _ => panic!("Choice variant {} must hold a single tagged type", ident)
+            };
+
+            write_asn1.extend(quote! {
+                #name::#ident(e) => e.write_asn1(writer),
+            });
+            read_asn1.extend(quote! {
+                if <#ty as ASN1>::accepts(tag) {
+                    let mut e = <#ty>::default();
+                    e.read_asn1(reader).map_err(|e| e.within_structure(stringify!(#name)))?;
+                    *self = #name::#ident(e);
+                    return Ok(())
+                }
+            });
+            accepts.extend(quote! {
+                || <#ty as ASN1>::accepts(tag)
+            });
+            if default.is_none() {
+                default = Some(quote! {
+                    #name::#ident(<#ty>::default())
+                });
+            }
+        }
+
+        quote! {
+            impl #impl_generics Default for #name #ty_generics #where_clause {
+                fn default() -> Self {
+                    #default
+                }
+            }
+
+            impl #impl_generics ASN1 for #name #ty_generics #where_clause {
+                fn write_asn1(&self, writer: DERWriter) -> KerlabResult<()> {
+                    match self {
+                        #write_asn1
+                    }
+                }
+
+                fn read_asn1(&mut self, reader: BERReader) -> KerlabResult<()> {
+                    let tag = reader.lookahead_tag()?;
+                    #read_asn1
+                    Err(::error::Error::unexpected(None, Some(tag)).within_structure(stringify!(#name)))
+                }
+
+                fn accepts(tag: ::yasna::Tag) -> bool {
+                    #accepts
+                }
+            }
+        }
+    }
+    else {
+        panic!("error");
+    }
+}
@@ -0,0 +1,276 @@
+use std::collections::HashMap;
+use chrono::{Utc, Duration, DateTime};
+use krbap::{ApReq, ApRep, ApOptionsType};
+use keytab::Keytab;
+use encryption::{EncryptionKey, KeyUsage};
+use ticket::{EncTicketPart, EncTicketPartBody, AdDataType};
+use authenticator::{Authenticator, AuthenticatorBody};
+use base::{PrincipalName, Realm, HostAddress, AuthorizationData, MessageType, DEFAULT_MAX_SKEW_MINUTES};
+use pac::PacType;
+use asn1::{Integer, from_der};
+use error::{KerlabResult, Error};
+use krberror::KrbErrorCode;
+
+/// Source of long term keys of the service
+enum ServiceKeys {
+    Key(EncryptionKey),
+    Keytab(Keytab)
+}
+
+/// Keep track of authenticators already seen
+/// to detect replay
+struct ReplayCache {
+    entries: HashMap<String, DateTime<Utc>>
+}
+
+impl ReplayCache {
+    fn new() -> Self {
+        Self {
+            entries: HashMap::new()
+        }
+    }
+
+    /// Return false if the authenticator was already presented
+    fn insert(&mut self, authenticator: &AuthenticatorBody, max_skew: Duration) -> bool {
+        let now = Utc::now();
+        // entries out of the skew window can't be replayed anymore
+        self.entries.retain(|_, seen| *seen + max_skew * 2 > now);
+
+        let entry = format!(
+            "{}@{}:{}:{}",
+            authenticator.cname.name(),
+            authenticator.crealm.as_str(),
+            authenticator.ctime.inner.inner.timestamp(),
+            authenticator.cusec.inner
+        );
+
+        if let std::collections::hash_map::Entry::Vacant(e) = self.entries.entry(entry) {
+            e.insert(now);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Result of a successful AP-REQ validation
+pub struct AuthContext {
+    pub client: PrincipalName,
+    pub realm: Realm,
+    pub session_key: EncryptionKey,
+    pub ticket: EncTicketPartBody,
+    pub authenticator: AuthenticatorBody,
+    pub mutual_required: bool,
+    pub pac: Option<PacType>
+}
+
+impl AuthContext {
+    /// Build the AP-REP use for mutual authentication
+    pub fn ap_rep(&self) -> KerlabResult<ApRep> {
+        ApRep::new(&self.session_key, &Authenticator {
+            inner: self.authenticator.clone()
+        })
+    }
+}
+
+/// Service side of the AP exchange
+///
+/// Decrypt the ticket with the service key, then the authenticator
+/// with the session key, and check all rules of RFC 4120 3.2.3
+pub struct Acceptor {
+    keys: ServiceKeys,
+    max_skew: Duration,
+    replay_cache: ReplayCache
+}
+
+impl Acceptor {
+    /// Accept tickets encrypted with a single service key
+    pub fn new(key: EncryptionKey) -> Self {
+        Self {
+            keys: ServiceKeys::Key(key),
+            max_skew: Duration::minutes(DEFAULT_MAX_SKEW_MINUTES),
+            replay_cache: ReplayCache::new()
+        }
+    }
+
+    /// Accept tickets for any service present into the keytab
+    pub fn from_keytab(keytab: Keytab) -> Self {
+        Self {
+            keys: ServiceKeys::Keytab(keytab),
+            max_skew: Duration::minutes(DEFAULT_MAX_SKEW_MINUTES),
+            replay_cache: ReplayCache::new()
+        }
+    }
+
+    /// Change the accepted clock skew
+    pub fn with_max_skew(mut self, max_skew: Duration) -> Self {
+        self.max_skew = max_skew;
+        self
+    }
+
+    fn find_key(&self, sname: &PrincipalName, etype: Integer) -> KerlabResult<&EncryptionKey> {
+        match &self.keys {
+            ServiceKeys::Key(key) => {
+                if key.keytype.inner != etype {
+                    return Err(Error::protocol(KrbErrorCode::KrbApErrNoKey, "Service key not available"))
+                }
+                Ok(key)
+            },
+            ServiceKeys::Keytab(keytab) => {
+                keytab.find(sname, etype)
+                    .ok_or_else(|| Error::protocol(KrbErrorCode::KrbApErrNoKey, "Service key not available"))
+            }
+        }
+    }
+
+    /// Validate an incoming AP-REQ
+    ///
+    /// client_address is the network address of the peer
+    /// use to check ticket address restrictions
+    pub fn accept(&mut self, ap_req: &ApReq, client_address: Option<&HostAddress>) -> KerlabResult<AuthContext> {
+        if ap_req.pvno.inner != 5 {
+            return Err(Error::protocol(KrbErrorCode::KrbApErrBadVersion, "Protocol version mismatch"))
+        }
+
+        if ap_req.msg_type.inner != MessageType::KrbApReq as Integer {
+            return Err(Error::protocol(KrbErrorCode::KrbApErrMsgType, "Invalid msg type"))
+        }
+
+        // decrypt the ticket with the long term key of the service
+        let key = self.find_key(&ap_req.ticket.sname, ap_req.ticket.enc_part.etype.inner)?;
+        let ticket = key.decrypt::<EncTicketPart>(
+            KeyUsage::KeyUsageAsRepTicket,
+            &ap_req.ticket.enc_part
+        ).map_err(|_| Error::protocol(KrbErrorCode::KrbApErrBadIntegrity, "Unable to decrypt ticket"))?.inner;
+
+        // decrypt the authenticator with the session key
+        let authenticator = ticket.key.decrypt::<Authenticator>(
+            KeyUsage::KeyUsageApReqAuthenticator,
+            &ap_req.authenticator
+        ).map_err(|_| Error::protocol(KrbErrorCode::KrbApErrBadIntegrity, "Unable to decrypt authenticator"))?.inner;
+
+        if authenticator.cname.inner != ticket.cname.inner || authenticator.crealm.inner != ticket.crealm.inner {
+            return Err(Error::protocol(KrbErrorCode::KrbApErrBadMatch, "Ticket and authenticator don't match"))
+        }
+
+        let now = Utc::now();
+        if (now - authenticator.ctime.inner.inner).num_seconds().abs() > self.max_skew.num_seconds() {
+            return Err(Error::protocol(KrbErrorCode::KrbApErrSkew, "Clock skew too great"))
+        }
+
+        let starttime = match &ticket.starttime {
+            Some(starttime) => starttime.inner.inner,
+            None => ticket.authtime.inner.inner
+        };
+
+        if starttime - self.max_skew > now {
+            return Err(Error::protocol(KrbErrorCode::KrbApErrTktNyv, "Ticket not yet valid"))
+        }
+
+        if ticket.endtime.inner.inner + self.max_skew < now {
+            return Err(Error::protocol(KrbErrorCode::KrbApErrTktExpired, "Ticket expired"))
+        }
+
+        if let Some(caddr) = &ticket.caddr {
+            if !caddr.is_empty() && !client_address.is_some_and(|address| caddr.contains(address)) {
+                return Err(Error::protocol(KrbErrorCode::KrbApErrBadAddr, "Incorrect net address"))
+            }
+        }
+
+        if !self.replay_cache.insert(&authenticator, self.max_skew) {
+            return Err(Error::protocol(KrbErrorCode::KrbApErrRepeat, "Request is a replay"))
+        }
+
+        let mutual_required = ap_req.ap_options.contains(ApOptionsType::MutualRequired);
+
+        Ok(AuthContext {
+            client: ticket.cname.inner.clone(),
+            realm: ticket.crealm.inner.clone(),
+            session_key: ticket.key.inner.clone(),
+            pac: find_pac(&ticket)?,
+            ticket,
+            authenticator,
+            mutual_required
+        })
+    }
+}
+
+/// Windows PAC is embedded into an AD-IF-RELEVANT element
+pub fn find_pac(ticket: &EncTicketPartBody) -> KerlabResult<Option<PacType>> {
+    if let Some(authorization_data) = &ticket.authorization_data {
+        for element in authorization_data.iter() {
+            if element.ad_type.inner != AdDataType::AdIfRelevant as Integer {
+                continue;
+            }
+            let mut relevant = AuthorizationData::default();
+            from_der(&mut relevant, &element.ad_data)?;
+            for inner_element in relevant.iter() {
+                if inner_element.ad_type.inner == AdDataType::AdWin2kPac as Integer {
+                    return Ok(Some(PacType::from_addata(inner_element.ad_data.inner.clone())?));
+                }
+            }
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use asn1::{Tag, GeneralString};
+    use base::{PrincipalNameType, TicketFlags, KerberosTime};
+    use ticket::{Ticket, TicketBody, TransitedEncoding};
+    use std::str::FromStr;
+
+    /// Same authenticator can't be presented twice
+    #[test]
+    fn test_accept_and_replay() {
+        let service_key = EncryptionKey::new_rc4_hmac("service").unwrap();
+        let session_key = EncryptionKey::new_rc4_hmac("session").unwrap();
+        let realm = GeneralString::from_str("LAB.LOCAL").unwrap();
+        let cname = PrincipalName::new(PrincipalNameType::NtPrincipal, vec![GeneralString::from_str("foo").unwrap()]);
+        let sname = PrincipalName::new(PrincipalNameType::NtSrvInst, vec![GeneralString::from_str("cifs").unwrap(), GeneralString::from_str("bar").unwrap()]);
+
+        let enc_ticket = EncTicketPart {
+            inner: EncTicketPartBody {
+                flags: Tag::new(TicketFlags::default()),
+                key: Tag::new(session_key.clone()),
+                crealm: Tag::new(realm.clone()),
+                cname: Tag::new(cname.clone()),
+                transited: Tag::new(TransitedEncoding::default()),
+                authtime: Tag::new(KerberosTime::new(Utc::now())),
+                starttime: None,
+                endtime: Tag::new(KerberosTime::new(Utc::now() + Duration::hours(10))),
+                renew_till: None,
+                caddr: None,
+                authorization_data: None
+            }
+        };
+
+        let ticket = Ticket {
+            inner: TicketBody {
+                tkt_vno: Tag::new(5),
+                realm: Tag::new(realm.clone()),
+                sname: Tag::new(sname),
+                enc_part: Tag::new(service_key.encrypt(KeyUsage::KeyUsageAsRepTicket, &enc_ticket).unwrap())
+            }
+        };
+
+        let authenticator = session_key.encrypt(
+            KeyUsage::KeyUsageApReqAuthenticator,
+            &Authenticator::new(realm, cname.clone())
+        ).unwrap();
+
+        let ap_req = ApReq::new(ticket, authenticator);
+        let mut acceptor = Acceptor::new(service_key);
+
+        let context = acceptor.accept(&ap_req, None).unwrap();
+        assert!(context.client == cname);
+        assert!(context.pac.is_none());
+
+        match acceptor.accept(&ap_req, None) {
+            Err(Error::Protocol(code, _)) => assert_eq!(code, KrbErrorCode::KrbApErrRepeat),
+            _ => panic!("replay must be detected")
+        }
+    }
+}
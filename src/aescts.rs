@@ -0,0 +1,397 @@
+use error::{KerlabResult, Error, KerlabErrorKind};
+use encryption::KeyUsage;
+use checksum::constant_time_eq;
+use rnd::random;
+use sha1::{Sha1, Digest};
+use hmac::{Hmac, Mac};
+use aes::{Aes128, Aes256};
+use aes::{BlockEncrypt, BlockDecrypt, NewBlockCipher};
+use aes::Block;
+
+/// AES block size
+const BLOCK_SIZE: usize = 16;
+
+/// Size of the truncated HMAC-SHA1
+const MAC_SIZE: usize = 12;
+
+/// Default iteration count of the string to key function
+pub const DEFAULT_ITERATIONS: u32 = 4096;
+
+/// Compute HMAC with SHA1 hash algorithm
+///
+/// # Example
+/// ```rust, ignore
+/// let signature = hmac_sha1(b"foo", b"bar");
+/// ```
+pub fn hmac_sha1(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut stream = Hmac::<Sha1>::new_varkey(key).unwrap();
+    stream.input(data);
+    stream.result().code().to_vec()
+}
+
+/// Compute the SHA1 Hash of input vector
+fn sha1(data: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha1::new();
+    hasher.input(data);
+    hasher.result().to_vec()
+}
+
+/// PBKDF2 with a pluggable pseudo random function
+///
+/// @see https://tools.ietf.org/html/rfc2898#section-5.2
+pub fn pbkdf2(prf: fn(&[u8], &[u8]) -> Vec<u8>, password: &[u8], salt: &[u8], iterations: u32, size: usize) -> Vec<u8> {
+    let mut result = vec![];
+    let mut index: u32 = 1;
+    while result.len() < size {
+        let mut block_salt = salt.to_vec();
+        block_salt.extend_from_slice(&index.to_be_bytes());
+        let mut u = prf(password, &block_salt);
+        let mut t = u.clone();
+        for _ in 1..iterations {
+            u = prf(password, &u);
+            for (x, y) in t.iter_mut().zip(u.iter()) {
+                *x ^= *y;
+            }
+        }
+        result.append(&mut t);
+        index += 1;
+    }
+    result.truncate(size);
+    result
+}
+
+/// PBKDF2 using HMAC-SHA1 as pseudo random function
+pub fn pbkdf2_hmac_sha1(password: &[u8], salt: &[u8], iterations: u32, size: usize) -> Vec<u8> {
+    pbkdf2(hmac_sha1, password, salt, iterations, size)
+}
+
+/// Stretch or shrink an input to a size multiple of bytes
+///
+/// This is a port of the MIT implementation
+/// @see https://tools.ietf.org/html/rfc3961#section-5.1
+pub fn n_fold(input: &[u8], size: usize) -> Vec<u8> {
+    let inbits = input.len();
+    let outbits = size;
+
+    // lcm of input and output size
+    let (mut a, mut b) = (outbits, inbits);
+    while b != 0 {
+        let c = b;
+        b = a % b;
+        a = c;
+    }
+    let lcm = outbits * inbits / a;
+
+    let mut out = vec![0u8; outbits];
+    let mut byte: u32 = 0;
+
+    for i in (0..lcm).rev() {
+        // msbit in k which gets added into this byte
+        let msbit = (
+            ((inbits << 3) - 1)
+            + (((inbits << 3) + 13) * (i / inbits))
+            + ((inbits - (i % inbits)) << 3)
+        ) % (inbits << 3);
+
+        byte += (((input[((inbits - 1) - (msbit >> 3)) % inbits] as u32) << 8
+            | input[(inbits - (msbit >> 3)) % inbits] as u32)
+            >> ((msbit & 7) + 1)) & 0xff;
+
+        byte += out[i % outbits] as u32;
+        out[i % outbits] = (byte & 0xff) as u8;
+        byte >>= 8;
+    }
+
+    // add back the carry
+    if byte != 0 {
+        for i in (0..outbits).rev() {
+            byte += out[i] as u32;
+            out[i] = (byte & 0xff) as u8;
+            byte >>= 8;
+        }
+    }
+
+    out
+}
+
+/// AES block cipher for both key size
+enum Aes {
+    Aes128(Box<Aes128>),
+    Aes256(Box<Aes256>)
+}
+
+impl Aes {
+    fn new(key: &[u8]) -> KerlabResult<Self> {
+        let cipher = match key.len() {
+            16 => Aes128::new_from_slice(key).map(|cipher| Aes::Aes128(Box::new(cipher))),
+            32 => Aes256::new_from_slice(key).map(|cipher| Aes::Aes256(Box::new(cipher))),
+            _ => return Err(Error::new(KerlabErrorKind::Crypto, "Invalid AES key size"))
+        };
+        cipher.map_err(|_| Error::new(KerlabErrorKind::Crypto, "Invalid AES key size"))
+    }
+
+    fn encrypt_block(&self, block: &mut [u8]) {
+        let block: &mut Block = block.into();
+        match self {
+            Aes::Aes128(cipher) => cipher.encrypt_block(block),
+            Aes::Aes256(cipher) => cipher.encrypt_block(block)
+        }
+    }
+
+    fn decrypt_block(&self, block: &mut [u8]) {
+        let block: &mut Block = block.into();
+        match self {
+            Aes::Aes128(cipher) => cipher.decrypt_block(block),
+            Aes::Aes256(cipher) => cipher.decrypt_block(block)
+        }
+    }
+}
+
+/// CBC mode with cipher text stealing and a null IV
+///
+/// Last two blocks are always swapped
+/// @see https://tools.ietf.org/html/rfc3962#section-5
+fn cts_encrypt(key: &[u8], data: &[u8]) -> KerlabResult<Vec<u8>> {
+    let cipher = Aes::new(key)?;
+    if data.len() < BLOCK_SIZE {
+        return Err(Error::new(KerlabErrorKind::Crypto, "AES CTS needs at least one block"))
+    }
+
+    let block_count = data.len().div_ceil(BLOCK_SIZE);
+    let mut padded = data.to_vec();
+    padded.resize(block_count * BLOCK_SIZE, 0);
+
+    let mut previous = [0u8; BLOCK_SIZE];
+    let mut blocks = vec![];
+    for chunk in padded.chunks(BLOCK_SIZE) {
+        let mut block = [0u8; BLOCK_SIZE];
+        for i in 0..BLOCK_SIZE {
+            block[i] = chunk[i] ^ previous[i];
+        }
+        cipher.encrypt_block(&mut block);
+        previous = block;
+        blocks.push(block);
+    }
+
+    if block_count == 1 {
+        return Ok(blocks[0].to_vec())
+    }
+
+    let last_size = data.len() - (block_count - 1) * BLOCK_SIZE;
+    let mut result = vec![];
+    for block in &blocks[0..block_count - 2] {
+        result.extend_from_slice(block);
+    }
+    result.extend_from_slice(&blocks[block_count - 1]);
+    result.extend_from_slice(&blocks[block_count - 2][0..last_size]);
+    Ok(result)
+}
+
+/// Inverse of cts_encrypt
+fn cts_decrypt(key: &[u8], data: &[u8]) -> KerlabResult<Vec<u8>> {
+    let cipher = Aes::new(key)?;
+    if data.len() < BLOCK_SIZE {
+        return Err(Error::new(KerlabErrorKind::Crypto, "AES CTS needs at least one block"))
+    }
+
+    let block_count = data.len().div_ceil(BLOCK_SIZE);
+    if block_count == 1 {
+        let mut block = data.to_vec();
+        cipher.decrypt_block(&mut block);
+        return Ok(block)
+    }
+
+    let last_size = data.len() - (block_count - 1) * BLOCK_SIZE;
+
+    // recover the real last cipher block and the partial plaintext
+    let mut last = data[(block_count - 2) * BLOCK_SIZE..(block_count - 1) * BLOCK_SIZE].to_vec();
+    cipher.decrypt_block(&mut last);
+    let stolen = &data[(block_count - 1) * BLOCK_SIZE..];
+
+    let mut penultimate = stolen.to_vec();
+    penultimate.extend_from_slice(&last[last_size..]);
+    let last_plain: Vec<u8> = stolen.iter().zip(last.iter()).map(|(x, y)| x ^ y).collect();
+
+    // now it's a classic cbc decryption
+    let mut blocks: Vec<Vec<u8>> = data[0..(block_count - 2) * BLOCK_SIZE]
+        .chunks(BLOCK_SIZE)
+        .map(|x| x.to_vec())
+        .collect();
+    blocks.push(penultimate);
+
+    let mut result = vec![];
+    let mut previous = vec![0u8; BLOCK_SIZE];
+    for block in blocks {
+        let mut plain = block.clone();
+        cipher.decrypt_block(&mut plain);
+        for i in 0..BLOCK_SIZE {
+            plain[i] ^= previous[i];
+        }
+        result.append(&mut plain);
+        previous = block;
+    }
+    result.extend_from_slice(&last_plain);
+    Ok(result)
+}
+
+/// Classic CBC decryption, use by password based encryption
+/// Padding is not removed
+pub fn cbc_decrypt(key: &[u8], iv: &[u8], data: &[u8]) -> KerlabResult<Vec<u8>> {
+    let cipher = Aes::new(key)?;
+    if !data.len().is_multiple_of(BLOCK_SIZE) || iv.len() != BLOCK_SIZE {
+        return Err(Error::new(KerlabErrorKind::Crypto, "AES CBC needs full blocks"))
+    }
+
+    let mut result = vec![];
+    let mut previous = iv.to_vec();
+    for block in data.chunks(BLOCK_SIZE) {
+        let mut plain = block.to_vec();
+        cipher.decrypt_block(&mut plain);
+        for i in 0..BLOCK_SIZE {
+            plain[i] ^= previous[i];
+        }
+        result.append(&mut plain);
+        previous = block.to_vec();
+    }
+    Ok(result)
+}
+
+/// Key derivation function
+///
+/// DK(Key, Constant) = random-to-key(DR(Key, Constant))
+/// random-to-key is the identity function for AES
+pub fn derive_key(key: &[u8], constant: &[u8]) -> KerlabResult<Vec<u8>> {
+    let cipher = Aes::new(key)?;
+    let mut block = n_fold(constant, BLOCK_SIZE);
+    let mut result = vec![];
+    while result.len() < key.len() {
+        cipher.encrypt_block(&mut block);
+        result.extend_from_slice(&block);
+    }
+    result.truncate(key.len());
+    Ok(result)
+}
+
+/// Derive a key for a particular usage
+fn usage_key(key: &[u8], usage: u32, suffix: u8) -> KerlabResult<Vec<u8>> {
+    let mut constant = usage.to_be_bytes().to_vec();
+    constant.push(suffix);
+    derive_key(key, &constant)
+}
+
+/// Compute the AES key from a password
+///
+/// The salt is generally the concatenation of realm and username
+/// @see https://tools.ietf.org/html/rfc3962#section-4
+pub fn string_to_key(password: &str, salt: &str, iterations: u32, size: usize) -> KerlabResult<Vec<u8>> {
+    let tkey = pbkdf2_hmac_sha1(password.as_bytes(), salt.as_bytes(), iterations, size);
+    derive_key(&tkey, b"kerberos")
+}
+
+/// Pseudo random function used by FAST
+///
+/// @see https://tools.ietf.org/html/rfc3962#section-6
+pub fn prf(key: &[u8], data: &[u8]) -> KerlabResult<Vec<u8>> {
+    let mut tmp = sha1(data);
+    tmp.truncate(BLOCK_SIZE);
+    cts_encrypt(&derive_key(key, b"prf")?, &tmp)
+}
+
+/// Checksum HMAC-SHA1-96 keyed with the derived checksum key
+///
+/// @see https://tools.ietf.org/html/rfc3962
+pub fn checksum(key: &[u8], usage: u32, data: &[u8]) -> KerlabResult<Vec<u8>> {
+    let mut result = hmac_sha1(&usage_key(key, usage, 0x99)?, data);
+    result.truncate(MAC_SIZE);
+    Ok(result)
+}
+
+/// AES CTS HMAC SHA1 96 encryption profile
+///
+/// @see https://tools.ietf.org/html/rfc3962
+pub struct AesCts {
+    key: Vec<u8>,
+    usage: KeyUsage
+}
+
+impl AesCts {
+    pub fn new(key: Vec<u8>, usage: KeyUsage) -> Self {
+        Self {
+            key,
+            usage
+        }
+    }
+
+    pub fn encrypt(&mut self, data: &[u8]) -> KerlabResult<Vec<u8>> {
+        let ke = usage_key(&self.key, self.usage as u32, 0xAA)?;
+        let ki = usage_key(&self.key, self.usage as u32, 0x55)?;
+
+        // generate the Confounder
+        let mut plaintext = random(BLOCK_SIZE);
+        plaintext.extend_from_slice(data);
+
+        let mut result = cts_encrypt(&ke, &plaintext)?;
+        result.extend_from_slice(&hmac_sha1(&ki, &plaintext)[0..MAC_SIZE]);
+        Ok(result)
+    }
+
+    pub fn decrypt(&mut self, data: &[u8]) -> KerlabResult<Vec<u8>> {
+        if data.len() < BLOCK_SIZE + MAC_SIZE {
+            return Err(Error::new(KerlabErrorKind::Crypto, "AES cipher is too short"))
+        }
+
+        let ke = usage_key(&self.key, self.usage as u32, 0xAA)?;
+        let ki = usage_key(&self.key, self.usage as u32, 0x55)?;
+
+        let (cipher, expected_mac) = data.split_at(data.len() - MAC_SIZE);
+        let plaintext = cts_decrypt(&ke, cipher)?;
+
+        if !constant_time_eq(&hmac_sha1(&ki, &plaintext)[0..MAC_SIZE], expected_mac) {
+            Err(Error::new(KerlabErrorKind::Kerberos, "AES HMAC checksum mismatch"))
+        }
+        else {
+            Ok(plaintext[BLOCK_SIZE..].to_vec())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// @see https://tools.ietf.org/html/rfc3961#appendix-A.1
+    #[test]
+    fn test_n_fold() {
+        assert_eq!(n_fold(b"012345", 8), [0xbe, 0x07, 0x26, 0x31, 0x27, 0x6b, 0x19, 0x55]);
+        assert_eq!(n_fold(b"password", 7), [0x78, 0xa0, 0x7b, 0x6c, 0xaf, 0x85, 0xfa]);
+        assert_eq!(n_fold(b"kerberos", 16), [0x6b, 0x65, 0x72, 0x62, 0x65, 0x72, 0x6f, 0x73, 0x7b, 0x9b, 0x5b, 0x2b, 0x93, 0x13, 0x2b, 0x93]);
+    }
+
+    /// @see https://tools.ietf.org/html/rfc3962#appendix-B
+    #[test]
+    fn test_string_to_key() {
+        assert_eq!(
+            string_to_key("password", "ATHENA.MIT.EDUraeburn", 1, 16).unwrap(),
+            [0x42, 0x26, 0x3c, 0x6e, 0x89, 0xf4, 0xfc, 0x28, 0xb8, 0xdf, 0x68, 0xee, 0x09, 0x79, 0x9f, 0x15]
+        );
+    }
+
+    /// @see https://tools.ietf.org/html/rfc3962#appendix-B
+    #[test]
+    fn test_cts() {
+        assert_eq!(
+            cts_encrypt(b"chicken teriyaki", b"I would like the ").unwrap(),
+            [0xc6, 0x35, 0x35, 0x68, 0xf2, 0xbf, 0x8c, 0xb4, 0xd8, 0xa5, 0x80, 0x36, 0x2d, 0xa7, 0xff, 0x7f, 0x97]
+        );
+    }
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        for size in &[0, 1, 16, 17, 31, 32, 45] {
+            let data = random(*size);
+            let key = random(32);
+            let cipher = AesCts::new(key.clone(), KeyUsage::KeyUsageAsRepEncPart).encrypt(&data).unwrap();
+            assert_eq!(AesCts::new(key, KeyUsage::KeyUsageAsRepEncPart).decrypt(&cipher).unwrap(), data);
+        }
+    }
+}
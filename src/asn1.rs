@@ -1,6 +1,8 @@
-use yasna::{Tag as YasnaTag, DERWriter, BERReader, ASN1Error, ASN1ErrorKind};
-use error::{KerlabResult};
-use yasna::tags::{TAG_GENERALSTRING, TAG_GENERALIZEDTIME};
+use yasna::{Tag as YasnaTag, DERWriter, BERReader, BERReaderSeq, ASN1Error, ASN1ErrorKind, ASN1Result};
+use yasna::models::ObjectIdentifier as YasnaObjectIdentifier;
+use num_bigint::BigUint;
+use error::{KerlabResult, Error};
+use yasna::tags::{TAG_GENERALSTRING, TAG_GENERALIZEDTIME, TAG_INTEGER, TAG_BOOLEAN, TAG_ENUM, TAG_OCTETSTRING, TAG_BITSTRING, TAG_SEQUENCE, TAG_SET, TAG_OID};
 use ascii::AsciiString;
 use bit_vec::BitVec;
 use chrono::{Utc, DateTime, NaiveDateTime};
@@ -14,6 +16,64 @@ pub trait ASN1 {
     fn write_asn1(&self, writer: DERWriter) -> KerlabResult<()>;
     /// Read the type from an ASN1 BER reader
     fn read_asn1(&mut self, reader: BERReader) -> KerlabResult<()>;
+    /// Tag of the type, None if it is known only after reading
+    fn tag() -> Option<YasnaTag> where Self: Sized {
+        None
+    }
+    /// Can the type be read from an element with this tag
+    /// Use to tell OPTIONAL fields and CHOICE alternatives apart without trial parsing
+    fn accepts(tag: YasnaTag) -> bool where Self: Sized {
+        Self::tag().is_none_or(|e| e == tag)
+    }
+}
+
+/// Keep the error of a read made into a yasna callback
+///
+/// yasna callbacks can only return an ASN1Error,
+/// the kerlab error is kept aside to not lose its context
+#[derive(Default)]
+pub struct Failure {
+    error: Option<Error>
+}
+
+impl Failure {
+    /// Keep the error and stop the yasna reader
+    pub fn keep<T>(&mut self, result: KerlabResult<T>) -> ASN1Result<T> {
+        result.map_err(|e| {
+            self.error = Some(e);
+            ASN1Error::new(ASN1ErrorKind::Invalid)
+        })
+    }
+
+    /// Kept error first, error of yasna otherwise
+    pub fn result<T>(self, result: ASN1Result<T>) -> KerlabResult<T> {
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(result?)
+        }
+    }
+}
+
+/// Check the tag of the next element before reading a type
+pub fn expect<T: ASN1>(reader: &BERReader) -> KerlabResult<()> {
+    match reader.lookahead_tag() {
+        Ok(tag) if T::accepts(tag) => Ok(()),
+        Ok(tag) => Err(Error::unexpected(T::tag(), Some(tag))),
+        // let the type fails by itself
+        Err(_) if T::tag().is_none() => Ok(()),
+        Err(_) => Err(Error::unexpected(T::tag(), None))
+    }
+}
+
+/// Read a field of a SEQUENCE
+/// The error carries the name and the position of the field
+pub fn read_field<T: ASN1>(field: &mut T, name: &str, sequence: &mut BERReaderSeq) -> KerlabResult<()> {
+    // an empty buffer points to the current position of the reader
+    let address = sequence.read_with_buffer(|_| Ok(())).ok().map(|(_, buffer)| buffer.as_ptr() as usize);
+    let reader = sequence.next();
+    expect::<T>(&reader)
+        .and_then(|_| field.read_asn1(reader))
+        .map_err(|e| e.within_field(name, address))
 }
 
 /// This type is used to declare an ASN1 type
@@ -29,23 +89,38 @@ pub type SequenceOf<T> = Vec<T>;
 impl<T: ASN1 + Default> ASN1 for SequenceOf<T> {
     /// Write in asn1 format a SequenceOf
     fn write_asn1(&self, writer: DERWriter) -> KerlabResult<()> {
+        let mut result = Ok(());
         writer.write_sequence_of(|sequence| {
             for child in self {
-                child.write_asn1(sequence.next()).unwrap();
+                if result.is_ok() {
+                    result = child.write_asn1(sequence.next());
+                }
             }
         });
-        Ok(())
+        result
     }
 
     /// Read asn1 sequence
     fn read_asn1(&mut self, reader: BERReader) -> KerlabResult<()> {
-        reader.read_sequence_of(|reader| {
+        let mut failure = Failure::default();
+        let result = reader.read_sequence_of(|reader| {
+            // no more element: end of the loop
+            reader.lookahead_tag()?;
             let mut element : T = Default::default();
-            element.read_asn1(reader)?;
+            let index = self.len();
+            failure.keep(
+                expect::<T>(&reader)
+                    .and_then(|_| element.read_asn1(reader))
+                    .map_err(|e| e.within_field(&format!("[{}]", index), None))
+            )?;
             self.push(element);
             Ok(())
-        })?;
-        Ok(())
+        });
+        failure.result(result)
+    }
+
+    fn tag() -> Option<YasnaTag> {
+        Some(TAG_SEQUENCE)
     }
 }
 
@@ -62,6 +137,10 @@ impl ASN1 for OctetString {
         *self = reader.read_bytes()?;
         Ok(())
     }
+
+    fn tag() -> Option<YasnaTag> {
+        Some(TAG_OCTETSTRING)
+    }
 }
 
 /// Tag is use to mark a field of
@@ -101,10 +180,15 @@ impl<const N: u64, T: ASN1 + PartialEq + Clone> ASN1 for Tag<{ N }, T> {
 
     /// Read a tag with appropriate number
     fn read_asn1(&mut self, reader: BERReader) -> KerlabResult<()> {
-        reader.read_tagged(YasnaTag::context({ N }), |tag_reader| {
-            Ok(self.inner.read_asn1(tag_reader)?)
-        })?;
-        Ok(())
+        let mut failure = Failure::default();
+        let result = reader.read_tagged(YasnaTag::context(N), |tag_reader| {
+            failure.keep(expect::<T>(&tag_reader).and_then(|_| self.inner.read_asn1(tag_reader)))
+        });
+        failure.result(result)
+    }
+
+    fn tag() -> Option<YasnaTag> {
+        Some(YasnaTag::context(N))
     }
 }
 
@@ -125,6 +209,67 @@ impl<const N: u64, T: ASN1 + PartialEq + Default + Clone> DerefMut for Tag<{ N }
     }
 }
 
+/// Implicit is use to mark a field of
+/// Sequence with a context tag that replace
+/// the tag of the inner type
+#[derive(PartialEq, Debug, Clone)]
+pub struct Implicit<const N: u64, T: PartialEq + Clone> {
+    /// The inner object
+    pub inner: T
+}
+
+impl<const N: u64, T: PartialEq + Clone> Implicit<{ N }, T> {
+    /// Constructor
+    pub fn new(inner: T) -> Self {
+        Implicit {
+            inner
+        }
+    }
+}
+
+impl<const N: u64, T: Default + PartialEq + Clone> Default for Implicit<{ N }, T> {
+    fn default() -> Self {
+        Implicit::new(T::default())
+    }
+}
+
+/// ASN1 implementation
+impl<const N: u64, T: ASN1 + PartialEq + Clone> ASN1 for Implicit<{ N }, T> {
+    /// Write the inner type with the context tag
+    fn write_asn1(&self, writer: DERWriter) -> KerlabResult<()> {
+        writer.write_tagged_implicit(YasnaTag::context(N), |writer| {
+            self.inner.write_asn1(writer)
+        })
+    }
+
+    /// Read the inner type with the context tag
+    /// The tag of the inner type is replaced, so it can't be checked
+    fn read_asn1(&mut self, reader: BERReader) -> KerlabResult<()> {
+        let mut failure = Failure::default();
+        let result = reader.read_tagged_implicit(YasnaTag::context(N), |tag_reader| {
+            failure.keep(self.inner.read_asn1(tag_reader))
+        });
+        failure.result(result)
+    }
+
+    fn tag() -> Option<YasnaTag> {
+        Some(YasnaTag::context(N))
+    }
+}
+
+impl<const N: u64, T: ASN1 + PartialEq + Default + Clone> Deref for Implicit<{ N }, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl<const N: u64, T: ASN1 + PartialEq + Default + Clone> DerefMut for Implicit<{ N }, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
+    }
+}
+
 /// Application is the top message for an ASN1 message
 #[derive(Default, PartialEq, Clone)]
 pub struct Application<const N: u64, T: Default + PartialEq + Clone> {
@@ -146,13 +291,18 @@ impl<const N: u64, T: ASN1 + Default + PartialEq + Clone> ASN1 for Application<{
 
     /// Read an application tag
     fn read_asn1(&mut self, reader: BERReader) -> KerlabResult<()> {
-        reader.read_tagged(
+        let mut failure = Failure::default();
+        let result = reader.read_tagged(
             YasnaTag::application({ N }),
             |tag_reader| {
-                Ok(self.inner.read_asn1(tag_reader)?)
+                failure.keep(expect::<T>(&tag_reader).and_then(|_| self.inner.read_asn1(tag_reader)))
             }
-        )?;
-        Ok(())
+        );
+        failure.result(result)
+    }
+
+    fn tag() -> Option<YasnaTag> {
+        Some(YasnaTag::application(N))
     }
 }
 
@@ -189,6 +339,10 @@ impl ASN1 for Integer {
         *self = reader.read_u32()?;
         Ok(())
     }
+
+    fn tag() -> Option<YasnaTag> {
+        Some(TAG_INTEGER)
+    }
 }
 
 /// An ASN1 Integer
@@ -209,6 +363,10 @@ impl ASN1 for SInteger {
         *self = reader.read_i32()?;
         Ok(())
     }
+
+    fn tag() -> Option<YasnaTag> {
+        Some(TAG_INTEGER)
+    }
 }
 
 /// ASN1 for boolean
@@ -227,6 +385,10 @@ impl ASN1 for bool {
         *self = reader.read_bool()?;
         Ok(())
     }
+
+    fn tag() -> Option<YasnaTag> {
+        Some(TAG_BOOLEAN)
+    }
 }
 
 /// An ASN1 Enumerate
@@ -247,6 +409,10 @@ impl ASN1 for Enumerate {
         *self = reader.read_enum()?;
         Ok(())
     }
+
+    fn tag() -> Option<YasnaTag> {
+        Some(TAG_ENUM)
+    }
 }
 
 /// General string is an alias for ascii string in kerbertos
@@ -273,11 +439,15 @@ impl ASN1 for GeneralString {
         })?;
         Ok(())
     }
+
+    fn tag() -> Option<YasnaTag> {
+        Some(TAG_GENERALSTRING)
+    }
 }
 
 /// Optional field
-/// Optional field can be processed or not
-/// during sequence reading
+/// Optional field is present when the next tag
+/// is the one of the inner type
 impl<T: ASN1 + Default> ASN1 for Option<T> {
     /// write ASN1
     fn write_asn1(&self, writer: DERWriter) -> KerlabResult<()> {
@@ -290,13 +460,24 @@ impl<T: ASN1 + Default> ASN1 for Option<T> {
     }
 
     /// Read ASN1
+    /// Errors of a present field are not hidden
     fn read_asn1(&mut self, reader: BERReader) -> KerlabResult<()> {
-        let mut result =T::default();
-        if let Ok(()) = result.read_asn1(reader) {
-            *self = Some(result);
-        }
+        *self = match reader.lookahead_tag() {
+            Ok(tag) if T::accepts(tag) => {
+                let mut result = T::default();
+                result.read_asn1(reader)?;
+                Some(result)
+            },
+            // another field or the end of the sequence
+            _ => None
+        };
         Ok(())
     }
+
+    /// Absent field is always accepted
+    fn accepts(_: YasnaTag) -> bool {
+        true
+    }
 }
 
 /// A wrapper to handle Rust time
@@ -339,13 +520,19 @@ impl ASN1 for GeneralizedTime {
     fn read_asn1(&mut self, reader: BERReader) -> KerlabResult<()> {
         *self = reader.read_tagged_implicit(TAG_GENERALIZEDTIME, |reader| {
             let bytes = reader.read_bytes()?;
-            match NaiveDateTime::parse_from_str(AsciiString::from_ascii(bytes).unwrap().as_str(), "%Y%m%d%H%M%SZ") {
-                Ok(date) => Ok(Self::new(DateTime::<Utc>::from_utc(date, Utc))),
-                Err(_) => Err(ASN1Error::new(ASN1ErrorKind::Invalid)),
+            let date = AsciiString::from_ascii(bytes).ok()
+                .and_then(|date| NaiveDateTime::parse_from_str(date.as_str(), "%Y%m%d%H%M%SZ").ok());
+            match date {
+                Some(date) => Ok(Self::new(DateTime::<Utc>::from_utc(date, Utc))),
+                None => Err(ASN1Error::new(ASN1ErrorKind::Invalid)),
             }
         })?;
         Ok(())
     }
+
+    fn tag() -> Option<YasnaTag> {
+        Some(TAG_GENERALIZEDTIME)
+    }
 }
 
 /// Use Bitvec for Bitstring
@@ -361,35 +548,202 @@ impl ASN1 for BitString {
         *self = reader.read_bitvec()?;
         Ok(())
     }
+
+    fn tag() -> Option<YasnaTag> {
+        Some(TAG_BITSTRING)
+    }
+}
+
+/// This type is used to declare an ASN1 type
+/// of SET OF
+/// Elements are sorted when written as DER
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct SetOf<T> {
+    pub inner: Vec<T>
+}
+
+impl<T> SetOf<T> {
+    /// constructor
+    pub fn new(inner: Vec<T>) -> Self {
+        Self {
+            inner
+        }
+    }
+}
+
+impl<T: ASN1 + Default> ASN1 for SetOf<T> {
+    fn write_asn1(&self, writer: DERWriter) -> KerlabResult<()> {
+        let mut result = Ok(());
+        writer.write_set_of(|set| {
+            for child in &self.inner {
+                if result.is_ok() {
+                    result = child.write_asn1(set.next());
+                }
+            }
+        });
+        result
+    }
+
+    fn read_asn1(&mut self, reader: BERReader) -> KerlabResult<()> {
+        let mut failure = Failure::default();
+        let result = reader.read_set_of(|reader| {
+            // no more element: end of the loop
+            reader.lookahead_tag()?;
+            let mut element : T = Default::default();
+            let index = self.inner.len();
+            failure.keep(
+                expect::<T>(&reader)
+                    .and_then(|_| element.read_asn1(reader))
+                    .map_err(|e| e.within_field(&format!("[{}]", index), None))
+            )?;
+            self.inner.push(element);
+            Ok(())
+        });
+        failure.result(result)
+    }
+
+    fn tag() -> Option<YasnaTag> {
+        Some(TAG_SET)
+    }
+}
+
+impl<T> Deref for SetOf<T> {
+    type Target = Vec<T>;
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+/// An ASN1 Object Identifier
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct ObjectIdentifier {
+    pub inner: Vec<u64>
+}
+
+impl ObjectIdentifier {
+    /// constructor
+    ///
+    /// # Example
+    /// ```
+    /// use kerlab::asn1::ObjectIdentifier;
+    /// let oid = ObjectIdentifier::new(&[1, 2, 840, 113549, 1, 7, 2]);
+    /// ```
+    pub fn new(components: &[u64]) -> Self {
+        Self {
+            inner: components.to_vec()
+        }
+    }
+}
+
+impl ASN1 for ObjectIdentifier {
+    fn write_asn1(&self, writer: DERWriter) -> KerlabResult<()> {
+        writer.write_oid(&YasnaObjectIdentifier::from_slice(&self.inner));
+        Ok(())
+    }
+
+    fn read_asn1(&mut self, reader: BERReader) -> KerlabResult<()> {
+        self.inner = reader.read_oid()?.components().clone();
+        Ok(())
+    }
+
+    fn tag() -> Option<YasnaTag> {
+        Some(TAG_OID)
+    }
+}
+
+/// Arbitrary size positive integer
+/// Use in public key cryptography
+pub type BigInteger = BigUint;
+
+impl ASN1 for BigInteger {
+    fn write_asn1(&self, writer: DERWriter) -> KerlabResult<()> {
+        writer.write_biguint(self);
+        Ok(())
+    }
+
+    fn read_asn1(&mut self, reader: BERReader) -> KerlabResult<()> {
+        *self = reader.read_biguint()?;
+        Ok(())
+    }
+
+    fn tag() -> Option<YasnaTag> {
+        Some(TAG_INTEGER)
+    }
+}
+
+/// Any ASN1 element kept as raw DER
+/// Use for fields that we don't need to interpret
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Any {
+    pub inner: Vec<u8>
+}
+
+impl Any {
+    /// constructor from a DER encoded element
+    pub fn new(der: Vec<u8>) -> Self {
+        Self {
+            inner: der
+        }
+    }
+
+    /// The ASN1 NULL element
+    pub fn null() -> Self {
+        Self::new(vec![0x05, 0x00])
+    }
+}
+
+impl ASN1 for Any {
+    fn write_asn1(&self, writer: DERWriter) -> KerlabResult<()> {
+        writer.write_der(&self.inner);
+        Ok(())
+    }
+
+    fn read_asn1(&mut self, reader: BERReader) -> KerlabResult<()> {
+        self.inner = reader.read_der()?;
+        Ok(())
+    }
+}
+
+impl Deref for Any {
+    type Target = Vec<u8>;
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
 }
 
 /// Serialize an ASN1 message into der stream
-pub fn to_der(message: &dyn ASN1) -> Vec<u8> {
-    yasna::construct_der(|writer| {
-        message.write_asn1(writer).unwrap();
+pub fn to_der(message: &dyn ASN1) -> KerlabResult<Vec<u8>> {
+    yasna::try_construct_der(|writer| {
+        message.write_asn1(writer)
     })
 }
 
 /// Deserialize an ASN1 message from a stream
+/// The offset of a decoding error is relative to the stream
 pub fn from_der(message: &mut dyn ASN1, stream: &[u8]) -> KerlabResult<()> {
-    Ok(yasna::parse_der(stream, |reader| {
-            Ok(message.read_asn1(reader)?)
-        })?
-    )
+    let mut failure = Failure::default();
+    let result = yasna::parse_der(stream, |reader| {
+        failure.keep(message.read_asn1(reader))
+    });
+    failure.result(result).map_err(|e| e.rebase(stream))
 }
 
 /// Deserialize an ASN1 message from a stream using BER
 pub fn from_ber(message: &mut dyn ASN1, stream: &[u8]) -> KerlabResult<()> {
-    Ok(yasna::parse_ber(stream, |reader| {
-            Ok(message.read_asn1(reader)?)
-        })?
-    )
+    let mut failure = Failure::default();
+    let result = yasna::parse_ber(stream, |reader| {
+        failure.keep(message.read_asn1(reader))
+    });
+    failure.result(result).map_err(|e| e.rebase(stream))
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
     use asn1::to_der;
+    use error::Error;
+    use encryption::EncryptedData;
+    use krbkdcrep::KdcRep;
 
     #[derive(Sequence)]
     pub struct TestOption {
@@ -408,6 +762,18 @@ mod test {
         }
     }
 
+    #[derive(Sequence, Default)]
+    pub struct TestTime {
+        time: Tag<0, GeneralizedTime>
+    }
+
+    #[derive(Choice, PartialEq, Clone)]
+    pub enum TestChoice {
+        Number(Tag<0, Integer>),
+        Flag(Implicit<1, bool>),
+        Message(Application<2, Integer>)
+    }
+
     /// Test format of the first client message
     #[test]
     fn test_optional_field() {
@@ -419,4 +785,49 @@ mod test {
         from_ber(&mut read_with, &[48, 15, 160, 3, 2, 1, 1, 161, 3, 2, 1, 2, 162, 3, 2, 1, 3]).unwrap();
         assert_eq!(*read_with.field_2.unwrap(), 2);
     }
+
+    /// Alternatives are selected by their tag
+    #[test]
+    fn test_choice() {
+        assert!(TestChoice::default() == TestChoice::Number(Tag::new(0)));
+
+        let message = TestChoice::Message(Application { inner: 3 });
+        assert_eq!(to_der(&message).unwrap(), [0x62, 3, 2, 1, 3]);
+
+        let mut read = TestChoice::default();
+        from_der(&mut read, &[0x81, 1, 0xff]).unwrap();
+        assert!(read == TestChoice::Flag(Implicit::new(true)));
+
+        from_der(&mut read, &[0x62, 3, 2, 1, 3]).unwrap();
+        assert!(read == message);
+
+        assert!(from_der(&mut read, &[0xa3, 3, 2, 1, 3]).is_err());
+    }
+
+    /// Errors carry the path, the offset and the expected tag
+    #[test]
+    fn test_decoding_error() {
+        // a present optional field must be valid
+        let error = from_ber(&mut TestOption::new(), &[48, 15, 160, 3, 2, 1, 1, 161, 3, 4, 1, 2, 162, 3, 2, 1, 3]).unwrap_err();
+        assert_eq!(format!("{:?}", error), "Decoding(TestOption.field_2: expected INTEGER, found OCTET STRING at offset 7)");
+
+        // a time from the wire is not always ASCII
+        let error = from_ber(&mut TestTime::default(), &[48, 7, 160, 5, 24, 3, 0xff, 0x31, 0x32]).unwrap_err();
+        assert_eq!(format!("{:?}", error), "Decoding(TestTime.time: Invalid at offset 2)");
+
+        let mut reply = KdcRep::default();
+        reply.enc_part.inner = EncryptedData::new(18, vec![1, 2, 3]);
+        let mut payload = to_der(&reply).unwrap();
+        let length = payload.len();
+        payload[length - 5] = 0x02;
+
+        match from_der(&mut KdcRep::default(), &payload) {
+            Err(Error::Decoding(e)) => {
+                assert_eq!(e.path(), "KdcRep.enc_part.cipher");
+                assert_eq!(e.offset, Some(length - 7));
+                assert_eq!(e.expected, Some(TAG_OCTETSTRING));
+            },
+            _ => panic!("KdcRep must not be decoded")
+        }
+    }
 }
@@ -0,0 +1,145 @@
+//! Security events of the KDC
+//! Events and fields mirror the Windows Security log,
+//! to test detection rules against traffic of kerlab tools
+
+use std::io::Write;
+use std::net::{SocketAddr, IpAddr};
+use std::sync::Mutex;
+use chrono::{Utc, DateTime};
+use json::{Json, ToJson};
+
+/// Windows Security event identifiers
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum EventId {
+    /// A Kerberos authentication ticket (TGT) was requested
+    TgtRequest = 4768,
+    /// A Kerberos service ticket was requested
+    ServiceTicket = 4769,
+    /// A Kerberos service ticket was renewed
+    TicketRenewal = 4770,
+    /// Kerberos pre-authentication failed
+    PreauthFailure = 4771
+}
+
+/// Ticket encryption type logged when no ticket was issued
+pub const NO_TICKET: u32 = 0xFFFFFFFF;
+
+/// Event emitted for each request processed by the KDC
+pub struct SecurityEvent {
+    pub event_id: EventId,
+    pub time_created: DateTime<Utc>,
+    pub target_user_name: String,
+    pub target_domain_name: String,
+    pub service_name: String,
+    pub ticket_options: u32,
+    /// Kerberos error code, 0 on success
+    pub status: u32,
+    /// Encryption type of the issued ticket, only for 4768 and 4769
+    pub ticket_encryption_type: Option<u32>,
+    /// Pre authentication padata type, only for 4768 and 4771
+    pub pre_auth_type: Option<u32>,
+    pub client: Option<SocketAddr>
+}
+
+impl SecurityEvent {
+    pub fn new(event_id: EventId, target_user_name: &str, target_domain_name: &str, service_name: &str) -> Self {
+        Self {
+            event_id,
+            time_created: Utc::now(),
+            target_user_name: target_user_name.to_string(),
+            target_domain_name: target_domain_name.to_string(),
+            service_name: service_name.to_string(),
+            ticket_options: 0,
+            status: 0,
+            ticket_encryption_type: None,
+            pre_auth_type: None,
+            client: None
+        }
+    }
+
+    /// Client address as written by Windows
+    /// IPv4 addresses are mapped into IPv6
+    fn ip_address(&self) -> String {
+        match self.client.map(|client| client.ip()) {
+            Some(IpAddr::V4(ip)) => format!("::ffff:{}", ip),
+            Some(IpAddr::V6(ip)) => ip.to_string(),
+            None => "-".to_string()
+        }
+    }
+}
+
+/// EventData values are strings, as in the Windows event
+impl ToJson for SecurityEvent {
+    fn to_json(&self) -> Json {
+        let mut fields = vec![
+            ("EventID", Json::Number(self.event_id as i64)),
+            ("TimeCreated", Json::String(self.time_created.to_rfc3339())),
+            ("TargetUserName", Json::string(&self.target_user_name)),
+            ("TargetDomainName", Json::string(&self.target_domain_name)),
+            ("ServiceName", Json::string(&self.service_name)),
+            ("TicketOptions", Json::String(format!("0x{:x}", self.ticket_options))),
+            ("Status", Json::String(format!("0x{:x}", self.status)))
+        ];
+        if let Some(ticket_encryption_type) = self.ticket_encryption_type {
+            fields.push(("TicketEncryptionType", Json::String(format!("0x{:x}", ticket_encryption_type))));
+        }
+        if let Some(pre_auth_type) = self.pre_auth_type {
+            fields.push(("PreAuthType", Json::String(pre_auth_type.to_string())));
+        }
+        fields.push(("IpAddress", Json::String(self.ip_address())));
+        fields.push(("IpPort", Json::String(self.client.map_or("0".to_string(), |client| client.port().to_string()))));
+        Json::object(fields)
+    }
+}
+
+/// Receive events of the KDC
+pub trait AuditSink: Send + Sync {
+    fn emit(&self, event: &SecurityEvent);
+}
+
+/// Write events as JSON lines
+pub struct JsonLines<W: Write + Send> {
+    writer: Mutex<W>
+}
+
+impl<W: Write + Send> JsonLines<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: Mutex::new(writer)
+        }
+    }
+}
+
+impl<W: Write + Send> AuditSink for JsonLines<W> {
+    fn emit(&self, event: &SecurityEvent) {
+        if let Ok(mut writer) = self.writer.lock() {
+            // audit must never stop the KDC
+            let _ = writeln!(writer, "{}", event.to_json());
+            let _ = writer.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// JSON line of a failed pre authentication
+    #[test]
+    fn test_to_json() {
+        let mut event = SecurityEvent::new(EventId::PreauthFailure, "alice", "LAB.LOCAL", "krbtgt/LAB.LOCAL");
+        event.ticket_options = 0x40810010;
+        event.status = 0x18;
+        event.pre_auth_type = Some(2);
+        event.client = Some("10.0.0.1:49152".parse().unwrap());
+
+        let json = event.to_json().to_string();
+        assert!(json.starts_with("{\"EventID\":4771,\"TimeCreated\":\""));
+        assert!(json.ends_with(concat!(
+            "\"TargetUserName\":\"alice\",\"TargetDomainName\":\"LAB.LOCAL\",\"ServiceName\":\"krbtgt/LAB.LOCAL\",",
+            "\"TicketOptions\":\"0x40810010\",\"Status\":\"0x18\",",
+            "\"PreAuthType\":\"2\",\"IpAddress\":\"::ffff:10.0.0.1\",\"IpPort\":\"49152\"}"
+        )));
+    }
+}
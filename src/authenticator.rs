@@ -1,10 +1,11 @@
-use asn1::{ASN1, Integer, Tag, Application};
+use asn1::{ASN1, Integer, Tag, Application, to_der};
 use yasna::{DERWriter, BERReader};
 use base::{Realm, PrincipalName, KerberosTime, AuthorizationData};
 use checksum::Checksum;
-use encryption::EncryptionKey;
+use encryption::{EncryptionKey, KeyUsage};
 use error::KerlabResult;
-use chrono::Utc;
+use krbkdcreq::KdcReqBody;
+use chrono::{Utc, DateTime, Timelike};
 
 /// Authenticator use to prove that we can decrypt TGT
 ///
@@ -56,4 +57,32 @@ impl Authenticator {
             }
         }
     }
+
+    /// Bind the authenticator of a TGS-REQ to the request body
+    ///
+    /// The checksum is keyed with the session key of the TGT,
+    /// RSA-MD5 is used when the key type has no keyed checksum
+    /// @see https://datatracker.ietf.org/doc/html/rfc4120#section-3.3.1
+    pub fn with_checksum(mut self, session_key: &EncryptionKey, req_body: &KdcReqBody) -> KerlabResult<Self> {
+        let body = to_der(req_body)?;
+        let checksum = match session_key.keytype.inner {
+            17 | 18 | 23 => Checksum::compute(session_key, KeyUsage::KrbKeyUsageTgsReqPaAuthenticatorCksum, &body)?,
+            _ => Checksum::rsa_md5(&body)
+        };
+        self.inner.cksum = Some(Tag::new(checksum));
+        Ok(self)
+    }
+
+    /// Time of the client, set to the KDC time when the clocks are skewed
+    pub fn with_ctime(mut self, time: DateTime<Utc>) -> Self {
+        self.inner.ctime = Tag::new(KerberosTime::new(time));
+        self.inner.cusec = Tag::new(time.nanosecond() / 1000 % 1000000);
+        self
+    }
+
+    /// Ask the KDC to encrypt its reply with this sub session key
+    pub fn with_subkey(mut self, subkey: EncryptionKey) -> Self {
+        self.inner.subkey = Some(Tag::new(subkey));
+        self
+    }
 }
\ No newline at end of file
@@ -1,6 +1,8 @@
 use error::{KerlabResult};
-use asn1::{ASN1, Integer, OctetString, SequenceOf, GeneralString, Tag, BitString, GeneralizedTime};
+use asn1::{ASN1, Integer, OctetString, SequenceOf, GeneralString, Tag, GeneralizedTime};
 use yasna::{BERReader, DERWriter};
+use std::net::IpAddr;
+use flags::{Flag, FlagSet};
 
 #[repr(u32)]
 pub enum MessageType {
@@ -23,9 +25,162 @@ pub type Realm = GeneralString;
 
 pub type KerberosTime = GeneralizedTime;
 
-pub type KDCOptions = BitString;
+/// Default tolerance between the clocks of two hosts
+/// @see https://datatracker.ietf.org/doc/html/rfc4120#appendix-A
+pub const DEFAULT_MAX_SKEW_MINUTES: i64 = 5;
 
-pub type TicketFlags = BitString;
+/// @see https://datatracker.ietf.org/doc/html/rfc4120#section-5.4.1
+/// ```asn.1
+/// KDCOptions      ::= KerberosFlags
+/// ```
+pub type KDCOptions = FlagSet<KdcOptionsType>;
+
+/// Bit position of KDC options
+/// cname-in-addl-tkt is defined by MS-SFU, canonicalize by RFC 6806
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum KdcOptionsType {
+    Reserved = 0,
+    Forwardable = 1,
+    Forwarded = 2,
+    Proxiable = 3,
+    Proxy = 4,
+    AllowPostDate = 5,
+    PostDated = 6,
+    Renewable = 8,
+    OptHardwareAuth = 11,
+    CNameInAddLTkt = 14,
+    Canonicalize = 15,
+    RequestAnonymous = 16,
+    DisableTransitedCheck = 26,
+    RenewableOk = 27,
+    EncTktInsKey = 28,
+    Renew = 30,
+    Validate = 31
+}
+
+impl Flag for KdcOptionsType {
+    const ALL: &'static [Self] = &[
+        KdcOptionsType::Reserved,
+        KdcOptionsType::Forwardable,
+        KdcOptionsType::Forwarded,
+        KdcOptionsType::Proxiable,
+        KdcOptionsType::Proxy,
+        KdcOptionsType::AllowPostDate,
+        KdcOptionsType::PostDated,
+        KdcOptionsType::Renewable,
+        KdcOptionsType::OptHardwareAuth,
+        KdcOptionsType::CNameInAddLTkt,
+        KdcOptionsType::Canonicalize,
+        KdcOptionsType::RequestAnonymous,
+        KdcOptionsType::DisableTransitedCheck,
+        KdcOptionsType::RenewableOk,
+        KdcOptionsType::EncTktInsKey,
+        KdcOptionsType::Renew,
+        KdcOptionsType::Validate
+    ];
+
+    fn position(self) -> u32 {
+        self as u32
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            KdcOptionsType::Reserved => "reserved",
+            KdcOptionsType::Forwardable => "forwardable",
+            KdcOptionsType::Forwarded => "forwarded",
+            KdcOptionsType::Proxiable => "proxiable",
+            KdcOptionsType::Proxy => "proxy",
+            KdcOptionsType::AllowPostDate => "allow-postdate",
+            KdcOptionsType::PostDated => "postdated",
+            KdcOptionsType::Renewable => "renewable",
+            KdcOptionsType::OptHardwareAuth => "opt-hardware-auth",
+            KdcOptionsType::CNameInAddLTkt => "cname-in-addl-tkt",
+            KdcOptionsType::Canonicalize => "canonicalize",
+            KdcOptionsType::RequestAnonymous => "request-anonymous",
+            KdcOptionsType::DisableTransitedCheck => "disable-transited-check",
+            KdcOptionsType::RenewableOk => "renewable-ok",
+            KdcOptionsType::EncTktInsKey => "enc-tkt-in-skey",
+            KdcOptionsType::Renew => "renew",
+            KdcOptionsType::Validate => "validate"
+        }
+    }
+}
+
+/// @see https://datatracker.ietf.org/doc/html/rfc4120#section-5.3
+/// ```asn.1
+/// TicketFlags     ::= KerberosFlags
+/// ```
+pub type TicketFlags = FlagSet<TicketFlagsType>;
+
+/// Bit position of ticket flags
+/// name-canonicalize is set by Windows KDC
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TicketFlagsType {
+    Reserved = 0,
+    Forwardable = 1,
+    Forwarded = 2,
+    Proxiable = 3,
+    Proxy = 4,
+    MayPostDate = 5,
+    PostDated = 6,
+    Invalid = 7,
+    Renewable = 8,
+    Initial = 9,
+    PreAuthent = 10,
+    HwAuthent = 11,
+    TransitedPolicyChecked = 12,
+    OkAsDelegate = 13,
+    NameCanonicalize = 15,
+    Anonymous = 16
+}
+
+impl Flag for TicketFlagsType {
+    const ALL: &'static [Self] = &[
+        TicketFlagsType::Reserved,
+        TicketFlagsType::Forwardable,
+        TicketFlagsType::Forwarded,
+        TicketFlagsType::Proxiable,
+        TicketFlagsType::Proxy,
+        TicketFlagsType::MayPostDate,
+        TicketFlagsType::PostDated,
+        TicketFlagsType::Invalid,
+        TicketFlagsType::Renewable,
+        TicketFlagsType::Initial,
+        TicketFlagsType::PreAuthent,
+        TicketFlagsType::HwAuthent,
+        TicketFlagsType::TransitedPolicyChecked,
+        TicketFlagsType::OkAsDelegate,
+        TicketFlagsType::NameCanonicalize,
+        TicketFlagsType::Anonymous
+    ];
+
+    fn position(self) -> u32 {
+        self as u32
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            TicketFlagsType::Reserved => "reserved",
+            TicketFlagsType::Forwardable => "forwardable",
+            TicketFlagsType::Forwarded => "forwarded",
+            TicketFlagsType::Proxiable => "proxiable",
+            TicketFlagsType::Proxy => "proxy",
+            TicketFlagsType::MayPostDate => "may-postdate",
+            TicketFlagsType::PostDated => "postdated",
+            TicketFlagsType::Invalid => "invalid",
+            TicketFlagsType::Renewable => "renewable",
+            TicketFlagsType::Initial => "initial",
+            TicketFlagsType::PreAuthent => "pre-authent",
+            TicketFlagsType::HwAuthent => "hw-authent",
+            TicketFlagsType::TransitedPolicyChecked => "transited-policy-checked",
+            TicketFlagsType::OkAsDelegate => "ok-as-delegate",
+            TicketFlagsType::NameCanonicalize => "name-canonicalize",
+            TicketFlagsType::Anonymous => "anonymous"
+        }
+    }
+}
 
 #[repr(u32)]
 pub enum PrincipalNameType {
@@ -65,6 +220,27 @@ impl PrincipalName {
             name_string: Tag::new(values)
         }
     }
+
+    /// Principal name components joined with /
+    pub fn name(&self) -> String {
+        self.name_string.iter()
+            .map(|x| x.as_str())
+            .collect::<Vec<&str>>()
+            .join("/")
+    }
+}
+
+#[repr(u32)]
+pub enum AddressType {
+    Ipv4 = 2,
+    Directional = 3,
+    ChaosNet = 5,
+    Xns = 6,
+    Iso = 7,
+    DecnetPhaseIv = 12,
+    AppletalkDdp = 16,
+    NetBios = 20,
+    Ipv6 = 24
 }
 
 /// Address type
@@ -91,6 +267,31 @@ impl HostAddress {
             address: Tag::new(OctetString::new())
         }
     }
+
+    /// Network address of a peer
+    pub fn from_ip(ip: &IpAddr) -> Self {
+        match ip {
+            IpAddr::V4(ip) => HostAddress {
+                addr_type: Tag::new(AddressType::Ipv4 as Integer),
+                address: Tag::new(ip.octets().to_vec())
+            },
+            IpAddr::V6(ip) => HostAddress {
+                addr_type: Tag::new(AddressType::Ipv6 as Integer),
+                address: Tag::new(ip.octets().to_vec())
+            }
+        }
+    }
+
+    /// NetBIOS name of a workstation as sent by Windows clients
+    /// upper case and padded with spaces to 16 characters
+    pub fn from_netbios(name: &str) -> Self {
+        let mut address = name.to_uppercase().into_bytes();
+        address.resize(16, b' ');
+        HostAddress {
+            addr_type: Tag::new(AddressType::NetBios as Integer),
+            address: Tag::new(address)
+        }
+    }
 }
 
 pub type HostAddresses = SequenceOf<HostAddress>;
@@ -138,6 +339,6 @@ mod test {
         let mut pn = PrincipalName::default();
         pn.name_type.inner = 2;
         pn.name_string.inner.push(GeneralString::from_ascii("foo").unwrap());
-        assert_eq!(to_der(&pn), [48, 14, 160, 3, 2, 1, 2, 161, 7, 48, 5, 27, 3, 102, 111, 111]);
+        assert_eq!(to_der(&pn).unwrap(), [48, 14, 160, 3, 2, 1, 2, 161, 7, 48, 5, 27, 3, 102, 111, 111]);
     }
 }
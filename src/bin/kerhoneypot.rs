@@ -0,0 +1,204 @@
+extern crate clap;
+extern crate kerlab;
+extern crate chrono;
+
+use clap::{App, Arg};
+use std::net::{TcpListener, TcpStream};
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time;
+use chrono::Utc;
+use kerlab::acceptor::{Acceptor, AuthContext};
+use kerlab::keytab::Keytab;
+use kerlab::encryption::EncryptionKey;
+use kerlab::krbap::ApReq;
+use kerlab::krberror::KrbError;
+use kerlab::base::HostAddress;
+use kerlab::asn1::{from_ber, to_der, ASN1};
+use kerlab::pac::PacStruct;
+use kerlab::error::{KerlabResult, Error, KerlabErrorKind};
+use kerlab::request::MAX_RECORD_SIZE;
+use kerlab::json::{Json, etype_name, error_code_name};
+
+const APPLICATION_NAME: &str = "kerhoneypot";
+
+/// A client that doesn't send its AP-REQ in time is dropped
+const READ_TIMEOUT: time::Duration = time::Duration::from_secs(10);
+
+/// Kerberos record mark: a 4 bytes big endian length
+fn read_message(stream: &mut TcpStream) -> KerlabResult<Vec<u8>> {
+    let mut length = [0; 4];
+    stream.read_exact(&mut length)?;
+    let length = u32::from_be_bytes(length) as usize;
+    if length > MAX_RECORD_SIZE {
+        return Err(Error::new(KerlabErrorKind::Parsing, "Record is too long"))
+    }
+    let mut payload = vec![0; length];
+    stream.read_exact(&mut payload)?;
+    Ok(payload)
+}
+
+fn write_message(stream: &mut TcpStream, message: &dyn ASN1) -> KerlabResult<()> {
+    let payload = to_der(message)?;
+    stream.write_all(&(payload.len() as u32).to_be_bytes())?;
+    stream.write_all(&payload)?;
+    Ok(())
+}
+
+/// Find the name of the client into the PAC
+fn pac_names(context: &AuthContext) -> Vec<(&'static str, String)> {
+    let mut result = vec![];
+    if let Some(pac) = &context.pac {
+        for buffer in &pac.buffers {
+            match buffer {
+                PacStruct::PacClientInfo(e) => result.push(("pac-client", e.name.clone())),
+                PacStruct::UpnDnsInfo(e) => result.push(("pac-upn", e.upn.clone())),
+                _ => ()
+            }
+        }
+    }
+    result
+}
+
+fn handle(acceptor: &Mutex<Acceptor>, stream: &mut TcpStream, json: bool) -> KerlabResult<()> {
+    let peer = stream.peer_addr()?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut ap_req = ApReq::default();
+    from_ber(&mut ap_req, &read_message(stream)?)?;
+
+    let service = format!("{}@{}", ap_req.ticket.sname.name(), ap_req.ticket.realm.as_str());
+    let etype = ap_req.ticket.enc_part.etype.inner;
+    let mut event = vec![
+        ("time", Json::String(Utc::now().to_rfc3339())),
+        ("peer", Json::String(peer.to_string())),
+        ("service", Json::String(service.clone())),
+        ("etype", Json::named(etype as i64, etype_name(etype)))
+    ];
+
+    let result = acceptor.lock().unwrap().accept(&ap_req, Some(&HostAddress::from_ip(&peer.ip())));
+    match result {
+        Ok(context) => {
+            let client = format!("{}@{}", context.client.name(), context.realm.as_str());
+            if json {
+                event.push(("result", Json::string("accepted")));
+                event.push(("client", Json::String(client)));
+                for (name, value) in pac_names(&context) {
+                    event.push((name, Json::String(value)));
+                }
+                println!("{}", Json::object(event));
+            } else {
+                println!(
+                    "{} peer={} service={} etype={} result=accepted client={} {}",
+                    Utc::now().to_rfc3339(),
+                    peer,
+                    service,
+                    etype,
+                    client,
+                    pac_names(&context).iter().map(|(name, value)| format!("{}={}", name, value)).collect::<Vec<String>>().join(" ")
+                );
+            }
+            if context.mutual_required {
+                write_message(stream, &context.ap_rep()?)?;
+            }
+        }
+        Err(Error::Protocol(code, message)) => {
+            if json {
+                event.push(("result", Json::string("rejected")));
+                event.push(("error", Json::named(code as i64, error_code_name(code as u32))));
+                event.push(("reason", Json::String(message.clone())));
+                println!("{}", Json::object(event));
+            } else {
+                println!(
+                    "{} peer={} service={} etype={} result=rejected error={} ({})",
+                    Utc::now().to_rfc3339(),
+                    peer,
+                    service,
+                    etype,
+                    code as u32,
+                    message
+                );
+            }
+            write_message(stream, &KrbError::new(
+                code,
+                ap_req.ticket.realm.inner.clone(),
+                ap_req.ticket.sname.inner.clone()
+            ))?;
+        }
+        Err(e) => return Err(e)
+    }
+    Ok(())
+}
+
+fn log_failure(error: &Error, json: bool) {
+    if json {
+        println!("{}", Json::object(vec![
+            ("time", Json::String(Utc::now().to_rfc3339())),
+            ("error", Json::String(format!("{:?}", error)))
+        ]));
+    } else {
+        println!("{} error {:?}", Utc::now().to_rfc3339(), error);
+    }
+}
+
+fn main() {
+    let matches = App::new(APPLICATION_NAME)
+        .version("0.1.0")
+        .author("Sylvain Peyrefitte <citronneur@gmail.com>")
+        .about("Kerberos Lab for Fun and Detection")
+        .arg(Arg::with_name("listen")
+            .long("listen")
+            .takes_value(true)
+            .default_value("0.0.0.0:4444")
+            .help("Listening address of the honeypot service"))
+        .arg(Arg::with_name("keytab")
+            .long("keytab")
+            .takes_value(true)
+            .help("Keytab that contains service keys"))
+        .arg(Arg::with_name("ntlm")
+            .long("ntlm")
+            .takes_value(true)
+            .help("NTLM hash of the service account"))
+        .arg(Arg::with_name("password")
+            .long("password")
+            .takes_value(true)
+            .help("Password of the service account"))
+        .arg(Arg::with_name("json")
+            .long("json")
+            .help("Print one JSON object per connection"))
+        .get_matches();
+
+    let json = matches.is_present("json");
+
+    let acceptor = if let Some(keytab) = matches.value_of("keytab") {
+        Acceptor::from_keytab(Keytab::from_file(keytab).unwrap())
+    } else if let Some(ntlm) = matches.value_of("ntlm") {
+        Acceptor::new(EncryptionKey::new_rc4_hmac_from_hash(hex::decode(ntlm).unwrap()).unwrap())
+    } else if let Some(password) = matches.value_of("password") {
+        Acceptor::new(EncryptionKey::new_rc4_hmac(password).unwrap())
+    } else {
+        panic!("You need to provide a keytab, ntlm or password argument");
+    };
+    // replay cache is shared by all connections
+    let acceptor = Arc::new(Mutex::new(acceptor));
+
+    let listen = matches.value_of("listen").unwrap_or_default();
+    let listener = TcpListener::bind(listen).unwrap();
+    if !json {
+        println!("Honeypot listening on {}", listen);
+    }
+
+    for stream in listener.incoming() {
+        match stream {
+            Ok(mut stream) => {
+                let acceptor = acceptor.clone();
+                thread::spawn(move || {
+                    if let Err(e) = handle(&acceptor, &mut stream, json) {
+                        log_failure(&e, json);
+                    }
+                });
+            }
+            Err(e) => log_failure(&Error::from(e), json)
+        }
+    }
+}
@@ -0,0 +1,168 @@
+extern crate clap;
+extern crate kerlab;
+extern crate chrono;
+
+use clap::{App, Arg};
+use std::fs::OpenOptions;
+use std::io;
+use std::fmt::Debug;
+use std::net::{TcpListener, UdpSocket};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread;
+use std::time;
+use chrono::{Utc, Duration};
+use kerlab::kdc::{Kdc, PrincipalDb};
+use kerlab::audit::JsonLines;
+use kerlab::krberror::KrbError;
+use kerlab::asn1::from_ber;
+use kerlab::ndr::Sid;
+use kerlab::base::DEFAULT_MAX_SKEW_MINUTES;
+use kerlab::json::{Json, error_code_name};
+
+const APPLICATION_NAME: &str = "kerkdc";
+
+/// A client that doesn't send its request in time is dropped
+const READ_TIMEOUT: time::Duration = time::Duration::from_secs(10);
+
+/// One line per reply
+fn log(protocol: &str, peer: &str, reply: &[u8], json: bool) {
+    let mut error = KrbError::default();
+    let is_error = from_ber(&mut error, reply).is_ok();
+    let e_text = error.e_text.as_ref().map(|e| e.as_str()).unwrap_or_default();
+
+    if json {
+        let mut event = vec![
+            ("time", Json::String(Utc::now().to_rfc3339())),
+            ("protocol", Json::string(protocol)),
+            ("peer", Json::string(peer)),
+            ("result", Json::string(if is_error { "error" } else { "ok" }))
+        ];
+        if is_error {
+            event.push(("error", Json::named(error.error_code.inner as i64, error_code_name(error.error_code.inner))));
+            event.push(("e_text", Json::string(e_text)));
+        }
+        println!("{}", Json::object(event));
+        return
+    }
+
+    let result = if is_error {
+        format!("result=error error={} ({})", error.error_code.inner, e_text)
+    } else {
+        "result=ok".to_string()
+    };
+    println!("{} {} peer={} {}", Utc::now().to_rfc3339(), protocol, peer, result);
+}
+
+/// Failure of the transport
+fn log_failure(protocol: &str, peer: &str, error: &dyn Debug, json: bool) {
+    if json {
+        println!("{}", Json::object(vec![
+            ("time", Json::String(Utc::now().to_rfc3339())),
+            ("protocol", Json::string(protocol)),
+            ("peer", Json::string(peer)),
+            ("failure", Json::String(format!("{:?}", error)))
+        ]));
+    } else {
+        println!("{} {} peer={} error {:?}", Utc::now().to_rfc3339(), protocol, peer, error);
+    }
+}
+
+fn main() {
+    let default_max_skew = DEFAULT_MAX_SKEW_MINUTES.to_string();
+    let matches = App::new(APPLICATION_NAME)
+        .version("0.1.0")
+        .author("Sylvain Peyrefitte <citronneur@gmail.com>")
+        .about("Kerberos Lab for Fun and Detection")
+        .arg(Arg::with_name("listen")
+            .long("listen")
+            .takes_value(true)
+            .default_value("127.0.0.1:88")
+            .help("Listening address of the KDC, for TCP and UDP"))
+        .arg(Arg::with_name("realm")
+            .long("realm")
+            .takes_value(true)
+            .help("Realm served by the KDC"))
+        .arg(Arg::with_name("domain-sid")
+            .long("domain-sid")
+            .takes_value(true)
+            .default_value("S-1-5-21-1111111111-2222222222-3333333333")
+            .help("SID of the domain use into the PAC"))
+        .arg(Arg::with_name("database")
+            .long("database")
+            .takes_value(true)
+            .help("Principal database, one principal per line"))
+        .arg(Arg::with_name("max-skew")
+            .long("max-skew")
+            .takes_value(true)
+            .default_value(&default_max_skew)
+            .help("Accepted clock skew in minutes"))
+        .arg(Arg::with_name("events")
+            .long("events")
+            .takes_value(true)
+            .help("Append security events (4768, 4769, 4770, 4771) as JSON lines to this file, - for stdout"))
+        .arg(Arg::with_name("json")
+            .long("json")
+            .help("Print one JSON object per reply"))
+        .get_matches();
+
+    let json = matches.is_present("json");
+
+    let realm = matches.value_of("realm").expect("You need to provide a realm argument");
+    let db = PrincipalDb::from_file(
+        realm,
+        matches.value_of("database").expect("You need to provide a database argument")
+    ).unwrap();
+
+    let mut kdc = Kdc::new(
+        realm,
+        Sid::from_str(matches.value_of("domain-sid").unwrap_or_default()).unwrap(),
+        db
+    ).with_max_skew(Duration::minutes(matches.value_of("max-skew").unwrap_or_default().parse().unwrap()));
+
+    kdc = match matches.value_of("events") {
+        Some("-") => kdc.with_audit(Box::new(JsonLines::new(io::stdout()))),
+        Some(path) => kdc.with_audit(Box::new(JsonLines::new(
+            OpenOptions::new().create(true).append(true).open(path).unwrap()
+        ))),
+        None => kdc
+    };
+    let kdc = Arc::new(kdc);
+
+    let listen = matches.value_of("listen").unwrap_or_default();
+
+    let udp_socket = UdpSocket::bind(listen).unwrap();
+    let udp_kdc = kdc.clone();
+    thread::spawn(move || {
+        loop {
+            match udp_kdc.handle_udp(&udp_socket) {
+                Ok((reply, peer)) => log("udp", &peer.to_string(), &reply, json),
+                Err(e) => log_failure("udp", "-", &e, json)
+            }
+        }
+    });
+
+    let listener = TcpListener::bind(listen).unwrap();
+    if !json {
+        println!("KDC of {} listening on {} (TCP and UDP)", realm.to_uppercase(), listen);
+    }
+
+    for stream in listener.incoming() {
+        match stream {
+            Ok(mut stream) => {
+                let kdc = kdc.clone();
+                thread::spawn(move || {
+                    let peer = stream.peer_addr().map(|e| e.to_string()).unwrap_or_default();
+                    if let Err(e) = stream.set_read_timeout(Some(READ_TIMEOUT)) {
+                        return log_failure("tcp", &peer, &e, json)
+                    }
+                    match kdc.handle_tcp(&mut stream) {
+                        Ok(reply) => log("tcp", &peer, &reply, json),
+                        Err(e) => log_failure("tcp", &peer, &e, json)
+                    }
+                });
+            }
+            Err(e) => log_failure("tcp", "-", &e, json)
+        }
+    }
+}
@@ -0,0 +1,216 @@
+extern crate clap;
+extern crate kerlab;
+
+use clap::{App, Arg};
+use kerlab::pcap::{Capture, KrbMessage};
+use kerlab::detect::Engine;
+use kerlab::display::{Formatter, Display};
+use kerlab::json::{Printer, ToJson};
+use kerlab::encryption::{EncryptionKey, EncryptedData, KeyUsage, EType};
+use kerlab::asn1::{ASN1, Integer, from_der};
+use kerlab::base::PrincipalName;
+use kerlab::keytab::Keytab;
+use kerlab::kdc::salt;
+use kerlab::krbap::ApReq;
+use kerlab::krbkdcrep::{EncASRepPart, EncTGSRepPart};
+use kerlab::padata::{PaDataType, PaEncTsEnc};
+use kerlab::ticket::{Ticket, EncTicketPart};
+use kerlab::authenticator::Authenticator;
+
+const APPLICATION_NAME: &str = "kerpcap";
+
+/// Keys known to decrypt messages of the capture
+struct Keys {
+    keytab: Option<Keytab>,
+    password: Option<String>,
+    ntlm: Option<EncryptionKey>,
+    /// Session keys found into decrypted replies and tickets
+    session_keys: Vec<EncryptionKey>
+}
+
+impl Keys {
+    /// Long term key of a client
+    fn client_key(&self, realm: &str, cname: &PrincipalName, etype: Integer) -> Option<EncryptionKey> {
+        if let Some(key) = self.keytab.as_ref().and_then(|keytab| keytab.find(cname, etype)) {
+            return Some(key.clone())
+        }
+        if etype == EType::Rc4Hmac as Integer {
+            if let Some(ntlm) = &self.ntlm {
+                return Some(ntlm.clone())
+            }
+        }
+
+        let password = self.password.as_ref()?;
+        match etype {
+            e if e == EType::Rc4Hmac as Integer => EncryptionKey::new_rc4_hmac(password).ok(),
+            e if e == EType::Aes256CtsHmacSha196 as Integer => EncryptionKey::new_aes(EType::Aes256CtsHmacSha196, password, &salt(realm, &cname.name())).ok(),
+            e if e == EType::Aes128CtsHmacSha196 as Integer => EncryptionKey::new_aes(EType::Aes128CtsHmacSha196, password, &salt(realm, &cname.name())).ok(),
+            _ => None
+        }
+    }
+
+    /// Decrypt a ticket using the keytab
+    fn ticket(&mut self, ticket: &Ticket, printer: &Printer) {
+        let key = match self.keytab.as_ref().and_then(|keytab| keytab.find(&ticket.sname, ticket.enc_part.etype.inner)) {
+            Some(key) => key.clone(),
+            None => return
+        };
+
+        if let Ok(enc_part) = key.decrypt::<EncTicketPart>(KeyUsage::KeyUsageAsRepTicket, &ticket.enc_part) {
+            printer.structure("EncTicketPart", &enc_part);
+            self.session_keys.push(enc_part.key.inner.clone());
+        }
+    }
+
+    /// Try the session keys
+    fn session<T: ASN1 + Default>(&self, key_usage: KeyUsage, data: &EncryptedData) -> Option<T> {
+        self.session_keys.iter().rev().filter_map(|key| key.decrypt::<T>(key_usage, data).ok()).next()
+    }
+}
+
+/// Decrypt the enc-parts of a message with the known keys
+fn decrypt(message: &KrbMessage, keys: &mut Keys, printer: &Printer) {
+    match message {
+        KrbMessage::AsReq(request) => {
+            let body = &request.req_body;
+            let padata = request.padata.as_ref()
+                .and_then(|padata| padata.iter().find(|e| e.padata_type.inner == PaDataType::PaEncTimestamp as Integer));
+            if let (Some(padata), Some(cname)) = (padata, &body.cname) {
+                let mut encrypted = EncryptedData::default();
+                if from_der(&mut encrypted, &padata.padata_value).is_err() {
+                    return
+                }
+                let timestamp = keys.client_key(body.realm.as_str(), cname, encrypted.etype.inner)
+                    .and_then(|key| key.decrypt::<PaEncTsEnc>(KeyUsage::KeyUsageAsReqTimestamp, &encrypted).ok());
+                if let Some(timestamp) = timestamp {
+                    printer.structure("PaEncTsEnc", &timestamp);
+                    if !printer.is_json() {
+                        println!();
+                    }
+                }
+            }
+        },
+        KrbMessage::AsRep(reply) => {
+            keys.ticket(&reply.ticket, printer);
+            let enc_part = keys.client_key(reply.crealm.as_str(), &reply.cname, reply.enc_part.etype.inner)
+                .and_then(|key| {
+                    key.decrypt::<EncASRepPart>(KeyUsage::KeyUsageAsRepEncPart1, &reply.enc_part)
+                        .or_else(|_| key.decrypt::<EncASRepPart>(KeyUsage::KeyUsageAsRepEncPart, &reply.enc_part))
+                        .ok()
+                });
+            if let Some(enc_part) = enc_part {
+                printer.structure("EncASRepPart", &enc_part);
+                keys.session_keys.push(enc_part.key.inner.clone());
+            }
+        },
+        KrbMessage::TgsReq(request) => {
+            let padata = request.padata.as_ref()
+                .and_then(|padata| padata.iter().find(|e| e.padata_type.inner == PaDataType::PaTgsReq as Integer));
+            let mut ap_req = ApReq::default();
+            if padata.is_none_or(|padata| from_der(&mut ap_req, &padata.padata_value).is_err()) {
+                return
+            }
+            keys.ticket(&ap_req.ticket, printer);
+            if let Some(authenticator) = keys.session::<Authenticator>(KeyUsage::KrbKeyUsageTgsReqPaAuthenticator, &ap_req.authenticator) {
+                printer.structure("Authenticator", &authenticator);
+            }
+        },
+        KrbMessage::TgsRep(reply) => {
+            keys.ticket(&reply.ticket, printer);
+            if let Some(enc_part) = keys.session::<EncTGSRepPart>(KeyUsage::KeyUsageAsRepEncPart, &reply.enc_part) {
+                printer.structure("EncTGSRepPart", &enc_part);
+                keys.session_keys.push(enc_part.key.inner.clone());
+            }
+        },
+        KrbMessage::KrbError(_) => ()
+    }
+}
+
+fn main() {
+    let matches = App::new(APPLICATION_NAME)
+        .version("0.1.0")
+        .author("Sylvain Peyrefitte <citronneur@gmail.com>")
+        .about("Kerberos Lab for Fun and Detection")
+        .arg(Arg::with_name("pcap")
+            .long("pcap")
+            .takes_value(true)
+            .help("Capture file, pcap or pcapng"))
+        .arg(Arg::with_name("keytab")
+            .long("keytab")
+            .takes_value(true)
+            .help("Keytab use to decrypt tickets and enc-parts"))
+        .arg(Arg::with_name("password")
+            .long("password")
+            .takes_value(true)
+            .help("Password of the client use to decrypt AS exchanges"))
+        .arg(Arg::with_name("ntlm")
+            .long("ntlm")
+            .takes_value(true)
+            .help("NTLM hash of the client use to decrypt AS exchanges"))
+        .arg(Arg::with_name("detect")
+            .long("detect")
+            .help("Apply the detection rules and print findings as JSON lines"))
+        .arg(Arg::with_name("json")
+            .long("json")
+            .help("Print messages as JSON lines"))
+        .get_matches();
+
+    let printer = Printer::new(matches.is_present("json"));
+
+    let capture = Capture::from_file(
+        matches.value_of("pcap").expect("You need to provide a pcap argument")
+    ).unwrap();
+
+    if matches.is_present("detect") {
+        let mut engine = Engine::new();
+        if let Some(path) = matches.value_of("keytab") {
+            engine = engine.with_keytab(Keytab::from_file(path).unwrap());
+        }
+        for finding in engine.analyze(&capture.packets) {
+            println!("{}", finding.to_json());
+        }
+        return
+    }
+
+    let mut keys = Keys {
+        keytab: matches.value_of("keytab").map(|path| Keytab::from_file(path).unwrap()),
+        password: matches.value_of("password").map(|password| password.to_string()),
+        ntlm: matches.value_of("ntlm").map(|ntlm| EncryptionKey::new_rc4_hmac_from_hash(hex::decode(ntlm).unwrap()).unwrap()),
+        session_keys: vec![]
+    };
+
+    for packet in &capture.packets {
+        if printer.is_json() {
+            println!("{}", packet.to_json());
+            if let Ok(message) = packet.decode() {
+                decrypt(&message, &mut keys, &printer);
+            }
+            continue;
+        }
+
+        println!("**************************************************");
+        match packet.decode() {
+            Ok(message) => {
+                println!(
+                    "{} {:?} {} -> {} {}",
+                    packet.timestamp.to_rfc3339(),
+                    packet.transport,
+                    packet.source,
+                    packet.destination,
+                    message.name()
+                );
+                message.format(&mut Formatter::new());
+                decrypt(&message, &mut keys, &printer);
+            },
+            Err(e) => println!(
+                "{} {:?} {} -> {} unable to decode message {:?}",
+                packet.timestamp.to_rfc3339(),
+                packet.transport,
+                packet.source,
+                packet.destination,
+                e
+            )
+        }
+    }
+    printer.message(&format!("{} Kerberos messages", capture.packets.len()));
+}
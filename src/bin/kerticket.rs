@@ -5,7 +5,7 @@ use clap::{App, Arg};
 use std::fs;
 use kerlab::krbcred::{KrbCred, EncKrbCredPart};
 use kerlab::asn1::{from_ber};
-use kerlab::display::{Formatter, Display};
+use kerlab::json::Printer;
 use kerlab::encryption::{EncryptionKey, KeyUsage};
 use kerlab::ticket::EncTicketPart;
 use std::fs::File;
@@ -34,8 +34,13 @@ fn main() {
             .long("hashcat")
             .takes_value(true)
             .help("output file for hash cat brute forcing"))
+        .arg(Arg::with_name("json")
+            .long("json")
+            .help("Print messages as JSON lines"))
         .get_matches();
 
+    let printer = Printer::new(matches.is_present("json"));
+
     // load ticket info from tgt
     let contents = fs::read(
         matches.value_of("ticket")
@@ -45,19 +50,14 @@ fn main() {
     let mut ticket = KrbCred::default();
     from_ber(&mut ticket, &contents).unwrap();
 
-    println!("*******************************************");
-    println!("KRB-CRED := ");
-    ticket.format(&mut Formatter::new());
+    printer.structure("KRB-CRED", &ticket);
 
-    println!("*******************************************");
-    println!("Decrypting KRB-CRED.enc_part");
-    println!("EncKrbCredPart := ");
     let mut body = EncryptionKey::new_no_encryption()
         .decrypt::<EncKrbCredPart>(
-            KeyUsage::KeyUsageAsRepEncPart,
+            KeyUsage::KeyUsageKrbCredEncPart,
             &ticket.enc_part,
         ).unwrap();
-    body.format(&mut Formatter::new());
+    printer.structure("EncKrbCredPart", &body);
 
 
     let mut key: Option<EncryptionKey> = None;
@@ -71,19 +71,15 @@ fn main() {
     let tgs = ticket.tickets.pop().unwrap();
 
     if let Some(key) = key {
-        println!("**************************************************");
-        println!("Trying to decrypt the first ticket.enc-part");
-        println!("EncTicketPart := ");
+        // first ticket of the KRB-CRED
         let ticket_enc_part = key.decrypt::<EncTicketPart>(
                 KeyUsage::KeyUsageAsRepTicket,
                 &tgs.enc_part,
             ).unwrap();
-        ticket_enc_part.format(&mut Formatter::new());
-
-
+        printer.structure("EncTicketPart", &ticket_enc_part);
     }
 
-    println!("**************************************************");
+    printer.end();
 
     if let Some(hashcat) = matches.value_of("hashcat") {
         let mut file = File::create(hashcat).unwrap();
@@ -0,0 +1,190 @@
+use std::io::{Cursor, Read, Write};
+use std::fs::File;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use chrono::Duration;
+use error::{KerlabResult, Error, KerlabErrorKind};
+use base::{PrincipalName, Realm, KerberosTime};
+use asn1::to_der;
+use ticket::Ticket;
+use krbkdcrep::EncKDCRepPart;
+
+/// Header tag that stores the offset of the KDC clock
+const DELTA_TIME_TAG: u16 = 1;
+
+fn write_counted_string(writer: &mut dyn Write, data: &[u8]) -> KerlabResult<()> {
+    writer.write_u32::<BigEndian>(data.len() as u32)?;
+    writer.write_all(data)?;
+    Ok(())
+}
+
+fn write_principal(writer: &mut dyn Write, realm: &Realm, name: &PrincipalName) -> KerlabResult<()> {
+    writer.write_u32::<BigEndian>(name.name_type.inner)?;
+    writer.write_u32::<BigEndian>(name.name_string.len() as u32)?;
+    write_counted_string(writer, realm.as_bytes())?;
+    for component in name.name_string.iter() {
+        write_counted_string(writer, component.as_bytes())?;
+    }
+    Ok(())
+}
+
+fn write_time(writer: &mut dyn Write, time: Option<&KerberosTime>) -> KerlabResult<()> {
+    writer.write_u32::<BigEndian>(time.map_or(0, |e| e.inner.timestamp() as u32))?;
+    Ok(())
+}
+
+/// A ticket with its session key
+struct Credential {
+    client: (Realm, PrincipalName),
+    ticket: Ticket,
+    enc_part: EncKDCRepPart
+}
+
+impl Credential {
+    fn write(&self, writer: &mut dyn Write) -> KerlabResult<()> {
+        write_principal(writer, &self.client.0, &self.client.1)?;
+        write_principal(writer, &self.enc_part.srealm, &self.enc_part.sname)?;
+
+        writer.write_u16::<BigEndian>(self.enc_part.key.keytype.inner as u16)?;
+        write_counted_string(writer, &self.enc_part.key.keyvalue)?;
+
+        write_time(writer, Some(&self.enc_part.authtime))?;
+        write_time(writer, self.enc_part.starttime.as_ref().map(|e| &e.inner).or(Some(&self.enc_part.authtime)))?;
+        write_time(writer, Some(&self.enc_part.endtime))?;
+        write_time(writer, self.enc_part.renew_till.as_ref().map(|e| &e.inner))?;
+
+        // is_skey
+        writer.write_u8(0)?;
+        writer.write_u32::<BigEndian>(self.enc_part.flags.bits())?;
+
+        let addresses = self.enc_part.caddr.as_ref().map(|e| e.inner.clone()).unwrap_or_default();
+        writer.write_u32::<BigEndian>(addresses.len() as u32)?;
+        for address in addresses.iter() {
+            writer.write_u16::<BigEndian>(address.addr_type.inner as u16)?;
+            write_counted_string(writer, &address.address)?;
+        }
+
+        // authdata
+        writer.write_u32::<BigEndian>(0)?;
+
+        write_counted_string(writer, &to_der(&self.ticket)?)?;
+        // second ticket
+        write_counted_string(writer, &[])
+    }
+}
+
+/// MIT credential cache file, version 4
+///
+/// Used by MIT and Heimdal tools, and by impacket through KRB5CCNAME.
+/// The header carries the offset of the KDC clock,
+/// so the following requests are made at the KDC time
+///
+/// @see https://web.mit.edu/kerberos/krb5-devel/doc/formats/ccache_file_format.html
+pub struct CCache {
+    realm: Realm,
+    principal: PrincipalName,
+    time_offset: Option<Duration>,
+    credentials: Vec<Credential>
+}
+
+impl CCache {
+    /// constructor
+    /// Default principal of the cache
+    pub fn new(realm: Realm, principal: PrincipalName) -> Self {
+        Self {
+            realm,
+            principal,
+            time_offset: None,
+            credentials: vec![]
+        }
+    }
+
+    /// Offset of the KDC clock from the local clock
+    pub fn with_time_offset(mut self, offset: Duration) -> Self {
+        self.time_offset = Some(offset);
+        self
+    }
+
+    /// Add a ticket of the default principal
+    pub fn with_credential(mut self, ticket: Ticket, enc_part: EncKDCRepPart) -> Self {
+        self.credentials.push(Credential {
+            client: (self.realm.clone(), self.principal.clone()),
+            ticket,
+            enc_part
+        });
+        self
+    }
+
+    pub fn write(&self, writer: &mut dyn Write) -> KerlabResult<()> {
+        writer.write_u16::<BigEndian>(0x0504)?;
+
+        let mut header = vec![];
+        if let Some(offset) = self.time_offset {
+            let microseconds = offset.num_microseconds().unwrap_or_default();
+            header.write_u16::<BigEndian>(DELTA_TIME_TAG)?;
+            header.write_u16::<BigEndian>(8)?;
+            header.write_i32::<BigEndian>(microseconds.div_euclid(1000000) as i32)?;
+            header.write_i32::<BigEndian>(microseconds.rem_euclid(1000000) as i32)?;
+        }
+        writer.write_u16::<BigEndian>(header.len() as u16)?;
+        writer.write_all(&header)?;
+
+        write_principal(writer, &self.realm, &self.principal)?;
+        for credential in &self.credentials {
+            credential.write(writer)?;
+        }
+        Ok(())
+    }
+
+    /// Save the cache on disk
+    pub fn to_file(&self, path: &str) -> KerlabResult<()> {
+        self.write(&mut File::create(path)?)
+    }
+}
+
+/// Read the offset of the KDC clock from the header of a cache
+pub fn read_time_offset(buf: &[u8]) -> KerlabResult<Option<Duration>> {
+    let mut cursor = Cursor::new(buf);
+    if cursor.read_u16::<BigEndian>()? != 0x0504 {
+        return Err(Error::new(KerlabErrorKind::Parsing, "Only ccache version 4 has a header"))
+    }
+
+    let header_length = cursor.read_u16::<BigEndian>()? as u64;
+    let end = cursor.position() + header_length;
+    while cursor.position() < end {
+        let tag = cursor.read_u16::<BigEndian>()?;
+        let length = cursor.read_u16::<BigEndian>()?;
+        if tag == DELTA_TIME_TAG && length == 8 {
+            let seconds = cursor.read_i32::<BigEndian>()?;
+            let microseconds = cursor.read_i32::<BigEndian>()?;
+            return Ok(Some(Duration::seconds(seconds as i64) + Duration::microseconds(microseconds as i64)))
+        }
+        let mut skip = vec![0; length as usize];
+        cursor.read_exact(&mut skip)?;
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use asn1::GeneralString;
+    use base::PrincipalNameType;
+    use std::str::FromStr;
+
+    /// The offset written into the header is read back
+    #[test]
+    fn test_time_offset() {
+        let principal = PrincipalName::new(PrincipalNameType::NtPrincipal, vec![GeneralString::from_str("alice").unwrap()]);
+        let realm = GeneralString::from_str("LAB.LOCAL").unwrap();
+
+        let mut buffer = vec![];
+        CCache::new(realm.clone(), principal.clone()).write(&mut buffer).unwrap();
+        assert_eq!(read_time_offset(&buffer).unwrap(), None);
+        assert_eq!(&buffer[..4], [0x05, 0x04, 0x00, 0x00]);
+
+        let mut buffer = vec![];
+        CCache::new(realm, principal).with_time_offset(Duration::milliseconds(-1500)).write(&mut buffer).unwrap();
+        assert_eq!(&buffer[4..16], [0x00, 0x01, 0x00, 0x08, 0xff, 0xff, 0xff, 0xfe, 0x00, 0x07, 0xa1, 0x20]);
+        assert_eq!(read_time_offset(&buffer).unwrap(), Some(Duration::milliseconds(-1500)));
+    }
+}
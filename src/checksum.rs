@@ -1,8 +1,12 @@
 use asn1::{ASN1, Tag, OctetString, SInteger};
 use yasna::{DERWriter, BERReader};
-use error::KerlabResult;
+use error::{KerlabResult, Error, KerlabErrorKind};
 use rc4hmac::hmac_md5;
 use md5::{Md5, Digest};
+use json::{Json, ToJson, checksum_type_name};
+use encryption::{ChecksumType, EncryptionKey, KeyUsage};
+use krberror::KrbErrorCode;
+use aescts;
 
 /// Compute the MD5 Hash of input vector
 ///
@@ -32,6 +36,26 @@ pub fn kerberos_hmac_md5(key: &[u8], key_usage: i32, plaintext: &[u8]) -> Vec<u8
     return hmac_md5(&ksign, &tmp);
 }
 
+/// CRC-32 used by Kerberos
+/// No bit inversion, neither at the start nor at the end
+///
+/// @see https://datatracker.ietf.org/doc/html/rfc3961#section-6.1.3
+pub fn crc32(data: &[u8]) -> Vec<u8> {
+    let mut crc = 0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+    crc.to_le_bytes().to_vec()
+}
+
+/// Compare two checksums or MACs in a time that doesn't depend on their first difference
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
 /// @see https://www.freesoft.org/CIE/RFC/1510/77.htm
 /// ```asn.1
 /// Checksum ::=   SEQUENCE {
@@ -53,4 +77,123 @@ impl Checksum {
             checksum: Tag::new(checksum)
         }
     }
-}
\ No newline at end of file
+
+    /// Unkeyed checksum, for keys without a keyed checksum
+    pub fn rsa_md5(data: &[u8]) -> Self {
+        Self::new(ChecksumType::RsaMd5 as SInteger, md5(data))
+    }
+
+    /// Keyed checksum associated to the encryption type of the key
+    pub fn compute(key: &EncryptionKey, usage: KeyUsage, data: &[u8]) -> KerlabResult<Self> {
+        let cksumtype = match key.keytype.inner {
+            17 => ChecksumType::HmacSha196Aes128,
+            18 => ChecksumType::HmacSha196Aes256,
+            23 | 24 => ChecksumType::HmacMd5,
+            _ => return Err(Error::new(KerlabErrorKind::Crypto, "Unsupported Algorithm"))
+        };
+        Self::compute_with(cksumtype, key, usage, data)
+    }
+
+    /// Checksum of a given type
+    /// The key is ignored by unkeyed checksums
+    pub fn compute_with(cksumtype: ChecksumType, key: &EncryptionKey, usage: KeyUsage, data: &[u8]) -> KerlabResult<Self> {
+        Ok(Self::new(
+            cksumtype as SInteger,
+            digest(cksumtype as SInteger, key, usage, data)?
+        ))
+    }
+
+    /// Check the checksum using its own type
+    pub fn verify(&self, key: &EncryptionKey, usage: KeyUsage, data: &[u8]) -> KerlabResult<()> {
+        if !constant_time_eq(&digest(self.cksumtype.inner, key, usage, data)?, &self.checksum.inner) {
+            return Err(Error::protocol(KrbErrorCode::KrbApErrModified, "Checksum mismatch"))
+        }
+        Ok(())
+    }
+
+    /// Keyed checksums can't be computed again without the key
+    pub fn is_keyed(&self) -> bool {
+        [ChecksumType::HmacSha196Aes128, ChecksumType::HmacSha196Aes256, ChecksumType::HmacMd5]
+            .iter()
+            .any(|e| *e as SInteger == self.cksumtype.inner)
+    }
+
+    pub fn cksumtype(&self) -> SInteger {
+        self.cksumtype.inner
+    }
+
+    pub fn checksum(&self) -> &[u8] {
+        &self.checksum.inner
+    }
+}
+
+/// Dispatch on checksum type
+fn digest(cksumtype: SInteger, key: &EncryptionKey, usage: KeyUsage, data: &[u8]) -> KerlabResult<Vec<u8>> {
+    match cksumtype {
+        1 => Ok(crc32(data)),
+        7 => Ok(md5(data)),
+        15 | 16 => {
+            let keytype = if cksumtype == 15 { 17 } else { 18 };
+            if key.keytype.inner != keytype {
+                return Err(Error::protocol(KrbErrorCode::KrbApErrInappCksum, "Key doesn't match the checksum type"))
+            }
+            aescts::checksum(&key.keyvalue, usage as u32, data)
+        },
+        // Windows computes HMAC-MD5 with any key, for PA-FOR-USER
+        -138 => Ok(kerberos_hmac_md5(&key.keyvalue, usage as i32, data)),
+        _ => Err(Error::protocol(KrbErrorCode::KdcErrSumtypeNoSupp, "Unsupported checksum type"))
+    }
+}
+
+impl ToJson for Checksum {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("cksumtype", Json::named(self.cksumtype.inner as i64, checksum_type_name(self.cksumtype.inner))),
+            ("checksum", self.checksum.to_json())
+        ])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use encryption::EType;
+
+    /// @see https://datatracker.ietf.org/doc/html/rfc3961#appendix-A.5
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"foo"), [0x33, 0xbc, 0x32, 0x73]);
+        assert_eq!(crc32(b"test0123456789"), [0xd6, 0x88, 0x3e, 0xb8]);
+        assert_eq!(crc32(b"MASSACHVSETTS INSTITVTE OF TECHNOLOGY"), [0xf7, 0x80, 0x41, 0xe3]);
+    }
+
+    /// @see https://datatracker.ietf.org/doc/html/rfc1321#appendix-A.5
+    #[test]
+    fn test_rsa_md5() {
+        assert_eq!(hex::encode(Checksum::rsa_md5(b"").checksum()), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(hex::encode(Checksum::rsa_md5(b"abc").checksum()), "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(hex::encode(Checksum::rsa_md5(b"message digest").checksum()), "f96b697d7cb7938d525a2f31aaf161d0");
+        assert_eq!(Checksum::rsa_md5(b"abc").cksumtype(), ChecksumType::RsaMd5 as SInteger);
+    }
+
+    /// Checksums are verified with their own type
+    #[test]
+    fn test_verify() {
+        let key = EncryptionKey::new_aes(EType::Aes256CtsHmacSha196, "password", "ATHENA.MIT.EDUraeburn").unwrap();
+        let checksum = Checksum::compute(&key, KeyUsage::KeyUsageKrbSafeCksum, b"data").unwrap();
+        assert_eq!(checksum.cksumtype(), ChecksumType::HmacSha196Aes256 as SInteger);
+        assert_eq!(checksum.checksum().len(), 12);
+        assert!(checksum.verify(&key, KeyUsage::KeyUsageKrbSafeCksum, b"data").is_ok());
+        assert!(checksum.verify(&key, KeyUsage::KeyUsageApReqAuthenticatorCksum, b"data").is_err());
+        assert!(checksum.verify(&key, KeyUsage::KeyUsageKrbSafeCksum, b"other").is_err());
+
+        let rc4 = EncryptionKey::new_rc4_hmac("password").unwrap();
+        assert!(checksum.verify(&rc4, KeyUsage::KeyUsageKrbSafeCksum, b"data").is_err());
+
+        let checksum = Checksum::compute_with(ChecksumType::HmacMd5, &key, KeyUsage::KeyUsageKerbNonKerbCksumSalt, b"data").unwrap();
+        assert!(checksum.verify(&key, KeyUsage::KeyUsageKerbNonKerbCksumSalt, b"data").is_ok());
+
+        let crc = Checksum::compute_with(ChecksumType::Crc32, &key, KeyUsage::KeyUsageKrbSafeCksum, b"foo").unwrap();
+        assert_eq!(crc.checksum(), [0x33, 0xbc, 0x32, 0x73]);
+    }
+}
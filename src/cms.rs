@@ -0,0 +1,233 @@
+//! Cryptographic Message Syntax
+//! Only SignedData with RSA signer is supported
+//! @see https://tools.ietf.org/html/rfc5652
+
+use asn1::{ASN1, Tag, Implicit, Integer, OctetString, ObjectIdentifier, Any, SetOf, to_der, from_ber};
+use yasna::{DERWriter, BERReader};
+use error::{KerlabResult, Error, KerlabErrorKind};
+use x509::{AlgorithmIdentifier, Certificate};
+use rsa::{RsaPrivateKey, DigestAlgorithm};
+
+/// id-data
+pub const OID_DATA: [u64; 7] = [1, 2, 840, 113549, 1, 7, 1];
+/// id-signedData
+pub const OID_SIGNED_DATA: [u64; 7] = [1, 2, 840, 113549, 1, 7, 2];
+/// id-encryptedData
+pub const OID_ENCRYPTED_DATA: [u64; 7] = [1, 2, 840, 113549, 1, 7, 6];
+/// id-contentType
+pub const OID_CONTENT_TYPE: [u64; 7] = [1, 2, 840, 113549, 1, 9, 3];
+/// id-messageDigest
+pub const OID_MESSAGE_DIGEST: [u64; 7] = [1, 2, 840, 113549, 1, 9, 4];
+
+/// ```asn.1
+/// ContentInfo ::= SEQUENCE {
+///     contentType ContentType,
+///     content [0] EXPLICIT ANY DEFINED BY contentType
+/// }
+/// ```
+#[derive(Sequence, Default, Clone, PartialEq)]
+pub struct ContentInfo {
+    pub content_type: ObjectIdentifier,
+    pub content: Tag<0, Any>
+}
+
+/// ```asn.1
+/// EncapsulatedContentInfo ::= SEQUENCE {
+///     eContentType ContentType,
+///     eContent [0] EXPLICIT OCTET STRING OPTIONAL
+/// }
+/// ```
+#[derive(Sequence, Default, Clone, PartialEq)]
+pub struct EncapsulatedContentInfo {
+    pub e_content_type: ObjectIdentifier,
+    pub e_content: Option<Tag<0, OctetString>>
+}
+
+/// ```asn.1
+/// Attribute ::= SEQUENCE {
+///     attrType OBJECT IDENTIFIER,
+///     attrValues SET OF AttributeValue
+/// }
+/// ```
+#[derive(Sequence, Default, Clone, PartialEq)]
+pub struct Attribute {
+    pub attr_type: ObjectIdentifier,
+    pub attr_values: SetOf<Any>
+}
+
+impl Attribute {
+    /// constructor for single value attribute
+    pub fn new(attr_type: &[u64], value: &dyn ASN1) -> KerlabResult<Self> {
+        Ok(Self {
+            attr_type: ObjectIdentifier::new(attr_type),
+            attr_values: SetOf::new(vec![Any::new(to_der(value)?)])
+        })
+    }
+}
+
+/// ```asn.1
+/// SignerInfo ::= SEQUENCE {
+///     version CMSVersion,
+///     sid SignerIdentifier,
+///     digestAlgorithm DigestAlgorithmIdentifier,
+///     signedAttrs [0] IMPLICIT SignedAttributes OPTIONAL,
+///     signatureAlgorithm SignatureAlgorithmIdentifier,
+///     signature SignatureValue,
+///     unsignedAttrs [1] IMPLICIT UnsignedAttributes OPTIONAL
+/// }
+/// ```
+#[derive(Sequence, Default, Clone, PartialEq)]
+pub struct SignerInfo {
+    pub version: Integer,
+    pub sid: Any,
+    pub digest_algorithm: AlgorithmIdentifier,
+    pub signed_attrs: Option<Implicit<0, SetOf<Attribute>>>,
+    pub signature_algorithm: AlgorithmIdentifier,
+    pub signature: OctetString,
+    pub unsigned_attrs: Option<Implicit<1, SetOf<Attribute>>>
+}
+
+impl SignerInfo {
+    /// Find the value of a signed attribute
+    fn signed_attr(&self, attr_type: &[u64]) -> Option<&Any> {
+        let attr_type = ObjectIdentifier::new(attr_type);
+        self.signed_attrs.as_ref()?.iter()
+            .find(|attr| attr.attr_type == attr_type)?
+            .attr_values.first()
+    }
+}
+
+/// ```asn.1
+/// SignedData ::= SEQUENCE {
+///     version CMSVersion,
+///     digestAlgorithms DigestAlgorithmIdentifiers,
+///     encapContentInfo EncapsulatedContentInfo,
+///     certificates [0] IMPLICIT CertificateSet OPTIONAL,
+///     crls [1] IMPLICIT RevocationInfoChoices OPTIONAL,
+///     signerInfos SignerInfos
+/// }
+/// ```
+#[derive(Sequence, Default, Clone, PartialEq)]
+pub struct SignedData {
+    pub version: Integer,
+    pub digest_algorithms: SetOf<AlgorithmIdentifier>,
+    pub encap_content_info: EncapsulatedContentInfo,
+    pub certificates: Option<Implicit<0, SetOf<Any>>>,
+    pub crls: Option<Implicit<1, SetOf<Any>>>,
+    pub signer_infos: SetOf<SignerInfo>
+}
+
+impl SignedData {
+    /// Sign a content with the private key of the certificate
+    ///
+    /// Content type and message digest are signed as attributes
+    pub fn sign(content_type: &[u64], content: Vec<u8>, certificate: &Certificate, key: &RsaPrivateKey, digest: DigestAlgorithm) -> KerlabResult<Self> {
+        let digest_algorithm = AlgorithmIdentifier {
+            algorithm: digest.oid(),
+            parameters: Some(Any::null())
+        };
+
+        let signed_attrs = SetOf::new(vec![
+            Attribute::new(&OID_CONTENT_TYPE, &ObjectIdentifier::new(content_type))?,
+            Attribute::new(&OID_MESSAGE_DIGEST, &digest.digest(&content))?
+        ]);
+
+        let signature = key.sign(digest, &to_der(&signed_attrs)?)?;
+
+        Ok(Self {
+            version: 3,
+            digest_algorithms: SetOf::new(vec![digest_algorithm.clone()]),
+            encap_content_info: EncapsulatedContentInfo {
+                e_content_type: ObjectIdentifier::new(content_type),
+                e_content: Some(Tag::new(content))
+            },
+            certificates: Some(Implicit::new(SetOf::new(vec![Any::new(to_der(certificate)?)]))),
+            crls: None,
+            signer_infos: SetOf::new(vec![SignerInfo {
+                version: 1,
+                sid: Any::new(to_der(&certificate.issuer_and_serial_number())?),
+                digest_algorithm,
+                signed_attrs: Some(Implicit::new(signed_attrs)),
+                signature_algorithm: AlgorithmIdentifier {
+                    algorithm: digest.signature_oid(),
+                    parameters: Some(Any::null())
+                },
+                signature,
+                unsigned_attrs: None
+            }])
+        })
+    }
+
+    /// Parse a SignedData wrapped into a ContentInfo
+    pub fn from_content_info(buf: &[u8]) -> KerlabResult<Self> {
+        let mut content_info = ContentInfo::default();
+        from_ber(&mut content_info, buf)?;
+        if content_info.content_type != ObjectIdentifier::new(&OID_SIGNED_DATA) {
+            return Err(Error::new(KerlabErrorKind::Parsing, "ContentInfo is not a SignedData"))
+        }
+        let mut signed_data = SignedData::default();
+        from_ber(&mut signed_data, &content_info.content.inner)?;
+        Ok(signed_data)
+    }
+
+    /// Wrap into a ContentInfo
+    pub fn to_content_info(&self) -> KerlabResult<ContentInfo> {
+        Ok(ContentInfo {
+            content_type: ObjectIdentifier::new(&OID_SIGNED_DATA),
+            content: Tag::new(Any::new(to_der(self)?))
+        })
+    }
+
+    /// Content signed by the signer
+    pub fn content(&self) -> KerlabResult<&OctetString> {
+        match &self.encap_content_info.e_content {
+            Some(content) => Ok(&content.inner),
+            None => Err(Error::new(KerlabErrorKind::Parsing, "SignedData without content"))
+        }
+    }
+
+    /// Certificates embedded into the message
+    pub fn certificates(&self) -> KerlabResult<Vec<Certificate>> {
+        match &self.certificates {
+            Some(certificates) => certificates.iter()
+                .map(|der| Certificate::from_der(&der.inner))
+                .collect(),
+            None => Ok(vec![])
+        }
+    }
+
+    /// Check the signature of the first signer
+    /// against the certificate embedded into the message
+    ///
+    /// Return the certificate of the signer
+    pub fn verify(&self, content_type: &[u64]) -> KerlabResult<Certificate> {
+        if self.encap_content_info.e_content_type != ObjectIdentifier::new(content_type) {
+            return Err(Error::new(KerlabErrorKind::Crypto, "Unexpected SignedData content type"))
+        }
+
+        let signer = self.signer_infos.first()
+            .ok_or_else(|| Error::new(KerlabErrorKind::Crypto, "SignedData without signer"))?;
+
+        let certificate = self.certificates()?.into_iter()
+            .find(|certificate| to_der(&certificate.issuer_and_serial_number()).is_ok_and(|sid| sid == signer.sid.inner))
+            .ok_or_else(|| Error::new(KerlabErrorKind::Crypto, "Signer certificate not found"))?;
+
+        let digest = DigestAlgorithm::from_oid(&signer.digest_algorithm.algorithm)?;
+        let content = self.content()?;
+
+        let signed = match &signer.signed_attrs {
+            Some(signed_attrs) => {
+                let message_digest = signer.signed_attr(&OID_MESSAGE_DIGEST)
+                    .ok_or_else(|| Error::new(KerlabErrorKind::Crypto, "Signed attributes without message digest"))?;
+                if message_digest.inner != to_der(&digest.digest(content))? {
+                    return Err(Error::new(KerlabErrorKind::Crypto, "SignedData message digest mismatch"))
+                }
+                to_der(&signed_attrs.inner)?
+            },
+            None => content.clone()
+        };
+
+        certificate.public_key()?.verify(digest, &signed, &signer.signature)?;
+        Ok(certificate)
+    }
+}
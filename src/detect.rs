@@ -0,0 +1,504 @@
+//! Detection of Kerberos attacks into captured traffic
+//!
+//! Rules match the attacks kerlab can perform,
+//! findings reference the index of the offending messages into the capture
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::IpAddr;
+use chrono::Duration;
+use asn1::Integer;
+use base::KdcOptionsType;
+use encryption::{EType, KeyUsage};
+use json::{Json, ToJson};
+use keytab::Keytab;
+use krbap::ApReq;
+use krberror::KrbErrorCode;
+use krbkdcreq::KdcReq;
+use padata::{PaData, PaDataType};
+use pcap::{KerberosPacket, KrbMessage};
+use ticket::{Ticket, EncTicketPart};
+
+const DEFAULT_KERBEROASTING_SPNS: usize = 3;
+const DEFAULT_SPRAYING_USERS: usize = 3;
+const DEFAULT_BRUTE_FORCE_ATTEMPTS: usize = 5;
+const DEFAULT_MAX_LIFETIME_HOURS: i64 = 10;
+
+/// Rules of the detection engine
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Rule {
+    /// TGS-REQ for many SPNs asking for RC4 tickets
+    Kerberoasting,
+    /// AS-REP delivered without pre authentication
+    AsRepRoasting,
+    /// Pre authentication failures across many users
+    PasswordSpraying,
+    /// Pre authentication failures or revoked account on a single user
+    BruteForce,
+    /// S4U2Self, followed or not by S4U2Proxy
+    S4u,
+    /// User to user ticket request
+    UserToUser,
+    /// Ticket lifetime longer than the domain policy
+    AbnormalLifetime,
+    /// Request offering only RC4 or weaker encryption types
+    Rc4Downgrade
+}
+
+impl Rule {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Rule::Kerberoasting => "kerberoasting",
+            Rule::AsRepRoasting => "asrep-roasting",
+            Rule::PasswordSpraying => "password-spraying",
+            Rule::BruteForce => "brute-force",
+            Rule::S4u => "s4u",
+            Rule::UserToUser => "user-to-user",
+            Rule::AbnormalLifetime => "abnormal-lifetime",
+            Rule::Rc4Downgrade => "rc4-downgrade"
+        }
+    }
+}
+
+/// A match of a rule
+pub struct Finding {
+    pub rule: Rule,
+    pub description: String,
+    /// Address of the client
+    pub source: IpAddr,
+    /// Index of the offending messages into the capture
+    pub messages: Vec<usize>
+}
+
+impl ToJson for Finding {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("rule", Json::string(self.rule.name())),
+            ("description", Json::string(&self.description)),
+            ("source", Json::String(self.source.to_string())),
+            ("messages", Json::Array(self.messages.iter().map(|e| Json::Number(*e as i64)).collect()))
+        ])
+    }
+}
+
+fn has_padata(request: &KdcReq, padata_type: PaDataType) -> bool {
+    let padata_type = padata_type as Integer;
+    request.padata.as_ref().is_some_and(|padata| padata.iter().any(|e| e.padata_type.inner == padata_type))
+}
+
+fn find_padata(request: &KdcReq, padata_type: PaDataType) -> Option<&PaData> {
+    let padata_type = padata_type as Integer;
+    request.padata.as_ref().and_then(|padata| padata.iter().find(|e| e.padata_type.inner == padata_type))
+}
+
+fn is_weak(etype: Integer) -> bool {
+    etype != EType::Aes128CtsHmacSha196 as Integer && etype != EType::Aes256CtsHmacSha196 as Integer
+}
+
+fn cname(request: &KdcReq) -> String {
+    request.req_body.cname.as_ref().map_or("-".to_string(), |cname| cname.name())
+}
+
+fn sname(request: &KdcReq) -> String {
+    request.req_body.sname.as_ref().map_or("-".to_string(), |sname| sname.name())
+}
+
+/// Index of the reply of each message
+/// The reply is the next message sent back on the same addresses and ports
+fn replies(packets: &[KerberosPacket]) -> Vec<Option<usize>> {
+    let mut result = vec![None; packets.len()];
+    let mut next = BTreeMap::new();
+    for (index, packet) in packets.iter().enumerate().rev() {
+        result[index] = next.get(&(packet.destination, packet.source)).cloned();
+        next.insert((packet.source, packet.destination), index);
+    }
+    result
+}
+
+/// Add a message to the group of a key
+fn group<K: Ord>(groups: &mut BTreeMap<K, Vec<usize>>, key: K, index: usize) {
+    groups.entry(key).or_default().push(index);
+}
+
+/// Rule engine over a capture
+pub struct Engine {
+    kerberoasting_spns: usize,
+    spraying_users: usize,
+    brute_force_attempts: usize,
+    max_lifetime: Duration,
+    keytab: Option<Keytab>
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Self {
+            kerberoasting_spns: DEFAULT_KERBEROASTING_SPNS,
+            spraying_users: DEFAULT_SPRAYING_USERS,
+            brute_force_attempts: DEFAULT_BRUTE_FORCE_ATTEMPTS,
+            max_lifetime: Duration::hours(DEFAULT_MAX_LIFETIME_HOURS),
+            keytab: None
+        }
+    }
+
+    /// Minimal number of distinct SPNs requested with RC4 by a client
+    pub fn with_kerberoasting_spns(mut self, spns: usize) -> Self {
+        self.kerberoasting_spns = spns;
+        self
+    }
+
+    /// Minimal number of distinct users failing pre authentication from a client
+    pub fn with_spraying_users(mut self, users: usize) -> Self {
+        self.spraying_users = users;
+        self
+    }
+
+    /// Minimal number of failures for a single user
+    pub fn with_brute_force_attempts(mut self, attempts: usize) -> Self {
+        self.brute_force_attempts = attempts;
+        self
+    }
+
+    /// Maximum ticket lifetime of the domain policy
+    pub fn with_max_lifetime(mut self, max_lifetime: Duration) -> Self {
+        self.max_lifetime = max_lifetime;
+        self
+    }
+
+    /// Keys use to decrypt tickets and check their lifetime
+    pub fn with_keytab(mut self, keytab: Keytab) -> Self {
+        self.keytab = Some(keytab);
+        self
+    }
+
+    /// Apply all rules on the packets of a capture
+    pub fn analyze(&self, packets: &[KerberosPacket]) -> Vec<Finding> {
+        let messages: Vec<Option<KrbMessage>> = packets.iter().map(|packet| packet.decode().ok()).collect();
+        let replies = replies(packets);
+
+        let mut roasted_spns: BTreeMap<IpAddr, Vec<usize>> = BTreeMap::new();
+        let mut asrep_roasted: BTreeMap<IpAddr, Vec<usize>> = BTreeMap::new();
+        let mut failures: BTreeMap<(IpAddr, String), Vec<usize>> = BTreeMap::new();
+        let mut wrong_passwords: BTreeMap<(IpAddr, String), Vec<usize>> = BTreeMap::new();
+        let mut downgrades: BTreeMap<IpAddr, Vec<usize>> = BTreeMap::new();
+        let mut findings = vec![];
+
+        for (index, message) in messages.iter().enumerate() {
+            let source = packets[index].source.ip();
+            match message {
+                Some(KrbMessage::AsReq(request)) => {
+                    if request.req_body.etype.iter().all(|etype| is_weak(*etype)) {
+                        group(&mut downgrades, source, index);
+                    }
+
+                    let reply_index = match replies[index] {
+                        Some(reply_index) => reply_index,
+                        None => continue
+                    };
+                    match &messages[reply_index] {
+                        Some(KrbMessage::AsRep(_)) => {
+                            let preauth = has_padata(request, PaDataType::PaEncTimestamp)
+                                || has_padata(request, PaDataType::PaPkAsReq)
+                                || has_padata(request, PaDataType::PaEncryptedChallenge)
+                                || has_padata(request, PaDataType::PaFxFast);
+                            if !preauth {
+                                let indexes = asrep_roasted.entry(source).or_default();
+                                indexes.push(index);
+                                indexes.push(reply_index);
+                            }
+                        },
+                        Some(KrbMessage::KrbError(error)) => {
+                            let code = error.error_code.inner;
+                            if code == KrbErrorCode::KdcErrPreauthFailed as Integer || code == KrbErrorCode::KdcErrClientRevoked as Integer {
+                                let indexes = failures.entry((source, cname(request))).or_default();
+                                indexes.push(index);
+                                indexes.push(reply_index);
+                            }
+                            // a locked out account tells nothing about spraying
+                            if code == KrbErrorCode::KdcErrPreauthFailed as Integer {
+                                let indexes = wrong_passwords.entry((source, cname(request))).or_default();
+                                indexes.push(index);
+                                indexes.push(reply_index);
+                            }
+                        },
+                        _ => ()
+                    }
+                },
+                Some(KrbMessage::TgsReq(request)) => {
+                    let body = &request.req_body;
+                    let options = body.kdc_options.inner;
+                    let weak = body.etype.iter().all(|etype| is_weak(*etype));
+                    if weak {
+                        group(&mut downgrades, source, index);
+                    }
+
+                    if options.contains(KdcOptionsType::EncTktInsKey) {
+                        findings.push(Finding {
+                            rule: Rule::UserToUser,
+                            description: format!("User to user ticket requested for {}", sname(request)),
+                            source,
+                            messages: vec![index]
+                        });
+                    }
+
+                    let s4u2self = has_padata(request, PaDataType::PaForUser);
+                    let s4u2proxy = options.contains(KdcOptionsType::CNameInAddLTkt);
+                    if s4u2self {
+                        findings.push(self.s4u(packets, &messages, &replies, index));
+                    }
+
+                    let is_krbtgt = body.sname.as_ref()
+                        .and_then(|sname| sname.name_string.first())
+                        .is_some_and(|name| name.as_str().eq_ignore_ascii_case("krbtgt"));
+                    if weak && !is_krbtgt && !s4u2self && !s4u2proxy && !options.contains(KdcOptionsType::EncTktInsKey) {
+                        group(&mut roasted_spns, source, index);
+                    }
+
+                    if let Some(padata) = find_padata(request, PaDataType::PaTgsReq) {
+                        let mut ap_req = ApReq::default();
+                        if ::asn1::from_der(&mut ap_req, &padata.padata_value).is_ok() {
+                            self.check_lifetime(&ap_req.ticket, source, index, &mut findings);
+                        }
+                    }
+                },
+                // the ticket is checked for the client that receives it
+                Some(KrbMessage::AsRep(reply)) => {
+                    self.check_lifetime(&reply.ticket, packets[index].destination.ip(), index, &mut findings);
+                },
+                Some(KrbMessage::TgsRep(reply)) => {
+                    self.check_lifetime(&reply.ticket, packets[index].destination.ip(), index, &mut findings);
+                },
+                _ => ()
+            }
+        }
+
+        for (source, indexes) in roasted_spns {
+            let spns: BTreeSet<String> = indexes.iter().filter_map(|index| match &messages[*index] {
+                Some(KrbMessage::TgsReq(request)) => Some(sname(request)),
+                _ => None
+            }).collect();
+            if spns.len() >= self.kerberoasting_spns {
+                findings.push(Finding {
+                    rule: Rule::Kerberoasting,
+                    description: format!("{} SPNs requested with RC4: {}", spns.len(), spns.into_iter().collect::<Vec<String>>().join(", ")),
+                    source,
+                    messages: indexes
+                });
+            }
+        }
+
+        for (source, indexes) in asrep_roasted {
+            let users: BTreeSet<String> = indexes.iter().filter_map(|index| match &messages[*index] {
+                Some(KrbMessage::AsReq(request)) => Some(cname(request)),
+                _ => None
+            }).collect();
+            findings.push(Finding {
+                rule: Rule::AsRepRoasting,
+                description: format!("AS-REP delivered without pre authentication for {}", users.into_iter().collect::<Vec<String>>().join(", ")),
+                source,
+                messages: indexes
+            });
+        }
+
+        for ((source, user), indexes) in failures {
+            // a request and its error for each attempt
+            if indexes.len() / 2 >= self.brute_force_attempts {
+                findings.push(Finding {
+                    rule: Rule::BruteForce,
+                    description: format!("{} pre authentication failures for {}", indexes.len() / 2, user),
+                    source,
+                    messages: indexes
+                });
+            }
+        }
+
+        // spraying tries a few passwords on many users
+        let mut sprayed: BTreeMap<IpAddr, (Vec<String>, Vec<usize>)> = BTreeMap::new();
+        for ((source, user), indexes) in wrong_passwords {
+            let spray = sprayed.entry(source).or_insert_with(|| (vec![], vec![]));
+            spray.0.push(user);
+            spray.1.extend(indexes);
+        }
+
+        for (source, (users, mut indexes)) in sprayed {
+            if users.len() >= self.spraying_users {
+                indexes.sort();
+                findings.push(Finding {
+                    rule: Rule::PasswordSpraying,
+                    description: format!("Pre authentication failures for {} users: {}", users.len(), users.join(", ")),
+                    source,
+                    messages: indexes
+                });
+            }
+        }
+
+        for (source, indexes) in downgrades {
+            findings.push(Finding {
+                rule: Rule::Rc4Downgrade,
+                description: format!("{} requests offering only RC4 or weaker encryption types", indexes.len()),
+                source,
+                messages: indexes
+            });
+        }
+
+        findings.sort_by_key(|finding| finding.messages.first().cloned());
+        findings
+    }
+
+    /// S4U2Self request and the S4U2Proxy requests of the same client using its ticket
+    fn s4u(&self, packets: &[KerberosPacket], messages: &[Option<KrbMessage>], replies: &[Option<usize>], index: usize) -> Finding {
+        let source = packets[index].source;
+        let mut indexes = vec![index];
+        let mut description = "S4U2Self".to_string();
+
+        let ticket = match replies[index].and_then(|reply_index| messages[reply_index].as_ref().map(|reply| (reply_index, reply))) {
+            Some((reply_index, KrbMessage::TgsRep(reply))) => {
+                indexes.push(reply_index);
+                description = format!("S4U2Self for {}", reply.cname.name());
+                Some(reply.ticket.inner.clone())
+            },
+            _ => None
+        };
+
+        if let Some(ticket) = ticket {
+            for (proxy_index, message) in messages.iter().enumerate().skip(index + 1) {
+                if let Some(KrbMessage::TgsReq(request)) = message {
+                    let uses_ticket = request.req_body.additional_tickets.as_ref()
+                        .is_some_and(|tickets| tickets.iter().any(|e| e.enc_part == ticket.enc_part));
+                    if packets[proxy_index].source.ip() == source.ip() && uses_ticket {
+                        indexes.push(proxy_index);
+                        description = format!("{} then S4U2Proxy to {}", description, sname(request));
+                    }
+                }
+            }
+        }
+
+        Finding {
+            rule: Rule::S4u,
+            description,
+            source: source.ip(),
+            messages: indexes
+        }
+    }
+
+    /// Decrypt a ticket with the keytab and check its lifetime
+    fn check_lifetime(&self, ticket: &Ticket, source: IpAddr, index: usize, findings: &mut Vec<Finding>) {
+        let key = match self.keytab.as_ref().and_then(|keytab| keytab.find(&ticket.sname, ticket.enc_part.etype.inner)) {
+            Some(key) => key,
+            None => return
+        };
+
+        if let Ok(enc_part) = key.decrypt::<EncTicketPart>(KeyUsage::KeyUsageAsRepTicket, &ticket.enc_part) {
+            let start = enc_part.starttime.as_ref().map_or(enc_part.authtime.inner.inner, |starttime| starttime.inner.inner);
+            let lifetime = enc_part.endtime.inner.inner - start;
+            if lifetime > self.max_lifetime {
+                findings.push(Finding {
+                    rule: Rule::AbnormalLifetime,
+                    description: format!(
+                        "Ticket of {} for {} valid {} hours",
+                        enc_part.cname.name(),
+                        ticket.sname.name(),
+                        lifetime.num_hours()
+                    ),
+                    source,
+                    messages: vec![index]
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::str::FromStr;
+    use asn1::{GeneralString, to_der};
+    use base::{PrincipalName, PrincipalNameType};
+    use chrono::Utc;
+    use encryption::EncryptionKey;
+    use krberror::KrbError;
+    use krbkdcreq::{AsReq, TgsReq};
+    use authenticator::Authenticator;
+    use pcap::Transport;
+
+    fn packet(source: &str, destination: &str, payload: Vec<u8>) -> KerberosPacket {
+        KerberosPacket {
+            timestamp: Utc::now(),
+            source: source.parse().unwrap(),
+            destination: destination.parse().unwrap(),
+            transport: Transport::Tcp,
+            payload
+        }
+    }
+
+    fn error(code: KrbErrorCode) -> Vec<u8> {
+        to_der(&KrbError::new(code, GeneralString::from_str("LAB.LOCAL").unwrap(), PrincipalName::default())).unwrap()
+    }
+
+    /// Kerberoasting, spraying and brute force from the same client
+    #[test]
+    fn test_rules() {
+        let client = "10.0.0.1";
+        let kdc = "10.0.0.2:88";
+        let key = EncryptionKey::new_rc4_hmac("alice").unwrap();
+        let mut packets = vec![];
+        let mut port = 50000;
+        let mut exchange = |packets: &mut Vec<KerberosPacket>, request: Vec<u8>, reply: Vec<u8>| {
+            port += 1;
+            packets.push(packet(&format!("{}:{}", client, port), kdc, request));
+            packets.push(packet(kdc, &format!("{}:{}", client, port), reply));
+        };
+
+        for user in &["bob", "carol", "dave"] {
+            exchange(&mut packets, to_der(&AsReq::new("LAB.LOCAL", user, &[]).unwrap().with_preauth(&key).unwrap()).unwrap(), error(KrbErrorCode::KdcErrPreauthFailed));
+        }
+        for _ in 0..5 {
+            exchange(&mut packets, to_der(&AsReq::new("LAB.LOCAL", "erin", &[]).unwrap().with_preauth(&key).unwrap()).unwrap(), error(KrbErrorCode::KdcErrPreauthFailed));
+        }
+
+        let ticket = Ticket::default();
+        for spn in &["MSSQLSvc/sql.lab.local", "HTTP/web.lab.local", "cifs/fs.lab.local"] {
+            let sname = PrincipalName::new(
+                PrincipalNameType::NtSrvInst,
+                spn.split('/').map(|e| GeneralString::from_str(e).unwrap()).collect()
+            );
+            let request = TgsReq::new("LAB.LOCAL", "alice", sname, &[]).unwrap()
+                .with_authenticator(
+                    ticket.clone(),
+                    &key,
+                    Authenticator::new(GeneralString::from_str("LAB.LOCAL").unwrap(), PrincipalName::default())
+                ).unwrap();
+            exchange(&mut packets, to_der(&request).unwrap(), error(KrbErrorCode::KdcErrSPrincipalUnknown));
+        }
+
+        let findings = Engine::new().analyze(&packets);
+        let rules: Vec<Rule> = findings.iter().map(|finding| finding.rule).collect();
+        assert_eq!(rules, vec![Rule::PasswordSpraying, Rule::Rc4Downgrade, Rule::BruteForce, Rule::Kerberoasting]);
+
+        assert_eq!(findings[2].messages, (6..16).collect::<Vec<usize>>());
+        assert_eq!(findings[3].messages, vec![16, 18, 20]);
+        assert_eq!(
+            findings[3].to_json().to_string(),
+            "{\"rule\":\"kerberoasting\",\"description\":\"3 SPNs requested with RC4: HTTP/web.lab.local, MSSQLSvc/sql.lab.local, cifs/fs.lab.local\",\"source\":\"10.0.0.1\",\"messages\":[16,18,20]}"
+        );
+    }
+    /// Revoked accounts raise brute force but not spraying
+    #[test]
+    fn test_revoked_accounts() {
+        let kdc = "10.0.0.2:88";
+        let key = EncryptionKey::new_rc4_hmac("alice").unwrap();
+        let mut packets = vec![];
+        for (port, user) in ["bob", "carol", "dave", "erin", "erin", "erin", "erin", "erin"].iter().enumerate() {
+            let client = format!("10.0.0.1:{}", 50000 + port);
+            packets.push(packet(&client, kdc, to_der(&AsReq::new("LAB.LOCAL", user, &[]).unwrap().with_preauth(&key).unwrap()).unwrap()));
+            packets.push(packet(kdc, &client, error(KrbErrorCode::KdcErrClientRevoked)));
+        }
+
+        let rules: Vec<Rule> = Engine::new().analyze(&packets).iter().map(|finding| finding.rule).collect();
+        assert_eq!(rules, vec![Rule::Rc4Downgrade, Rule::BruteForce]);
+    }
+}
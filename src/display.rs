@@ -1,32 +1,130 @@
 use krbkdcrep::{KdcRep, EncKDCRepPart};
 use asn1::{Tag, Application, SequenceOf, Integer, OctetString, GeneralString, GeneralizedTime, from_der};
 use padata::PaData;
-use base::{PrincipalName, KDCOptions, HostAddress, LastReqBody, AuthorizationDataElement, AuthorizationData};
+use base::{PrincipalName, HostAddress, LastReqBody, AuthorizationDataElement, AuthorizationData};
 use ticket::{TicketBody, EncTicketPartBody, TransitedEncoding};
 use encryption::{EncryptedData, EncryptionKey};
 use krberror::KrbErrorBody;
 use krbkdcreq::{KdcReq, KdcReqBody};
 use krbcred::{KrbCredBody, EncKrbCredPartBody, KrbCredInfo};
-use pac::{PacType, PacStruct, PacClientInfo, PacSignatureData, UpnDnsInfo, KerbValidationInfo};
+use pac::{PacType, PacStruct, PacClientInfo, PacSignatureData, UpnDnsInfo, KerbValidationInfo, PacCredentialInfo};
 use ndr::{FileTime, RpcUnicodeString};
+use krbap::{ApRepBody, EncAPRepPartBody};
+use authenticator::AuthenticatorBody;
+use padata::PaEncTsEnc;
+use pcap::KrbMessage;
+use flags::{Flag, FlagSet};
+use std::{fmt, io};
 
-pub struct Formatter {
+/// ANSI escape sequences
+const COLOR_NAME: &str = "\x1b[32m";
+const COLOR_FLAG: &str = "\x1b[33m";
+const COLOR_RESET: &str = "\x1b[0m";
+
+/// Amount of details written by the formatter
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Verbosity {
+    /// Binary blobs (ciphers, keys, signatures) are replaced by their size
+    Brief,
+    /// Everything
+    Full
+}
+
+/// Adapter from io::Write to fmt::Write
+struct IoWriter<W: io::Write>(W);
+
+impl<W: io::Write> fmt::Write for IoWriter<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.write_all(s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+/// Indented text output of Kerberos structures
+///
+/// # Example
+/// ```rust, ignore
+/// let mut output = String::new();
+/// ticket.format(&mut Formatter::from_fmt(&mut output).with_verbosity(Verbosity::Brief));
+/// ```
+pub struct Formatter<'a> {
+    sink: Box<dyn fmt::Write + 'a>,
     indent: u32,
     is_indent: bool,
+    indentation: String,
+    color: bool,
+    verbosity: Verbosity,
+    /// First error of the sink
+    result: fmt::Result
+}
+
+impl Default for Formatter<'static> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-impl Formatter {
+impl Formatter<'static> {
+    /// Write on the standard output
     pub fn new() -> Self {
+        Formatter::from_writer(io::stdout())
+    }
+}
+
+impl<'a> Formatter<'a> {
+    /// Write into a String, a fmt::Formatter or any fmt::Write
+    pub fn from_fmt<W: fmt::Write + 'a>(sink: W) -> Self {
         Self {
+            sink: Box::new(sink),
             indent: 0,
             is_indent: true,
+            indentation: "\t".to_string(),
+            color: false,
+            verbosity: Verbosity::Full,
+            result: Ok(())
         }
     }
 
+    /// Write into a file, a socket or any io::Write
+    pub fn from_writer<W: io::Write + 'a>(writer: W) -> Self {
+        Formatter::from_fmt(IoWriter(writer))
+    }
+
+    /// String written for each indentation level, a tab by default
+    pub fn with_indentation(mut self, indentation: &str) -> Self {
+        self.indentation = indentation.to_string();
+        self
+    }
+
+    /// Highlight names and flags with ANSI colors
+    pub fn with_color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn with_verbosity(mut self, verbosity: Verbosity) -> Self {
+        self.verbosity = verbosity;
+        self
+    }
+
+    pub fn verbosity(&self) -> Verbosity {
+        self.verbosity
+    }
+
+    /// Error of the sink if any
+    pub fn result(&self) -> fmt::Result {
+        self.result
+    }
+
+    fn write(&mut self, object: &str) {
+        if self.result.is_ok() {
+            self.result = self.sink.write_str(object);
+        }
+    }
 
-    fn print_indent(&self) {
+    fn print_indent(&mut self) {
         for _ in 0..self.indent {
-            print!("\t");
+            let indentation = self.indentation.clone();
+            self.write(&indentation);
         }
     }
 
@@ -34,7 +132,8 @@ impl Formatter {
         if self.is_indent {
             self.print_indent();
         }
-        println!("{}", object);
+        self.write(object);
+        self.write("\n");
         self.is_indent = true;
     }
 
@@ -42,12 +141,39 @@ impl Formatter {
         if self.is_indent {
             self.print_indent();
         }
-        print!("{}", object);
+        self.write(object);
         self.is_indent = false;
     }
 
+    /// Name of an enumerated value, after the value
+    pub fn name(&mut self, name: &str) {
+        if self.color {
+            self.print(&format!(" ({}{}{})", COLOR_NAME, name, COLOR_RESET));
+        } else {
+            self.print(&format!(" ({})", name));
+        }
+    }
+
+    /// Names of the flags set
+    pub fn flags(&mut self, names: &[String]) {
+        let flags = names.join(", ");
+        if self.color {
+            self.print(&format!("{}{}{}", COLOR_FLAG, flags, COLOR_RESET));
+        } else {
+            self.print(&flags);
+        }
+    }
+
+    /// Binary data, base64 encoded or replaced by its size
+    pub fn binary(&mut self, data: &[u8]) {
+        match self.verbosity {
+            Verbosity::Brief => self.print(&format!("<{} bytes>", data.len())),
+            Verbosity::Full => self.print(&base64::encode(data))
+        }
+    }
+
     pub fn new_line(&mut self) {
-        print!("\n");
+        self.write("\n");
         self.is_indent = true;
     }
 
@@ -63,6 +189,54 @@ impl Formatter {
 
 pub trait Display {
     fn format(&self, f: &mut Formatter);
+
+    /// Bridge to fmt::Display, to use the structure with format!
+    fn display(&self) -> Show<'_, Self> where Self: Sized {
+        Show(self)
+    }
+}
+
+/// fmt::Display of a Kerberos structure
+pub struct Show<'a, T: Display>(pub &'a T);
+
+impl<'a, T: Display> fmt::Display for Show<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut formatter = Formatter::from_fmt(f);
+        self.0.format(&mut formatter);
+        formatter.result()
+    }
+}
+
+/// fmt::Display of the structures of kerlab
+macro_rules! display_bridge {
+    ($($t:ty),*) => {
+        $(impl fmt::Display for $t {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                self.display().fmt(f)
+            }
+        })*
+    }
+}
+
+display_bridge!(
+    KdcReq, KdcReqBody, KdcRep, EncKDCRepPart, KrbErrorBody, PaData, PaEncTsEnc,
+    PrincipalName, HostAddress, LastReqBody, AuthorizationDataElement,
+    TicketBody, EncTicketPartBody, TransitedEncoding, EncryptedData, EncryptionKey, GeneralizedTime,
+    KrbCredBody, EncKrbCredPartBody, KrbCredInfo, ApRepBody, EncAPRepPartBody, AuthenticatorBody,
+    PacType, PacStruct, PacClientInfo, PacSignatureData, PacCredentialInfo, UpnDnsInfo, KerbValidationInfo,
+    FileTime, RpcUnicodeString, KrbMessage
+);
+
+impl<const N: u64, T: PartialEq + Display + Default + Clone> fmt::Display for Application<{ N }, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.display().fmt(f)
+    }
+}
+
+impl<T: Flag> fmt::Display for FlagSet<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.display().fmt(f)
+    }
 }
 
 impl<const N: u64, T: PartialEq + Display + Clone> Display for Tag<{ N }, T> {
@@ -163,18 +337,18 @@ impl Display for PrincipalName {
         f.new_line();
         f.print("name_type    ");
         self.name_type.format(f);
-        f.print(
+        f.name(
             match self.name_type.inner {
-                0 => " (NtUnknown)",
-                1 => " (NtPrincipal)",
-                2 => " (NtSrvInst)",
-                3 => " (NtSrvHst)",
-                4 => " (NtSrvXhst)",
-                5 => " (NtUid)",
-                6 => " (NtX500Principal)",
-                7 => " (NtSmtpName)",
-                10 => " (NtEnterprise)",
-                _ => " (UNKNOWN)"
+                0 => "NtUnknown",
+                1 => "NtPrincipal",
+                2 => "NtSrvInst",
+                3 => "NtSrvHst",
+                4 => "NtSrvXhst",
+                5 => "NtUid",
+                6 => "NtX500Principal",
+                7 => "NtSmtpName",
+                10 => "NtEnterprise",
+                _ => "UNKNOWN"
             }
         );
         f.new_line();
@@ -211,12 +385,12 @@ impl Display for EncryptedData {
         f.new_line();
         f.print("etype  ");
         self.etype.format(f);
-        f.print(
+        f.name(
             match self.etype.inner {
-                17 => " (AES-128-CTS-HMAC-SHA-196)",
-                18 => " (AES-256-CTS-HMAC-SHA-196)",
-                23 => " (RC4-HMAC)",
-                _ => " (UNKNOWN)"
+                17 => "AES-128-CTS-HMAC-SHA-196",
+                18 => "AES-256-CTS-HMAC-SHA-196",
+                23 => "RC4-HMAC",
+                _ => "UNKNOWN"
             }
         );
         f.new_line();
@@ -231,7 +405,7 @@ impl Display for EncryptedData {
 
 impl Display for OctetString {
     fn format(&self, f: &mut Formatter) {
-        f.print(base64::encode(self).as_str());
+        f.binary(self);
     }
 }
 
@@ -260,56 +434,57 @@ impl Display for KrbErrorBody {
         f.new_line();
         f.print("error_code   ");
         self.error_code.format(f);
-        f.print(
+        f.name(
             match self.error_code.inner {
-                0 => " (KDC_ERR_NONE: No error)",
-                1 => " (KDC_ERR_NAME_EXP: Client's entry in database has expired)",
-                2 => " (KDC_ERR_SERVICE_EXP: Server's entry in database has expired)",
-                3 => " (KDC_ERR_BAD_PVNO: Requested protocol version number not supported)",
-                4 => " (KDC_ERR_C_OLD_MAST_KVNO: Client's key encrypted in old master key)",
-                5 => " (KDC_ERR_S_OLD_MAST_KVNO: Server's key encrypted in old master key)",
-                6 => " (KDC_ERR_C_PRINCIPAL_UNKNOWN: Client not found in Kerberos database)",
-                7 => " (KDC_ERR_S_PRINCIPAL_UNKNOWN: Server not found in Kerberos database)",
-                8 => " (KDC_ERR_PRINCIPAL_NOT_UNIQUE: Multiple principal entries in database)",
-                9 => " (KDC_ERR_NULL_KEY: The client or server has a null key)",
-                10 => " (KDC_ERR_CANNOT_POSTDATE: Ticket not eligible for postdating)",
-                11 => " (KDC_ERR_NEVER_VALID: Requested start time is later than end time)",
-                12 => " (KDC_ERR_POLICY: KDC policy rejects request)",
-                13 => " (KDC_ERR_BADOPTION: KDC cannot accommodate requested option)",
-                14 => " (KDC_ERR_ETYPE_NOSUPP: KDC has no support for encryption type)",
-                15 => " (KDC_ERR_SUMTYPE_NOSUPP: KDC has no support for checksum type)",
-                16 => " (KDC_ERR_PADATA_TYPE_NOSUPP: KDC has no support for padata type)",
-                17 => " (KDC_ERR_TRTYPE_NOSUPP: KDC has no support for transited type)",
-                18 => " (KDC_ERR_CLIENT_REVOKED: Clients credentials have been revoked)",
-                19 => " (KDC_ERR_SERVICE_REVOKED: Credentials for server have been revoked)",
-                20 => " (KDC_ERR_TGT_REVOKED: TGT has been revoked)",
-                21 => " (KDC_ERR_CLIENT_NOTYET: Client not yet valid - try again later)",
-                22 => " (KDC_ERR_SERVICE_NOTYET: Server not yet valid - try again later)",
-                23 => " (KDC_ERR_KEY_EXPIRED: Password has expired - change password to reset)",
-                24 => " (KDC_ERR_PREAUTH_FAILED: Pre-authentication information was invalid)",
-                25 => " (KDC_ERR_PREAUTH_REQUIRED: Additional pre-authentication required)",
-                31 => " (KRB_AP_ERR_BAD_INTEGRITY: Integrity check on decrypted field failed)",
-                32 => " (KRB_AP_ERR_TKT_EXPIRED: Ticket expired)",
-                33 => " (KRB_AP_ERR_TKT_NYV: Ticket not yet valid)",
-                34 => " (KRB_AP_ERR_REPEAT: Request is a replay)",
-                35 => " (KRB_AP_ERR_NOT_US: The ticket isn't for us)",
-                36 => " (KRB_AP_ERR_BADMATCH: Ticket and authenticator don't match)",
-                37 => " (KRB_AP_ERR_SKEW: Clock skew too great)",
-                38 => " (KRB_AP_ERR_BADADDR: Incorrect net address)",
-                39 => " (KRB_AP_ERR_BADVERSION: Protocol version mismatch)",
-                40 => " (KRB_AP_ERR_MSG_TYPE: Invalid msg type)",
-                41 => " (KRB_AP_ERR_MODIFIED: Message stream modified)",
-                42 => " (KRB_AP_ERR_BADORDER: Message out of order)",
-                44 => " (KRB_AP_ERR_BADKEYVER: Specified version of key is not available)",
-                45 => " (KRB_AP_ERR_NOKEY: Service key not available)",
-                46 => " (KRB_AP_ERR_MUT_FAIL: Mutual authentication failed)",
-                47 => " (KRB_AP_ERR_BADDIRECTION: Incorrect message direction)",
-                48 => " (KRB_AP_ERR_METHOD: Alternative authentication method required)",
-                49 => " (KRB_AP_ERR_BADSEQ:  Incorrect sequence number in message)",
-                50 => " (KRB_AP_ERR_INAPP_CKSUM: Inappropriate type of checksum in message)",
-                60 => " (KRB_ERR_GENERIC: Generic error (description in e-text))",
-                61 => " (KRB_ERR_FIELD_TOOLONG: Field is too long for this implementation)",
-                _ => " (Unknown)"
+                0 => "KDC_ERR_NONE: No error",
+                1 => "KDC_ERR_NAME_EXP: Client's entry in database has expired",
+                2 => "KDC_ERR_SERVICE_EXP: Server's entry in database has expired",
+                3 => "KDC_ERR_BAD_PVNO: Requested protocol version number not supported",
+                4 => "KDC_ERR_C_OLD_MAST_KVNO: Client's key encrypted in old master key",
+                5 => "KDC_ERR_S_OLD_MAST_KVNO: Server's key encrypted in old master key",
+                6 => "KDC_ERR_C_PRINCIPAL_UNKNOWN: Client not found in Kerberos database",
+                7 => "KDC_ERR_S_PRINCIPAL_UNKNOWN: Server not found in Kerberos database",
+                8 => "KDC_ERR_PRINCIPAL_NOT_UNIQUE: Multiple principal entries in database",
+                9 => "KDC_ERR_NULL_KEY: The client or server has a null key",
+                10 => "KDC_ERR_CANNOT_POSTDATE: Ticket not eligible for postdating",
+                11 => "KDC_ERR_NEVER_VALID: Requested start time is later than end time",
+                12 => "KDC_ERR_POLICY: KDC policy rejects request",
+                13 => "KDC_ERR_BADOPTION: KDC cannot accommodate requested option",
+                14 => "KDC_ERR_ETYPE_NOSUPP: KDC has no support for encryption type",
+                15 => "KDC_ERR_SUMTYPE_NOSUPP: KDC has no support for checksum type",
+                16 => "KDC_ERR_PADATA_TYPE_NOSUPP: KDC has no support for padata type",
+                17 => "KDC_ERR_TRTYPE_NOSUPP: KDC has no support for transited type",
+                18 => "KDC_ERR_CLIENT_REVOKED: Clients credentials have been revoked",
+                19 => "KDC_ERR_SERVICE_REVOKED: Credentials for server have been revoked",
+                20 => "KDC_ERR_TGT_REVOKED: TGT has been revoked",
+                21 => "KDC_ERR_CLIENT_NOTYET: Client not yet valid - try again later",
+                22 => "KDC_ERR_SERVICE_NOTYET: Server not yet valid - try again later",
+                23 => "KDC_ERR_KEY_EXPIRED: Password has expired - change password to reset",
+                24 => "KDC_ERR_PREAUTH_FAILED: Pre-authentication information was invalid",
+                25 => "KDC_ERR_PREAUTH_REQUIRED: Additional pre-authentication required",
+                31 => "KRB_AP_ERR_BAD_INTEGRITY: Integrity check on decrypted field failed",
+                32 => "KRB_AP_ERR_TKT_EXPIRED: Ticket expired",
+                33 => "KRB_AP_ERR_TKT_NYV: Ticket not yet valid",
+                34 => "KRB_AP_ERR_REPEAT: Request is a replay",
+                35 => "KRB_AP_ERR_NOT_US: The ticket isn't for us",
+                36 => "KRB_AP_ERR_BADMATCH: Ticket and authenticator don't match",
+                37 => "KRB_AP_ERR_SKEW: Clock skew too great",
+                38 => "KRB_AP_ERR_BADADDR: Incorrect net address",
+                39 => "KRB_AP_ERR_BADVERSION: Protocol version mismatch",
+                40 => "KRB_AP_ERR_MSG_TYPE: Invalid msg type",
+                41 => "KRB_AP_ERR_MODIFIED: Message stream modified",
+                42 => "KRB_AP_ERR_BADORDER: Message out of order",
+                44 => "KRB_AP_ERR_BADKEYVER: Specified version of key is not available",
+                45 => "KRB_AP_ERR_NOKEY: Service key not available",
+                46 => "KRB_AP_ERR_MUT_FAIL: Mutual authentication failed",
+                47 => "KRB_AP_ERR_BADDIRECTION: Incorrect message direction",
+                48 => "KRB_AP_ERR_METHOD: Alternative authentication method required",
+                49 => "KRB_AP_ERR_BADSEQ:  Incorrect sequence number in message",
+                50 => "KRB_AP_ERR_INAPP_CKSUM: Inappropriate type of checksum in message",
+                52 => "KRB_ERR_RESPONSE_TOO_BIG: Response too big for UDP, retry with TCP",
+                60 => "KRB_ERR_GENERIC: Generic error (description in e-text)",
+                61 => "KRB_ERR_FIELD_TOOLONG: Field is too long for this implementation",
+                _ => "Unknown"
             }
         );
         f.new_line();
@@ -401,8 +576,10 @@ impl Display for KdcReqBody {
     }
 }
 
-impl Display for KDCOptions {
-    fn format(&self, _: &mut Formatter) {}
+impl<T: Flag> Display for FlagSet<T> {
+    fn format(&self, f: &mut Formatter) {
+        f.flags(&self.names());
+    }
 }
 
 impl Display for HostAddress {
@@ -436,6 +613,9 @@ impl Display for EncKDCRepPart {
         f.print("key_expiration");
         self.key_expiration.format(f);
         f.new_line();
+        f.print("flags        ");
+        self.flags.format(f);
+        f.new_line();
         f.print("authtime     ");
         self.authtime.format(f);
         f.new_line();
@@ -456,6 +636,9 @@ impl Display for EncKDCRepPart {
         f.new_line();
         f.print("caddr        ");
         self.caddr.format(f);
+        f.new_line();
+        f.print("encrypted_pa_data ");
+        self.encrypted_pa_data.format(f);
         f.dedent();
     }
 }
@@ -550,22 +733,22 @@ impl Display for AuthorizationDataElement {
         f.new_line();
         f.print("ad_type  ");
         self.ad_type.format(f);
-        f.print(
+        f.name(
             match self.ad_type.inner {
-                1 => " (AD-IF-RELEVANT)",
-                2 => " (AD-INTENDED-FOR-SERVER)",
-                3 => " (AD-INTENDED-FOR-APPLICATION-CLASS)",
-                4 => " (AD-KDC-ISSUED)",
-                5 => " (AD-AND-OR)",
-                6 => " (AD-MANDATORY-TICKET-EXTENSIONS)",
-                7 => " (AD-IN-TICKET-EXTENSIONS)",
-                8 => " (AD-MANDATORY-FOR-KDC)",
-                64 => " (OSF-DCE)",
-                65 => " (SESAME)",
-                66 => " (AD-OSF-DCE-PKI-CERTID)",
-                128 => " (AD-WIN2K-PAC)",
-                129 => " (AD-ETYPE-NEGOTIATION)",
-                _ => " (UNKNOWN)"
+                1 => "AD-IF-RELEVANT",
+                2 => "AD-INTENDED-FOR-SERVER",
+                3 => "AD-INTENDED-FOR-APPLICATION-CLASS",
+                4 => "AD-KDC-ISSUED",
+                5 => "AD-AND-OR",
+                6 => "AD-MANDATORY-TICKET-EXTENSIONS",
+                7 => "AD-IN-TICKET-EXTENSIONS",
+                8 => "AD-MANDATORY-FOR-KDC",
+                64 => "OSF-DCE",
+                65 => "SESAME",
+                66 => "AD-OSF-DCE-PKI-CERTID",
+                128 => "AD-WIN2K-PAC",
+                129 => "AD-ETYPE-NEGOTIATION",
+                _ => "UNKNOWN"
             }
         );
         f.new_line();
@@ -713,6 +896,13 @@ impl Display for PacStruct {
             },
             PacStruct::KerbValidationInfo(e) => {
                 e.format(f)
+            },
+            PacStruct::PacCredentialInfo(e) => {
+                e.format(f)
+            },
+            PacStruct::Unknown(ul_type, e) => {
+                f.print(&format!("PAC_INFO_BUFFER {:#X}    ", ul_type));
+                e.format(f)
             }
         }
     }
@@ -738,7 +928,23 @@ impl Display for PacSignatureData {
         f.new_line();
         f.print(&format!("signature_type  {}", self.signature_type));
         f.new_line();
-        f.print(&format!("signature       {}", base64::encode(&self.signature).as_str()));
+        f.print("signature       ");
+        f.binary(&self.signature);
+        f.dedent()
+    }
+}
+
+impl Display for PacCredentialInfo {
+    fn format(&self, f: &mut Formatter) {
+        f.print("PAC_CREDENTIAL_INFO");
+        f.indent();
+        f.new_line();
+        f.print(&format!("version          {}", self.version));
+        f.new_line();
+        f.print(&format!("encryption_type  {}", self.encryption_type));
+        f.new_line();
+        f.print("serialized_data  ");
+        f.binary(&self.serialized_data);
         f.dedent()
     }
 }
@@ -778,17 +984,47 @@ impl Display for KerbValidationInfo {
         f.print("PasswordMustChange ");
         self.password_must_change.format(f);
         f.new_line();
-        f.print("EffectiveName ");
-        self.effective_name.format(f);
+        f.print(&format!("EffectiveName      {}", self.effective_name));
+        f.new_line();
+        f.print(&format!("FullName           {}", self.full_name));
+        f.new_line();
+        f.print(&format!("LogonCount         {}", self.logon_count));
+        f.new_line();
+        f.print(&format!("BadPasswordCount   {}", self.bad_password_count));
+        f.new_line();
+        f.print(&format!("UserId             {}", self.user_id));
+        f.new_line();
+        f.print(&format!("PrimaryGroupId     {}", self.primary_group_id));
+        f.new_line();
+        f.print(&format!("GroupIds           {}", self.group_ids.iter()
+            .map(|e| e.relative_id.to_string())
+            .collect::<Vec<String>>()
+            .join(", ")));
+        f.new_line();
+        f.print(&format!("UserFlags          {:#X}", self.user_flags));
         f.new_line();
-        f.print("FullName ");
-        //self.full_name.format(f);
+        f.print(&format!("LogonServer        {}", self.logon_server));
+        f.new_line();
+        f.print(&format!("LogonDomainName    {}", self.logon_domain_name));
+        f.new_line();
+        f.print(&format!("LogonDomainId      {}", self.logon_domain_id.as_ref().map(|e| e.to_string()).unwrap_or_default()));
+        f.new_line();
+        f.print(&format!("UserAccountControl {:#X}", self.user_account_control));
+        f.new_line();
+        f.print(&format!("ExtraSids          {}", self.extra_sids.iter()
+            .map(|e| e.sid.to_string())
+            .collect::<Vec<String>>()
+            .join(", ")));
         f.dedent()
     }
 }
 
 impl Display for FileTime {
     fn format(&self, f: &mut Formatter) {
+        if f.verbosity() == Verbosity::Brief {
+            f.print(&format!("{}", self.datetime()));
+            return
+        }
         f.print("FILETIME");
         f.indent();
         f.new_line();
@@ -805,4 +1041,127 @@ impl Display for RpcUnicodeString {
     fn format(&self, f: &mut Formatter) {
         f.print(&format!("RPC_UNICODE_STRING {}", self.buffer))
     }
-}
\ No newline at end of file
+}
+
+impl Display for ApRepBody {
+    fn format(&self, f: &mut Formatter) {
+        f.print("ApRepBody");
+        f.indent();
+        f.new_line();
+        f.print("pvno         ");
+        self.pvno.format(f);
+        f.new_line();
+        f.print("msg_type     ");
+        self.msg_type.format(f);
+        f.new_line();
+        f.print("enc_part     ");
+        self.enc_part.format(f);
+        f.dedent();
+    }
+}
+
+impl Display for EncAPRepPartBody {
+    fn format(&self, f: &mut Formatter) {
+        f.print("EncAPRepPartBody");
+        f.indent();
+        f.new_line();
+        f.print("ctime        ");
+        self.ctime.format(f);
+        f.new_line();
+        f.print("cusec        ");
+        self.cusec.format(f);
+        f.new_line();
+        f.print("subkey       ");
+        self.subkey.format(f);
+        f.new_line();
+        f.print("seq_number   ");
+        self.seq_number.format(f);
+        f.dedent();
+    }
+}
+
+impl Display for AuthenticatorBody {
+    fn format(&self, f: &mut Formatter) {
+        f.print("AuthenticatorBody");
+        f.indent();
+        f.new_line();
+        f.print("authenticator_vno ");
+        self.authenticator_vno.format(f);
+        f.new_line();
+        f.print("crealm       ");
+        self.crealm.format(f);
+        f.new_line();
+        f.print("cname        ");
+        self.cname.format(f);
+        f.new_line();
+        f.print("cusec        ");
+        self.cusec.format(f);
+        f.new_line();
+        f.print("ctime        ");
+        self.ctime.format(f);
+        f.new_line();
+        f.print("subkey       ");
+        self.subkey.format(f);
+        f.new_line();
+        f.print("seq_number   ");
+        self.seq_number.format(f);
+        f.new_line();
+        f.print("authorization_data ");
+        self.authorization_data.format(f);
+        f.dedent();
+    }
+}
+
+impl Display for PaEncTsEnc {
+    fn format(&self, f: &mut Formatter) {
+        f.print("PaEncTsEnc");
+        f.indent();
+        f.new_line();
+        f.print("patimestamp  ");
+        self.patimestamp.format(f);
+        f.new_line();
+        f.print("pausec       ");
+        self.pausec.format(f);
+        f.dedent();
+    }
+}
+
+impl Display for KrbMessage {
+    fn format(&self, f: &mut Formatter) {
+        match self {
+            KrbMessage::AsReq(message) => message.format(f),
+            KrbMessage::AsRep(message) => message.format(f),
+            KrbMessage::TgsReq(message) => message.format(f),
+            KrbMessage::TgsRep(message) => message.format(f),
+            KrbMessage::KrbError(message) => message.format(f)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use base::KdcOptionsType;
+    use krbkdcreq::AsReq;
+    use encryption::EType;
+
+    /// Output into a String, verbosity, colors and the fmt::Display bridge
+    #[test]
+    fn test_formatter() {
+        let request = AsReq::new("LAB.LOCAL", "alice", &[KdcOptionsType::Forwardable]).unwrap();
+        let encrypted = EncryptedData::new(EType::Rc4Hmac as Integer, vec![0; 32]);
+
+        let mut output = String::new();
+        encrypted.format(&mut Formatter::from_fmt(&mut output).with_indentation("  ").with_verbosity(Verbosity::Brief));
+        assert_eq!(output, "EncryptedData\n  etype  [0] : 23 (RC4-HMAC)\n  kvno   None\n  cipher [2] : <32 bytes>");
+
+        let mut output = String::new();
+        request.req_body.format(&mut Formatter::from_fmt(&mut output).with_color(true));
+        assert!(output.contains("kdc_options  [0] : \x1b[33mforwardable\x1b[0m\n"));
+        assert_eq!(request.req_body.kdc_options.to_string(), "forwardable");
+
+        let text = format!("{}", request);
+        assert!(text.starts_with("[APPLICATION 10] KdcReq\n\tpvno     [1] : 5\n"));
+        assert_eq!(text, request.display().to_string());
+    }
+}
@@ -1,11 +1,14 @@
 use asn1::{ASN1, Tag, Integer, OctetString, to_der, from_ber};
 use error::{KerlabResult, Error, KerlabErrorKind};
 use yasna::{DERWriter, BERReader};
-use rc4hmac::Rc4Hmac;
+use rc4hmac::{Rc4Hmac, self};
+use aescts::{AesCts, self};
+use rnd::random;
 use ntlm::{ntlm};
 
 
 #[repr(u32)]
+#[derive(Copy, Clone)]
 pub enum EType {
     NoEncryption = 0,
     DesCbcCrc = 1,
@@ -16,14 +19,81 @@ pub enum EType {
     Rc4HmacExp = 24
 }
 
+/// Key usage numbers, mixed into the key derivation
+///
+/// @see https://datatracker.ietf.org/doc/html/rfc4120#section-7.5.1
+/// @see https://datatracker.ietf.org/doc/html/rfc6113#section-5.4.6
 #[repr(u32)]
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum KeyUsage {
+    /// AS-REQ PA-ENC-TIMESTAMP, client key
     KeyUsageAsReqTimestamp = 1,
+    /// Ticket enc-part, service key
     KeyUsageAsRepTicket = 2,
+    /// AS-REP enc-part, client key
     KeyUsageAsRepEncPart1 = 3,
+    /// TGS-REQ enc-authorization-data, session key
+    KeyUsageTgsReqAuthDataSessionKey = 4,
+    /// TGS-REQ enc-authorization-data, authenticator subkey
+    KeyUsageTgsReqAuthDataSubkey = 5,
+    /// TGS-REQ PA-TGS-REQ authenticator checksum, session key
+    KrbKeyUsageTgsReqPaAuthenticatorCksum = 6,
+    /// TGS-REQ PA-TGS-REQ authenticator, session key
     KrbKeyUsageTgsReqPaAuthenticator = 7,
-    KeyUsageAsRepEncPart = 8
+    /// TGS-REP enc-part, session key
+    KeyUsageAsRepEncPart = 8,
+    /// TGS-REP enc-part, authenticator subkey
+    KeyUsageTgsRepEncPartSubkey = 9,
+    /// AP-REQ authenticator checksum, session key
+    KeyUsageApReqAuthenticatorCksum = 10,
+    /// AP-REQ authenticator, session key
+    KeyUsageApReqAuthenticator = 11,
+    /// AP-REP enc-part, session key
+    KeyUsageApRepEncPart = 12,
+    /// KRB-PRIV enc-part
+    KeyUsageKrbPrivEncPart = 13,
+    /// KRB-CRED enc-part
+    KeyUsageKrbCredEncPart = 14,
+    /// KRB-SAFE checksum
+    KeyUsageKrbSafeCksum = 15,
+    /// PAC_CREDENTIAL_INFO, AS reply key [MS-PAC]
+    KeyUsagePacCredentials = 16,
+    /// PAC signatures and PA-FOR-USER checksum [MS-PAC] [MS-SFU]
+    KeyUsageKerbNonKerbCksumSalt = 17,
+    /// AD-KDCIssued checksum
+    KeyUsageAdKdcIssuedCksum = 19,
+    /// GSS-API per message tokens
+    /// @see https://datatracker.ietf.org/doc/html/rfc4121#section-2
+    KeyUsageGssAcceptorSeal = 22,
+    KeyUsageGssAcceptorSign = 23,
+    KeyUsageGssInitiatorSeal = 24,
+    KeyUsageGssInitiatorSign = 25,
+    /// PA-S4U-X509-USER checksum [MS-SFU]
+    KeyUsagePaS4uX509UserRequest = 26,
+    KeyUsagePaS4uX509UserReply = 27,
+    /// FAST request checksum, armor key
+    KeyUsageFastReqChksum = 50,
+    /// FAST KrbFastReq, armor key
+    KeyUsageFastEnc = 51,
+    /// FAST KrbFastResponse, armor key
+    KeyUsageFastRep = 52,
+    /// FAST ticket checksum, armor key
+    KeyUsageFastFinished = 53,
+    /// PA-ENCRYPTED-CHALLENGE sent by the client
+    KeyUsageEncChallengeClient = 54,
+    /// PA-ENCRYPTED-CHALLENGE sent by the KDC
+    KeyUsageEncChallengeKdc = 55
+}
+
+/// Checksum type
+#[repr(i32)]
+#[derive(Copy, Clone)]
+pub enum ChecksumType {
+    Crc32 = 1,
+    RsaMd5 = 7,
+    HmacSha196Aes128 = 15,
+    HmacSha196Aes256 = 16,
+    HmacMd5 = -138
 }
 
 
@@ -111,6 +181,28 @@ impl EncryptionKey {
             keyvalue: Tag::new(hash)
         })
     }
+
+    /// Compute the AES key of a user
+    /// salt is generally the realm in upper case followed by the username
+    pub fn new_aes(etype: EType, password: &str, salt: &str) -> KerlabResult<Self> {
+        let size = match etype {
+            EType::Aes128CtsHmacSha196 => 16,
+            EType::Aes256CtsHmacSha196 => 32,
+            _ => return Err(Error::new(KerlabErrorKind::Crypto, "Not an AES encryption type"))
+        };
+        Ok(Self {
+            keytype: Tag::new(etype as Integer),
+            keyvalue: Tag::new(aescts::string_to_key(password, salt, aescts::DEFAULT_ITERATIONS, size)?)
+        })
+    }
+
+    /// Generate a random key, use for sub session key
+    pub fn random(etype: Integer) -> KerlabResult<Self> {
+        Ok(Self {
+            keytype: Tag::new(etype),
+            keyvalue: Tag::new(random(key_size(etype)?))
+        })
+    }
 }
 
 impl EncryptionKey {
@@ -119,11 +211,18 @@ impl EncryptionKey {
             0 => {
                 Ok(EncryptedData::new(
                     self.keytype.inner,
-                    to_der(object)
+                    to_der(object)?
+                ))
+            },
+            17 | 18 => {
+                let cipher = AesCts::new(self.keyvalue.inner.clone(), key_usage).encrypt(&to_der(object)?)?;
+                Ok(EncryptedData::new(
+                    self.keytype.inner,
+                    cipher
                 ))
             },
             23 => {
-                let cipher = Rc4Hmac::new(self.keyvalue.inner.clone(), key_usage).encrypt(&to_der(object));
+                let cipher = Rc4Hmac::new(self.keyvalue.inner.clone(), key_usage).encrypt(&to_der(object)?);
                 Ok(EncryptedData::new(
                     self.keytype.inner,
                     cipher
@@ -134,23 +233,100 @@ impl EncryptionKey {
     }
 
     pub fn decrypt<T: ASN1 + Default>(&self, key_usage: KeyUsage, data: &EncryptedData) -> KerlabResult<T> {
+        let plaintext = self.decrypt_raw(key_usage, data)?;
+        let mut result = T::default();
+        from_ber(&mut result, &plaintext)?;
+        Ok(result)
+    }
+
+    /// Decrypt without decoding the plaintext
+    /// Use for payloads that are not ASN.1 encoded, like the PAC credentials
+    pub fn decrypt_raw(&self, key_usage: KeyUsage, data: &EncryptedData) -> KerlabResult<Vec<u8>> {
         if self.keytype.inner != data.etype.inner {
             return Err(Error::new(KerlabErrorKind::Crypto, "Bad Key"))
         }
 
         match self.keytype.inner {
-            0 => {
-                let mut result = T::default();
-                from_ber(&mut result, &data.cipher.inner)?;
-                Ok(result)
-            },
-            23 => {
-                let plaintext = Rc4Hmac::new(self.keyvalue.inner.clone(), key_usage).decrypt(&data.cipher.inner)?;
-                let mut result = T::default();
-                from_ber(&mut result, &plaintext)?;
-                Ok(result)
-            },
+            0 => Ok(data.cipher.inner.clone()),
+            17 | 18 => AesCts::new(self.keyvalue.inner.clone(), key_usage).decrypt(&data.cipher.inner),
+            23 => Rc4Hmac::new(self.keyvalue.inner.clone(), key_usage).decrypt(&data.cipher.inner),
             _ => Err(Error::new(KerlabErrorKind::Crypto, "Unsupported Algorithm"))
         }
     }
-}
\ No newline at end of file
+
+    /// Pseudo random function of the encryption type
+    pub fn prf(&self, data: &[u8]) -> KerlabResult<Vec<u8>> {
+        match self.keytype.inner {
+            17 | 18 => aescts::prf(&self.keyvalue, data),
+            23 => Ok(rc4hmac::prf(&self.keyvalue, data)),
+            _ => Err(Error::new(KerlabErrorKind::Crypto, "Unsupported Algorithm"))
+        }
+    }
+
+    /// PRF+ defined by RFC 6113
+    /// concatenate PRF output with a counter to reach the expected size
+    fn prf_plus(&self, pepper: &[u8], size: usize) -> KerlabResult<Vec<u8>> {
+        let mut result = vec![];
+        let mut counter: u8 = 1;
+        while result.len() < size {
+            let mut input = vec![counter];
+            input.extend_from_slice(pepper);
+            result.append(&mut self.prf(&input)?);
+            counter += 1;
+        }
+        result.truncate(size);
+        Ok(result)
+    }
+}
+
+/// Size in bytes of the key for an encryption type
+pub fn key_size(etype: Integer) -> KerlabResult<usize> {
+    match etype {
+        17 | 23 => Ok(16),
+        18 => Ok(32),
+        _ => Err(Error::new(KerlabErrorKind::Crypto, "Unsupported Algorithm"))
+    }
+}
+
+/// Combine two keys into a new one
+///
+/// @see https://tools.ietf.org/html/rfc6113#section-5.1
+pub fn krb_fx_cf2(key1: &EncryptionKey, key2: &EncryptionKey, pepper1: &str, pepper2: &str) -> KerlabResult<EncryptionKey> {
+    let size = key1.keyvalue.len();
+    let mut result = key1.prf_plus(pepper1.as_bytes(), size)?;
+    let other = key2.prf_plus(pepper2.as_bytes(), size)?;
+    for (x, y) in result.iter_mut().zip(other.iter()) {
+        *x ^= *y;
+    }
+
+    Ok(EncryptionKey {
+        keytype: Tag::new(key1.keytype.inner),
+        keyvalue: Tag::new(result)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// k1 and k2 are computed from "key1" and "key2", with the same string as salt
+    /// @see https://tools.ietf.org/html/rfc6113#appendix-A
+    #[test]
+    fn test_krb_fx_cf2() {
+        let aes = |etype, password| EncryptionKey::new_aes(etype, password, password).unwrap();
+        let cf2 = |key1: EncryptionKey, key2: EncryptionKey| hex::encode(&krb_fx_cf2(&key1, &key2, "a", "b").unwrap().keyvalue.inner);
+
+        assert_eq!(
+            cf2(aes(EType::Aes128CtsHmacSha196, "key1"), aes(EType::Aes128CtsHmacSha196, "key2")),
+            "97df97e4b798b29eb31ed7280287a92a"
+        );
+        assert_eq!(
+            cf2(aes(EType::Aes256CtsHmacSha196, "key1"), aes(EType::Aes256CtsHmacSha196, "key2")),
+            "4d6ca4e629785c1f01baf55e2e548566b9617ae3a96868c337cb93b5e72b1c7b"
+        );
+        assert_eq!(
+            cf2(EncryptionKey::new_rc4_hmac("key1").unwrap(), EncryptionKey::new_rc4_hmac("key2").unwrap()),
+            "24d7f6b6bae4e5c00d2082c5ebab3672"
+        );
+    }
+}
@@ -1,7 +1,9 @@
-use yasna::{ASN1Error, ASN1ErrorKind};
+use yasna::{ASN1Error, ASN1ErrorKind, Tag as YasnaTag, TagClass};
 use std;
+use std::fmt;
 use ascii::AsAsciiStrError;
 use chrono::ParseError;
+use krberror::KrbErrorCode;
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum KerlabErrorKind {
@@ -53,15 +55,182 @@ pub enum Error {
     KerlabError(KerlabError),
     /// ASN1 parser error
     ASN1Error(ASN1Error),
+    /// ASN1 decoding error with its context
+    Decoding(Box<DecodingError>),
     Io(std::io::Error),
     AsAsciiStrError(AsAsciiStrError),
-    ChronoParseError(ParseError)
+    ChronoParseError(ParseError),
+    /// Protocol error that can be sent back into a KRB-ERROR
+    Protocol(KrbErrorCode, String)
 }
 
 impl Error {
     pub fn new(kind: KerlabErrorKind, message: &str) -> Self {
         Error::KerlabError(KerlabError::new(kind, message))
     }
+
+    /// Protocol error with the associated Kerberos error code
+    pub fn protocol(code: KrbErrorCode, message: &str) -> Self {
+        Error::Protocol(code, String::from(message))
+    }
+
+    /// Decoding error when the next tag is not the expected one
+    pub fn unexpected(expected: Option<YasnaTag>, found: Option<YasnaTag>) -> Self {
+        let mut error = DecodingError::new(if found.is_some() { ASN1ErrorKind::Invalid } else { ASN1ErrorKind::Eof });
+        error.expected = expected;
+        error.found = found;
+        Error::Decoding(Box::new(error))
+    }
+
+    /// Add the field that contains the error to the path
+    /// address is the position of the field into memory
+    pub fn within_field(self, field: &str, address: Option<usize>) -> Self {
+        match self.into_decoding() {
+            Ok(mut error) => {
+                error.path.insert(0, field.to_string());
+                error.structure = None;
+                if error.address.is_none() && error.offset.is_none() {
+                    error.address = address;
+                }
+                Error::Decoding(error)
+            },
+            Err(e) => e
+        }
+    }
+
+    /// Set the structure that contains the error
+    /// Only the outer one is kept
+    pub fn within_structure(self, structure: &str) -> Self {
+        match self.into_decoding() {
+            Ok(mut error) => {
+                error.structure = Some(structure.to_string());
+                Error::Decoding(error)
+            },
+            Err(e) => e
+        }
+    }
+
+    /// Compute the offset of a decoding error from the start of the buffer
+    pub fn rebase(self, buffer: &[u8]) -> Self {
+        match self {
+            Error::Decoding(mut e) => {
+                if let Some(address) = e.address.take() {
+                    e.offset = address.checked_sub(buffer.as_ptr() as usize);
+                }
+                Error::Decoding(e)
+            },
+            Error::ASN1Error(e) => Error::Decoding(Box::new(DecodingError::new(e.kind()))),
+            e => e
+        }
+    }
+
+    /// Other errors are raised by the types themselves (ascii, time...)
+    /// and are kept as they are
+    fn into_decoding(self) -> Result<Box<DecodingError>, Error> {
+        match self {
+            Error::Decoding(e) => Ok(e),
+            Error::ASN1Error(e) => Ok(Box::new(DecodingError::new(e.kind()))),
+            e => Err(e)
+        }
+    }
+}
+
+/// ASN1 decoding error
+///
+/// Carry the path of the field, the offset of the field into the buffer,
+/// and the expected tag when the error is a tag mismatch
+pub struct DecodingError {
+    pub kind: ASN1ErrorKind,
+    /// Outer structure that fails
+    pub structure: Option<String>,
+    /// Names of fields from the outer type, indexes for SEQUENCE OF
+    pub path: Vec<String>,
+    /// yasna doesn't expose the position of its reader,
+    /// field are located into memory then rebased on the buffer
+    address: Option<usize>,
+    pub offset: Option<usize>,
+    pub expected: Option<YasnaTag>,
+    pub found: Option<YasnaTag>
+}
+
+impl DecodingError {
+    pub fn new(kind: ASN1ErrorKind) -> Self {
+        DecodingError {
+            kind,
+            structure: None,
+            path: vec![],
+            address: None,
+            offset: None,
+            expected: None,
+            found: None
+        }
+    }
+
+    /// Path of the field
+    /// # Example
+    /// ```rust, ignore
+    /// assert_eq!(error.path(), "KdcRep.padata[0].padata_value");
+    /// ```
+    pub fn path(&self) -> String {
+        let mut result = self.structure.clone().unwrap_or_default();
+        for field in &self.path {
+            if !result.is_empty() && !field.starts_with('[') {
+                result.push('.');
+            }
+            result.push_str(field);
+        }
+        result
+    }
+}
+
+/// Name of a tag as written into ASN1 modules
+fn tag_name(tag: &YasnaTag) -> String {
+    match tag.tag_class {
+        TagClass::Universal => match tag.tag_number {
+            1 => "BOOLEAN".to_string(),
+            2 => "INTEGER".to_string(),
+            3 => "BIT STRING".to_string(),
+            4 => "OCTET STRING".to_string(),
+            6 => "OBJECT IDENTIFIER".to_string(),
+            10 => "ENUMERATED".to_string(),
+            16 => "SEQUENCE".to_string(),
+            17 => "SET".to_string(),
+            24 => "GeneralizedTime".to_string(),
+            27 => "GeneralString".to_string(),
+            n => format!("[UNIVERSAL {}]", n)
+        },
+        TagClass::Application => format!("[APPLICATION {}]", tag.tag_number),
+        TagClass::ContextSpecific => format!("[{}]", tag.tag_number),
+        TagClass::Private => format!("[PRIVATE {}]", tag.tag_number)
+    }
+}
+
+/// ```text
+/// KdcRep.enc_part.cipher: expected OCTET STRING, found INTEGER at offset 42
+/// ```
+impl fmt::Display for DecodingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let path = self.path();
+        if !path.is_empty() {
+            write!(f, "{}: ", path)?;
+        }
+        match (&self.expected, &self.found) {
+            (Some(expected), Some(found)) => write!(f, "expected {}, found {}", tag_name(expected), tag_name(found))?,
+            (Some(expected), None) => write!(f, "expected {}, found end of data", tag_name(expected))?,
+            (None, Some(found)) => write!(f, "unexpected {}", tag_name(found))?,
+            (None, None) => write!(f, "{:?}", self.kind)?
+        }
+        if let Some(offset) = self.offset {
+            write!(f, " at offset {}", offset)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for DecodingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
 }
 
 impl From<ASN1Error> for Error {
@@ -91,7 +260,8 @@ impl From<Error> for ASN1Error {
     fn from(e: Error) -> Self {
         match e {
             Error::ASN1Error(e) => e,
-            _ => ASN1Error::new(ASN1ErrorKind::Extra)
+            Error::Decoding(e) => ASN1Error::new(e.kind),
+            _ => ASN1Error::new(ASN1ErrorKind::Invalid)
         }
     }
 }
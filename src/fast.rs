@@ -0,0 +1,348 @@
+//! Flexible Authentication Secure Tunneling
+//! @see https://tools.ietf.org/html/rfc6113
+
+use asn1::{ASN1, Tag, Integer, OctetString, SequenceOf, BitString, to_der, from_der};
+use yasna::{DERWriter, BERReader};
+use error::{KerlabResult, Error, KerlabErrorKind};
+use base::{PrincipalName, Realm, KerberosTime};
+use checksum::Checksum;
+use encryption::{EncryptedData, EncryptionKey, KeyUsage, krb_fx_cf2};
+use padata::{PaData, PaDataType};
+use krbkdcreq::{KdcReq, KdcReqBody};
+use krbkdcrep::KdcRep;
+use krberror::KrbError;
+use krbap::ApReq;
+use authenticator::Authenticator;
+use ticket::Ticket;
+
+pub type FastOptions = BitString;
+
+/// Armor types
+#[repr(u32)]
+pub enum ArmorType {
+    FxFastArmorApRequest = 1
+}
+
+/// ```asn.1
+/// KrbFastArmor ::= SEQUENCE {
+///     armor-type   [0] Int32,
+///     armor-value  [1] OCTET STRING,
+///     ...
+/// }
+/// ```
+#[derive(Sequence, Default, Clone, PartialEq)]
+pub struct KrbFastArmor {
+    pub armor_type: Tag<0, Integer>,
+    pub armor_value: Tag<1, OctetString>
+}
+
+/// ```asn.1
+/// KrbFastArmoredReq ::= SEQUENCE {
+///     armor        [0] KrbFastArmor OPTIONAL,
+///     req-checksum [1] Checksum,
+///     enc-fast-req [2] EncryptedData -- KrbFastReq --
+/// }
+/// ```
+#[derive(Sequence, Default, Clone, PartialEq)]
+pub struct KrbFastArmoredReq {
+    pub armor: Option<Tag<0, KrbFastArmor>>,
+    pub req_checksum: Tag<1, Checksum>,
+    pub enc_fast_req: Tag<2, EncryptedData>
+}
+
+/// ```asn.1
+/// PA-FX-FAST-REQUEST ::= CHOICE {
+///     armored-data [0] KrbFastArmoredReq,
+///     ...
+/// }
+/// ```
+#[derive(Choice, Clone, PartialEq)]
+pub enum PaFxFastRequest {
+    ArmoredData(Tag<0, KrbFastArmoredReq>)
+}
+
+/// ```asn.1
+/// KrbFastReq ::= SEQUENCE {
+///     fast-options [0] FastOptions,
+///     padata       [1] SEQUENCE OF PA-DATA,
+///     req-body     [2] KDC-REQ-BODY,
+///     ...
+/// }
+/// ```
+#[derive(Sequence, Default, Clone, PartialEq)]
+pub struct KrbFastReq {
+    pub fast_options: Tag<0, FastOptions>,
+    pub padata: Tag<1, SequenceOf<PaData>>,
+    pub req_body: Tag<2, KdcReqBody>
+}
+
+/// ```asn.1
+/// KrbFastArmoredRep ::= SEQUENCE {
+///     enc-fast-rep      [0] EncryptedData, -- KrbFastResponse --
+///     ...
+/// }
+/// ```
+#[derive(Sequence, Default, Clone, PartialEq)]
+pub struct KrbFastArmoredRep {
+    pub enc_fast_rep: Tag<0, EncryptedData>
+}
+
+/// ```asn.1
+/// PA-FX-FAST-REPLY ::= CHOICE {
+///     armored-data [0] KrbFastArmoredRep,
+///     ...
+/// }
+/// ```
+#[derive(Choice, Clone, PartialEq)]
+pub enum PaFxFastReply {
+    ArmoredData(Tag<0, KrbFastArmoredRep>)
+}
+
+/// ```asn.1
+/// KrbFastFinished ::= SEQUENCE {
+///     timestamp       [0] KerberosTime,
+///     usec            [1] Microseconds,
+///     crealm          [2] Realm,
+///     cname           [3] PrincipalName,
+///     ticket-checksum [4] Checksum,
+///     ...
+/// }
+/// ```
+#[derive(Sequence, Default, Clone, PartialEq)]
+pub struct KrbFastFinished {
+    pub timestamp: Tag<0, KerberosTime>,
+    pub usec: Tag<1, Integer>,
+    pub crealm: Tag<2, Realm>,
+    pub cname: Tag<3, PrincipalName>,
+    pub ticket_checksum: Tag<4, Checksum>
+}
+
+/// ```asn.1
+/// KrbFastResponse ::= SEQUENCE {
+///     padata         [0] SEQUENCE OF PA-DATA,
+///     strengthen-key [1] EncryptionKey OPTIONAL,
+///     finished       [2] KrbFastFinished OPTIONAL,
+///     nonce          [3] UInt32,
+///     ...
+/// }
+/// ```
+#[derive(Sequence, Default, Clone, PartialEq)]
+pub struct KrbFastResponse {
+    pub padata: Tag<0, SequenceOf<PaData>>,
+    pub strengthen_key: Option<Tag<1, EncryptionKey>>,
+    pub finished: Option<Tag<2, KrbFastFinished>>,
+    pub nonce: Tag<3, Integer>
+}
+
+impl KrbFastResponse {
+    /// Find a padata returned into the FAST tunnel
+    pub fn find_padata(&self, padata_type: PaDataType) -> Option<&PaData> {
+        let padata_type = padata_type as Integer;
+        self.padata.iter().find(|e| e.padata_type.inner == padata_type)
+    }
+}
+
+/// Find a padata by type
+fn find_padata(padata: &[PaData], padata_type: PaDataType) -> Option<&PaData> {
+    let padata_type = padata_type as Integer;
+    padata.iter().find(|e| e.padata_type.inner == padata_type)
+}
+
+/// Key and armor use to protect a KDC exchange
+pub struct FastArmor {
+    armor_key: EncryptionKey,
+    armor: Option<KrbFastArmor>
+}
+
+impl FastArmor {
+    /// Explicit armor use for AS exchange
+    ///
+    /// The armor is an AP-REQ built from a TGT, generally a machine account TGT
+    /// armor key = KRB-FX-CF2(subkey, ticket session key, "subkeyarmor", "ticketarmor")
+    pub fn from_tgt(ticket: Ticket, session_key: &EncryptionKey, crealm: Realm, cname: PrincipalName) -> KerlabResult<Self> {
+        let subkey = EncryptionKey::random(session_key.keytype.inner)?;
+
+        let mut authenticator = Authenticator::new(crealm, cname);
+        authenticator.subkey = Some(Tag::new(subkey.clone()));
+
+        let ap_req = ApReq::new(
+            ticket,
+            session_key.encrypt(KeyUsage::KeyUsageApReqAuthenticator, &authenticator)?
+        );
+
+        Ok(Self {
+            armor_key: krb_fx_cf2(&subkey, session_key, "subkeyarmor", "ticketarmor")?,
+            armor: Some(KrbFastArmor {
+                armor_type: Tag::new(ArmorType::FxFastArmorApRequest as Integer),
+                armor_value: Tag::new(to_der(&ap_req)?)
+            })
+        })
+    }
+
+    /// Implicit armor use for TGS exchange
+    ///
+    /// Keys come from the authenticator of the PA-TGS-REQ
+    pub fn from_tgs(subkey: &EncryptionKey, session_key: &EncryptionKey) -> KerlabResult<Self> {
+        Ok(Self {
+            armor_key: krb_fx_cf2(subkey, session_key, "subkeyarmor", "ticketarmor")?,
+            armor: None
+        })
+    }
+
+    /// The armor key
+    pub fn armor_key(&self) -> &EncryptionKey {
+        &self.armor_key
+    }
+
+    /// Move the padata of the request into the FAST tunnel
+    ///
+    /// PA-TGS-REQ stays outside, it's the implicit armor of TGS exchange
+    pub fn armor(&self, request: &mut KdcReq) -> KerlabResult<()> {
+        let mut inner_padata = vec![];
+        let mut outer_padata = vec![];
+        if let Some(padata) = request.padata.take() {
+            for e in padata.inner {
+                if e.padata_type.inner == PaDataType::PaTgsReq as Integer {
+                    outer_padata.push(e);
+                } else {
+                    inner_padata.push(e);
+                }
+            }
+        }
+
+        // checksum is computed over the AP-REQ for TGS and over req-body for AS
+        let req_checksum = match find_padata(&outer_padata, PaDataType::PaTgsReq) {
+            Some(tgs_req) => Checksum::compute(&self.armor_key, KeyUsage::KeyUsageFastReqChksum, &tgs_req.padata_value)?,
+            None => Checksum::compute(&self.armor_key, KeyUsage::KeyUsageFastReqChksum, &to_der(&request.req_body.inner)?)?
+        };
+
+        let fast_req = KrbFastReq {
+            fast_options: Tag::new(FastOptions::from_elem(32, false)),
+            padata: Tag::new(inner_padata),
+            req_body: Tag::new(request.req_body.inner.clone())
+        };
+
+        outer_padata.push(PaData::new(
+            PaDataType::PaFxFast,
+            &PaFxFastRequest::ArmoredData(Tag::new(KrbFastArmoredReq {
+                armor: self.armor.clone().map(Tag::new),
+                req_checksum: Tag::new(req_checksum),
+                enc_fast_req: Tag::new(self.armor_key.encrypt(KeyUsage::KeyUsageFastEnc, &fast_req)?)
+            }))
+        )?);
+
+        request.padata = Some(Tag::new(outer_padata));
+        Ok(())
+    }
+
+    /// Decrypt the KrbFastResponse from a list of padata
+    fn decrypt_response(&self, padata: &[PaData], nonce: Integer) -> KerlabResult<KrbFastResponse> {
+        let fast = find_padata(padata, PaDataType::PaFxFast)
+            .ok_or_else(|| Error::new(KerlabErrorKind::Kerberos, "No PA-FX-FAST in reply"))?;
+
+        let mut reply = PaFxFastReply::default();
+        from_der(&mut reply, &fast.padata_value)?;
+
+        let PaFxFastReply::ArmoredData(armored) = reply;
+        let response = self.armor_key.decrypt::<KrbFastResponse>(
+            KeyUsage::KeyUsageFastRep,
+            &armored.enc_fast_rep
+        )?;
+
+        if response.nonce.inner != nonce {
+            return Err(Error::new(KerlabErrorKind::Kerberos, "FAST response nonce mismatch"))
+        }
+
+        Ok(response)
+    }
+
+    /// Decrypt the FAST response of an armored KDC-REP
+    ///
+    /// The ticket checksum of the finished field is checked
+    pub fn unwrap_reply(&self, reply: &KdcRep, nonce: Integer) -> KerlabResult<KrbFastResponse> {
+        let padata = reply.padata.as_ref()
+            .ok_or_else(|| Error::new(KerlabErrorKind::Kerberos, "No padata in reply"))?;
+
+        let response = self.decrypt_response(padata, nonce)?;
+
+        match &response.finished {
+            Some(finished) => {
+                finished.ticket_checksum.verify(
+                    &self.armor_key,
+                    KeyUsage::KeyUsageFastFinished,
+                    &to_der(&reply.ticket.inner)?
+                ).map_err(|_| Error::new(KerlabErrorKind::Kerberos, "FAST ticket checksum mismatch"))?;
+            },
+            None => return Err(Error::new(KerlabErrorKind::Kerberos, "No finished field in FAST response"))
+        }
+
+        Ok(response)
+    }
+
+    /// Compute the key use to decrypt the enc-part of the reply
+    ///
+    /// When the KDC sends a strengthen key
+    /// reply key = KRB-FX-CF2(strengthen key, reply key, "strengthenkey", "replykey")
+    pub fn reply_key(&self, response: &KrbFastResponse, reply_key: &EncryptionKey) -> KerlabResult<EncryptionKey> {
+        match &response.strengthen_key {
+            Some(strengthen_key) => krb_fx_cf2(strengthen_key, reply_key, "strengthenkey", "replykey"),
+            None => Ok(reply_key.clone())
+        }
+    }
+
+    /// Unwrap an armored KRB-ERROR
+    ///
+    /// The real error is sent into PA-FX-ERROR inside the FAST response
+    /// padata of the response are returned to be reused (PA-FX-COOKIE, PA-ETYPE-INFO2...)
+    pub fn unwrap_error(&self, error: &KrbError, nonce: Integer) -> KerlabResult<(KrbError, KrbFastResponse)> {
+        let e_data = error.e_data.as_ref()
+            .ok_or_else(|| Error::new(KerlabErrorKind::Kerberos, "KRB-ERROR is not armored"))?;
+
+        let mut method_data = SequenceOf::<PaData>::default();
+        from_der(&mut method_data, e_data)?;
+
+        let response = self.decrypt_response(&method_data, nonce)?;
+
+        let fx_error = response.find_padata(PaDataType::PaFxError)
+            .ok_or_else(|| Error::new(KerlabErrorKind::Kerberos, "No PA-FX-ERROR in FAST response"))?;
+
+        let mut inner_error = KrbError::default();
+        from_der(&mut inner_error, &fx_error.padata_value)?;
+
+        Ok((inner_error, response))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use krbkdcreq::AsReq;
+
+    /// KDC side must be able to open the armored request
+    #[test]
+    fn test_armor_as_req() {
+        let session_key = EncryptionKey::random(18).unwrap();
+        let subkey = EncryptionKey::random(18).unwrap();
+        let armor = FastArmor::from_tgs(&subkey, &session_key).unwrap();
+
+        let mut request = AsReq::new("LAB.LOCAL", "foo", &[]).unwrap()
+            .with_preauth(&EncryptionKey::new_rc4_hmac("foo").unwrap()).unwrap();
+        armor.armor(&mut request).unwrap();
+
+        let padata = request.padata.as_ref().unwrap();
+        assert_eq!(padata.len(), 1);
+
+        let mut fast_request = PaFxFastRequest::default();
+        from_der(&mut fast_request, &padata[0].padata_value).unwrap();
+        let PaFxFastRequest::ArmoredData(armored) = fast_request;
+
+        assert!(armored.req_checksum.verify(
+            armor.armor_key(),
+            KeyUsage::KeyUsageFastReqChksum,
+            &to_der(&request.req_body.inner).unwrap()
+        ).is_ok());
+
+        let fast_req = armor.armor_key().decrypt::<KrbFastReq>(KeyUsage::KeyUsageFastEnc, &armored.enc_fast_req).unwrap();
+        assert_eq!(fast_req.padata[0].padata_type.inner, PaDataType::PaEncTimestamp as Integer);
+    }
+}
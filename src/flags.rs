@@ -0,0 +1,213 @@
+//! Typed flag sets encoded as ASN.1 BIT STRING
+//!
+//! KDCOptions, TicketFlags and APOptions are KerberosFlags,
+//! bit 0 is the most significant bit of the first byte
+//!
+//! @see https://datatracker.ietf.org/doc/html/rfc4120#section-5.2.8
+
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::{BitAnd, BitOr};
+use asn1::{ASN1, BitString};
+use yasna::{DERWriter, BERReader, Tag};
+use yasna::tags::TAG_BITSTRING;
+use error::KerlabResult;
+use json::{Json, ToJson};
+
+/// A named flag of a KerberosFlags bit string
+pub trait Flag: Copy + 'static {
+    /// Every known flag
+    const ALL: &'static [Self];
+    /// Position of the flag into the bit string
+    fn position(self) -> u32;
+    /// Name of the flag as written by the RFC
+    fn name(self) -> &'static str;
+}
+
+/// @see https://datatracker.ietf.org/doc/html/rfc4120#section-5.2.8
+/// ```asn.1
+/// KerberosFlags   ::= BIT STRING (SIZE (32..MAX))
+///                     -- minimum number of bits shall be sent,
+///                     -- but no fewer than 32
+/// ```
+pub struct FlagSet<T: Flag> {
+    bits: u32,
+    flag: PhantomData<T>
+}
+
+impl<T: Flag> FlagSet<T> {
+    /// constructor
+    pub fn new(flags: &[T]) -> Self {
+        let mut result = Self::default();
+        for flag in flags {
+            result.insert(*flag);
+        }
+        result
+    }
+
+    /// Flags from their integer value
+    pub fn from_bits(bits: u32) -> Self {
+        Self {
+            bits,
+            flag: PhantomData
+        }
+    }
+
+    /// Integer value of the flags,
+    /// as logged by Windows
+    pub fn bits(&self) -> u32 {
+        self.bits
+    }
+
+    fn mask(flag: T) -> u32 {
+        0x80000000 >> flag.position()
+    }
+
+    pub fn contains(&self, flag: T) -> bool {
+        self.bits & Self::mask(flag) != 0
+    }
+
+    pub fn insert(&mut self, flag: T) {
+        self.bits |= Self::mask(flag);
+    }
+
+    pub fn remove(&mut self, flag: T) {
+        self.bits &= !Self::mask(flag);
+    }
+
+    /// Set a flag
+    pub fn with(mut self, flag: T) -> Self {
+        self.insert(flag);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bits == 0
+    }
+
+    /// Known flags of the set
+    pub fn flags(&self) -> Vec<T> {
+        T::ALL.iter().filter(|flag| self.contains(**flag)).cloned().collect()
+    }
+
+    /// Names of the flags of the set
+    /// Bits without a name are written bit-N
+    pub fn names(&self) -> Vec<String> {
+        (0..32)
+            .filter(|position| self.bits & (0x80000000 >> position) != 0)
+            .map(|position| match T::ALL.iter().find(|flag| flag.position() == position) {
+                Some(flag) => flag.name().to_string(),
+                None => format!("bit-{}", position)
+            })
+            .collect()
+    }
+}
+
+impl<T: Flag> Default for FlagSet<T> {
+    fn default() -> Self {
+        Self::from_bits(0)
+    }
+}
+
+impl<T: Flag> Clone for FlagSet<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: Flag> Copy for FlagSet<T> {}
+
+impl<T: Flag> PartialEq for FlagSet<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.bits == other.bits
+    }
+}
+
+impl<T: Flag> fmt::Debug for FlagSet<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.names()).finish()
+    }
+}
+
+impl<T: Flag> BitOr for FlagSet<T> {
+    type Output = Self;
+    fn bitor(self, other: Self) -> Self {
+        Self::from_bits(self.bits | other.bits)
+    }
+}
+
+impl<T: Flag> BitAnd for FlagSet<T> {
+    type Output = Self;
+    fn bitand(self, other: Self) -> Self {
+        Self::from_bits(self.bits & other.bits)
+    }
+}
+
+/// Bits after the 32th are dropped
+impl<'a, T: Flag> From<&'a BitString> for FlagSet<T> {
+    fn from(bits: &'a BitString) -> Self {
+        let mut bytes = bits.to_bytes();
+        bytes.resize(4, 0);
+        Self::from_bits(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+/// Always 32 bits
+impl<'a, T: Flag> From<&'a FlagSet<T>> for BitString {
+    fn from(flags: &'a FlagSet<T>) -> Self {
+        BitString::from_bytes(&flags.bits.to_be_bytes())
+    }
+}
+
+impl<T: Flag> ASN1 for FlagSet<T> {
+    fn write_asn1(&self, writer: DERWriter) -> KerlabResult<()> {
+        BitString::from(self).write_asn1(writer)
+    }
+
+    fn read_asn1(&mut self, reader: BERReader) -> KerlabResult<()> {
+        let mut bits = BitString::new();
+        bits.read_asn1(reader)?;
+        *self = Self::from(&bits);
+        Ok(())
+    }
+
+    fn tag() -> Option<Tag> {
+        Some(TAG_BITSTRING)
+    }
+}
+
+impl<T: Flag> ToJson for FlagSet<T> {
+    fn to_json(&self) -> Json {
+        Json::Array(self.names().into_iter().map(Json::String).collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use asn1::{to_der, from_der};
+    use base::{KDCOptions, KdcOptionsType, TicketFlags, TicketFlagsType};
+
+    /// forwardable is the second bit of the first byte
+    #[test]
+    fn test_bit_order() {
+        let options = KDCOptions::new(&[KdcOptionsType::Forwardable, KdcOptionsType::Canonicalize, KdcOptionsType::Renew]);
+        assert_eq!(options.bits(), 0x40010002);
+        assert_eq!(to_der(&options).unwrap(), [3, 5, 0, 0x40, 0x01, 0x00, 0x02]);
+        assert_eq!(options.names(), ["forwardable", "canonicalize", "renew"]);
+
+        let mut decoded = KDCOptions::default();
+        from_der(&mut decoded, &[3, 2, 0, 0x40]).unwrap();
+        assert!(decoded.contains(KdcOptionsType::Forwardable));
+        assert!(!decoded.contains(KdcOptionsType::Forwarded));
+
+        let flags = TicketFlags::from_bits(0x40e10000);
+        assert_eq!(flags.flags(), [
+            TicketFlagsType::Forwardable,
+            TicketFlagsType::Renewable,
+            TicketFlagsType::Initial,
+            TicketFlagsType::PreAuthent,
+            TicketFlagsType::NameCanonicalize
+        ]);
+        assert_eq!(TicketFlags::from_bits(0x00000001).names(), ["bit-31"]);
+    }
+}
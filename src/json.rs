@@ -0,0 +1,909 @@
+//! Machine readable output of Kerberos structures
+//!
+//! Times are ISO-8601, bit strings are decoded into named flags
+//! and enumerated values carry their names
+
+use std::fmt;
+use asn1::{Tag, Application, SequenceOf, Integer, OctetString, GeneralString, GeneralizedTime, from_der};
+use base::{PrincipalName, HostAddress, LastReqBody, AuthorizationDataElement, AuthorizationData};
+use krbkdcrep::{KdcRep, EncKDCRepPart};
+use krbkdcreq::{KdcReq, KdcReqBody};
+use krberror::KrbErrorBody;
+use krbap::{ApReqBody, ApRepBody, EncAPRepPartBody};
+use krbcred::{KrbCredBody, EncKrbCredPartBody, KrbCredInfo};
+use ticket::{TicketBody, EncTicketPartBody, TransitedEncoding};
+use encryption::{EncryptedData, EncryptionKey};
+use padata::{PaData, PaEncTsEnc};
+use authenticator::AuthenticatorBody;
+use pac::{PacType, PacStruct, PacClientInfo, PacSignatureData, UpnDnsInfo, KerbValidationInfo, PacCredentialInfo};
+use ndr::{FileTime, Sid, GroupMembership};
+use pcap::{KrbMessage, KerberosPacket};
+use display::{Display, Formatter};
+
+/// A JSON value
+/// Members of an object keep their order
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(i64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>)
+}
+
+impl Json {
+    /// Object from a list of members
+    pub fn object(members: Vec<(&str, Json)>) -> Self {
+        Json::Object(members.into_iter().map(|(name, value)| (name.to_string(), value)).collect())
+    }
+
+    pub fn string(value: &str) -> Self {
+        Json::String(value.to_string())
+    }
+
+    /// Enumerated value with its name
+    /// ```json
+    /// {"value":23,"name":"rc4-hmac"}
+    /// ```
+    pub fn named(value: i64, name: Option<&str>) -> Self {
+        Self::object(vec![
+            ("value", Json::Number(value)),
+            ("name", name.map_or(Json::Null, Json::string))
+        ])
+    }
+
+    /// Member of an object
+    pub fn get(&self, name: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members.iter().find(|(e, _)| e == name).map(|(_, value)| value),
+            _ => None
+        }
+    }
+}
+
+/// Escape a JSON string value
+pub fn escape(value: &str) -> String {
+    let mut result = String::new();
+    for c in value.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c)
+        }
+    }
+    result
+}
+
+/// Compact JSON, one line
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(value) => write!(f, "{}", value),
+            Json::Number(value) => write!(f, "{}", value),
+            Json::String(value) => write!(f, "\"{}\"", escape(value)),
+            Json::Array(values) => {
+                write!(f, "[")?;
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            },
+            Json::Object(members) => {
+                write!(f, "{{")?;
+                for (index, (name, value)) in members.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "\"{}\":{}", escape(name), value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+/// Structured output of a Kerberos structure
+pub trait ToJson {
+    fn to_json(&self) -> Json;
+}
+
+/// Output of the binaries
+/// Indented text, or one JSON object per line
+pub struct Printer {
+    json: bool
+}
+
+impl Printer {
+    pub fn new(json: bool) -> Self {
+        Self {
+            json
+        }
+    }
+
+    pub fn is_json(&self) -> bool {
+        self.json
+    }
+
+    /// A structure with its title
+    /// ```json
+    /// {"type":"AS-REQ","value":{...}}
+    /// ```
+    pub fn structure<T: Display + ToJson>(&self, title: &str, object: &T) {
+        if self.json {
+            println!("{}", Json::object(vec![("type", Json::string(title)), ("value", object.to_json())]));
+        } else {
+            println!("**************************************************");
+            println!("{} ::=", title);
+            object.format(&mut Formatter::new());
+        }
+    }
+
+    /// An information message
+    pub fn message(&self, message: &str) {
+        if self.json {
+            println!("{}", Json::object(vec![("message", Json::string(message))]));
+        } else {
+            println!("**************************************************");
+            println!("{}", message);
+        }
+    }
+
+    /// Close the text output
+    pub fn end(&self) {
+        if !self.json {
+            println!("**************************************************");
+        }
+    }
+}
+
+pub fn message_type_name(value: Integer) -> Option<&'static str> {
+    Some(match value {
+        10 => "KRB_AS_REQ",
+        11 => "KRB_AS_REP",
+        12 => "KRB_TGS_REQ",
+        13 => "KRB_TGS_REP",
+        14 => "KRB_AP_REQ",
+        15 => "KRB_AP_REP",
+        20 => "KRB_SAFE",
+        21 => "KRB_PRIV",
+        22 => "KRB_CRED",
+        30 => "KRB_ERROR",
+        _ => return None
+    })
+}
+
+pub fn name_type_name(value: Integer) -> Option<&'static str> {
+    Some(match value {
+        0 => "NT-UNKNOWN",
+        1 => "NT-PRINCIPAL",
+        2 => "NT-SRV-INST",
+        3 => "NT-SRV-HST",
+        4 => "NT-SRV-XHST",
+        5 => "NT-UID",
+        6 => "NT-X500-PRINCIPAL",
+        7 => "NT-SMTP-NAME",
+        10 => "NT-ENTERPRISE",
+        _ => return None
+    })
+}
+
+pub fn etype_name(value: Integer) -> Option<&'static str> {
+    Some(match value {
+        0 => "null",
+        1 => "des-cbc-crc",
+        3 => "des-cbc-md5",
+        17 => "aes128-cts-hmac-sha1-96",
+        18 => "aes256-cts-hmac-sha1-96",
+        23 => "rc4-hmac",
+        24 => "rc4-hmac-exp",
+        _ => return None
+    })
+}
+
+pub fn checksum_type_name(value: i32) -> Option<&'static str> {
+    Some(match value {
+        1 => "crc32",
+        7 => "rsa-md5",
+        15 => "hmac-sha1-96-aes128",
+        16 => "hmac-sha1-96-aes256",
+        -138 => "hmac-md5",
+        0x8003 => "gss-api",
+        _ => return None
+    })
+}
+
+pub fn padata_type_name(value: Integer) -> Option<&'static str> {
+    Some(match value {
+        1 => "PA-TGS-REQ",
+        2 => "PA-ENC-TIMESTAMP",
+        3 => "PA-PW-SALT",
+        11 => "PA-ETYPE-INFO",
+        16 => "PA-PK-AS-REQ",
+        17 => "PA-PK-AS-REP",
+        19 => "PA-ETYPE-INFO2",
+        20 => "PA-SVR-REFERRAL-INFO",
+        128 => "PA-PAC-REQUEST",
+        129 => "PA-FOR-USER",
+        130 => "PA-FOR-X509-USER",
+        133 => "PA-FX-COOKIE",
+        136 => "PA-FX-FAST",
+        137 => "PA-FX-ERROR",
+        138 => "PA-ENCRYPTED-CHALLENGE",
+        149 => "PA-REQ-ENC-PA-REP",
+        161 => "KERB-KEY-LIST-REQ",
+        162 => "KERB-KEY-LIST-REP",
+        165 => "PA-SUPPORTED-ENCTYPES",
+        167 => "PA-PAC-OPTIONS",
+        _ => return None
+    })
+}
+
+pub fn error_code_name(value: Integer) -> Option<&'static str> {
+    Some(match value {
+        0 => "KDC_ERR_NONE",
+        1 => "KDC_ERR_NAME_EXP",
+        2 => "KDC_ERR_SERVICE_EXP",
+        3 => "KDC_ERR_BAD_PVNO",
+        4 => "KDC_ERR_C_OLD_MAST_KVNO",
+        5 => "KDC_ERR_S_OLD_MAST_KVNO",
+        6 => "KDC_ERR_C_PRINCIPAL_UNKNOWN",
+        7 => "KDC_ERR_S_PRINCIPAL_UNKNOWN",
+        8 => "KDC_ERR_PRINCIPAL_NOT_UNIQUE",
+        9 => "KDC_ERR_NULL_KEY",
+        10 => "KDC_ERR_CANNOT_POSTDATE",
+        11 => "KDC_ERR_NEVER_VALID",
+        12 => "KDC_ERR_POLICY",
+        13 => "KDC_ERR_BADOPTION",
+        14 => "KDC_ERR_ETYPE_NOSUPP",
+        15 => "KDC_ERR_SUMTYPE_NOSUPP",
+        16 => "KDC_ERR_PADATA_TYPE_NOSUPP",
+        17 => "KDC_ERR_TRTYPE_NOSUPP",
+        18 => "KDC_ERR_CLIENT_REVOKED",
+        19 => "KDC_ERR_SERVICE_REVOKED",
+        20 => "KDC_ERR_TGT_REVOKED",
+        21 => "KDC_ERR_CLIENT_NOTYET",
+        22 => "KDC_ERR_SERVICE_NOTYET",
+        23 => "KDC_ERR_KEY_EXPIRED",
+        24 => "KDC_ERR_PREAUTH_FAILED",
+        25 => "KDC_ERR_PREAUTH_REQUIRED",
+        31 => "KRB_AP_ERR_BAD_INTEGRITY",
+        32 => "KRB_AP_ERR_TKT_EXPIRED",
+        33 => "KRB_AP_ERR_TKT_NYV",
+        34 => "KRB_AP_ERR_REPEAT",
+        35 => "KRB_AP_ERR_NOT_US",
+        36 => "KRB_AP_ERR_BADMATCH",
+        37 => "KRB_AP_ERR_SKEW",
+        38 => "KRB_AP_ERR_BADADDR",
+        39 => "KRB_AP_ERR_BADVERSION",
+        40 => "KRB_AP_ERR_MSG_TYPE",
+        41 => "KRB_AP_ERR_MODIFIED",
+        42 => "KRB_AP_ERR_BADORDER",
+        44 => "KRB_AP_ERR_BADKEYVER",
+        45 => "KRB_AP_ERR_NOKEY",
+        46 => "KRB_AP_ERR_MUT_FAIL",
+        47 => "KRB_AP_ERR_BADDIRECTION",
+        48 => "KRB_AP_ERR_METHOD",
+        49 => "KRB_AP_ERR_BADSEQ",
+        50 => "KRB_AP_ERR_INAPP_CKSUM",
+        52 => "KRB_ERR_RESPONSE_TOO_BIG",
+        60 => "KRB_ERR_GENERIC",
+        61 => "KRB_ERR_FIELD_TOOLONG",
+        _ => return None
+    })
+}
+
+pub fn address_type_name(value: Integer) -> Option<&'static str> {
+    Some(match value {
+        2 => "IPv4",
+        3 => "Directional",
+        5 => "ChaosNet",
+        6 => "XNS",
+        7 => "ISO",
+        12 => "DECNET Phase IV",
+        16 => "AppleTalk DDP",
+        20 => "NetBios",
+        24 => "IPv6",
+        _ => return None
+    })
+}
+
+pub fn ad_type_name(value: Integer) -> Option<&'static str> {
+    Some(match value {
+        1 => "AD-IF-RELEVANT",
+        2 => "AD-INTENDED-FOR-SERVER",
+        3 => "AD-INTENDED-FOR-APPLICATION-CLASS",
+        4 => "AD-KDC-ISSUED",
+        5 => "AD-AND-OR",
+        6 => "AD-MANDATORY-TICKET-EXTENSIONS",
+        7 => "AD-IN-TICKET-EXTENSIONS",
+        8 => "AD-MANDATORY-FOR-KDC",
+        64 => "OSF-DCE",
+        65 => "SESAME",
+        66 => "AD-OSF-DCE-PKI-CERTID",
+        128 => "AD-WIN2K-PAC",
+        129 => "AD-ETYPE-NEGOTIATION",
+        _ => return None
+    })
+}
+
+/// @see https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-pac/3341cfa2-6ef5-42e0-b7bc-4544884bf399
+pub fn pac_buffer_name(value: u32) -> Option<&'static str> {
+    Some(match value {
+        0x00000001 => "KERB_VALIDATION_INFO",
+        0x00000002 => "PAC_CREDENTIAL_INFO",
+        0x00000006 => "SERVER_CHECKSUM",
+        0x00000007 => "KDC_CHECKSUM",
+        0x0000000A => "PAC_CLIENT_INFO",
+        0x0000000B => "S4U_DELEGATION_INFO",
+        0x0000000C => "UPN_DNS_INFO",
+        0x0000000D => "PAC_CLIENT_CLAIMS_INFO",
+        0x0000000E => "PAC_DEVICE_INFO",
+        0x0000000F => "PAC_DEVICE_CLAIMS_INFO",
+        0x00000010 => "PAC_TICKET_CHECKSUM",
+        0x00000011 => "PAC_ATTRIBUTES_INFO",
+        0x00000012 => "PAC_REQUESTOR",
+        _ => return None
+    })
+}
+
+fn msg_type(value: Integer) -> Json {
+    Json::named(value as i64, message_type_name(value))
+}
+
+/// Binary data is base64 encoded
+fn binary(data: &[u8]) -> Json {
+    Json::String(base64::encode(data))
+}
+
+fn group_ids(groups: &[GroupMembership]) -> Json {
+    Json::Array(groups.iter().map(|e| Json::object(vec![
+        ("relative_id", e.relative_id.to_json()),
+        ("attributes", e.attributes.to_json())
+    ])).collect())
+}
+
+impl<const N: u64, T: PartialEq + ToJson + Clone> ToJson for Tag<{ N }, T> {
+    fn to_json(&self) -> Json {
+        self.inner.to_json()
+    }
+}
+
+impl<const N: u64, T: PartialEq + ToJson + Default + Clone> ToJson for Application<{ N }, T> {
+    fn to_json(&self) -> Json {
+        self.inner.to_json()
+    }
+}
+
+impl<T: ToJson> ToJson for Option<T> {
+    fn to_json(&self) -> Json {
+        match self {
+            Some(e) => e.to_json(),
+            None => Json::Null
+        }
+    }
+}
+
+impl<T: ToJson> ToJson for SequenceOf<T> {
+    fn to_json(&self) -> Json {
+        Json::Array(self.iter().map(|e| e.to_json()).collect())
+    }
+}
+
+impl ToJson for Integer {
+    fn to_json(&self) -> Json {
+        Json::Number(*self as i64)
+    }
+}
+
+impl ToJson for OctetString {
+    fn to_json(&self) -> Json {
+        binary(self)
+    }
+}
+
+impl ToJson for GeneralString {
+    fn to_json(&self) -> Json {
+        Json::string(self.as_str())
+    }
+}
+
+impl ToJson for GeneralizedTime {
+    fn to_json(&self) -> Json {
+        Json::String(self.inner.to_rfc3339())
+    }
+}
+
+impl ToJson for FileTime {
+    fn to_json(&self) -> Json {
+        Json::String(self.datetime().to_rfc3339())
+    }
+}
+
+impl ToJson for Sid {
+    fn to_json(&self) -> Json {
+        Json::String(self.to_string())
+    }
+}
+
+impl ToJson for PrincipalName {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("name_type", Json::named(self.name_type.inner as i64, name_type_name(self.name_type.inner))),
+            ("name_string", self.name_string.to_json())
+        ])
+    }
+}
+
+impl ToJson for HostAddress {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("addr_type", Json::named(self.addr_type.inner as i64, address_type_name(self.addr_type.inner))),
+            ("address", self.address.to_json())
+        ])
+    }
+}
+
+impl ToJson for LastReqBody {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("lr_type", self.lr_type.to_json()),
+            ("lr_value", self.lr_value.to_json())
+        ])
+    }
+}
+
+/// AD-IF-RELEVANT and the PAC are decoded
+impl ToJson for AuthorizationDataElement {
+    fn to_json(&self) -> Json {
+        let ad_data = match self.ad_type.inner {
+            1 => {
+                let mut data = AuthorizationData::default();
+                from_der(&mut data, &self.ad_data).ok().map(|_| data.to_json())
+            },
+            128 => PacType::from_addata(self.ad_data.inner.clone()).ok().map(|pac| pac.to_json()),
+            _ => None
+        };
+
+        Json::object(vec![
+            ("ad_type", Json::named(self.ad_type.inner as i64, ad_type_name(self.ad_type.inner))),
+            ("ad_data", ad_data.unwrap_or_else(|| self.ad_data.to_json()))
+        ])
+    }
+}
+
+impl ToJson for EncryptedData {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("etype", Json::named(self.etype.inner as i64, etype_name(self.etype.inner))),
+            ("kvno", self.kvno.to_json()),
+            ("cipher", self.cipher.to_json())
+        ])
+    }
+}
+
+impl ToJson for EncryptionKey {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("keytype", Json::named(self.keytype.inner as i64, etype_name(self.keytype.inner))),
+            ("keyvalue", self.keyvalue.to_json())
+        ])
+    }
+}
+
+impl ToJson for PaData {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("padata_type", Json::named(self.padata_type.inner as i64, padata_type_name(self.padata_type.inner))),
+            ("padata_value", self.padata_value.to_json())
+        ])
+    }
+}
+
+impl ToJson for PaEncTsEnc {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("patimestamp", self.patimestamp.to_json()),
+            ("pausec", self.pausec.to_json())
+        ])
+    }
+}
+
+impl ToJson for TicketBody {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("tkt_vno", self.tkt_vno.to_json()),
+            ("realm", self.realm.to_json()),
+            ("sname", self.sname.to_json()),
+            ("enc_part", self.enc_part.to_json())
+        ])
+    }
+}
+
+impl ToJson for TransitedEncoding {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("tr_type", self.tr_type.to_json()),
+            ("contents", self.contents.to_json())
+        ])
+    }
+}
+
+impl ToJson for EncTicketPartBody {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("flags", self.flags.to_json()),
+            ("key", self.key.to_json()),
+            ("crealm", self.crealm.to_json()),
+            ("cname", self.cname.to_json()),
+            ("transited", self.transited.to_json()),
+            ("authtime", self.authtime.to_json()),
+            ("starttime", self.starttime.to_json()),
+            ("endtime", self.endtime.to_json()),
+            ("renew_till", self.renew_till.to_json()),
+            ("caddr", self.caddr.to_json()),
+            ("authorization_data", self.authorization_data.to_json())
+        ])
+    }
+}
+
+impl ToJson for KdcReq {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("pvno", self.pvno.to_json()),
+            ("msg_type", msg_type(self.msg_type.inner)),
+            ("padata", self.padata.to_json()),
+            ("req_body", self.req_body.to_json())
+        ])
+    }
+}
+
+impl ToJson for KdcReqBody {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("kdc_options", self.kdc_options.to_json()),
+            ("cname", self.cname.to_json()),
+            ("realm", self.realm.to_json()),
+            ("sname", self.sname.to_json()),
+            ("from", self.from.to_json()),
+            ("till", self.till.to_json()),
+            ("rtime", self.rtime.to_json()),
+            ("nonce", self.nonce.to_json()),
+            ("etype", Json::Array(self.etype.iter().map(|e| Json::named(*e as i64, etype_name(*e))).collect())),
+            ("addresses", self.addresses.to_json()),
+            ("enc_authorization_data", self.enc_authorization_data.to_json()),
+            ("additional_tickets", self.additional_tickets.to_json())
+        ])
+    }
+}
+
+impl ToJson for KdcRep {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("pvno", self.pvno.to_json()),
+            ("msg_type", msg_type(self.msg_type.inner)),
+            ("padata", self.padata.to_json()),
+            ("crealm", self.crealm.to_json()),
+            ("cname", self.cname.to_json()),
+            ("ticket", self.ticket.to_json()),
+            ("enc_part", self.enc_part.to_json())
+        ])
+    }
+}
+
+impl ToJson for EncKDCRepPart {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("key", self.key.to_json()),
+            ("last_req", self.last_req.to_json()),
+            ("nonce", self.nonce.to_json()),
+            ("key_expiration", self.key_expiration.to_json()),
+            ("flags", self.flags.to_json()),
+            ("authtime", self.authtime.to_json()),
+            ("starttime", self.starttime.to_json()),
+            ("endtime", self.endtime.to_json()),
+            ("renew_till", self.renew_till.to_json()),
+            ("srealm", self.srealm.to_json()),
+            ("sname", self.sname.to_json()),
+            ("caddr", self.caddr.to_json()),
+            ("encrypted_pa_data", self.encrypted_pa_data.to_json())
+        ])
+    }
+}
+
+impl ToJson for KrbErrorBody {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("pvno", self.pvno.to_json()),
+            ("msg_type", msg_type(self.msg_type.inner)),
+            ("ctime", self.ctime.to_json()),
+            ("cusec", self.cusec.to_json()),
+            ("stime", self.stime.to_json()),
+            ("susec", self.susec.to_json()),
+            ("error_code", Json::named(self.error_code.inner as i64, error_code_name(self.error_code.inner))),
+            ("crealm", self.crealm.to_json()),
+            ("cname", self.cname.to_json()),
+            ("realm", self.realm.to_json()),
+            ("sname", self.sname.to_json()),
+            ("e_text", self.e_text.to_json()),
+            ("e_data", self.e_data.to_json())
+        ])
+    }
+}
+
+impl ToJson for ApReqBody {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("pvno", self.pvno.to_json()),
+            ("msg_type", msg_type(self.msg_type.inner)),
+            ("ap_options", self.ap_options.to_json()),
+            ("ticket", self.ticket.to_json()),
+            ("authenticator", self.authenticator.to_json())
+        ])
+    }
+}
+
+impl ToJson for ApRepBody {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("pvno", self.pvno.to_json()),
+            ("msg_type", msg_type(self.msg_type.inner)),
+            ("enc_part", self.enc_part.to_json())
+        ])
+    }
+}
+
+impl ToJson for EncAPRepPartBody {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("ctime", self.ctime.to_json()),
+            ("cusec", self.cusec.to_json()),
+            ("subkey", self.subkey.to_json()),
+            ("seq_number", self.seq_number.to_json())
+        ])
+    }
+}
+
+impl ToJson for AuthenticatorBody {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("authenticator_vno", self.authenticator_vno.to_json()),
+            ("crealm", self.crealm.to_json()),
+            ("cname", self.cname.to_json()),
+            ("cksum", self.cksum.to_json()),
+            ("cusec", self.cusec.to_json()),
+            ("ctime", self.ctime.to_json()),
+            ("subkey", self.subkey.to_json()),
+            ("seq_number", self.seq_number.to_json()),
+            ("authorization_data", self.authorization_data.to_json())
+        ])
+    }
+}
+
+impl ToJson for KrbCredBody {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("pvno", self.pvno.to_json()),
+            ("msg_type", msg_type(self.msg_ticket.inner)),
+            ("tickets", self.tickets.to_json()),
+            ("enc_part", self.enc_part.to_json())
+        ])
+    }
+}
+
+impl ToJson for EncKrbCredPartBody {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("ticket_info", self.ticket_info.to_json()),
+            ("nonce", self.nonce.to_json()),
+            ("timestamp", self.timestamp.to_json()),
+            ("usec", self.usec.to_json()),
+            ("s_address", self.s_address.to_json()),
+            ("r_address", self.r_address.to_json())
+        ])
+    }
+}
+
+impl ToJson for KrbCredInfo {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("key", self.key.to_json()),
+            ("prealm", self.prealm.to_json()),
+            ("pname", self.pname.to_json()),
+            ("flags", self.flags.to_json()),
+            ("authtime", self.authtime.to_json()),
+            ("starttime", self.starttime.to_json()),
+            ("endtime", self.endtime.to_json()),
+            ("renew_till", self.renew_till.to_json()),
+            ("srealm", self.srealm.to_json()),
+            ("sname", self.sname.to_json()),
+            ("caddr", self.caddr.to_json())
+        ])
+    }
+}
+
+impl ToJson for PacType {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("c_buffers", self.c_buffers.to_json()),
+            ("version", self.version.to_json()),
+            ("buffers", Json::Array(self.buffers.iter().map(|e| e.to_json()).collect()))
+        ])
+    }
+}
+
+/// Each buffer carries its ulType
+impl ToJson for PacStruct {
+    fn to_json(&self) -> Json {
+        let ul_type = self.ul_type();
+        Json::object(vec![
+            ("ul_type", Json::named(ul_type as i64, pac_buffer_name(ul_type))),
+            ("value", match self {
+                PacStruct::KerbValidationInfo(e) => e.to_json(),
+                PacStruct::PacCredentialInfo(e) => e.to_json(),
+                PacStruct::PacClientInfo(e) => e.to_json(),
+                PacStruct::UpnDnsInfo(e) => e.to_json(),
+                PacStruct::ServerChecksum(e) => e.to_json(),
+                PacStruct::KDCChecksum(e) => e.to_json(),
+                PacStruct::Unknown(_, e) => binary(e)
+            })
+        ])
+    }
+}
+
+impl ToJson for PacClientInfo {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("client_id", self.client_id.to_json()),
+            ("name", Json::string(&self.name))
+        ])
+    }
+}
+
+impl ToJson for PacSignatureData {
+    fn to_json(&self) -> Json {
+        let signature_type = self.signature_type as i32;
+        Json::object(vec![
+            ("signature_type", Json::named(signature_type as i64, checksum_type_name(signature_type))),
+            ("signature", binary(&self.signature)),
+            ("rodcidentifier", self.rodcidentifier.map_or(Json::Null, |e| Json::Number(e as i64)))
+        ])
+    }
+}
+
+impl ToJson for PacCredentialInfo {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("version", self.version.to_json()),
+            ("encryption_type", Json::named(self.encryption_type as i64, etype_name(self.encryption_type))),
+            ("serialized_data", binary(&self.serialized_data))
+        ])
+    }
+}
+
+impl ToJson for UpnDnsInfo {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("upn", Json::string(&self.upn)),
+            ("dns", Json::string(&self.dns))
+        ])
+    }
+}
+
+impl ToJson for KerbValidationInfo {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("logon_time", self.logon_time.to_json()),
+            ("logoff_time", self.logoff_time.to_json()),
+            ("kick_off_time", self.kick_off_time.to_json()),
+            ("password_last_set", self.password_last_set.to_json()),
+            ("password_can_change", self.password_can_change.to_json()),
+            ("password_must_change", self.password_must_change.to_json()),
+            ("effective_name", Json::string(&self.effective_name)),
+            ("full_name", Json::string(&self.full_name)),
+            ("logon_script", Json::string(&self.logon_script)),
+            ("profile_path", Json::string(&self.profile_path)),
+            ("home_directory", Json::string(&self.home_directory)),
+            ("home_directory_drive", Json::string(&self.home_directory_drive)),
+            ("logon_count", Json::Number(self.logon_count as i64)),
+            ("bad_password_count", Json::Number(self.bad_password_count as i64)),
+            ("user_id", self.user_id.to_json()),
+            ("primary_group_id", self.primary_group_id.to_json()),
+            ("group_ids", group_ids(&self.group_ids)),
+            ("user_flags", self.user_flags.to_json()),
+            ("user_session_key", binary(&self.user_session_key)),
+            ("logon_server", Json::string(&self.logon_server)),
+            ("logon_domain_name", Json::string(&self.logon_domain_name)),
+            ("logon_domain_id", self.logon_domain_id.to_json()),
+            ("user_account_control", self.user_account_control.to_json()),
+            ("sub_auth_status", self.sub_auth_status.to_json()),
+            ("last_successful_i_logon", self.last_successful_i_logon.to_json()),
+            ("last_failed_i_logon", self.last_failed_i_logon.to_json()),
+            ("failed_i_logon_count", self.failed_i_logon_count.to_json()),
+            ("extra_sids", Json::Array(self.extra_sids.iter().map(|e| Json::object(vec![
+                ("sid", e.sid.to_json()),
+                ("attributes", e.attributes.to_json())
+            ])).collect())),
+            ("resource_group_domain_sid", self.resource_group_domain_sid.to_json()),
+            ("resource_group_ids", group_ids(&self.resource_group_ids))
+        ])
+    }
+}
+
+impl ToJson for KrbMessage {
+    fn to_json(&self) -> Json {
+        match self {
+            KrbMessage::AsReq(message) => message.to_json(),
+            KrbMessage::AsRep(message) => message.to_json(),
+            KrbMessage::TgsReq(message) => message.to_json(),
+            KrbMessage::TgsRep(message) => message.to_json(),
+            KrbMessage::KrbError(message) => message.to_json()
+        }
+    }
+}
+
+/// The message is decoded, or replaced by the decoding error
+impl ToJson for KerberosPacket {
+    fn to_json(&self) -> Json {
+        let mut members = vec![
+            ("timestamp", Json::String(self.timestamp.to_rfc3339())),
+            ("transport", Json::String(format!("{:?}", self.transport))),
+            ("source", Json::String(self.source.to_string())),
+            ("destination", Json::String(self.destination.to_string()))
+        ];
+        match self.decode() {
+            Ok(message) => {
+                members.push(("type", Json::string(message.name())));
+                members.push(("message", message.to_json()));
+            },
+            Err(e) => members.push(("error", Json::String(format!("{:?}", e))))
+        }
+        Json::object(members)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::str::FromStr;
+    use asn1::to_der;
+    use base::{PrincipalNameType, KdcOptionsType};
+    use krbkdcreq::AsReq;
+    use krberror::{KrbError, KrbErrorCode};
+
+    /// Flags, names of enumerated values and escaping
+    #[test]
+    fn test_to_json() {
+        let request = AsReq::new("LAB.LOCAL", "al\"ice", &[KdcOptionsType::Forwardable, KdcOptionsType::Renewable]).unwrap();
+        let json = request.to_json();
+        let body = json.get("req_body").unwrap();
+
+        assert_eq!(json.get("msg_type").unwrap().to_string(), "{\"value\":10,\"name\":\"KRB_AS_REQ\"}");
+        assert_eq!(body.get("kdc_options").unwrap().to_string(), "[\"forwardable\",\"renewable\"]");
+        assert_eq!(body.get("etype").unwrap().to_string(), "[{\"value\":23,\"name\":\"rc4-hmac\"}]");
+        assert_eq!(
+            body.get("cname").unwrap().to_string(),
+            "{\"name_type\":{\"value\":1,\"name\":\"NT-PRINCIPAL\"},\"name_string\":[\"al\\\"ice\"]}"
+        );
+        assert_eq!(body.get("till").unwrap(), &Json::String(request.req_body.till.inner.inner.to_rfc3339()));
+
+        let error = KrbError::new(
+            KrbErrorCode::KdcErrPreauthRequired,
+            GeneralString::from_str("LAB.LOCAL").unwrap(),
+            PrincipalName::new(PrincipalNameType::NtSrvInst, vec![GeneralString::from_str("krbtgt").unwrap()])
+        );
+        let message = KrbMessage::from(&to_der(&error).unwrap()).unwrap();
+        assert_eq!(
+            message.to_json().get("error_code").unwrap().to_string(),
+            "{\"value\":25,\"name\":\"KDC_ERR_PREAUTH_REQUIRED\"}"
+        );
+    }
+}
@@ -0,0 +1,1417 @@
+//! In memory Key Distribution Center
+//! Answer AS-REQ and TGS-REQ for a single realm
+//! Use to test tools on localhost without a Windows DC
+//! @see https://tools.ietf.org/html/rfc4120#section-3.1.2
+
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{TcpStream, UdpSocket, SocketAddr};
+use std::str::FromStr;
+use chrono::{Utc, Duration, DateTime};
+use asn1::{Tag, Integer, GeneralString, Application, to_der, from_der, from_ber};
+use base::{PrincipalName, PrincipalNameType, KerberosTime, TicketFlags, TicketFlagsType, KdcOptionsType, MessageType, Realm, DEFAULT_MAX_SKEW_MINUTES};
+use encryption::{EncryptionKey, EType, KeyUsage};
+use error::{KerlabResult, Error, KerlabErrorKind};
+use forge::Forge;
+use krbap::ApReq;
+use krberror::{KrbError, KrbErrorCode};
+use krbkdcreq::{AsReq, TgsReq, KdcReq, KdcReqBody};
+use krbkdcrep::{AsRep, TgsRep, KdcRep, EncKDCRepPart, EncASRepPart, EncTGSRepPart};
+use authenticator::Authenticator;
+use acceptor::find_pac;
+use audit::{AuditSink, SecurityEvent, EventId, NO_TICKET};
+use ndr::Sid;
+use pac::PacType;
+use padata::{PaData, PaDataType, PaEncTsEnc, PaForUser, KerbPaPacRequest, EtypeInfo2, EtypeInfo2Entry};
+use referral::krbtgt;
+use request::MAX_RECORD_SIZE;
+use ticket::{Ticket, TicketBody, EncTicketPart, EncTicketPartBody, TransitedEncoding, TransitedType};
+
+/// userAccountControl flags checked by the KDC
+/// @see [MS-ADTS] 2.2.16 userAccountControl
+pub const UF_ACCOUNTDISABLE: u32 = 0x00000002;
+pub const UF_TRUSTED_FOR_DELEGATION: u32 = 0x00080000;
+pub const UF_NOT_DELEGATED: u32 = 0x00100000;
+pub const UF_DONT_REQUIRE_PREAUTH: u32 = 0x00400000;
+pub const UF_TRUSTED_TO_AUTH_FOR_DELEGATION: u32 = 0x01000000;
+
+/// Relative id of the Protected Users group
+pub const PROTECTED_USERS: u32 = 525;
+
+/// Biggest reply sent over UDP, MaxDatagramReplySize of Windows
+const MAX_DATAGRAM_SIZE: usize = 1465;
+
+/// Encryption types supported by the KDC by order of preference
+const SUPPORTED_ETYPES: [EType; 3] = [EType::Aes256CtsHmacSha196, EType::Aes128CtsHmacSha196, EType::Rc4Hmac];
+
+/// Salt use to derive AES keys from the password
+///
+/// Users are salted with the realm followed by the name,
+/// computer accounts with the realm followed by host and the FQDN
+pub fn salt(realm: &str, name: &str) -> String {
+    if name.ends_with('$') {
+        format!(
+            "{}host{}.{}",
+            realm.to_uppercase(),
+            name.trim_end_matches('$').to_lowercase(),
+            realm.to_lowercase()
+        )
+    } else {
+        format!("{}{}", realm.to_uppercase(), name)
+    }
+}
+
+/// An account of the principal database
+#[derive(Clone)]
+pub struct Principal {
+    pub name: String,
+    pub user_id: u32,
+    pub groups: Vec<u32>,
+    pub user_account_control: u32,
+    pub spns: Vec<String>,
+    /// Services this account can delegate to (msDS-AllowedToDelegateTo)
+    pub allowed_to_delegate_to: Vec<String>,
+    /// Accounts that can delegate to this account (msDS-AllowedToActOnBehalfOfOtherIdentity)
+    pub allowed_to_act: Vec<String>,
+    /// Long term keys, by order of preference
+    pub keys: Vec<EncryptionKey>
+}
+
+impl Principal {
+    pub fn new(name: &str, user_id: u32) -> Self {
+        Self {
+            name: name.to_string(),
+            user_id,
+            groups: vec![513],
+            user_account_control: 0,
+            spns: vec![],
+            allowed_to_delegate_to: vec![],
+            allowed_to_act: vec![],
+            keys: vec![]
+        }
+    }
+
+    /// Derive AES and RC4 keys from the password
+    pub fn with_password(mut self, realm: &str, password: &str) -> KerlabResult<Self> {
+        let salt = salt(realm, &self.name);
+        self.keys.push(EncryptionKey::new_aes(EType::Aes256CtsHmacSha196, password, &salt)?);
+        self.keys.push(EncryptionKey::new_aes(EType::Aes128CtsHmacSha196, password, &salt)?);
+        self.keys.push(EncryptionKey::new_rc4_hmac(password)?);
+        Ok(self)
+    }
+
+    pub fn with_key(mut self, key: EncryptionKey) -> Self {
+        self.keys.push(key);
+        self
+    }
+
+    pub fn with_spn(mut self, spn: &str) -> Self {
+        self.spns.push(spn.to_string());
+        self
+    }
+
+    /// Constrained delegation to a service
+    pub fn with_delegation_to(mut self, spn: &str) -> Self {
+        self.allowed_to_delegate_to.push(spn.to_string());
+        self
+    }
+
+    /// Resource based constrained delegation from an account
+    pub fn with_allowed_to_act(mut self, account: &str) -> Self {
+        self.allowed_to_act.push(account.to_string());
+        self
+    }
+
+    /// Relative id of the groups into the domain
+    pub fn with_groups(mut self, groups: &[u32]) -> Self {
+        self.groups = groups.to_vec();
+        self
+    }
+
+    /// userAccountControl flags (UF_*)
+    pub fn with_flags(mut self, flags: u32) -> Self {
+        self.user_account_control |= flags;
+        self
+    }
+
+    pub fn is_disabled(&self) -> bool {
+        self.user_account_control & UF_ACCOUNTDISABLE != 0
+    }
+
+    pub fn is_preauth_required(&self) -> bool {
+        self.user_account_control & UF_DONT_REQUIRE_PREAUTH == 0
+    }
+
+    /// Sensitive accounts and members of Protected Users can't be delegated
+    pub fn is_delegable(&self) -> bool {
+        self.user_account_control & UF_NOT_DELEGATED == 0 && !self.groups.contains(&PROTECTED_USERS)
+    }
+
+    /// Protocol transition is allowed to produce forwardable tickets
+    pub fn is_trusted_to_auth_for_delegation(&self) -> bool {
+        self.user_account_control & UF_TRUSTED_TO_AUTH_FOR_DELEGATION != 0
+    }
+
+    /// Constrained delegation allow-list
+    pub fn can_delegate_to(&self, sname: &PrincipalName) -> bool {
+        let name = sname.name();
+        self.allowed_to_delegate_to.iter().any(|spn| spn.eq_ignore_ascii_case(&name))
+    }
+
+    /// Resource based constrained delegation
+    pub fn accepts_delegation_from(&self, account: &str) -> bool {
+        self.allowed_to_act.iter().any(|e| e.eq_ignore_ascii_case(account))
+    }
+
+    /// Key of an encryption type
+    pub fn key(&self, etype: Integer) -> Option<&EncryptionKey> {
+        self.keys.iter().find(|key| key.keytype.inner == etype)
+    }
+
+    /// First key that match the encryption types of the client
+    pub fn negotiate(&self, etypes: &[Integer]) -> Option<&EncryptionKey> {
+        etypes.iter().filter_map(|etype| self.key(*etype)).next()
+    }
+
+    /// Check if the principal is the target of a service name
+    pub fn is_for(&self, realm: &str, sname: &PrincipalName) -> bool {
+        let name = sname.name();
+        if sname.name_string.len() == 1 {
+            return name.eq_ignore_ascii_case(&self.name)
+        }
+        if self.name.eq_ignore_ascii_case("krbtgt") && name.eq_ignore_ascii_case(&format!("krbtgt/{}", realm)) {
+            return true
+        }
+        self.spns.iter().any(|spn| spn.eq_ignore_ascii_case(&name))
+    }
+
+    /// Default salt use for the AES keys of the principal
+    fn etype_info2(&self, realm: &str) -> KerlabResult<EtypeInfo2> {
+        let salt = salt(realm, &self.name);
+        let mut result = vec![];
+        for key in &self.keys {
+            result.push(match key.keytype.inner {
+                23 => EtypeInfo2Entry::new(key.keytype.inner, None)?,
+                _ => EtypeInfo2Entry::new(key.keytype.inner, Some(&salt))?
+            });
+        }
+        Ok(result)
+    }
+}
+
+/// Principal database of the KDC
+///
+/// The text format use one line per principal,
+/// the name followed by attributes separated by space
+/// ```text
+/// # name        attributes
+/// krbtgt        rid=502 password=krbtgt
+/// administrator rid=500 password=P@ssw0rd groups=513,512,519
+/// alice         rid=1104 password=alice flags=DONT_REQ_PREAUTH
+/// svc_sql       rid=1105 ntlm=<hash> spn=MSSQLSvc/sql.lab.local:1433
+/// svc_web       rid=1106 password=web flags=TRUSTED_TO_AUTH_FOR_DELEGATION delegate=MSSQLSvc/sql.lab.local:1433
+/// ws01$         rid=1107 password=ws01 spn=cifs/ws01.lab.local rbcd=svc_web
+/// ```
+#[derive(Default)]
+pub struct PrincipalDb {
+    pub principals: Vec<Principal>
+}
+
+impl PrincipalDb {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, principal: Principal) -> Self {
+        self.principals.push(principal);
+        self
+    }
+
+    /// Parse the text format of the database
+    pub fn from(realm: &str, content: &str) -> KerlabResult<Self> {
+        let mut result = Self::new();
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut fields = line.split_whitespace();
+            let mut principal = Principal::new(fields.next().unwrap_or_default(), 0);
+            for field in fields {
+                let mut attribute = field.splitn(2, '=');
+                let name = attribute.next().unwrap_or_default();
+                let value = attribute.next()
+                    .ok_or_else(|| Error::new(KerlabErrorKind::Parsing, &format!("Invalid attribute {} for {}", field, principal.name)))?;
+
+                principal = match name {
+                    "rid" => {
+                        principal.user_id = parse_u32(value)?;
+                        principal
+                    },
+                    "password" => principal.with_password(realm, value)?,
+                    "ntlm" => principal.with_key(EncryptionKey::new_rc4_hmac_from_hash(parse_hex(value)?)?),
+                    "aes256" => principal.with_key(EncryptionKey::new(EType::Aes256CtsHmacSha196, parse_hex(value)?)),
+                    "aes128" => principal.with_key(EncryptionKey::new(EType::Aes128CtsHmacSha196, parse_hex(value)?)),
+                    "spn" => value.split(',').fold(principal, |principal, spn| principal.with_spn(spn)),
+                    "delegate" => value.split(',').fold(principal, |principal, spn| principal.with_delegation_to(spn)),
+                    "rbcd" => value.split(',').fold(principal, |principal, account| principal.with_allowed_to_act(account)),
+                    "groups" => {
+                        let mut groups = vec![];
+                        for group in value.split(',') {
+                            groups.push(parse_u32(group)?);
+                        }
+                        principal.with_groups(&groups)
+                    },
+                    "flags" => {
+                        let mut flags = 0;
+                        for flag in value.split(',') {
+                            flags |= match flag {
+                                "DISABLED" => UF_ACCOUNTDISABLE,
+                                "DONT_REQ_PREAUTH" => UF_DONT_REQUIRE_PREAUTH,
+                                "NOT_DELEGATED" => UF_NOT_DELEGATED,
+                                "TRUSTED_FOR_DELEGATION" => UF_TRUSTED_FOR_DELEGATION,
+                                "TRUSTED_TO_AUTH_FOR_DELEGATION" => UF_TRUSTED_TO_AUTH_FOR_DELEGATION,
+                                _ => return Err(Error::new(KerlabErrorKind::Parsing, &format!("Unknown flag {}", flag)))
+                            };
+                        }
+                        principal.with_flags(flags)
+                    },
+                    _ => return Err(Error::new(KerlabErrorKind::Parsing, &format!("Unknown attribute {}", name)))
+                };
+            }
+            result.principals.push(principal);
+        }
+        Ok(result)
+    }
+
+    /// Load a database from disk
+    pub fn from_file(realm: &str, path: &str) -> KerlabResult<Self> {
+        Self::from(realm, &fs::read_to_string(path)?)
+    }
+
+    /// Find a client by its account name
+    pub fn client(&self, name: &str) -> Option<&Principal> {
+        self.principals.iter().find(|principal| principal.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Find the principal targeted by a service name
+    pub fn service(&self, realm: &str, sname: &PrincipalName) -> Option<&Principal> {
+        self.principals.iter().find(|principal| principal.is_for(realm, sname))
+    }
+}
+
+fn parse_u32(value: &str) -> KerlabResult<u32> {
+    value.parse::<u32>().map_err(|_| Error::new(KerlabErrorKind::Parsing, &format!("Invalid number {}", value)))
+}
+
+fn parse_hex(value: &str) -> KerlabResult<Vec<u8>> {
+    if !value.len().is_multiple_of(2) {
+        return Err(Error::new(KerlabErrorKind::Parsing, "Invalid hexadecimal key"))
+    }
+    let mut result = vec![];
+    for i in (0..value.len()).step_by(2) {
+        result.push(
+            u8::from_str_radix(&value[i..i + 2], 16)
+                .map_err(|_| Error::new(KerlabErrorKind::Parsing, "Invalid hexadecimal key"))?
+        );
+    }
+    Ok(result)
+}
+
+/// Error code of an exchange, KDC_ERR_NONE on success
+fn status<T>(result: &KerlabResult<T>) -> KrbErrorCode {
+    match result {
+        Ok(_) => KrbErrorCode::KdcErrNone,
+        Err(Error::Protocol(code, _)) => *code,
+        Err(_) => KrbErrorCode::KrbErrGeneric
+    }
+}
+
+/// Find a padata sent by the client
+fn find_padata(request: &KdcReq, padata_type: PaDataType) -> Option<&PaData> {
+    let padata_type = padata_type as Integer;
+    request.padata.as_ref()?.iter().find(|padata| padata.padata_type.inner == padata_type)
+}
+
+/// Client of the issued ticket
+/// It differs from the client of the TGT with S4U
+struct Impersonation {
+    crealm: Realm,
+    cname: PrincipalName,
+    authtime: DateTime<Utc>,
+    flags: TicketFlags,
+    pac: Option<PacType>
+}
+
+/// In memory KDC
+pub struct Kdc {
+    realm: String,
+    domain_sid: Sid,
+    db: PrincipalDb,
+    max_skew: Duration,
+    lifetime: Duration,
+    max_datagram_size: usize,
+    audit: Option<Box<dyn AuditSink>>
+}
+
+impl Kdc {
+    /// The database must contain the krbtgt account
+    pub fn new(realm: &str, domain_sid: Sid, db: PrincipalDb) -> Self {
+        Self {
+            realm: realm.to_uppercase(),
+            domain_sid,
+            db,
+            max_skew: Duration::minutes(DEFAULT_MAX_SKEW_MINUTES),
+            lifetime: Duration::hours(10),
+            max_datagram_size: MAX_DATAGRAM_SIZE,
+            audit: None
+        }
+    }
+
+    /// Change the accepted clock skew
+    pub fn with_max_skew(mut self, max_skew: Duration) -> Self {
+        self.max_skew = max_skew;
+        self
+    }
+
+    /// Maximum lifetime of tickets
+    pub fn with_lifetime(mut self, lifetime: Duration) -> Self {
+        self.lifetime = lifetime;
+        self
+    }
+
+    /// Biggest reply sent over UDP, the client retries bigger ones over TCP
+    pub fn with_max_datagram_size(mut self, size: usize) -> Self {
+        self.max_datagram_size = size;
+        self
+    }
+
+    /// Emit a security event for each processed request
+    pub fn with_audit(mut self, audit: Box<dyn AuditSink>) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    fn krbtgt(&self) -> KerlabResult<&Principal> {
+        self.db.client("krbtgt")
+            .ok_or_else(|| Error::protocol(KrbErrorCode::KdcErrSPrincipalUnknown, "No krbtgt account"))
+    }
+
+    /// Key of the krbtgt account that signs the PAC
+    fn kdc_key<'a>(&self, krbtgt: &'a Principal) -> KerlabResult<&'a EncryptionKey> {
+        krbtgt.keys.first()
+            .ok_or_else(|| Error::protocol(KrbErrorCode::KdcErrNullKey, "krbtgt account has no key"))
+    }
+
+    /// Session key use the first encryption type of the client supported by the KDC
+    fn session_key(&self, etypes: &[Integer]) -> KerlabResult<EncryptionKey> {
+        let etype = etypes.iter()
+            .find(|etype| SUPPORTED_ETYPES.iter().any(|supported| *supported as Integer == **etype))
+            .ok_or_else(|| Error::protocol(KrbErrorCode::KdcErrEtypeNoSupp, "No supported encryption type"))?;
+        EncryptionKey::random(*etype)
+    }
+
+    /// Key use to encrypt the ticket of a service
+    fn service_key<'a>(&self, service: &'a Principal, etypes: &[Integer]) -> KerlabResult<&'a EncryptionKey> {
+        service.negotiate(etypes)
+            .or_else(|| service.keys.first())
+            .ok_or_else(|| Error::protocol(KrbErrorCode::KdcErrNullKey, "Service has no key"))
+    }
+
+    /// Build the PAC of a client
+    fn pac(&self, client: &Principal, authtime: DateTime<Utc>, server_key: &EncryptionKey) -> KerlabResult<PacType> {
+        let kdc_key = self.kdc_key(self.krbtgt()?)?;
+        Forge::new(&self.realm, &client.name, self.domain_sid.clone())
+            .with_user_id(client.user_id)
+            .with_groups(&client.groups)
+            .pac(authtime, server_key, kdc_key)
+    }
+
+    /// Encrypt the ticket and build the associated encrypted part of the reply
+    fn issue(&self, enc_ticket: EncTicketPartBody, sname: &PrincipalName, service_key: &EncryptionKey, nonce: Integer) -> KerlabResult<(Ticket, EncKDCRepPart)> {
+        let realm = GeneralString::from_str(&self.realm)?;
+        let enc_part = service_key.encrypt(KeyUsage::KeyUsageAsRepTicket, &EncTicketPart {
+            inner: enc_ticket.clone()
+        })?;
+
+        let ticket = Application {
+            inner: TicketBody {
+                tkt_vno: Tag::new(5),
+                realm: Tag::new(realm.clone()),
+                sname: Tag::new(sname.clone()),
+                enc_part: Tag::new(enc_part)
+            }
+        };
+
+        Ok((ticket, EncKDCRepPart {
+            key: Tag::new(enc_ticket.key.inner),
+            last_req: Tag::new(vec![]),
+            nonce: Tag::new(nonce),
+            key_expiration: None,
+            flags: Tag::new(enc_ticket.flags.inner),
+            authtime: enc_ticket.authtime,
+            starttime: enc_ticket.starttime,
+            endtime: enc_ticket.endtime,
+            renew_till: enc_ticket.renew_till,
+            srealm: Tag::new(realm),
+            sname: Tag::new(sname.clone()),
+            caddr: None,
+            encrypted_pa_data: None
+        }))
+    }
+
+    /// Check the encrypted timestamp of the client
+    /// Return the key use by the client
+    fn preauth<'a>(&self, client: &'a Principal, padata: &PaData) -> KerlabResult<&'a EncryptionKey> {
+        let mut encrypted = ::encryption::EncryptedData::default();
+        from_der(&mut encrypted, &padata.padata_value)
+            .map_err(|_| Error::protocol(KrbErrorCode::KdcErrPreauthFailed, "Invalid PA-ENC-TIMESTAMP"))?;
+
+        let key = client.key(encrypted.etype.inner)
+            .ok_or_else(|| Error::protocol(KrbErrorCode::KdcErrEtypeNoSupp, "No key for the pre authentication encryption type"))?;
+
+        let timestamp = key.decrypt::<PaEncTsEnc>(KeyUsage::KeyUsageAsReqTimestamp, &encrypted)
+            .map_err(|_| Error::protocol(KrbErrorCode::KdcErrPreauthFailed, "Pre authentication failed"))?;
+
+        if (Utc::now() - timestamp.patimestamp.inner.inner).num_seconds().abs() > self.max_skew.num_seconds() {
+            return Err(Error::protocol(KrbErrorCode::KrbApErrSkew, "Clock skew too great"))
+        }
+        Ok(key)
+    }
+
+    /// Authentication service exchange
+    pub fn as_exchange(&self, request: &AsReq) -> KerlabResult<AsRep> {
+        let body = &request.req_body;
+        let now = Utc::now();
+
+        let cname = body.cname.as_ref()
+            .ok_or_else(|| Error::protocol(KrbErrorCode::KdcErrCPrincipalUnknown, "No client name"))?;
+        let client = cname.name_string.first()
+            .and_then(|name| self.db.client(name.as_str()))
+            .ok_or_else(|| Error::protocol(KrbErrorCode::KdcErrCPrincipalUnknown, "Client not found in Kerberos database"))?;
+
+        if client.is_disabled() {
+            return Err(Error::protocol(KrbErrorCode::KdcErrClientRevoked, "Client account is disabled"))
+        }
+
+        let sname = body.sname.as_ref()
+            .ok_or_else(|| Error::protocol(KrbErrorCode::KdcErrSPrincipalUnknown, "No service name"))?;
+        let service = self.db.service(&self.realm, sname)
+            .ok_or_else(|| Error::protocol(KrbErrorCode::KdcErrSPrincipalUnknown, "Server not found in Kerberos database"))?;
+
+        let mut flags = TicketFlags::new(&[TicketFlagsType::Initial]);
+        let reply_key = match find_padata(request, PaDataType::PaEncTimestamp) {
+            Some(padata) => {
+                flags.insert(TicketFlagsType::PreAuthent);
+                self.preauth(client, padata)?
+            },
+            None => {
+                if client.is_preauth_required() {
+                    return Err(Error::protocol(KrbErrorCode::KdcErrPreauthRequired, "Additional pre-authentication required"))
+                }
+                client.negotiate(&body.etype)
+                    .ok_or_else(|| Error::protocol(KrbErrorCode::KdcErrEtypeNoSupp, "No key for the requested encryption types"))?
+            }
+        };
+
+        // a ticket can't end before it starts
+        let till = body.till.inner.inner;
+        if till <= now {
+            return Err(Error::protocol(KrbErrorCode::KdcErrNeverValid, "Requested end time is in the past"))
+        }
+
+        let options = body.kdc_options.inner;
+        if client.is_delegable() && options.contains(KdcOptionsType::Forwardable) {
+            flags.insert(TicketFlagsType::Forwardable);
+        }
+        let renew_till = if options.contains(KdcOptionsType::Renewable) {
+            flags.insert(TicketFlagsType::Renewable);
+            Some(Tag::new(KerberosTime::new(now + Duration::days(7))))
+        } else {
+            None
+        };
+
+        let service_key = self.service_key(service, &body.etype)?;
+        let include_pac = match find_padata(request, PaDataType::PaPacRequest) {
+            Some(padata) => {
+                let mut pac_request = KerbPaPacRequest::default();
+                from_der(&mut pac_request, &padata.padata_value)?;
+                pac_request.include_pac.inner
+            },
+            None => true
+        };
+
+        let authorization_data = if include_pac {
+            Some(Tag::new(self.pac(client, now, service_key)?.to_authorization_data()?))
+        } else {
+            None
+        };
+
+        let enc_ticket = EncTicketPartBody {
+            flags: Tag::new(flags),
+            key: Tag::new(self.session_key(&body.etype)?),
+            crealm: Tag::new(GeneralString::from_str(&self.realm)?),
+            cname: Tag::new(PrincipalName::new(
+                PrincipalNameType::NtPrincipal,
+                vec![GeneralString::from_str(&client.name)?]
+            )),
+            transited: Tag::new(TransitedEncoding {
+                tr_type: Tag::new(TransitedType::DomainX500Compress as Integer),
+                contents: Tag::new(vec![])
+            }),
+            authtime: Tag::new(KerberosTime::new(now)),
+            starttime: Some(Tag::new(KerberosTime::new(now))),
+            endtime: Tag::new(KerberosTime::new(now + self.lifetime.min(till - now))),
+            renew_till,
+            caddr: None,
+            authorization_data
+        };
+
+        let cname = enc_ticket.cname.inner.clone();
+        let (ticket, enc_part) = self.issue(enc_ticket, sname, service_key, body.nonce.inner)?;
+
+        Ok(AsRep {
+            inner: KdcRep {
+                pvno: Tag::new(5),
+                msg_type: Tag::new(MessageType::KrbAsRep as Integer),
+                padata: Some(Tag::new(vec![
+                    PaData::new(PaDataType::PaEtypeInfo2, &client.etype_info2(&self.realm)?)?
+                ])),
+                crealm: Tag::new(GeneralString::from_str(&self.realm)?),
+                cname: Tag::new(cname),
+                ticket: Tag::new(ticket),
+                enc_part: Tag::new(reply_key.encrypt(
+                    KeyUsage::KeyUsageAsRepEncPart1,
+                    &EncASRepPart { inner: enc_part }
+                )?)
+            }
+        })
+    }
+
+    /// Ticket granting service exchange
+    ///
+    /// Handle S4U2Self (PA-FOR-USER) and S4U2Proxy (cname-in-addl-tkt)
+    /// and enforce the delegation policy of the principals
+    pub fn tgs_exchange(&self, request: &TgsReq) -> KerlabResult<TgsRep> {
+        let body = &request.req_body;
+        let now = Utc::now();
+
+        let krbtgt = self.krbtgt()?;
+        let (ap_req, tgt) = self.tgt(request)?;
+
+        let authenticator = tgt.key.decrypt::<Authenticator>(KeyUsage::KrbKeyUsageTgsReqPaAuthenticator, &ap_req.authenticator)
+            .map_err(|_| Error::protocol(KrbErrorCode::KrbApErrBadIntegrity, "Unable to decrypt authenticator"))?.inner;
+
+        if authenticator.cname.inner != tgt.cname.inner || authenticator.crealm.inner != tgt.crealm.inner {
+            return Err(Error::protocol(KrbErrorCode::KrbApErrBadMatch, "Ticket and authenticator don't match"))
+        }
+
+        if (now - authenticator.ctime.inner.inner).num_seconds().abs() > self.max_skew.num_seconds() {
+            return Err(Error::protocol(KrbErrorCode::KrbApErrSkew, "Clock skew too great"))
+        }
+
+        // the authenticator binds the TGT to this request body
+        let cksum = authenticator.cksum.as_ref()
+            .ok_or_else(|| Error::protocol(KrbErrorCode::KrbApErrInappCksum, "Authenticator without request checksum"))?;
+        // an unkeyed checksum can be computed again for a modified body
+        if !cksum.is_keyed() {
+            return Err(Error::protocol(KrbErrorCode::KrbApErrInappCksum, "Unkeyed checksum is not allowed"))
+        }
+        cksum.verify(&tgt.key, KeyUsage::KrbKeyUsageTgsReqPaAuthenticatorCksum, &to_der(&body.inner)?)?;
+
+        let options = body.kdc_options.inner;
+        let tgt_flags = tgt.flags.inner;
+
+        // a renewed ticket can be expired but not after its renew time
+        let renew_till = if options.contains(KdcOptionsType::Renew) {
+            let renew_till = tgt.renew_till.as_ref()
+                .filter(|_| tgt_flags.contains(TicketFlagsType::Renewable))
+                .ok_or_else(|| Error::protocol(KrbErrorCode::KdcErrBadOption, "TGT is not renewable"))?;
+            Some(renew_till.inner.inner)
+        } else {
+            None
+        };
+
+        if renew_till.unwrap_or(tgt.endtime.inner.inner) + self.max_skew < now {
+            return Err(Error::protocol(KrbErrorCode::KrbApErrTktExpired, "Ticket expired"))
+        }
+
+        // client account could be disabled since the TGT was issued
+        let requester = tgt.cname.name_string.first().and_then(|name| self.db.client(name.as_str()));
+        if requester.is_some_and(|requester| requester.is_disabled()) {
+            return Err(Error::protocol(KrbErrorCode::KdcErrClientRevoked, "Client account is disabled"))
+        }
+
+        let sname = body.sname.as_ref()
+            .ok_or_else(|| Error::protocol(KrbErrorCode::KdcErrSPrincipalUnknown, "No service name"))?;
+        let service = self.db.service(&self.realm, sname)
+            .ok_or_else(|| Error::protocol(KrbErrorCode::KdcErrSPrincipalUnknown, "Server not found in Kerberos database"))?;
+        let service_key = self.service_key(service, &body.etype)?;
+
+        if options.contains(KdcOptionsType::Forwarded) && !tgt_flags.contains(TicketFlagsType::Forwardable) {
+            return Err(Error::protocol(KrbErrorCode::KdcErrBadOption, "TGT is not forwardable"))
+        }
+
+        let impersonation = if let Some(padata) = find_padata(request, PaDataType::PaForUser) {
+            self.s4u2self(&tgt, requester, service, padata, service_key, now)?
+        } else if options.contains(KdcOptionsType::CNameInAddLTkt) {
+            self.s4u2proxy(body, requester, service, sname)?
+        } else if renew_till.is_some() {
+            // renewal keeps the flags and the authentication time of the TGT
+            Impersonation {
+                crealm: tgt.crealm.inner.clone(),
+                cname: tgt.cname.inner.clone(),
+                authtime: tgt.authtime.inner.inner,
+                flags: tgt_flags,
+                pac: find_pac(&tgt)?
+            }
+        } else {
+            let client = tgt.cname.name_string.first().and_then(|name| self.db.client(name.as_str()));
+            let mut flags = tgt_flags & TicketFlags::new(&[TicketFlagsType::PreAuthent]);
+            if client.is_none_or(|client| client.is_delegable()) {
+                if tgt_flags.contains(TicketFlagsType::Forwardable) && options.contains(KdcOptionsType::Forwardable) {
+                    flags.insert(TicketFlagsType::Forwardable);
+                }
+                if tgt_flags.contains(TicketFlagsType::Forwardable) && options.contains(KdcOptionsType::Forwarded) {
+                    flags.insert(TicketFlagsType::Forwarded);
+                }
+            }
+            Impersonation {
+                crealm: tgt.crealm.inner.clone(),
+                cname: tgt.cname.inner.clone(),
+                authtime: tgt.authtime.inner.inner,
+                flags,
+                pac: find_pac(&tgt)?
+            }
+        };
+
+        // PAC is signed again with the key of the service
+        let authorization_data = match impersonation.pac {
+            Some(pac) => {
+                let mut pac = PacType::new(pac.buffers);
+                pac.sign(service_key, self.kdc_key(krbtgt)?)?;
+                Some(Tag::new(pac.to_authorization_data()?))
+            },
+            None => None
+        };
+
+        let mut flags = impersonation.flags;
+        if tgt_flags.contains(TicketFlagsType::Renewable) && options.contains(KdcOptionsType::Renewable) {
+            flags.insert(TicketFlagsType::Renewable);
+        }
+        let endtime = (now + self.lifetime).min(renew_till.unwrap_or(tgt.endtime.inner.inner));
+        let enc_ticket = EncTicketPartBody {
+            flags: Tag::new(flags),
+            key: Tag::new(self.session_key(&body.etype)?),
+            crealm: Tag::new(impersonation.crealm.clone()),
+            cname: Tag::new(impersonation.cname.clone()),
+            transited: tgt.transited.clone(),
+            authtime: Tag::new(KerberosTime::new(impersonation.authtime)),
+            starttime: Some(Tag::new(KerberosTime::new(now))),
+            endtime: Tag::new(KerberosTime::new(endtime)),
+            renew_till: if flags.contains(TicketFlagsType::Renewable) { tgt.renew_till.clone() } else { None },
+            caddr: None,
+            authorization_data
+        };
+
+        let (ticket, enc_part) = self.issue(enc_ticket, sname, service_key, body.nonce.inner)?;
+
+        Ok(TgsRep {
+            inner: KdcRep {
+                pvno: Tag::new(5),
+                msg_type: Tag::new(MessageType::KrbTgsRep as Integer),
+                padata: None,
+                crealm: Tag::new(impersonation.crealm),
+                cname: Tag::new(impersonation.cname),
+                ticket: Tag::new(ticket),
+                enc_part: Tag::new(match &authenticator.subkey {
+                    Some(subkey) => subkey.encrypt(KeyUsage::KeyUsageTgsRepEncPartSubkey, &EncTGSRepPart { inner: enc_part })?,
+                    None => tgt.key.encrypt(KeyUsage::KeyUsageAsRepEncPart, &EncTGSRepPart { inner: enc_part })?
+                })
+            }
+        })
+    }
+
+    /// Decrypt the TGT carried by the PA-TGS-REQ of a request
+    fn tgt(&self, request: &TgsReq) -> KerlabResult<(ApReq, EncTicketPartBody)> {
+        let padata = find_padata(request, PaDataType::PaTgsReq)
+            .ok_or_else(|| Error::protocol(KrbErrorCode::KdcErrPadataTypeNoSupp, "No PA-TGS-REQ"))?;
+        let mut ap_req = ApReq::default();
+        from_der(&mut ap_req, &padata.padata_value)?;
+
+        // only TGT of the realm are accepted
+        let krbtgt = self.krbtgt()?;
+        if !krbtgt.is_for(&self.realm, &ap_req.ticket.sname) {
+            return Err(Error::protocol(KrbErrorCode::KrbApErrNotUs, "Ticket is not a TGT of the realm"))
+        }
+
+        let tgt_key = krbtgt.key(ap_req.ticket.enc_part.etype.inner)
+            .ok_or_else(|| Error::protocol(KrbErrorCode::KrbApErrNoKey, "No krbtgt key for the ticket encryption type"))?;
+        let tgt = tgt_key.decrypt::<EncTicketPart>(KeyUsage::KeyUsageAsRepTicket, &ap_req.ticket.enc_part)
+            .map_err(|_| Error::protocol(KrbErrorCode::KrbApErrBadIntegrity, "Unable to decrypt TGT"))?.inner;
+        Ok((ap_req, tgt))
+    }
+
+    /// S4U2Self, a service asks a ticket for itself in place of a user
+    ///
+    /// The ticket is forwardable only if the service is trusted
+    /// to authenticate for delegation and the user can be delegated,
+    /// a ticket for another service is rejected with KDC_ERR_BADOPTION
+    /// @see [MS-SFU] 3.2.5.1 KDC Replies to Service's S4U2self Request
+    fn s4u2self(&self, tgt: &EncTicketPartBody, requester: Option<&Principal>, service: &Principal, padata: &PaData, service_key: &EncryptionKey, now: DateTime<Utc>) -> KerlabResult<Impersonation> {
+        let requester = requester
+            .ok_or_else(|| Error::protocol(KrbErrorCode::KdcErrCPrincipalUnknown, "Requester not found in Kerberos database"))?;
+
+        if !service.name.eq_ignore_ascii_case(&requester.name) {
+            return Err(Error::protocol(KrbErrorCode::KdcErrBadOption, "S4U2Self ticket must be for the requester"))
+        }
+
+        let mut for_user = PaForUser::default();
+        from_der(&mut for_user, &padata.padata_value)?;
+
+        // checksum is keyed with the session key of the TGT
+        let expected = PaForUser::new(for_user.user_name.inner.clone(), for_user.user_realm.inner.clone(), &tgt.key)?;
+        if expected.cksum != for_user.cksum {
+            return Err(Error::protocol(KrbErrorCode::KrbApErrModified, "Invalid PA-FOR-USER checksum"))
+        }
+
+        let user = for_user.user_name.name_string.first()
+            .and_then(|name| self.db.client(name.as_str()))
+            .ok_or_else(|| Error::protocol(KrbErrorCode::KdcErrCPrincipalUnknown, "Impersonated user not found in Kerberos database"))?;
+        if user.is_disabled() {
+            return Err(Error::protocol(KrbErrorCode::KdcErrClientRevoked, "Impersonated account is disabled"))
+        }
+
+        let mut flags = tgt.flags.inner & TicketFlags::new(&[TicketFlagsType::PreAuthent]);
+        if requester.is_trusted_to_auth_for_delegation() && user.is_delegable() {
+            flags.insert(TicketFlagsType::Forwardable);
+        }
+
+        Ok(Impersonation {
+            crealm: GeneralString::from_str(&self.realm)?,
+            cname: PrincipalName::new(PrincipalNameType::NtPrincipal, vec![GeneralString::from_str(&user.name)?]),
+            authtime: now,
+            flags,
+            pac: Some(self.pac(user, now, service_key)?)
+        })
+    }
+
+    /// S4U2Proxy, a service uses a ticket of a user to ask a ticket for another service
+    ///
+    /// Classic constrained delegation needs a forwardable evidence ticket,
+    /// resource based delegation is checked on the target service.
+    /// Sensitive users and members of Protected Users are rejected with KDC_ERR_POLICY,
+    /// services not allowed to delegate with KDC_ERR_BADOPTION
+    /// @see [MS-SFU] 3.2.5.2 KDC Replies to Service's S4U2proxy Request
+    fn s4u2proxy(&self, body: &KdcReqBody, requester: Option<&Principal>, service: &Principal, sname: &PrincipalName) -> KerlabResult<Impersonation> {
+        let requester = requester
+            .ok_or_else(|| Error::protocol(KrbErrorCode::KdcErrCPrincipalUnknown, "Requester not found in Kerberos database"))?;
+
+        let evidence = body.additional_tickets.as_ref()
+            .and_then(|tickets| tickets.first())
+            .ok_or_else(|| Error::protocol(KrbErrorCode::KdcErrBadOption, "No additional ticket"))?;
+
+        if !requester.is_for(&self.realm, &evidence.sname) {
+            return Err(Error::protocol(KrbErrorCode::KdcErrBadOption, "Additional ticket is not for the requester"))
+        }
+
+        let evidence_key = requester.key(evidence.enc_part.etype.inner)
+            .ok_or_else(|| Error::protocol(KrbErrorCode::KrbApErrNoKey, "No key for the additional ticket"))?;
+        let evidence = evidence_key.decrypt::<EncTicketPart>(KeyUsage::KeyUsageAsRepTicket, &evidence.enc_part)
+            .map_err(|_| Error::protocol(KrbErrorCode::KrbApErrBadIntegrity, "Unable to decrypt additional ticket"))?.inner;
+
+        let user = evidence.cname.name_string.first().and_then(|name| self.db.client(name.as_str()));
+        if !user.is_none_or(|user| user.is_delegable()) {
+            return Err(Error::protocol(KrbErrorCode::KdcErrPolicy, "User account can't be delegated"))
+        }
+
+        let evidence_flags = evidence.flags.inner;
+        if requester.can_delegate_to(sname) {
+            if !evidence_flags.contains(TicketFlagsType::Forwardable) {
+                return Err(Error::protocol(KrbErrorCode::KdcErrBadOption, "Additional ticket is not forwardable"))
+            }
+        } else if !service.accepts_delegation_from(&requester.name) {
+            return Err(Error::protocol(KrbErrorCode::KdcErrBadOption, "Requester is not allowed to delegate to the service"))
+        }
+
+        Ok(Impersonation {
+            crealm: evidence.crealm.inner.clone(),
+            cname: evidence.cname.inner.clone(),
+            authtime: evidence.authtime.inner.inner,
+            flags: TicketFlags::new(&[TicketFlagsType::Forwardable]) | evidence_flags & TicketFlags::new(&[TicketFlagsType::PreAuthent]),
+            pac: find_pac(&evidence)?
+        })
+    }
+
+    /// KRB-ERROR sent back to the client
+    ///
+    /// Pre authentication errors carry the accepted padata
+    /// and the salt of the client keys into e-data
+    fn error(&self, code: KrbErrorCode, message: &str, request: &KdcReq) -> KerlabResult<KrbError> {
+        let sname = match &request.req_body.sname {
+            Some(sname) => sname.inner.clone(),
+            None => krbtgt(&self.realm)?
+        };
+
+        let mut error = KrbError::new(code, GeneralString::from_str(&self.realm)?, sname);
+        error.e_text = Some(Tag::new(GeneralString::from_str(message)?));
+        if let Some(cname) = &request.req_body.cname {
+            error.crealm = Some(Tag::new(request.req_body.realm.inner.clone()));
+            error.cname = Some(Tag::new(cname.inner.clone()));
+        }
+
+        let client = request.req_body.cname.as_ref()
+            .and_then(|cname| cname.name_string.first())
+            .and_then(|name| self.db.client(name.as_str()));
+
+        if let (KrbErrorCode::KdcErrPreauthRequired, Some(client)) | (KrbErrorCode::KdcErrPreauthFailed, Some(client)) = (code, client) {
+            let method_data = vec![
+                PaData::new(PaDataType::PaEtypeInfo2, &client.etype_info2(&self.realm)?)?,
+                PaData::new(PaDataType::PaEncTimestamp, &Vec::<u8>::new())?
+            ];
+            error.e_data = Some(Tag::new(to_der(&method_data)?));
+        }
+        Ok(error)
+    }
+
+    /// Answer a Kerberos message without framing
+    ///
+    /// Protocol errors are sent back as KRB-ERROR,
+    /// an error is returned only if the request can't be parsed.
+    /// The client address is reported into security events
+    pub fn handle(&self, request: &[u8], client: Option<SocketAddr>) -> KerlabResult<Vec<u8>> {
+        let mut as_req = AsReq::default();
+        if from_ber(&mut as_req, request).is_ok() {
+            let result = self.as_exchange(&as_req);
+            self.audit_as(&as_req, &result, client);
+            return match result {
+                Ok(reply) => to_der(&reply),
+                Err(e) => self.reply_error(e, &as_req)
+            }
+        }
+
+        let mut tgs_req = TgsReq::default();
+        from_ber(&mut tgs_req, request)?;
+        let result = self.tgs_exchange(&tgs_req);
+        self.audit_tgs(&tgs_req, &result, client);
+        match result {
+            Ok(reply) => to_der(&reply),
+            Err(e) => self.reply_error(e, &tgs_req)
+        }
+    }
+
+    /// 4768 for each AS exchange, 4771 when the pre authentication failed
+    /// Pre authentication required is not logged, as on a Windows DC
+    fn audit_as(&self, request: &AsReq, result: &KerlabResult<AsRep>, client: Option<SocketAddr>) {
+        let audit = match &self.audit {
+            Some(audit) => audit,
+            None => return
+        };
+
+        let body = &request.req_body;
+        let status = status(result);
+        let event_id = match status {
+            KrbErrorCode::KdcErrPreauthRequired => return,
+            KrbErrorCode::KdcErrPreauthFailed | KrbErrorCode::KrbApErrSkew => EventId::PreauthFailure,
+            _ => EventId::TgtRequest
+        };
+
+        let sname = body.sname.as_ref().map_or("-".to_string(), |sname| sname.name());
+        let mut event = SecurityEvent::new(
+            event_id,
+            &body.cname.as_ref().map_or("-".to_string(), |cname| cname.name()),
+            body.realm.as_str(),
+            &match event_id {
+                // 4768 reports the account of the service
+                EventId::TgtRequest => body.sname.as_ref()
+                    .and_then(|sname| self.db.service(&self.realm, sname))
+                    .map_or(sname, |service| service.name.clone()),
+                _ => sname
+            }
+        );
+        event.ticket_options = body.kdc_options.bits();
+        event.status = status as u32;
+        if event_id == EventId::TgtRequest {
+            event.ticket_encryption_type = Some(result.as_ref().map_or(NO_TICKET, |reply| reply.ticket.enc_part.etype.inner));
+        }
+        event.pre_auth_type = Some(
+            [PaDataType::PaEncTimestamp as Integer, PaDataType::PaPkAsReq as Integer, PaDataType::PaEncryptedChallenge as Integer].iter()
+                .cloned()
+                .find(|padata_type| request.padata.as_ref().is_some_and(|padata| padata.iter().any(|e| e.padata_type.inner == *padata_type)))
+                .unwrap_or(0)
+        );
+        event.client = client;
+        audit.emit(&event);
+    }
+
+    /// 4770 for a renewed TGT, 4769 for other TGS exchanges
+    fn audit_tgs(&self, request: &TgsReq, result: &KerlabResult<TgsRep>, client: Option<SocketAddr>) {
+        let audit = match &self.audit {
+            Some(audit) => audit,
+            None => return
+        };
+
+        let body = &request.req_body;
+        let options = body.kdc_options.inner;
+        let status = status(result);
+
+        // requester is the client of the TGT
+        let (target_user_name, target_domain_name) = match self.tgt(request) {
+            Ok((_, tgt)) => (format!("{}@{}", tgt.cname.name(), tgt.crealm.as_str()), tgt.crealm.as_str().to_string()),
+            Err(_) => (
+                body.cname.as_ref().map_or("-".to_string(), |cname| cname.name()),
+                body.realm.as_str().to_string()
+            )
+        };
+
+        let event_id = if options.contains(KdcOptionsType::Renew) && status == KrbErrorCode::KdcErrNone {
+            EventId::TicketRenewal
+        } else {
+            EventId::ServiceTicket
+        };
+
+        let service_name = body.sname.as_ref().map_or("-".to_string(), |sname| {
+            self.db.service(&self.realm, sname).map_or(sname.name(), |service| service.name.clone())
+        });
+
+        let mut event = SecurityEvent::new(event_id, &target_user_name, &target_domain_name, &service_name);
+        event.ticket_options = options.bits();
+        event.status = status as u32;
+        event.ticket_encryption_type = Some(result.as_ref().map_or(NO_TICKET, |reply| reply.ticket.enc_part.etype.inner));
+        event.client = client;
+        audit.emit(&event);
+    }
+
+    fn reply_error(&self, error: Error, request: &KdcReq) -> KerlabResult<Vec<u8>> {
+        let error = match error {
+            Error::Protocol(code, message) => self.error(code, &message, request)?,
+            e => self.error(KrbErrorCode::KrbErrGeneric, &format!("{:?}", e), request)?
+        };
+        to_der(&error)
+    }
+
+    /// Error sent without decoding the request
+    fn transport_error(&self, code: KrbErrorCode, text: &str) -> KerlabResult<Vec<u8>> {
+        let mut error = KrbError::new(code, GeneralString::from_str(&self.realm)?, krbtgt(&self.realm)?);
+        error.e_text = Some(Tag::new(GeneralString::from_str(text)?));
+        to_der(&error)
+    }
+
+    /// Answer a request sent over TCP
+    /// Messages are prefixed by their length
+    /// A request bigger than MAX_RECORD_SIZE is not read
+    /// and is answered by KRB_ERR_FIELD_TOOLONG
+    /// Return the reply sent to the client
+    pub fn handle_tcp(&self, stream: &mut TcpStream) -> KerlabResult<Vec<u8>> {
+        let mut length = [0; 4];
+        stream.read_exact(&mut length)?;
+        let length = u32::from_be_bytes(length) as usize;
+
+        let reply = if length > MAX_RECORD_SIZE {
+            self.transport_error(KrbErrorCode::KrbErrFieldTooLong, "Request is too long")?
+        } else {
+            let mut request = vec![0; length];
+            stream.read_exact(&mut request)?;
+            self.handle(&request, stream.peer_addr().ok())?
+        };
+        stream.write_all(&(reply.len() as u32).to_be_bytes())?;
+        stream.write_all(&reply)?;
+        Ok(reply)
+    }
+
+    /// Answer a request sent over UDP
+    /// Messages are not framed, but the framing of TCP is tolerated
+    /// A reply bigger than the datagram size is replaced by KRB_ERR_RESPONSE_TOO_BIG
+    /// @see https://tools.ietf.org/html/rfc4120#section-7.2.1
+    /// Return the reply sent and the address of the client
+    pub fn handle_udp(&self, socket: &UdpSocket) -> KerlabResult<(Vec<u8>, SocketAddr)> {
+        let mut datagram = vec![0; 65535];
+        let (size, peer) = socket.recv_from(&mut datagram)?;
+        datagram.truncate(size);
+
+        let framed = size > 4 && u32::from_be_bytes([datagram[0], datagram[1], datagram[2], datagram[3]]) as usize == size - 4;
+        let mut reply = self.handle(if framed { &datagram[4..] } else { &datagram }, Some(peer))?;
+        if reply.len() > self.max_datagram_size {
+            reply = self.transport_error(KrbErrorCode::KrbErrResponseTooBig, "Response is too big for UDP")?;
+        }
+
+        let mut payload = vec![];
+        if framed {
+            payload.extend_from_slice(&(reply.len() as u32).to_be_bytes());
+        }
+        payload.extend_from_slice(&reply);
+        socket.send_to(&payload, peer)?;
+        Ok((reply, peer))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use request::{TcpRequest, KrbResponse};
+    use checksum::{Checksum, crc32};
+    use encryption::ChecksumType;
+    use asn1::SInteger;
+
+    const DATABASE: &str = "
+        # test realm
+        krbtgt   rid=502 password=krbtgt
+        alice    rid=1104 password=alice
+        bob      rid=1105 password=bob flags=DONT_REQ_PREAUTH
+        carol    rid=1106 password=carol flags=DISABLED
+        svc_sql  rid=1107 password=svc spn=MSSQLSvc/sql.lab.local:1433
+        svc_web  rid=1108 password=web flags=TRUSTED_TO_AUTH_FOR_DELEGATION delegate=MSSQLSvc/sql.lab.local:1433
+        svc_app  rid=1109 password=app
+        ws01$    rid=1110 password=ws01 spn=cifs/ws01.lab.local rbcd=svc_app
+        dave     rid=1111 password=dave flags=NOT_DELEGATED
+        erin     rid=1112 password=erin groups=513,525
+    ";
+
+    fn kdc() -> Kdc {
+        Kdc::new(
+            "lab.local",
+            Sid::from_str("S-1-5-21-1111-2222-3333").unwrap(),
+            PrincipalDb::from("LAB.LOCAL", DATABASE).unwrap()
+        )
+    }
+
+    fn error_code(result: KerlabResult<AsRep>) -> KrbErrorCode {
+        match result {
+            Err(Error::Protocol(code, _)) => code,
+            _ => panic!("KDC must reply with an error")
+        }
+    }
+
+    /// AS exchange errors of the KDC
+    #[test]
+    fn test_as_errors() {
+        let kdc = kdc();
+        let rc4 = |password| EncryptionKey::new_rc4_hmac(password).unwrap();
+
+        assert_eq!(error_code(kdc.as_exchange(&AsReq::new("LAB.LOCAL", "nobody", &[]).unwrap())), KrbErrorCode::KdcErrCPrincipalUnknown);
+        assert_eq!(error_code(kdc.as_exchange(&AsReq::new("LAB.LOCAL", "carol", &[]).unwrap())), KrbErrorCode::KdcErrClientRevoked);
+        assert_eq!(error_code(kdc.as_exchange(&AsReq::new("LAB.LOCAL", "alice", &[]).unwrap())), KrbErrorCode::KdcErrPreauthRequired);
+        assert_eq!(
+            error_code(kdc.as_exchange(&AsReq::new("LAB.LOCAL", "alice", &[]).unwrap().with_preauth(&rc4("wrong")).unwrap())),
+            KrbErrorCode::KdcErrPreauthFailed
+        );
+
+        // pre authentication required carries the salt of the client
+        let mut error = KrbError::default();
+        from_der(&mut error, &kdc.handle(&to_der(&AsReq::new("LAB.LOCAL", "alice", &[]).unwrap()).unwrap(), None).unwrap()).unwrap();
+        let mut method_data = Vec::<PaData>::default();
+        from_der(&mut method_data, error.e_data.as_ref().unwrap()).unwrap();
+        let mut etype_info2 = EtypeInfo2::default();
+        from_der(&mut etype_info2, &method_data[0].padata_value).unwrap();
+        assert_eq!(etype_info2[0].salt.as_ref().unwrap().as_str(), "LAB.LOCALalice");
+
+        // clock skew
+        let key = rc4("alice");
+        let mut request = AsReq::new("LAB.LOCAL", "alice", &[]).unwrap();
+        request.padata = Some(Tag::new(vec![PaData::new(
+            PaDataType::PaEncTimestamp,
+            &key.encrypt(KeyUsage::KeyUsageAsReqTimestamp, &PaEncTsEnc {
+                patimestamp: Tag::new(KerberosTime::new(Utc::now() - Duration::hours(1))),
+                pausec: None
+            }).unwrap()
+        ).unwrap()]));
+        assert_eq!(error_code(kdc.as_exchange(&request)), KrbErrorCode::KrbApErrSkew);
+
+        // ticket ending before its start
+        let mut request = AsReq::new("LAB.LOCAL", "bob", &[]).unwrap();
+        request.req_body.till.inner = KerberosTime::new(Utc::now() - Duration::hours(1));
+        assert_eq!(error_code(kdc.as_exchange(&request)), KrbErrorCode::KdcErrNeverValid);
+
+        // AS-REP roasting
+        let reply = kdc.as_exchange(&AsReq::new("LAB.LOCAL", "bob", &[]).unwrap()).unwrap();
+        rc4("bob").decrypt::<EncASRepPart>(KeyUsage::KeyUsageAsRepEncPart1, &reply.enc_part).unwrap();
+
+        // PAC can't be signed without a key for krbtgt
+        let kdc = Kdc::new(
+            "lab.local",
+            Sid::from_str("S-1-5-21-1111-2222-3333").unwrap(),
+            PrincipalDb::from("LAB.LOCAL", "krbtgt rid=502\nbob rid=1105 password=bob flags=DONT_REQ_PREAUTH\nsvc_sql rid=1107 password=svc spn=MSSQLSvc/sql.lab.local:1433").unwrap()
+        );
+        let mut request = AsReq::new("LAB.LOCAL", "bob", &[]).unwrap();
+        request.req_body.sname = Some(Tag::new(principal(&["MSSQLSvc", "sql.lab.local:1433"])));
+        assert_eq!(error_code(kdc.as_exchange(&request)), KrbErrorCode::KdcErrNullKey);
+    }
+
+    /// AS then TGS exchanges over TCP
+    #[test]
+    fn test_as_and_tgs_over_tcp() {
+        let kdc = kdc();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            for _ in 0..2 {
+                let (mut stream, _) = listener.accept().unwrap();
+                kdc.handle_tcp(&mut stream).unwrap();
+            }
+        });
+
+        let key = EncryptionKey::new_rc4_hmac("alice").unwrap();
+        let request = AsReq::new("LAB.LOCAL", "alice", &[KdcOptionsType::Forwardable]).unwrap()
+            .with_preauth(&key).unwrap();
+        let as_rep = match TcpRequest::ask_for::<AsRep, _>(&request, address).unwrap() {
+            KrbResponse::Response(response) => response,
+            KrbResponse::Error(_) => panic!("AS exchange must succeed")
+        };
+        let enc_part = key.decrypt::<EncASRepPart>(KeyUsage::KeyUsageAsRepEncPart1, &as_rep.enc_part).unwrap();
+        assert_eq!(enc_part.flags.flags(), [TicketFlagsType::Forwardable, TicketFlagsType::Initial, TicketFlagsType::PreAuthent]);
+
+        let sname = PrincipalName::new(PrincipalNameType::NtSrvInst, vec![
+            GeneralString::from_str("MSSQLSvc").unwrap(),
+            GeneralString::from_str("sql.lab.local:1433").unwrap()
+        ]);
+        let request = TgsReq::new("LAB.LOCAL", "alice", sname, &[]).unwrap()
+            .with_authenticator(
+                as_rep.ticket.inner.clone(),
+                &enc_part.key,
+                Authenticator::new(as_rep.crealm.inner.clone(), as_rep.cname.inner.clone())
+            ).unwrap();
+        let tgs_rep = match TcpRequest::ask_for::<TgsRep, _>(&request, address).unwrap() {
+            KrbResponse::Response(response) => response,
+            KrbResponse::Error(_) => panic!("TGS exchange must succeed")
+        };
+        server.join().unwrap();
+
+        // service ticket is encrypted with the RC4 key of the service and carry the PAC
+        let service_key = EncryptionKey::new_rc4_hmac("svc").unwrap();
+        let ticket = service_key.decrypt::<EncTicketPart>(KeyUsage::KeyUsageAsRepTicket, &tgs_rep.ticket.enc_part).unwrap();
+        assert_eq!(ticket.cname.name(), "alice");
+        assert!(find_pac(&ticket).unwrap().is_some());
+    }
+
+    /// A record bigger than MAX_RECORD_SIZE is refused without being read
+    #[test]
+    fn test_tcp_record_too_long() {
+        let kdc = kdc();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            kdc.handle_tcp(&mut stream).unwrap();
+        });
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(&u32::MAX.to_be_bytes()).unwrap();
+        let mut length = [0; 4];
+        stream.read_exact(&mut length).unwrap();
+        let mut reply = vec![0; u32::from_be_bytes(length) as usize];
+        stream.read_exact(&mut reply).unwrap();
+        server.join().unwrap();
+
+        let mut error = KrbError::default();
+        from_der(&mut error, &reply).unwrap();
+        assert!(error.is(KrbErrorCode::KrbErrFieldTooLong));
+    }
+
+    /// A reply bigger than a datagram asks the client to retry over TCP
+    #[test]
+    fn test_udp_response_too_big() {
+        let kdc = kdc().with_max_datagram_size(100);
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.send_to(&to_der(&AsReq::new("LAB.LOCAL", "bob", &[]).unwrap()).unwrap(), server.local_addr().unwrap()).unwrap();
+        kdc.handle_udp(&server).unwrap();
+
+        let mut reply = vec![0; 65535];
+        let size = client.recv(&mut reply).unwrap();
+        let mut error = KrbError::default();
+        from_der(&mut error, &reply[..size]).unwrap();
+        assert!(error.is(KrbErrorCode::KrbErrResponseTooBig));
+    }
+
+    fn principal(components: &[&str]) -> PrincipalName {
+        PrincipalName::new(
+            if components.len() == 1 { PrincipalNameType::NtPrincipal } else { PrincipalNameType::NtSrvInst },
+            components.iter().map(|e| GeneralString::from_str(e).unwrap()).collect()
+        )
+    }
+
+    /// TGT and its session key
+    fn tgt(kdc: &Kdc, name: &str, password: &str) -> (Ticket, EncryptionKey) {
+        let key = EncryptionKey::new_rc4_hmac(password).unwrap();
+        let request = AsReq::new("LAB.LOCAL", name, &[KdcOptionsType::Forwardable]).unwrap()
+            .with_preauth(&key).unwrap();
+        let reply = kdc.as_exchange(&request).unwrap();
+        let enc_part = key.decrypt::<EncASRepPart>(KeyUsage::KeyUsageAsRepEncPart1, &reply.enc_part).unwrap();
+        (reply.ticket.inner.clone(), enc_part.key.inner.clone())
+    }
+
+    fn tgs_req(tgt: &(Ticket, EncryptionKey), name: &str, sname: PrincipalName, options: &[KdcOptionsType]) -> TgsReq {
+        sign(tgt, name, TgsReq::new("LAB.LOCAL", name, sname, options).unwrap())
+    }
+
+    /// Add the PA-TGS-REQ once the body is complete
+    fn sign(tgt: &(Ticket, EncryptionKey), name: &str, request: TgsReq) -> TgsReq {
+        let realm = GeneralString::from_str("LAB.LOCAL").unwrap();
+        request.with_authenticator(tgt.0.clone(), &tgt.1, Authenticator::new(realm, principal(&[name]))).unwrap()
+    }
+
+    /// S4U2Self then S4U2Proxy, return the S4U2Self ticket and the S4U2Proxy result
+    fn s4u(kdc: &Kdc, service: &str, password: &str, user: &str, target: PrincipalName) -> (EncTicketPartBody, KerlabResult<TgsRep>) {
+        let tgt = tgt(kdc, service, password);
+        let request = tgs_req(&tgt, service, principal(&[service]), &[KdcOptionsType::Forwardable])
+            .for_user(principal(&[user]), GeneralString::from_str("LAB.LOCAL").unwrap(), &tgt.1).unwrap();
+        let reply = kdc.tgs_exchange(&request).unwrap();
+        assert_eq!(reply.cname.name(), user);
+
+        let service_key = EncryptionKey::new_rc4_hmac(password).unwrap();
+        let evidence = service_key.decrypt::<EncTicketPart>(KeyUsage::KeyUsageAsRepTicket, &reply.ticket.enc_part).unwrap().inner;
+
+        let request = TgsReq::new("LAB.LOCAL", service, target, &[KdcOptionsType::Forwardable, KdcOptionsType::CNameInAddLTkt]).unwrap()
+            .with_additional_ticket(reply.ticket.inner.clone());
+        (evidence, kdc.tgs_exchange(&sign(&tgt, service, request)))
+    }
+
+    fn tgs_error(result: KerlabResult<TgsRep>) -> KrbErrorCode {
+        match result {
+            Err(Error::Protocol(code, _)) => code,
+            _ => panic!("KDC must reply with an error")
+        }
+    }
+
+    /// Constrained and resource based delegation policy
+    #[test]
+    fn test_s4u_delegation() {
+        let kdc = kdc();
+        let sql = principal(&["MSSQLSvc", "sql.lab.local:1433"]);
+        let cifs = principal(&["cifs", "ws01.lab.local"]);
+
+        // protocol transition and constrained delegation
+        let (evidence, result) = s4u(&kdc, "svc_web", "web", "alice", sql.clone());
+        assert!(evidence.flags.contains(TicketFlagsType::Forwardable));
+        let reply = result.unwrap();
+        let ticket = EncryptionKey::new_rc4_hmac("svc").unwrap()
+            .decrypt::<EncTicketPart>(KeyUsage::KeyUsageAsRepTicket, &reply.ticket.enc_part).unwrap();
+        assert_eq!(ticket.cname.name(), "alice");
+        assert!(find_pac(&ticket).unwrap().is_some());
+
+        // service not into the allow-list
+        assert_eq!(tgs_error(s4u(&kdc, "svc_web", "web", "alice", cifs.clone()).1), KrbErrorCode::KdcErrBadOption);
+
+        // resource based delegation accepts a non forwardable evidence ticket
+        let (evidence, result) = s4u(&kdc, "svc_app", "app", "alice", cifs.clone());
+        assert!(!evidence.flags.contains(TicketFlagsType::Forwardable));
+        assert_eq!(result.unwrap().cname.name(), "alice");
+        assert_eq!(tgs_error(s4u(&kdc, "svc_app", "app", "alice", sql).1), KrbErrorCode::KdcErrBadOption);
+
+        // sensitive account and protected users
+        let (evidence, result) = s4u(&kdc, "svc_app", "app", "dave", cifs.clone());
+        assert!(!evidence.flags.contains(TicketFlagsType::Forwardable));
+        assert_eq!(tgs_error(result), KrbErrorCode::KdcErrPolicy);
+        assert_eq!(tgs_error(s4u(&kdc, "svc_app", "app", "erin", cifs).1), KrbErrorCode::KdcErrPolicy);
+
+        // S4U2Self is only for the requester itself
+        let web = tgt(&kdc, "svc_web", "web");
+        let request = tgs_req(&web, "svc_web", principal(&["MSSQLSvc", "sql.lab.local:1433"]), &[KdcOptionsType::Forwardable])
+            .for_user(principal(&["alice"]), GeneralString::from_str("LAB.LOCAL").unwrap(), &web.1).unwrap();
+        assert_eq!(tgs_error(kdc.tgs_exchange(&request)), KrbErrorCode::KdcErrBadOption);
+
+        // forwarded option needs a forwardable TGT
+        let request = tgs_req(&tgt(&kdc, "dave", "dave"), "dave", principal(&["krbtgt", "LAB.LOCAL"]), &[KdcOptionsType::Forwarded]);
+        assert_eq!(tgs_error(kdc.tgs_exchange(&request)), KrbErrorCode::KdcErrBadOption);
+    }
+
+    /// Event id, status, service name and ticket encryption type
+    type Event = (EventId, u32, String, Option<u32>);
+
+    /// Keep the events emitted by the KDC
+    struct Events(Arc<Mutex<Vec<Event>>>);
+
+    impl AuditSink for Events {
+        fn emit(&self, event: &SecurityEvent) {
+            self.0.lock().unwrap().push((event.event_id, event.status, event.service_name.clone(), event.ticket_encryption_type));
+        }
+    }
+
+    /// Security events of AS and TGS exchanges, and TGT renewal
+    #[test]
+    fn test_audit_and_renew() {
+        let events = Arc::new(Mutex::new(vec![]));
+        let kdc = kdc().with_audit(Box::new(Events(events.clone())));
+        let peer = Some("127.0.0.1:49152".parse().unwrap());
+        let key = EncryptionKey::new_rc4_hmac("alice").unwrap();
+
+        // pre authentication required is not logged
+        kdc.handle(&to_der(&AsReq::new("LAB.LOCAL", "alice", &[]).unwrap()).unwrap(), peer).unwrap();
+        kdc.handle(&to_der(&AsReq::new("LAB.LOCAL", "alice", &[]).unwrap()
+            .with_preauth(&EncryptionKey::new_rc4_hmac("wrong").unwrap()).unwrap()).unwrap(), peer).unwrap();
+
+        let request = AsReq::new("LAB.LOCAL", "alice", &[KdcOptionsType::Renewable]).unwrap()
+            .with_preauth(&key).unwrap();
+        let mut as_rep = AsRep::default();
+        from_der(&mut as_rep, &kdc.handle(&to_der(&request).unwrap(), peer).unwrap()).unwrap();
+        let enc_part = key.decrypt::<EncASRepPart>(KeyUsage::KeyUsageAsRepEncPart1, &as_rep.enc_part).unwrap();
+        let renewable = (as_rep.ticket.inner.clone(), enc_part.key.inner.clone());
+
+        let request = tgs_req(&renewable, "alice", principal(&["MSSQLSvc", "sql.lab.local:1433"]), &[]);
+        kdc.handle(&to_der(&request).unwrap(), peer).unwrap();
+
+        // renewal keeps the renew time of the TGT
+        let request = tgs_req(&renewable, "alice", principal(&["krbtgt", "LAB.LOCAL"]), &[KdcOptionsType::Renew]);
+        let reply = kdc.tgs_exchange(&request).unwrap();
+        let renewed = renewable.1.decrypt::<EncTGSRepPart>(KeyUsage::KeyUsageAsRepEncPart, &reply.enc_part).unwrap();
+        assert!(renewed.renew_till.as_ref().unwrap().inner == enc_part.renew_till.as_ref().unwrap().inner);
+        kdc.handle(&to_der(&request).unwrap(), peer).unwrap();
+
+        let request = tgs_req(&tgt(&kdc, "dave", "dave"), "dave", principal(&["krbtgt", "LAB.LOCAL"]), &[KdcOptionsType::Renew]);
+        assert_eq!(tgs_error(kdc.tgs_exchange(&request)), KrbErrorCode::KdcErrBadOption);
+        kdc.handle(&to_der(&request).unwrap(), peer).unwrap();
+
+        assert_eq!(*events.lock().unwrap(), vec![
+            (EventId::PreauthFailure, 0x18, "krbtgt/LAB.LOCAL".to_string(), None),
+            (EventId::TgtRequest, 0, "krbtgt".to_string(), Some(EType::Rc4Hmac as u32)),
+            (EventId::ServiceTicket, 0, "svc_sql".to_string(), Some(EType::Rc4Hmac as u32)),
+            (EventId::TicketRenewal, 0, "krbtgt".to_string(), Some(EType::Rc4Hmac as u32)),
+            (EventId::ServiceTicket, 0xd, "krbtgt".to_string(), Some(NO_TICKET))
+        ]);
+    }
+
+    /// The authenticator binds the TGT to the request body
+    #[test]
+    fn test_tgs_checksum_and_subkey() {
+        let kdc = kdc();
+        let tgt = tgt(&kdc, "alice", "alice");
+        let sname = principal(&["MSSQLSvc", "sql.lab.local:1433"]);
+
+        let subkey = EncryptionKey::random(EType::Aes256CtsHmacSha196 as Integer).unwrap();
+        let authenticator = Authenticator::new(GeneralString::from_str("LAB.LOCAL").unwrap(), principal(&["alice"]))
+            .with_subkey(subkey.clone());
+        let request = TgsReq::new("LAB.LOCAL", "alice", sname.clone(), &[]).unwrap()
+            .with_authenticator(tgt.0.clone(), &tgt.1, authenticator).unwrap();
+        let reply = kdc.tgs_exchange(&request).unwrap();
+        assert!(reply.decrypt(&tgt.1, Some(&subkey)).is_ok());
+        assert!(reply.decrypt(&tgt.1, None).is_err());
+
+        // body changed after the authenticator
+        let mut request = tgs_req(&tgt, "alice", sname.clone(), &[]);
+        request.req_body.till.inner = KerberosTime::new(Utc::now() + Duration::days(30));
+        assert_eq!(tgs_error(kdc.tgs_exchange(&request)), KrbErrorCode::KrbApErrModified);
+
+        // authenticator without checksum
+        let authenticator = tgt.1.encrypt(
+            KeyUsage::KrbKeyUsageTgsReqPaAuthenticator,
+            &Authenticator::new(GeneralString::from_str("LAB.LOCAL").unwrap(), principal(&["alice"]))
+        ).unwrap();
+        let mut request = TgsReq::new("LAB.LOCAL", "alice", sname.clone(), &[]).unwrap();
+        request.padata = Some(Tag::new(vec![PaData::new(PaDataType::PaTgsReq, &ApReq::new(tgt.0.clone(), authenticator)).unwrap()]));
+        assert_eq!(tgs_error(kdc.tgs_exchange(&request)), KrbErrorCode::KrbApErrInappCksum);
+
+        // unkeyed checksums of the body
+        for cksum in [Checksum::rsa_md5(b"body"), Checksum::new(ChecksumType::Crc32 as SInteger, crc32(b"body"))].iter() {
+            let mut request = tgs_req(&tgt, "alice", sname.clone(), &[]);
+            let mut authenticator = Authenticator::new(GeneralString::from_str("LAB.LOCAL").unwrap(), principal(&["alice"]));
+            authenticator.cksum = Some(Tag::new(cksum.clone()));
+            let authenticator = tgt.1.encrypt(KeyUsage::KrbKeyUsageTgsReqPaAuthenticator, &authenticator).unwrap();
+            request.padata = Some(Tag::new(vec![PaData::new(PaDataType::PaTgsReq, &ApReq::new(tgt.0.clone(), authenticator)).unwrap()]));
+            assert_eq!(tgs_error(kdc.tgs_exchange(&request)), KrbErrorCode::KrbApErrInappCksum);
+        }
+    }
+}
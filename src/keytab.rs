@@ -0,0 +1,154 @@
+use std::io::{Cursor, Read};
+use std::fs;
+use byteorder::{BigEndian, ReadBytesExt};
+use error::{KerlabResult, Error, KerlabErrorKind};
+use encryption::EncryptionKey;
+use base::PrincipalName;
+use asn1::Integer;
+
+/// Read a counted octet string used by keytab format
+fn read_counted_string(cursor: &mut Cursor<&[u8]>) -> KerlabResult<String> {
+    let length = cursor.read_u16::<BigEndian>()?;
+    let mut buffer = vec![0; length as usize];
+    cursor.read_exact(&mut buffer)?;
+    String::from_utf8(buffer)
+        .map_err(|_| Error::new(KerlabErrorKind::Parsing, "keytab string is not utf8"))
+}
+
+/// A key of a service principal
+/// as stored in the MIT keytab file format
+///
+/// @see https://web.mit.edu/kerberos/krb5-devel/doc/formats/keytab_file_format.html
+pub struct KeytabEntry {
+    pub realm: String,
+    pub components: Vec<String>,
+    pub name_type: u32,
+    pub timestamp: u32,
+    pub kvno: u32,
+    pub key: EncryptionKey
+}
+
+impl KeytabEntry {
+    fn from(buf: &[u8], version: u16) -> KerlabResult<Self> {
+        let mut cursor = Cursor::new(buf);
+
+        let mut component_count = cursor.read_u16::<BigEndian>()?;
+        // version 1 count the realm as a component
+        if version == 0x0501 {
+            component_count = component_count.checked_sub(1)
+                .ok_or_else(|| Error::new(KerlabErrorKind::Parsing, "Keytab entry has no realm"))?;
+        }
+
+        let realm = read_counted_string(&mut cursor)?;
+        let mut components = vec![];
+        for _ in 0..component_count {
+            components.push(read_counted_string(&mut cursor)?);
+        }
+
+        let name_type = if version == 0x0501 { 1 } else { cursor.read_u32::<BigEndian>()? };
+        let timestamp = cursor.read_u32::<BigEndian>()?;
+        let mut kvno = cursor.read_u8()? as u32;
+
+        let keytype = cursor.read_u16::<BigEndian>()?;
+        let key_length = cursor.read_u16::<BigEndian>()?;
+        let mut keyvalue = vec![0; key_length as usize];
+        cursor.read_exact(&mut keyvalue)?;
+
+        // 32 bits kvno overrides the 8 bits one when present
+        if buf.len() as u64 - cursor.position() >= 4 {
+            kvno = cursor.read_u32::<BigEndian>()?;
+        }
+
+        let mut key = EncryptionKey::default();
+        key.keytype.inner = keytype as Integer;
+        key.keyvalue.inner = keyvalue;
+
+        Ok(Self {
+            realm,
+            components,
+            name_type,
+            timestamp,
+            kvno,
+            key
+        })
+    }
+
+    /// Check if the entry match the principal name of a ticket
+    pub fn is_for(&self, sname: &PrincipalName) -> bool {
+        self.components.len() == sname.name_string.len()
+            && self.components.iter()
+            .zip(sname.name_string.iter())
+            .all(|(a, b)| a.eq_ignore_ascii_case(b.as_str()))
+    }
+}
+
+/// Keytab file
+/// Use to store long term keys of services
+pub struct Keytab {
+    pub entries: Vec<KeytabEntry>
+}
+
+impl Keytab {
+    /// Parse a keytab file content
+    pub fn from(buf: &[u8]) -> KerlabResult<Self> {
+        let mut cursor = Cursor::new(buf);
+        let version = cursor.read_u16::<BigEndian>()?;
+        if version != 0x0501 && version != 0x0502 {
+            return Err(Error::new(KerlabErrorKind::Parsing, &format!("Unsupported keytab version {:#X}", version)))
+        }
+
+        let mut entries = vec![];
+        while cursor.position() < buf.len() as u64 {
+            let size = cursor.read_i32::<BigEndian>()?;
+            let start = cursor.position() as usize;
+            let length = size.checked_abs()
+                .ok_or_else(|| Error::new(KerlabErrorKind::Parsing, "Invalid keytab entry size"))?;
+            let end = start + length as usize;
+            if end > buf.len() {
+                return Err(Error::new(KerlabErrorKind::Parsing, "Truncated keytab entry"))
+            }
+
+            // negative size is a deleted entry
+            if size > 0 {
+                entries.push(KeytabEntry::from(&buf[start..end], version)?);
+            }
+            cursor.set_position(end as u64);
+        }
+
+        Ok(Self {
+            entries
+        })
+    }
+
+    /// Load a keytab from disk
+    pub fn from_file(path: &str) -> KerlabResult<Self> {
+        Self::from(&fs::read(path)?)
+    }
+
+    /// Find the key use to encrypt a ticket
+    pub fn find(&self, sname: &PrincipalName, etype: Integer) -> Option<&EncryptionKey> {
+        self.entries.iter()
+            .filter(|e| e.key.keytype.inner == etype && e.is_for(sname))
+            .max_by_key(|e| e.kvno)
+            .map(|e| &e.key)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Malformed sizes are parsing errors
+    #[test]
+    fn test_malformed_keytab() {
+        let is_parsing = |buf: &[u8]| match Keytab::from(buf) {
+            Err(Error::KerlabError(e)) => e.kind() == KerlabErrorKind::Parsing,
+            _ => false
+        };
+
+        // version 1 entry without any component
+        assert!(is_parsing(&[0x05, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00]));
+        // i32::MIN entry size
+        assert!(is_parsing(&[0x05, 0x02, 0x80, 0x00, 0x00, 0x00]));
+    }
+}
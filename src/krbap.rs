@@ -1,12 +1,46 @@
-use asn1::{ASN1, Integer, Tag, BitString, Application};
+use asn1::{ASN1, Integer, Tag, Application};
+use flags::{Flag, FlagSet};
 use yasna::{DERWriter, BERReader};
 use ticket::Ticket;
-use encryption::EncryptedData;
-use error::KerlabResult;
-use base::MessageType;
+use encryption::{EncryptedData, EncryptionKey, KeyUsage};
+use error::{KerlabResult, Error, KerlabErrorKind};
+use base::{MessageType, KerberosTime};
+use authenticator::Authenticator;
 
-pub type APOptions = BitString;
+/// @see https://datatracker.ietf.org/doc/html/rfc4120#section-5.5.1
+/// ```asn.1
+/// APOptions       ::= KerberosFlags
+/// ```
+pub type APOptions = FlagSet<ApOptionsType>;
+
+/// Bit position of AP options
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ApOptionsType {
+    Reserved = 0,
+    UseSessionKey = 1,
+    MutualRequired = 2
+}
+
+impl Flag for ApOptionsType {
+    const ALL: &'static [Self] = &[
+        ApOptionsType::Reserved,
+        ApOptionsType::UseSessionKey,
+        ApOptionsType::MutualRequired
+    ];
 
+    fn position(self) -> u32 {
+        self as u32
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            ApOptionsType::Reserved => "reserved",
+            ApOptionsType::UseSessionKey => "use-session-key",
+            ApOptionsType::MutualRequired => "mutual-required"
+        }
+    }
+}
 
 /// @see https://www.freesoft.org/CIE/RFC/1510/58.htm
 /// ```asn.1
@@ -36,10 +70,120 @@ impl ApReq {
             inner: ApReqBody{
                 pvno: Tag::new(5),
                 msg_type: Tag::new(MessageType::KrbApReq as Integer),
-                ap_options: Tag::new(APOptions::new()),
+                ap_options: Tag::new(APOptions::default()),
                 ticket: Tag::new(ticket),
                 authenticator: Tag::new(authenticator)
             }
         }
     }
-}
\ No newline at end of file
+
+    /// Set the ap-options field
+    /// Use MutualRequired to ask the service for an AP-REP
+    pub fn with_options(mut self, options: &[ApOptionsType]) -> Self {
+        self.inner.ap_options.inner = APOptions::new(options);
+        self
+    }
+}
+
+/// @see https://www.freesoft.org/CIE/RFC/1510/59.htm
+/// ```asn.1
+/// AP-REP ::=         [APPLICATION 15] SEQUENCE {
+///     pvno[0]                   INTEGER,
+///     msg-type[1]               INTEGER,
+///     enc-part[2]               EncryptedData
+/// }
+/// ```
+#[derive(Sequence, Default, Clone, PartialEq)]
+pub struct ApRepBody {
+    pub pvno: Tag<0, Integer>,
+    pub msg_type: Tag<1, Integer>,
+    pub enc_part: Tag<2, EncryptedData>
+}
+
+pub type ApRep = Application<15, ApRepBody>;
+
+impl ApRep {
+    /// Build the reply of a service for mutual authentication
+    /// The client authenticator time is sent back encrypted with the session key
+    pub fn new(session_key: &EncryptionKey, authenticator: &Authenticator) -> KerlabResult<Self> {
+        Ok(Self {
+            inner: ApRepBody {
+                pvno: Tag::new(5),
+                msg_type: Tag::new(MessageType::KrbApRep as Integer),
+                enc_part: Tag::new(session_key.encrypt(
+                    KeyUsage::KeyUsageApRepEncPart,
+                    &EncAPRepPart {
+                        inner: EncAPRepPartBody {
+                            ctime: Tag::new(authenticator.ctime.inner.clone()),
+                            cusec: Tag::new(authenticator.cusec.inner),
+                            subkey: None,
+                            seq_number: None
+                        }
+                    }
+                )?)
+            }
+        })
+    }
+
+    /// Client side of mutual authentication
+    ///
+    /// Decrypt the AP-REP using the session key of the ticket
+    /// and check that the service echoes the time of the authenticator we sent
+    pub fn verify(&self, session_key: &EncryptionKey, authenticator: &Authenticator) -> KerlabResult<EncAPRepPart> {
+        let enc_part = session_key.decrypt::<EncAPRepPart>(
+            KeyUsage::KeyUsageApRepEncPart,
+            &self.enc_part
+        )?;
+
+        // time are exchanged with a precision of one second
+        if enc_part.ctime.inner.inner.timestamp() != authenticator.ctime.inner.inner.timestamp()
+            || enc_part.cusec.inner != authenticator.cusec.inner {
+            return Err(Error::new(KerlabErrorKind::Kerberos, "KRB_AP_ERR_MUT_FAIL: Mutual authentication failed"))
+        }
+
+        Ok(enc_part)
+    }
+}
+
+/// @see https://www.freesoft.org/CIE/RFC/1510/59.htm
+/// ```asn.1
+/// EncAPRepPart ::=   [APPLICATION 27] SEQUENCE {
+///     ctime[0]                  KerberosTime,
+///     cusec[1]                  INTEGER,
+///     subkey[2]                 EncryptionKey OPTIONAL,
+///     seq-number[3]             INTEGER OPTIONAL
+/// }
+/// ```
+#[derive(Sequence, Default, Clone, PartialEq)]
+pub struct EncAPRepPartBody {
+    pub ctime: Tag<0, KerberosTime>,
+    pub cusec: Tag<1, Integer>,
+    pub subkey: Option<Tag<2, EncryptionKey>>,
+    pub seq_number: Option<Tag<3, Integer>>
+}
+
+pub type EncAPRepPart = Application<27, EncAPRepPartBody>;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use asn1::GeneralString;
+    use base::{PrincipalName, PrincipalNameType};
+    use std::str::FromStr;
+
+    /// A service must echo the authenticator time
+    #[test]
+    fn test_mutual_authentication() {
+        let session_key = EncryptionKey::new_rc4_hmac("foo").unwrap();
+        let authenticator = Authenticator::new(
+            GeneralString::from_str("LAB.LOCAL").unwrap(),
+            PrincipalName::new(PrincipalNameType::NtPrincipal, vec![GeneralString::from_str("bar").unwrap()])
+        );
+        let ap_rep = ApRep::new(&session_key, &authenticator).unwrap();
+        assert!(ap_rep.verify(&session_key, &authenticator).is_ok());
+
+        let mut other = authenticator.clone();
+        other.cusec.inner = 1;
+        assert!(ap_rep.verify(&session_key, &other).is_err());
+    }
+}
@@ -121,7 +121,7 @@ impl KrbCred {
                 ]),
                 enc_part: Tag::new(
                     key.encrypt(
-                        KeyUsage::KeyUsageAsRepEncPart,
+                        KeyUsage::KeyUsageKrbCredEncPart,
                         &EncKrbCredPart {
                             inner: EncKrbCredPartBody {
                                 ticket_info: Tag::new(vec![
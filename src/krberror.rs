@@ -1,10 +1,45 @@
 use asn1::{Integer, Tag, GeneralString, OctetString, Application, ASN1};
 use yasna::{DERWriter, BERReader};
-use base::{KerberosTime, Realm, PrincipalName};
+use base::{KerberosTime, Realm, PrincipalName, MessageType};
 use error::{KerlabResult};
+use chrono::{Utc, Timelike, DateTime, Duration};
 
 pub type KrbError = Application<30, KrbErrorBody>;
 
+impl KrbError {
+    /// Build an error sent back by a server
+    pub fn new(code: KrbErrorCode, realm: Realm, sname: PrincipalName) -> Self {
+        let now = Utc::now();
+        Self {
+            inner: KrbErrorBody {
+                pvno: Tag::new(5),
+                msg_type: Tag::new(MessageType::KrbError as Integer),
+                ctime: None,
+                cusec: None,
+                stime: Tag::new(KerberosTime::new(now)),
+                susec: Tag::new(now.nanosecond() / 1000 % 1000000),
+                error_code: Tag::new(code as Integer),
+                crealm: None,
+                cname: None,
+                realm: Tag::new(realm),
+                sname: Tag::new(sname),
+                e_text: None,
+                e_data: None
+            }
+        }
+    }
+
+    /// Time of the server that sends the error
+    pub fn server_time(&self) -> DateTime<Utc> {
+        self.stime.inner.inner + Duration::microseconds(self.susec.inner as i64)
+    }
+
+    /// Check the error code
+    pub fn is(&self, code: KrbErrorCode) -> bool {
+        self.error_code.inner == code as Integer
+    }
+}
+
 /// @see https://www.freesoft.org/CIE/RFC/1510/68.htm
 /// ```asn.1
 /// KRB-ERROR ::=   [APPLICATION 30] SEQUENCE {
@@ -38,4 +73,60 @@ pub struct KrbErrorBody {
     pub sname: Tag<10, PrincipalName>,
     pub e_text: Option<Tag<11, GeneralString>>,
     pub e_data: Option<Tag<12, OctetString>>
-}
\ No newline at end of file
+}
+
+/// Error codes carried by KRB-ERROR
+///
+/// @see https://www.freesoft.org/CIE/RFC/1510/78.htm
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum KrbErrorCode {
+    KdcErrNone = 0,
+    KdcErrNameExp = 1,
+    KdcErrServiceExp = 2,
+    KdcErrBadPvno = 3,
+    KdcErrCOldMastKvno = 4,
+    KdcErrSOldMastKvno = 5,
+    KdcErrCPrincipalUnknown = 6,
+    KdcErrSPrincipalUnknown = 7,
+    KdcErrPrincipalNotUnique = 8,
+    KdcErrNullKey = 9,
+    KdcErrCannotPostdate = 10,
+    KdcErrNeverValid = 11,
+    KdcErrPolicy = 12,
+    KdcErrBadOption = 13,
+    KdcErrEtypeNoSupp = 14,
+    KdcErrSumtypeNoSupp = 15,
+    KdcErrPadataTypeNoSupp = 16,
+    KdcErrTrtypeNoSupp = 17,
+    KdcErrClientRevoked = 18,
+    KdcErrServiceRevoked = 19,
+    KdcErrTgtRevoked = 20,
+    KdcErrClientNotYet = 21,
+    KdcErrServiceNotYet = 22,
+    KdcErrKeyExpired = 23,
+    KdcErrPreauthFailed = 24,
+    KdcErrPreauthRequired = 25,
+    KrbApErrBadIntegrity = 31,
+    KrbApErrTktExpired = 32,
+    KrbApErrTktNyv = 33,
+    KrbApErrRepeat = 34,
+    KrbApErrNotUs = 35,
+    KrbApErrBadMatch = 36,
+    KrbApErrSkew = 37,
+    KrbApErrBadAddr = 38,
+    KrbApErrBadVersion = 39,
+    KrbApErrMsgType = 40,
+    KrbApErrModified = 41,
+    KrbApErrBadOrder = 42,
+    KrbApErrBadKeyVer = 44,
+    KrbApErrNoKey = 45,
+    KrbApErrMutFail = 46,
+    KrbApErrBadDirection = 47,
+    KrbApErrMethod = 48,
+    KrbApErrBadSeq = 49,
+    KrbApErrInappCksum = 50,
+    KrbErrResponseTooBig = 52,
+    KrbErrGeneric = 60,
+    KrbErrFieldTooLong = 61
+}
@@ -1,5 +1,5 @@
 use asn1::{Tag, Integer, SequenceOf, ASN1, Application};
-use padata::PaData;
+use padata::{PaData, PaDataType};
 use base::{PrincipalName, Realm, LastReq, KerberosTime, TicketFlags, HostAddresses};
 use ticket::Ticket;
 use yasna::{DERWriter, BERReader};
@@ -55,7 +55,8 @@ impl AsRep {
 ///     renew-till[8]                KerberosTime OPTIONAL,
 ///     srealm[9]                    Realm,
 ///     sname[10]                    PrincipalName,
-///     caddr[11]                    HostAddresses OPTIONAL
+///     caddr[11]                    HostAddresses OPTIONAL,
+///     encrypted-pa-data[12]        METHOD-DATA OPTIONAL
 /// }
 #[derive(Sequence, Default, PartialEq, Clone)]
 pub struct EncKDCRepPart {
@@ -70,7 +71,18 @@ pub struct EncKDCRepPart {
     pub renew_till: Option<Tag<8, KerberosTime>>,
     pub srealm: Tag<9, Realm>,
     pub sname: Tag<10, PrincipalName>,
-    pub caddr: Option<Tag<11, HostAddresses>>
+    pub caddr: Option<Tag<11, HostAddresses>>,
+    pub encrypted_pa_data: Option<Tag<12, SequenceOf<PaData>>>
+}
+
+impl EncKDCRepPart {
+    /// Find a padata sent into the encrypted part of the reply
+    /// @see https://tools.ietf.org/html/rfc6806#section-11
+    pub fn find_encrypted_padata(&self, padata_type: PaDataType) -> Option<&PaData> {
+        let padata_type = padata_type as Integer;
+        self.encrypted_pa_data.as_ref()?.iter()
+            .find(|padata| padata.padata_type.inner == padata_type)
+    }
 }
 
 /// @see https://www.freesoft.org/CIE/RFC/1510/56.htm
@@ -87,6 +99,17 @@ pub type EncASRepPart = Application<25, EncKDCRepPart>;
 /// ```
 pub type TgsRep = Application<13, KdcRep>;
 
+impl TgsRep {
+    /// Decrypt the encrypted part of the response
+    /// with the authenticator subkey if one was sent, the TGT session key otherwise
+    pub fn decrypt(&self, session_key: &EncryptionKey, subkey: Option<&EncryptionKey>) -> KerlabResult<EncTGSRepPart> {
+        match subkey {
+            Some(subkey) => subkey.decrypt::<EncTGSRepPart>(KeyUsage::KeyUsageTgsRepEncPartSubkey, &self.enc_part),
+            None => session_key.decrypt::<EncTGSRepPart>(KeyUsage::KeyUsageAsRepEncPart, &self.enc_part)
+        }
+    }
+}
+
 /// @see https://www.freesoft.org/CIE/RFC/1510/56.htm
 /// ```asn1
 /// EncTGSRepPart ::=   [APPLICATION 26] EncKDCRepPart
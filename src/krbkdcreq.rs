@@ -1,57 +1,21 @@
 /// This file is linked to https://www.freesoft.org/CIE/RFC/1510/55.htm
 
-use base::{PrincipalName, Realm, KerberosTime, HostAddresses, KDCOptions, PrincipalNameType, MessageType};
+use base::{PrincipalName, Realm, KerberosTime, HostAddress, HostAddresses, KDCOptions, KdcOptionsType, PrincipalNameType, MessageType, AuthorizationData};
 use yasna::{DERWriter, BERReader};
 use asn1::{ASN1, Tag, Integer, SequenceOf, Application, GeneralString};
 use error::{KerlabResult};
-use encryption::{EncryptedData, EType, EncryptionKey};
+use encryption::{EncryptedData, EType, EncryptionKey, KeyUsage};
 use ticket::Ticket;
 use chrono::{Utc, Duration, DateTime};
 use padata::{PaData, PaDataType};
 use rnd::nonce;
 use std::str::FromStr;
+use std::net::{IpAddr, Ipv4Addr};
 use krbap::ApReq;
+use authenticator::Authenticator;
+use pkinit::Pkinit;
 
 
-#[repr(u32)]
-#[derive(Copy, Clone)]
-pub enum KdcOptionsType {
-    Validate = 0x00000001,
-    Renew = 0x00000002,
-    Unused29 = 0x00000004,
-    EncTktInsKey = 0x00000008,
-    RenewableOk = 0x00000010,
-    DisableTransitedCheck = 0x00000020,
-    Unused16 = 0x0000FFC0,
-    ConstrainedDelegation = 0x00020000,
-    Canonocalize = 0x00010000,
-    CNameInAddLTkt = 0x00004000,
-    OkAsDelegate = 0x00040000,
-    Unused12 = 0x00080000,
-    OpthardwareAuth = 0x00100000,
-    PreAuthent = 0x00200000,
-    Initial = 0x00400000,
-    Renewable = 0x00800000,
-    Unused7 = 0x01000000,
-    PostDated = 0x02000000,
-    AllowPostDate = 0x04000000,
-    Proxy = 0x08000000,
-    Proxiable = 0x10000000,
-    Forwarded = 0x20000000,
-    Forwardable = 0x40000000,
-    Reserved = 0x80000000,
-}
-
-impl KdcOptionsType {
-    fn join(options: &[KdcOptionsType]) -> u32 {
-        let mut result = 0;
-        for e in options {
-            result |= *e as u32;
-        }
-        result
-    }
-}
-
 /// @see https://www.freesoft.org/CIE/RFC/1510/55.htm
 /// ```asn.1
 /// AS-REQ ::=         [APPLICATION 10] KDC-REQ
@@ -61,41 +25,88 @@ pub type AsReq = Application<10, KdcReq>;
 impl AsReq {
     /// constructor
     pub fn new(domain: &str, username: &str, options: &[KdcOptionsType]) -> KerlabResult<AsReq> {
-        Ok(AsReq {
-            inner: KdcReq {
-                pvno: Tag::new(5),
-                msg_type: Tag::new(MessageType::KrbAsReq as Integer),
-                padata: None,
-                req_body: Tag::new(KdcReqBody::new(
-                    PrincipalName::new(
-                        PrincipalNameType::NtPrincipal,
-                        vec![
-                            GeneralString::from_str(username)?
-                        ],
-                    ),
-                    domain,
-                    PrincipalName::new(
-                        PrincipalNameType::NtSrvInst,
-                        vec![
-                            GeneralString::from_str("krbtgt")?,
-                            GeneralString::from_str(domain)?
-                        ],
-                    ),
-                    KdcOptionsType::join(options),
-                    Utc::now() + Duration::days(1),
-                )?),
-            }
-        })
+        Ok(KdcReqBuilder::new(domain)?
+            .with_options(options)
+            .with_cname(PrincipalName::new(
+                PrincipalNameType::NtPrincipal,
+                vec![
+                    GeneralString::from_str(username)?
+                ],
+            ))
+            .with_sname(PrincipalName::new(
+                PrincipalNameType::NtSrvInst,
+                vec![
+                    GeneralString::from_str("krbtgt")?,
+                    GeneralString::from_str(domain)?
+                ],
+            ))
+            .build_as_req())
     }
 
     /// Add pre authentication stuff for the request
     /// see padata.rs
-    pub fn with_preauth(mut self, key: &EncryptionKey) -> KerlabResult<Self> {
+    pub fn with_preauth(self, key: &EncryptionKey) -> KerlabResult<Self> {
+        self.with_preauth_at(key, Utc::now())
+    }
+
+    /// Pre authentication with the timestamp of the KDC clock
+    /// see request.rs KdcClock
+    pub fn with_preauth_at(mut self, key: &EncryptionKey, time: DateTime<Utc>) -> KerlabResult<Self> {
+        if let Some(e) = &mut self.inner.padata {
+            e.inner.push(PaData::pa_enc_timestamp_at(key, time)?);
+        } else {
+            self.inner.padata = Some(Tag::new(vec![
+                PaData::pa_enc_timestamp_at(key, time)?
+            ]));
+        }
+
+        Ok(self)
+    }
+
+    /// Ask the KDC to include or to omit the PAC
+    /// Without it the KDC policy applies, which include the PAC
+    pub fn with_pac_request(mut self, include_pac: bool) -> KerlabResult<Self> {
+        match &mut self.inner.padata {
+            Some(e) => e.inner.push(PaData::pa_pac_request(include_pac)?),
+            None => self.inner.padata = Some(Tag::new(vec![PaData::pa_pac_request(include_pac)?]))
+        }
+        Ok(self)
+    }
+
+    /// Certificate based pre authentication
+    /// see pkinit.rs
+    pub fn with_pkinit(self, pkinit: &Pkinit) -> KerlabResult<Self> {
+        self.with_pkinit_at(pkinit, Utc::now())
+    }
+
+    /// Certificate based pre authentication at the KDC time
+    pub fn with_pkinit_at(mut self, pkinit: &Pkinit, time: DateTime<Utc>) -> KerlabResult<Self> {
+        let pa_pk_as_req = pkinit.pa_pk_as_req_at(&self.inner.req_body, time)?;
+        if let Some(e) = &mut self.inner.padata {
+            e.inner.push(pa_pk_as_req);
+        } else {
+            self.inner.padata = Some(Tag::new(vec![
+                pa_pk_as_req
+            ]));
+        }
+
+        Ok(self)
+    }
+
+    /// Pre authentication use by armored request
+    /// must be called before FastArmor::armor
+    /// see padata.rs
+    pub fn with_encrypted_challenge(self, armor_key: &EncryptionKey, key: &EncryptionKey) -> KerlabResult<Self> {
+        self.with_encrypted_challenge_at(armor_key, key, Utc::now())
+    }
+
+    /// Encrypted challenge with the timestamp of the KDC clock
+    pub fn with_encrypted_challenge_at(mut self, armor_key: &EncryptionKey, key: &EncryptionKey, time: DateTime<Utc>) -> KerlabResult<Self> {
         if let Some(e) = &mut self.inner.padata {
-            e.inner.push(PaData::pa_enc_timestamp(key)?);
+            e.inner.push(PaData::pa_encrypted_challenge_at(armor_key, key, time)?);
         } else {
             self.inner.padata = Some(Tag::new(vec![
-                PaData::pa_enc_timestamp(key)?
+                PaData::pa_encrypted_challenge_at(armor_key, key, time)?
             ]));
         }
 
@@ -109,28 +120,41 @@ impl AsReq {
 pub type TgsReq = Application<12, KdcReq>;
 
 impl TgsReq {
-    pub fn new(domain: &str, username: &str, sname: PrincipalName, ap_req: &ApReq, options: &[KdcOptionsType]) -> KerlabResult<TgsReq> {
-        Ok(TgsReq {
-            inner: KdcReq {
-                pvno: Tag::new(5),
-                msg_type: Tag::new(MessageType::KrbTgsReq as Integer),
-                padata: Some(Tag::new(vec![
-                    PaData::new(PaDataType::PaTgsReq, ap_req)
-                ])),
-                req_body: Tag::new(KdcReqBody::new(
-                    PrincipalName::new(
-                        PrincipalNameType::NtPrincipal,
-                        vec![
-                            GeneralString::from_str(username)?
-                        ],
-                    ),
-                    domain,
-                    sname,
-                    KdcOptionsType::join(options),
-                    Utc::now() + Duration::days(1),
-                )?),
-            }
-        })
+    /// constructor
+    /// The PA-TGS-REQ is added by with_authenticator
+    pub fn new(domain: &str, username: &str, sname: PrincipalName, options: &[KdcOptionsType]) -> KerlabResult<TgsReq> {
+        Ok(KdcReqBuilder::new(domain)?
+            .with_options(options)
+            .with_cname(PrincipalName::new(
+                PrincipalNameType::NtPrincipal,
+                vec![
+                    GeneralString::from_str(username)?
+                ],
+            ))
+            .with_sname(sname)
+            .build_tgs_req())
+    }
+
+    /// Add the PA-TGS-REQ that carries the TGT
+    ///
+    /// The authenticator checksum covers the request body,
+    /// so it must be the last change made to the body.
+    /// An existing PA-TGS-REQ is replaced
+    pub fn with_authenticator(mut self, ticket: Ticket, session_key: &EncryptionKey, authenticator: Authenticator) -> KerlabResult<Self> {
+        let authenticator = authenticator.with_checksum(session_key, &self.inner.req_body)?;
+        let pa_tgs_req = PaData::new(
+            PaDataType::PaTgsReq,
+            &ApReq::new(ticket, session_key.encrypt(KeyUsage::KrbKeyUsageTgsReqPaAuthenticator, &authenticator)?)
+        )?;
+
+        match &mut self.inner.padata {
+            Some(e) => {
+                e.inner.retain(|padata| padata.padata_type.inner != PaDataType::PaTgsReq as Integer);
+                e.inner.insert(0, pa_tgs_req);
+            },
+            None => self.inner.padata = Some(Tag::new(vec![pa_tgs_req]))
+        }
+        Ok(self)
     }
 
     pub fn for_user(mut self, user_name: PrincipalName, user_realm: Realm, key: &EncryptionKey) -> KerlabResult<Self> {
@@ -152,6 +176,28 @@ impl TgsReq {
 
         Ok(self)
     }
+
+    /// Ask for the long term keys of the client
+    /// The TGT must be a partial TGT issued by a RODC
+    pub fn with_key_list_req(mut self, etypes: &[EType]) -> KerlabResult<Self> {
+        let etypes = etypes.iter().map(|etype| *etype as Integer).collect::<Vec<Integer>>();
+        match &mut self.inner.padata {
+            Some(e) => e.inner.push(PaData::kerb_key_list_req(&etypes)?),
+            None => self.inner.padata = Some(Tag::new(vec![PaData::kerb_key_list_req(&etypes)?]))
+        }
+        Ok(self)
+    }
+
+    /// User to user request
+    /// The ticket will be encrypted with the session key of the additional TGT
+    /// Needs the EncTktInsKey option
+    pub fn with_additional_ticket(mut self, ticket: Ticket) -> Self {
+        match &mut self.inner.req_body.additional_tickets {
+            Some(e) => e.inner.push(ticket),
+            None => self.inner.req_body.additional_tickets = Some(Tag::new(vec![ticket]))
+        }
+        self
+    }
 }
 
 /// ```asn1
@@ -207,24 +253,233 @@ pub struct KdcReqBody {
 }
 
 impl KdcReqBody {
-    pub fn new(cname: PrincipalName, domain: &str, sname: PrincipalName, kdc_options: u32, till: DateTime<Utc>) -> KerlabResult<Self> {
+    pub fn new(cname: PrincipalName, domain: &str, sname: PrincipalName, kdc_options: KDCOptions, till: DateTime<Utc>) -> KerlabResult<Self> {
+        Ok(KdcReqBuilder::new(domain)?
+            .with_kdc_options(kdc_options)
+            .with_cname(cname)
+            .with_sname(sname)
+            .with_till(till)
+            .build_body())
+    }
+}
+
+/// Builder of KDC requests
+///
+/// Every field of the KDC-REQ-BODY can be set,
+/// to send the exact request of a given client.
+/// By default the ticket is valid for one day, the nonce is random
+/// and RC4 is the only encryption type.
+/// Client name, service name and options are empty
+///
+/// # Example
+/// ```rust, ignore
+/// let request = KdcReqBuilder::new("LAB.LOCAL")?
+///     .with_client(PrincipalNameType::NtEnterprise, "alice@lab.local")?
+///     .with_service(PrincipalNameType::NtSrvInst, "krbtgt/LAB.LOCAL")?
+///     .with_etypes(&[EType::Aes256CtsHmacSha196, EType::Aes128CtsHmacSha196, EType::Rc4Hmac])
+///     .with_netbios_address("WORKSTATION")
+///     .build_as_req();
+/// ```
+pub struct KdcReqBuilder {
+    body: KdcReqBody
+}
+
+impl KdcReqBuilder {
+    /// Realm of the server, also the one of the client for AS-REQ
+    pub fn new(realm: &str) -> KerlabResult<Self> {
         Ok(Self {
-            kdc_options: Tag::new(KDCOptions::from_bytes(
-                &kdc_options.to_be_bytes()
-            )),
-            cname: Some(Tag::new(cname)),
-            realm: Tag::new(GeneralString::from_str(domain)?),
-            sname: Some(Tag::new(sname)),
-            from: None,
-            till: Tag::new(KerberosTime::new(till)),
-            rtime: None,
-            nonce: Tag::new(nonce() as Integer),
-            etype: Tag::new(vec![
-                EType::Rc4Hmac as Integer
-            ]),
-            addresses: None,
-            additional_tickets: None,
-            enc_authorization_data: None,
+            body: KdcReqBody {
+                realm: Tag::new(GeneralString::from_str(realm)?),
+                till: Tag::new(KerberosTime::new(Utc::now() + Duration::days(1))),
+                nonce: Tag::new(nonce() as Integer),
+                etype: Tag::new(vec![
+                    EType::Rc4Hmac as Integer
+                ]),
+                ..Default::default()
+            }
         })
     }
-}
\ No newline at end of file
+
+    pub fn with_options(self, options: &[KdcOptionsType]) -> Self {
+        self.with_kdc_options(KDCOptions::new(options))
+    }
+
+    pub fn with_kdc_options(mut self, kdc_options: KDCOptions) -> Self {
+        self.body.kdc_options = Tag::new(kdc_options);
+        self
+    }
+
+    pub fn with_cname(mut self, cname: PrincipalName) -> Self {
+        self.body.cname = Some(Tag::new(cname));
+        self
+    }
+
+    /// Client name, components are separated by a slash
+    pub fn with_client(self, name_type: PrincipalNameType, name: &str) -> KerlabResult<Self> {
+        Ok(self.with_cname(principal_name(name_type, name)?))
+    }
+
+    pub fn with_sname(mut self, sname: PrincipalName) -> Self {
+        self.body.sname = Some(Tag::new(sname));
+        self
+    }
+
+    /// Service name, components are separated by a slash
+    pub fn with_service(self, name_type: PrincipalNameType, name: &str) -> KerlabResult<Self> {
+        Ok(self.with_sname(principal_name(name_type, name)?))
+    }
+
+    /// Start time of a postdated ticket
+    pub fn with_from(mut self, from: DateTime<Utc>) -> Self {
+        self.body.from = Some(Tag::new(KerberosTime::new(from)));
+        self
+    }
+
+    pub fn with_till(mut self, till: DateTime<Utc>) -> Self {
+        self.body.till = Tag::new(KerberosTime::new(till));
+        self
+    }
+
+    /// Renew till time of a renewable ticket
+    pub fn with_rtime(mut self, rtime: DateTime<Utc>) -> Self {
+        self.body.rtime = Some(Tag::new(KerberosTime::new(rtime)));
+        self
+    }
+
+    pub fn with_nonce(mut self, nonce: Integer) -> Self {
+        self.body.nonce = Tag::new(nonce);
+        self
+    }
+
+    /// Encryption types in preference order
+    pub fn with_etypes(mut self, etypes: &[EType]) -> Self {
+        self.body.etype = Tag::new(etypes.iter().map(|etype| *etype as Integer).collect());
+        self
+    }
+
+    pub fn with_address(mut self, address: HostAddress) -> Self {
+        match &mut self.body.addresses {
+            Some(e) => e.inner.push(address),
+            None => self.body.addresses = Some(Tag::new(vec![address]))
+        }
+        self
+    }
+
+    pub fn with_ipv4_address(self, ip: Ipv4Addr) -> Self {
+        self.with_address(HostAddress::from_ip(&IpAddr::V4(ip)))
+    }
+
+    pub fn with_netbios_address(self, name: &str) -> Self {
+        self.with_address(HostAddress::from_netbios(name))
+    }
+
+    pub fn with_additional_ticket(mut self, ticket: Ticket) -> Self {
+        match &mut self.body.additional_tickets {
+            Some(e) => e.inner.push(ticket),
+            None => self.body.additional_tickets = Some(Tag::new(vec![ticket]))
+        }
+        self
+    }
+
+    /// Authorization data copied into the ticket by the KDC, TGS-REQ only
+    ///
+    /// Encrypted with the sub session key of the authenticator (KeyUsageTgsReqAuthDataSubkey),
+    /// or the TGT session key (KeyUsageTgsReqAuthDataSessionKey)
+    pub fn with_authorization_data(mut self, key: &EncryptionKey, key_usage: KeyUsage, authorization_data: &AuthorizationData) -> KerlabResult<Self> {
+        self.body.enc_authorization_data = Some(Tag::new(key.encrypt(key_usage, authorization_data)?));
+        Ok(self)
+    }
+
+    pub fn build_body(self) -> KdcReqBody {
+        self.body
+    }
+
+    /// Pre authentication is added by AsReq::with_preauth
+    pub fn build_as_req(self) -> AsReq {
+        AsReq {
+            inner: KdcReq {
+                pvno: Tag::new(5),
+                msg_type: Tag::new(MessageType::KrbAsReq as Integer),
+                padata: None,
+                req_body: Tag::new(self.body)
+            }
+        }
+    }
+
+    /// The PA-TGS-REQ is added by TgsReq::with_authenticator
+    pub fn build_tgs_req(self) -> TgsReq {
+        TgsReq {
+            inner: KdcReq {
+                pvno: Tag::new(5),
+                msg_type: Tag::new(MessageType::KrbTgsReq as Integer),
+                padata: None,
+                req_body: Tag::new(self.body)
+            }
+        }
+    }
+}
+
+fn principal_name(name_type: PrincipalNameType, name: &str) -> KerlabResult<PrincipalName> {
+    let mut components = vec![];
+    for component in name.split('/') {
+        components.push(GeneralString::from_str(component)?);
+    }
+    Ok(PrincipalName::new(name_type, components))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use asn1::{to_der, from_der};
+    use base::{AuthorizationDataElement, AddressType};
+
+    /// Every field of the body goes on the wire
+    #[test]
+    fn test_kdc_req_builder() {
+        let now = Utc::now();
+        let session_key = EncryptionKey::random(EType::Aes256CtsHmacSha196 as Integer).unwrap();
+        let authorization_data = vec![AuthorizationDataElement {
+            ad_type: Tag::new(1),
+            ad_data: Tag::new(vec![0x30, 0x00])
+        }];
+
+        let request = KdcReqBuilder::new("LAB.LOCAL").unwrap()
+            .with_options(&[KdcOptionsType::Forwardable, KdcOptionsType::Renewable, KdcOptionsType::Canonicalize])
+            .with_client(PrincipalNameType::NtEnterprise, "alice@lab.local").unwrap()
+            .with_service(PrincipalNameType::NtSrvInst, "cifs/dc.lab.local").unwrap()
+            .with_from(now)
+            .with_till(now + Duration::hours(10))
+            .with_rtime(now + Duration::days(7))
+            .with_nonce(0x12345678)
+            .with_etypes(&[EType::Aes256CtsHmacSha196, EType::Aes128CtsHmacSha196, EType::Rc4Hmac])
+            .with_netbios_address("ws01")
+            .with_ipv4_address(Ipv4Addr::new(10, 0, 0, 1))
+            .with_additional_ticket(Ticket::default())
+            .with_authorization_data(&session_key, KeyUsage::KeyUsageTgsReqAuthDataSessionKey, &authorization_data).unwrap()
+            .build_tgs_req();
+
+        // times are encoded without fraction of seconds
+        let encoded = to_der(&request).unwrap();
+        let mut decoded = TgsReq::default();
+        from_der(&mut decoded, &encoded).unwrap();
+        assert_eq!(to_der(&decoded).unwrap(), encoded);
+
+        let body = &decoded.req_body;
+        assert_eq!(decoded.msg_type.inner, MessageType::KrbTgsReq as Integer);
+        assert!(body.kdc_options.contains(KdcOptionsType::Canonicalize));
+        assert_eq!(body.cname.as_ref().unwrap().name_type.inner, PrincipalNameType::NtEnterprise as Integer);
+        assert_eq!(body.cname.as_ref().unwrap().name(), "alice@lab.local");
+        assert_eq!(body.sname.as_ref().unwrap().name_string.len(), 2);
+        assert_eq!(body.rtime.as_ref().unwrap().inner.inner.timestamp(), (now + Duration::days(7)).timestamp());
+        assert_eq!(body.nonce.inner, 0x12345678);
+        assert_eq!(body.etype.inner, vec![18, 17, 23]);
+
+        let addresses = body.addresses.as_ref().unwrap();
+        assert_eq!(addresses[0].addr_type.inner, AddressType::NetBios as Integer);
+        assert_eq!(addresses[0].address.inner, b"WS01            ".to_vec());
+        assert_eq!(addresses[1].address.inner, vec![10, 0, 0, 1]);
+
+        let enc_authorization_data = body.enc_authorization_data.as_ref().unwrap();
+        assert!(session_key.decrypt::<AuthorizationData>(KeyUsage::KeyUsageTgsReqAuthDataSessionKey, enc_authorization_data).unwrap() == authorization_data);
+    }
+}
@@ -13,11 +13,16 @@ extern crate md5;
 extern crate rand;
 extern crate hmac;
 extern crate base64;
+extern crate aes;
+extern crate sha1;
+extern crate sha2;
+extern crate num_bigint;
 
 #[macro_use]
 pub mod asn1;
 pub mod error;
 pub mod base;
+pub mod flags;
 pub mod krbkdcreq;
 pub mod krbkdcrep;
 pub mod krberror;
@@ -26,6 +31,7 @@ pub mod ntlm;
 pub mod request;
 pub mod padata;
 pub mod rc4hmac;
+pub mod aescts;
 pub mod rnd;
 pub mod display;
 pub mod encryption;
@@ -36,3 +42,20 @@ pub mod checksum;
 pub mod pac;
 pub mod message;
 pub mod ndr;
+pub mod keytab;
+pub mod ccache;
+pub mod acceptor;
+pub mod fast;
+pub mod x509;
+pub mod rsa;
+pub mod cms;
+pub mod pkcs12;
+pub mod pkinit;
+pub mod referral;
+pub mod forge;
+pub mod kdc;
+pub mod audit;
+pub mod pcap;
+pub mod detect;
+pub mod json;
+pub mod validate;
@@ -1,6 +1,9 @@
 use message::{Message, U16LE, U32LE};
-use std::io::{Write, Read};
-use error::KerlabResult;
+use std::io::{Write, Read, Cursor};
+use std::fmt;
+use std::str::FromStr;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use error::{KerlabResult, Error, KerlabErrorKind};
 use chrono::{DateTime, Utc, NaiveDateTime};
 
 /// Very basic NDR parser
@@ -19,13 +22,30 @@ pub struct PrivateHeader {
     filler: U32LE
 }
 
-#[derive(Component, Default)]
+#[derive(Component, Default, Clone, PartialEq, Debug)]
 pub struct FileTime {
     pub dw_low_date_time: U32LE,
     pub dw_high_date_time: U32LE
 }
 
 impl FileTime {
+    /// Convert a date into a FILETIME
+    pub fn new(date: DateTime<Utc>) -> Self {
+        let timestamp = (date.timestamp() as u64) * 10000000 + 116444736000000000;
+        Self {
+            dw_low_date_time: timestamp as u32,
+            dw_high_date_time: (timestamp >> 32) as u32
+        }
+    }
+
+    /// FILETIME use for time that never happen
+    pub fn never() -> Self {
+        Self {
+            dw_low_date_time: 0xFFFFFFFF,
+            dw_high_date_time: 0x7FFFFFFF
+        }
+    }
+
     pub fn datetime(&self) -> DateTime<Utc> {
         if self.dw_high_date_time == 0x7FFFFFFF
             || self.dw_low_date_time == 0xFFFFFFFF
@@ -46,4 +66,364 @@ pub struct RpcUnicodeString {
     pub length: U16LE,
     pub maximum_length: U16LE,
     pub buffer: U32LE
-}
\ No newline at end of file
+}
+
+/// Security identifier
+/// @see [MS-DTYP] 2.4.2 SID
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Sid {
+    pub revision: u8,
+    pub identifier_authority: u64,
+    pub sub_authority: Vec<u32>
+}
+
+impl Sid {
+    /// SID of an account into a domain
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// let enterprise_admins = Sid::from_str("S-1-5-21-1-2-3")?.with_rid(519);
+    /// ```
+    pub fn with_rid(&self, rid: u32) -> Self {
+        let mut result = self.clone();
+        result.sub_authority.push(rid);
+        result
+    }
+
+    /// Read the conformant structure
+    fn read_ndr(cursor: &mut Cursor<&[u8]>) -> KerlabResult<Self> {
+        // size of the conformant array
+        cursor.read_u32::<LittleEndian>()?;
+        let revision = cursor.read_u8()?;
+        let count = cursor.read_u8()?;
+        let mut identifier_authority = 0;
+        for _ in 0..6 {
+            identifier_authority = (identifier_authority << 8) | cursor.read_u8()? as u64;
+        }
+        let mut sub_authority = vec![];
+        for _ in 0..count {
+            sub_authority.push(cursor.read_u32::<LittleEndian>()?);
+        }
+        Ok(Self {
+            revision,
+            identifier_authority,
+            sub_authority
+        })
+    }
+
+    fn write_ndr(&self, writer: &mut Vec<u8>) -> KerlabResult<()> {
+        writer.write_u32::<LittleEndian>(self.sub_authority.len() as u32)?;
+        writer.write_u8(self.revision)?;
+        writer.write_u8(self.sub_authority.len() as u8)?;
+        writer.write_all(&self.identifier_authority.to_be_bytes()[2..])?;
+        for sub_authority in &self.sub_authority {
+            writer.write_u32::<LittleEndian>(*sub_authority)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Sid {
+    type Err = Error;
+
+    /// Parse the string format S-1-5-21-...
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::new(KerlabErrorKind::Parsing, "Invalid SID");
+        let mut parts = s.split('-');
+        if parts.next() != Some("S") {
+            return Err(invalid())
+        }
+        let revision = parts.next().ok_or_else(invalid)?.parse::<u8>().map_err(|_| invalid())?;
+        let identifier_authority = parts.next().ok_or_else(invalid)?.parse::<u64>().map_err(|_| invalid())?;
+        let sub_authority = parts
+            .map(|e| e.parse::<u32>().map_err(|_| invalid()))
+            .collect::<KerlabResult<Vec<u32>>>()?;
+        Ok(Self {
+            revision,
+            identifier_authority,
+            sub_authority
+        })
+    }
+}
+
+impl fmt::Display for Sid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "S-{}-{}", self.revision, self.identifier_authority)?;
+        for sub_authority in &self.sub_authority {
+            write!(f, "-{}", sub_authority)?;
+        }
+        Ok(())
+    }
+}
+
+/// GROUP_MEMBERSHIP
+#[derive(Component, Default, Clone, PartialEq, Debug)]
+pub struct GroupMembership {
+    pub relative_id: U32LE,
+    pub attributes: U32LE
+}
+
+/// KERB_SID_AND_ATTRIBUTES
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct KerbSidAndAttributes {
+    pub sid: Sid,
+    pub attributes: u32
+}
+
+/// NDR writer
+///
+/// Fixed part of the structure is written first,
+/// pointed data are deferred after it, in the order of pointers
+pub struct NdrWriter {
+    fixed: Vec<u8>,
+    deferred: Vec<u8>,
+    referent: u32
+}
+
+impl Default for NdrWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NdrWriter {
+    pub fn new() -> Self {
+        Self {
+            fixed: vec![],
+            deferred: vec![],
+            referent: 0x00020000
+        }
+    }
+
+    fn next_referent(&mut self) -> u32 {
+        self.referent += 4;
+        self.referent
+    }
+
+    /// Align the deferred data on 4 bytes
+    fn align(&mut self) {
+        while !self.deferred.len().is_multiple_of(4) {
+            self.deferred.push(0);
+        }
+    }
+
+    pub fn write_u16(&mut self, value: u16) -> KerlabResult<()> {
+        Ok(self.fixed.write_u16::<LittleEndian>(value)?)
+    }
+
+    pub fn write_u32(&mut self, value: u32) -> KerlabResult<()> {
+        Ok(self.fixed.write_u32::<LittleEndian>(value)?)
+    }
+
+    pub fn write_bytes(&mut self, value: &[u8]) {
+        self.fixed.extend_from_slice(value);
+    }
+
+    pub fn write(&mut self, message: &dyn Message) -> KerlabResult<()> {
+        message.write(&mut self.fixed)
+    }
+
+    /// Write a null pointer or a new referent
+    fn write_pointer(&mut self, present: bool) -> KerlabResult<()> {
+        let referent = if present { self.next_referent() } else { 0 };
+        self.write_u32(referent)
+    }
+
+    /// RPC_UNICODE_STRING with deferred conformant varying buffer
+    pub fn write_unicode_string(&mut self, value: &str) -> KerlabResult<()> {
+        let chars = value.encode_utf16().collect::<Vec<u16>>();
+        self.write_u16(chars.len() as u16 * 2)?;
+        self.write_u16(chars.len() as u16 * 2)?;
+        self.write_pointer(!chars.is_empty())?;
+        if !chars.is_empty() {
+            self.deferred.write_u32::<LittleEndian>(chars.len() as u32)?;
+            self.deferred.write_u32::<LittleEndian>(0)?;
+            self.deferred.write_u32::<LittleEndian>(chars.len() as u32)?;
+            for c in chars {
+                self.deferred.write_u16::<LittleEndian>(c)?;
+            }
+            self.align();
+        }
+        Ok(())
+    }
+
+    /// Pointer to a SID
+    pub fn write_sid(&mut self, sid: Option<&Sid>) -> KerlabResult<()> {
+        self.write_pointer(sid.is_some())?;
+        if let Some(sid) = sid {
+            sid.write_ndr(&mut self.deferred)?;
+        }
+        Ok(())
+    }
+
+    /// Pointer to a conformant array of GROUP_MEMBERSHIP
+    pub fn write_groups(&mut self, groups: &[GroupMembership]) -> KerlabResult<()> {
+        self.write_pointer(!groups.is_empty())?;
+        if !groups.is_empty() {
+            self.deferred.write_u32::<LittleEndian>(groups.len() as u32)?;
+            for group in groups {
+                group.write(&mut self.deferred)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Pointer to a conformant array of KERB_SID_AND_ATTRIBUTES
+    /// SIDs are deferred after the array
+    pub fn write_sids_and_attributes(&mut self, sids: &[KerbSidAndAttributes]) -> KerlabResult<()> {
+        self.write_pointer(!sids.is_empty())?;
+        if !sids.is_empty() {
+            self.deferred.write_u32::<LittleEndian>(sids.len() as u32)?;
+            for sid in sids {
+                let referent = self.next_referent();
+                self.deferred.write_u32::<LittleEndian>(referent)?;
+                self.deferred.write_u32::<LittleEndian>(sid.attributes)?;
+            }
+            for sid in sids {
+                sid.sid.write_ndr(&mut self.deferred)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Type serialization version 1 of a top level pointer
+    pub fn serialize(self) -> KerlabResult<Vec<u8>> {
+        let mut body = vec![];
+        body.write_u32::<LittleEndian>(0x00020000)?;
+        body.extend_from_slice(&self.fixed);
+        body.extend_from_slice(&self.deferred);
+        while body.len() % 8 != 0 {
+            body.push(0);
+        }
+
+        let mut result = vec![];
+        CommonTypeHeader {
+            version: 1,
+            endianness: 0x10,
+            common_header_length: 8,
+            filler: 0xcccccccc
+        }.write(&mut result)?;
+        PrivateHeader {
+            object_buffer_length: body.len() as u32,
+            filler: 0
+        }.write(&mut result)?;
+        result.extend_from_slice(&body);
+        Ok(result)
+    }
+}
+
+/// NDR reader
+///
+/// Read the fixed part first, then deferred data
+/// in the same order than pointers
+pub struct NdrReader<'a> {
+    cursor: Cursor<&'a [u8]>
+}
+
+impl<'a> NdrReader<'a> {
+    /// Skip type serialization headers and the top level pointer
+    pub fn new(buf: &'a [u8]) -> KerlabResult<Self> {
+        let mut cursor = Cursor::new(buf);
+        CommonTypeHeader::default().read(&mut cursor)?;
+        PrivateHeader::default().read(&mut cursor)?;
+        cursor.read_u32::<LittleEndian>()?;
+        Ok(Self {
+            cursor
+        })
+    }
+
+    pub fn read_u16(&mut self) -> KerlabResult<u16> {
+        Ok(self.cursor.read_u16::<LittleEndian>()?)
+    }
+
+    pub fn read_u32(&mut self) -> KerlabResult<u32> {
+        Ok(self.cursor.read_u32::<LittleEndian>()?)
+    }
+
+    pub fn read_bytes(&mut self, size: usize) -> KerlabResult<Vec<u8>> {
+        let mut result = vec![0; size];
+        self.cursor.read_exact(&mut result)?;
+        Ok(result)
+    }
+
+    pub fn read(&mut self, message: &mut dyn Message) -> KerlabResult<()> {
+        message.read(&mut self.cursor)
+    }
+
+    /// Read a conformant array
+    /// Conformant varying array have an offset and an actual count
+    pub fn read_array(&mut self, element_size: u64, varying: bool) -> KerlabResult<Vec<u8>> {
+        let mut count = self.read_u32()? as u64;
+        if varying {
+            self.read_u32()?;
+            count = self.read_u32()? as u64;
+        }
+        let start = self.cursor.position();
+        let end = start + count * element_size;
+        if end > self.cursor.get_ref().len() as u64 {
+            return Err(Error::new(KerlabErrorKind::Parsing, "NDR array overflow"))
+        }
+        let result = self.cursor.get_ref()[start as usize..end as usize].to_vec();
+        // next element is aligned on 4 bytes
+        self.cursor.set_position((end + 3) & !3);
+        Ok(result)
+    }
+
+    /// Deferred buffer of a RPC_UNICODE_STRING
+    pub fn read_unicode_string(&mut self, header: &RpcUnicodeString) -> KerlabResult<String> {
+        if header.buffer == 0 {
+            return Ok(String::new())
+        }
+        let raw = self.read_array(2, true)?;
+        let chars = raw.chunks(2)
+            .map(|e| u16::from_le_bytes([e[0], e[1]]))
+            .collect::<Vec<u16>>();
+        String::from_utf16(&chars).map_err(|_| Error::new(KerlabErrorKind::Parsing, "utf16"))
+    }
+
+    /// Deferred SID
+    pub fn read_sid(&mut self, pointer: u32) -> KerlabResult<Option<Sid>> {
+        if pointer == 0 {
+            return Ok(None)
+        }
+        Ok(Some(Sid::read_ndr(&mut self.cursor)?))
+    }
+
+    /// Deferred array of GROUP_MEMBERSHIP
+    pub fn read_groups(&mut self, pointer: u32) -> KerlabResult<Vec<GroupMembership>> {
+        let mut result = vec![];
+        if pointer == 0 {
+            return Ok(result)
+        }
+        let count = self.read_u32()?;
+        for _ in 0..count {
+            let mut group = GroupMembership::default();
+            self.read(&mut group)?;
+            result.push(group);
+        }
+        Ok(result)
+    }
+
+    /// Deferred array of KERB_SID_AND_ATTRIBUTES
+    pub fn read_sids_and_attributes(&mut self, pointer: u32) -> KerlabResult<Vec<KerbSidAndAttributes>> {
+        let mut result = vec![];
+        if pointer == 0 {
+            return Ok(result)
+        }
+        let count = self.read_u32()?;
+        let mut pointers = vec![];
+        for _ in 0..count {
+            pointers.push(self.read_u32()?);
+            result.push(KerbSidAndAttributes {
+                sid: Sid::default(),
+                attributes: self.read_u32()?
+            });
+        }
+        for (element, pointer) in result.iter_mut().zip(pointers) {
+            if let Some(sid) = self.read_sid(pointer)? {
+                element.sid = sid;
+            }
+        }
+        Ok(result)
+    }
+}
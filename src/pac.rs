@@ -1,9 +1,13 @@
 use std::io::{Read, Write, Cursor};
-use byteorder::{LittleEndian, ReadBytesExt};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use error::{KerlabResult, KerlabErrorKind, Error};
-use asn1::OctetString;
-use ndr::{FileTime, CommonTypeHeader, PrivateHeader, RpcUnicodeString};
+use asn1::{OctetString, Integer, Tag, to_der};
+use base::{AuthorizationData, AuthorizationDataElement};
+use ticket::AdDataType;
+use ndr::{FileTime, RpcUnicodeString, Sid, GroupMembership, KerbSidAndAttributes, NdrReader, NdrWriter};
 use message::{Message, U32LE};
+use encryption::{EncryptionKey, EncryptedData, KeyUsage};
+use checksum::Checksum;
 
 fn read_utf16(buf: &[u8]) -> KerlabResult<String> {
     let mut cursor = Cursor::new(buf);
@@ -35,11 +39,12 @@ impl PacInfoBuffer {
         let view = &buffer[self.offset as usize..self.offset as usize + self.cb_buffer_size as usize];
         match self.ul_type {
             0x00000001 => Ok(PacStruct::KerbValidationInfo(KerbValidationInfo::from(view)?)),
+            0x00000002 => Ok(PacStruct::PacCredentialInfo(PacCredentialInfo::from(view)?)),
             0x00000006 => Ok(PacStruct::ServerChecksum(PacSignatureData::from(view)?)),
             0x00000007 => Ok(PacStruct::KDCChecksum(PacSignatureData::from(view)?)),
             0x0000000A => Ok(PacStruct::PacClientInfo(PacClientInfo::from(view)?)),
             0x0000000C => Ok(PacStruct::UpnDnsInfo(UpnDnsInfo::from(view)?)),
-            _ => Err(Error::new(KerlabErrorKind::Parsing, &format!("Unimplemented PacDataType {}", self.ul_type)))
+            _ => Ok(PacStruct::Unknown(self.ul_type, view.to_vec()))
         }
     }
 }
@@ -90,39 +95,386 @@ impl PacType {
             buffers
         })
     }
+
+    /// constructor
+    pub fn new(buffers: Vec<PacStruct>) -> Self {
+        Self {
+            c_buffers: buffers.len() as u32,
+            version: 0,
+            buffers
+        }
+    }
+
+    /// Serialize the PAC
+    /// Each buffer is aligned on 8 bytes
+    pub fn to_bytes(&self) -> KerlabResult<Vec<u8>> {
+        let mut header = vec![];
+        header.write_u32::<LittleEndian>(self.buffers.len() as u32)?;
+        header.write_u32::<LittleEndian>(self.version)?;
+
+        let mut data = vec![];
+        let data_offset = 8 + 16 * self.buffers.len() as u64;
+        for buffer in &self.buffers {
+            let content = buffer.to_bytes()?;
+            header.write_u32::<LittleEndian>(buffer.ul_type())?;
+            header.write_u32::<LittleEndian>(content.len() as u32)?;
+            header.write_u64::<LittleEndian>(data_offset + data.len() as u64)?;
+            data.extend_from_slice(&content);
+            while data.len() % 8 != 0 {
+                data.push(0);
+            }
+        }
+        header.extend_from_slice(&data);
+        Ok(header)
+    }
+
+    /// PAC is embedded into an AD-IF-RELEVANT element
+    /// of the authorization data of the ticket
+    pub fn to_authorization_data(&self) -> KerlabResult<AuthorizationData> {
+        let if_relevant = vec![AuthorizationDataElement {
+            ad_type: Tag::new(AdDataType::AdWin2kPac as Integer),
+            ad_data: Tag::new(self.to_bytes()?)
+        }];
+        Ok(vec![AuthorizationDataElement {
+            ad_type: Tag::new(AdDataType::AdIfRelevant as Integer),
+            ad_data: Tag::new(to_der(&if_relevant)?)
+        }])
+    }
+
+    /// Compute signatures of the PAC
+    ///
+    /// The server signature is computed over the whole PAC with zeroed signatures,
+    /// the KDC signature is computed over the server signature
+    pub fn sign(&mut self, server_key: &EncryptionKey, kdc_key: &EncryptionKey) -> KerlabResult<()> {
+        for buffer in self.buffers.iter_mut() {
+            match buffer {
+                PacStruct::ServerChecksum(e) => *e = PacSignatureData::new(server_key)?,
+                PacStruct::KDCChecksum(e) => {
+                    // RODC number must survive the new signature
+                    let rodcidentifier = e.rodcidentifier;
+                    *e = PacSignatureData::new(kdc_key)?;
+                    e.rodcidentifier = rodcidentifier;
+                },
+                _ => ()
+            }
+        }
+
+        let data = self.to_bytes()?;
+        let mut server_signature = vec![];
+        for buffer in self.buffers.iter_mut() {
+            if let PacStruct::ServerChecksum(e) = buffer {
+                e.sign(server_key, &data)?;
+                server_signature = e.signature.clone();
+            }
+        }
+
+        for buffer in self.buffers.iter_mut() {
+            if let PacStruct::KDCChecksum(e) = buffer {
+                e.sign(kdc_key, &server_signature)?;
+            }
+        }
+        Ok(())
+    }
 }
 
+/// User flag set when ExtraSids are present
+pub const LOGON_EXTRA_SIDS: u32 = 0x00000020;
+/// User flag set when resource groups are present
+pub const LOGON_RESOURCE_GROUPS: u32 = 0x00000200;
+
 /// This is the most import information
-/// It use RPC marshalling it's partially implemented in kerlab
+/// It use RPC marshalling
 /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-pac/69e86ccc-85e3-41b9-b514-7d969cd0ed73
-#[derive(Component, Default)]
+#[derive(Default, Clone)]
 pub struct KerbValidationInfo {
-    pub common_header: CommonTypeHeader,
-    pub private_header: PrivateHeader,
-    pub padding: U32LE,
     pub logon_time: FileTime,
     pub logoff_time: FileTime,
     pub kick_off_time: FileTime,
     pub password_last_set: FileTime,
     pub password_can_change: FileTime,
     pub password_must_change: FileTime,
-    pub effective_name: RpcUnicodeString,
-    /*pub full_name: RpcUnicodeString,
-    pub logon_scripts: RpcUnicodeString,
-    pub profile_path: RpcUnicodeString,
-    pub home_directory: RpcUnicodeString,
-    pub home_directory_drive: RpcUnicodeString,
+    pub effective_name: String,
+    pub full_name: String,
+    pub logon_script: String,
+    pub profile_path: String,
+    pub home_directory: String,
+    pub home_directory_drive: String,
     pub logon_count: u16,
-    pub bad_password_count: u16,*/
+    pub bad_password_count: u16,
+    pub user_id: u32,
+    pub primary_group_id: u32,
+    pub group_ids: Vec<GroupMembership>,
+    pub user_flags: u32,
+    pub user_session_key: Vec<u8>,
+    pub logon_server: String,
+    pub logon_domain_name: String,
+    pub logon_domain_id: Option<Sid>,
+    pub user_account_control: u32,
+    pub sub_auth_status: u32,
+    pub last_successful_i_logon: FileTime,
+    pub last_failed_i_logon: FileTime,
+    pub failed_i_logon_count: u32,
+    pub extra_sids: Vec<KerbSidAndAttributes>,
+    pub resource_group_domain_sid: Option<Sid>,
+    pub resource_group_ids: Vec<GroupMembership>
 }
 
 impl KerbValidationInfo {
     fn from(buf: &[u8]) -> KerlabResult<Self> {
-        let mut cursor = Cursor::new(buf);
+        let mut reader = NdrReader::new(buf)?;
         let mut result = KerbValidationInfo::default();
-        result.read(&mut cursor)?;
+
+        reader.read(&mut result.logon_time)?;
+        reader.read(&mut result.logoff_time)?;
+        reader.read(&mut result.kick_off_time)?;
+        reader.read(&mut result.password_last_set)?;
+        reader.read(&mut result.password_can_change)?;
+        reader.read(&mut result.password_must_change)?;
+
+        let mut names = vec![];
+        for _ in 0..6 {
+            let mut name = RpcUnicodeString::default();
+            reader.read(&mut name)?;
+            names.push(name);
+        }
+
+        result.logon_count = reader.read_u16()?;
+        result.bad_password_count = reader.read_u16()?;
+        result.user_id = reader.read_u32()?;
+        result.primary_group_id = reader.read_u32()?;
+        reader.read_u32()?;
+        let group_ids = reader.read_u32()?;
+        result.user_flags = reader.read_u32()?;
+        result.user_session_key = reader.read_bytes(16)?;
+
+        let mut logon_server = RpcUnicodeString::default();
+        reader.read(&mut logon_server)?;
+        let mut logon_domain_name = RpcUnicodeString::default();
+        reader.read(&mut logon_domain_name)?;
+        let logon_domain_id = reader.read_u32()?;
+
+        // reserved1
+        reader.read_u32()?;
+        reader.read_u32()?;
+        result.user_account_control = reader.read_u32()?;
+        result.sub_auth_status = reader.read_u32()?;
+        reader.read(&mut result.last_successful_i_logon)?;
+        reader.read(&mut result.last_failed_i_logon)?;
+        result.failed_i_logon_count = reader.read_u32()?;
+        // reserved3
+        reader.read_u32()?;
+        // sid count, also given by the conformant array of extra sids
+        reader.read_u32()?;
+        let extra_sids = reader.read_u32()?;
+        let resource_group_domain_sid = reader.read_u32()?;
+        reader.read_u32()?;
+        let resource_group_ids = reader.read_u32()?;
+
+        // deferred data
+        let mut strings = vec![];
+        for name in &names {
+            strings.push(reader.read_unicode_string(name)?);
+        }
+        result.home_directory_drive = strings.pop().unwrap_or_default();
+        result.home_directory = strings.pop().unwrap_or_default();
+        result.profile_path = strings.pop().unwrap_or_default();
+        result.logon_script = strings.pop().unwrap_or_default();
+        result.full_name = strings.pop().unwrap_or_default();
+        result.effective_name = strings.pop().unwrap_or_default();
+
+        result.group_ids = reader.read_groups(group_ids)?;
+        result.logon_server = reader.read_unicode_string(&logon_server)?;
+        result.logon_domain_name = reader.read_unicode_string(&logon_domain_name)?;
+        result.logon_domain_id = reader.read_sid(logon_domain_id)?;
+        result.extra_sids = reader.read_sids_and_attributes(extra_sids)?;
+        result.resource_group_domain_sid = reader.read_sid(resource_group_domain_sid)?;
+        result.resource_group_ids = reader.read_groups(resource_group_ids)?;
         Ok(result)
     }
+
+    /// Serialize using NDR type serialization
+    pub fn to_bytes(&self) -> KerlabResult<Vec<u8>> {
+        let mut writer = NdrWriter::new();
+        writer.write(&self.logon_time)?;
+        writer.write(&self.logoff_time)?;
+        writer.write(&self.kick_off_time)?;
+        writer.write(&self.password_last_set)?;
+        writer.write(&self.password_can_change)?;
+        writer.write(&self.password_must_change)?;
+        writer.write_unicode_string(&self.effective_name)?;
+        writer.write_unicode_string(&self.full_name)?;
+        writer.write_unicode_string(&self.logon_script)?;
+        writer.write_unicode_string(&self.profile_path)?;
+        writer.write_unicode_string(&self.home_directory)?;
+        writer.write_unicode_string(&self.home_directory_drive)?;
+        writer.write_u16(self.logon_count)?;
+        writer.write_u16(self.bad_password_count)?;
+        writer.write_u32(self.user_id)?;
+        writer.write_u32(self.primary_group_id)?;
+        writer.write_u32(self.group_ids.len() as u32)?;
+        writer.write_groups(&self.group_ids)?;
+        writer.write_u32(self.user_flags)?;
+        writer.write_bytes(&self.user_session_key);
+        writer.write_unicode_string(&self.logon_server)?;
+        writer.write_unicode_string(&self.logon_domain_name)?;
+        writer.write_sid(self.logon_domain_id.as_ref())?;
+        writer.write_u32(0)?;
+        writer.write_u32(0)?;
+        writer.write_u32(self.user_account_control)?;
+        writer.write_u32(self.sub_auth_status)?;
+        writer.write(&self.last_successful_i_logon)?;
+        writer.write(&self.last_failed_i_logon)?;
+        writer.write_u32(self.failed_i_logon_count)?;
+        writer.write_u32(0)?;
+        writer.write_u32(self.extra_sids.len() as u32)?;
+        writer.write_sids_and_attributes(&self.extra_sids)?;
+        writer.write_sid(self.resource_group_domain_sid.as_ref())?;
+        writer.write_u32(self.resource_group_ids.len() as u32)?;
+        writer.write_groups(&self.resource_group_ids)?;
+        writer.serialize()
+    }
+}
+
+/// Credentials of the user, encrypted with the AS reply key
+/// Only present after a PKINIT authentication
+/// @see [MS-PAC] 2.6.1 PAC_CREDENTIAL_INFO
+#[derive(Default)]
+pub struct PacCredentialInfo {
+    pub version: u32,
+    pub encryption_type: u32,
+    pub serialized_data: Vec<u8>
+}
+
+impl PacCredentialInfo {
+    fn from(buf: &[u8]) -> KerlabResult<Self> {
+        let mut cursor = Cursor::new(buf);
+        let version = cursor.read_u32::<LittleEndian>()?;
+        let encryption_type = cursor.read_u32::<LittleEndian>()?;
+        Ok(PacCredentialInfo {
+            version,
+            encryption_type,
+            serialized_data: buf[cursor.position() as usize..].to_vec()
+        })
+    }
+
+    fn to_bytes(&self) -> KerlabResult<Vec<u8>> {
+        let mut result = vec![];
+        result.write_u32::<LittleEndian>(self.version)?;
+        result.write_u32::<LittleEndian>(self.encryption_type)?;
+        result.extend_from_slice(&self.serialized_data);
+        Ok(result)
+    }
+
+    /// Decrypt credentials using the reply key of the AS exchange
+    pub fn decrypt(&self, key: &EncryptionKey) -> KerlabResult<PacCredentialData> {
+        let plaintext = key.decrypt_raw(
+            KeyUsage::KeyUsagePacCredentials,
+            &EncryptedData::new(self.encryption_type, self.serialized_data.clone())
+        )?;
+        PacCredentialData::from(&plaintext)
+    }
+}
+
+/// Fixed part of SECPKG_SUPPLEMENTAL_CRED
+/// @see [MS-PAC] 2.6.3 SECPKG_SUPPLEMENTAL_CRED
+#[derive(Component, Default)]
+struct SecPkgSupplementalCredHeader {
+    package_name: RpcUnicodeString,
+    credential_size: U32LE,
+    credentials: U32LE
+}
+
+/// A credential associated to a security package
+pub struct SecPkgSupplementalCred {
+    pub package_name: String,
+    pub credentials: Vec<u8>
+}
+
+/// PAC_CREDENTIAL_DATA
+/// It use RPC marshalling
+/// @see [MS-PAC] 2.6.2 PAC_CREDENTIAL_DATA
+#[derive(Default)]
+pub struct PacCredentialData {
+    pub credentials: Vec<SecPkgSupplementalCred>
+}
+
+impl PacCredentialData {
+    fn from(buf: &[u8]) -> KerlabResult<Self> {
+        let mut reader = NdrReader::new(buf)?;
+
+        // conformant structure start with the size of the array
+        reader.read_u32()?;
+        let credential_count = reader.read_u32()?;
+
+        let mut headers = vec![];
+        for _ in 0..credential_count {
+            let mut header = SecPkgSupplementalCredHeader::default();
+            reader.read(&mut header)?;
+            headers.push(header);
+        }
+
+        // pointed data are deferred after the array
+        let mut result = PacCredentialData::default();
+        for header in headers {
+            let package_name = reader.read_unicode_string(&header.package_name)?;
+            let mut credentials = vec![];
+            if header.credentials != 0 {
+                credentials = reader.read_array(1, false)?;
+            }
+            result.credentials.push(SecPkgSupplementalCred {
+                package_name,
+                credentials
+            });
+        }
+        Ok(result)
+    }
+
+    /// Find the credential of the NTLM package
+    pub fn ntlm(&self) -> KerlabResult<Option<NtlmSupplementalCredential>> {
+        match self.credentials.iter().find(|e| e.package_name == "NTLM") {
+            Some(credential) => Ok(Some(NtlmSupplementalCredential::from(&credential.credentials)?)),
+            None => Ok(None)
+        }
+    }
+}
+
+/// NTLM_SUPPLEMENTAL_CREDENTIAL
+/// @see [MS-PAC] 2.6.4 NTLM_SUPPLEMENTAL_CREDENTIAL
+#[derive(Default)]
+pub struct NtlmSupplementalCredential {
+    pub version: u32,
+    pub flags: u32,
+    pub lm_password: Vec<u8>,
+    pub nt_password: Vec<u8>
+}
+
+impl NtlmSupplementalCredential {
+    /// LM one way function is present
+    pub const NTLM_LM_OWF: u32 = 0x00000001;
+    /// NT one way function is present
+    pub const NTLM_NT_OWF: u32 = 0x00000002;
+
+    fn from(buf: &[u8]) -> KerlabResult<Self> {
+        let mut cursor = Cursor::new(buf);
+        let mut result = NtlmSupplementalCredential {
+            version: cursor.read_u32::<LittleEndian>()?,
+            flags: cursor.read_u32::<LittleEndian>()?,
+            lm_password: vec![0; 16],
+            nt_password: vec![0; 16]
+        };
+        cursor.read_exact(&mut result.lm_password)?;
+        cursor.read_exact(&mut result.nt_password)?;
+        Ok(result)
+    }
+
+    /// NT hash of the user if present
+    pub fn nt_hash(&self) -> Option<&[u8]> {
+        if self.flags & Self::NTLM_NT_OWF != 0 {
+            Some(&self.nt_password)
+        } else {
+            None
+        }
+    }
 }
 
 /// PAC client information
@@ -135,6 +487,26 @@ pub struct PacClientInfo {
 }
 
 impl PacClientInfo {
+    /// constructor
+    /// client_id is the authentication time of the ticket
+    pub fn new(client_id: FileTime, name: &str) -> Self {
+        Self {
+            client_id,
+            name_length: name.encode_utf16().count() as u16 * 2,
+            name: name.to_string()
+        }
+    }
+
+    fn to_bytes(&self) -> KerlabResult<Vec<u8>> {
+        let mut result = vec![];
+        self.client_id.write(&mut result)?;
+        result.write_u16::<LittleEndian>(self.name_length)?;
+        for c in self.name.encode_utf16() {
+            result.write_u16::<LittleEndian>(c)?;
+        }
+        Ok(result)
+    }
+
     fn from(buf: &[u8]) -> KerlabResult<Self> {
         let mut cursor = Cursor::new(buf);
         let mut result = PacClientInfo::default();
@@ -175,6 +547,31 @@ impl UpnDnsInfo {
         result.dns = read_utf16(&buf[result.dns_domain_name_offset as usize..(result.dns_domain_name_offset + result.dns_domain_name_length) as usize])?;
         Ok(result)
     }
+
+    fn to_bytes(&self) -> KerlabResult<Vec<u8>> {
+        let upn = self.upn.encode_utf16().collect::<Vec<u16>>();
+        let dns = self.dns.encode_utf16().collect::<Vec<u16>>();
+
+        // strings follow the header, aligned on 8 bytes
+        let upn_offset = 16;
+        let dns_offset = (upn_offset + upn.len() * 2 + 7) & !7;
+
+        let mut result = vec![];
+        result.write_u16::<LittleEndian>(upn.len() as u16 * 2)?;
+        result.write_u16::<LittleEndian>(upn_offset as u16)?;
+        result.write_u16::<LittleEndian>(dns.len() as u16 * 2)?;
+        result.write_u16::<LittleEndian>(dns_offset as u16)?;
+        result.write_u32::<LittleEndian>(self.flags)?;
+        result.resize(upn_offset, 0);
+        for c in upn {
+            result.write_u16::<LittleEndian>(c)?;
+        }
+        result.resize(dns_offset, 0);
+        for c in dns {
+            result.write_u16::<LittleEndian>(c)?;
+        }
+        Ok(result)
+    }
 }
 
 /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-pac/6e95edd3-af93-41d4-8303-6c7955297315
@@ -186,6 +583,40 @@ pub struct PacSignatureData {
 }
 
 impl PacSignatureData {
+    /// Zeroed signature of the checksum type associated to the key
+    pub fn new(key: &EncryptionKey) -> KerlabResult<Self> {
+        let (signature_type, size) = match key.keytype.inner {
+            17 => (0x0000000F, 12),
+            18 => (0x00000010, 12),
+            23 => (0xFFFFFF76, 16),
+            _ => return Err(Error::new(KerlabErrorKind::Crypto, "Unsupported Algorithm"))
+        };
+        Ok(Self {
+            signature_type,
+            signature: vec![0; size],
+            rodcidentifier: None
+        })
+    }
+
+    /// Keyed checksum of the PAC
+    fn sign(&mut self, key: &EncryptionKey, data: &[u8]) -> KerlabResult<()> {
+        self.signature = match key.keytype.inner {
+            17 | 18 | 23 => Checksum::compute(key, KeyUsage::KeyUsageKerbNonKerbCksumSalt, data)?.checksum().to_vec(),
+            _ => return Err(Error::new(KerlabErrorKind::Crypto, "Unsupported Algorithm"))
+        };
+        Ok(())
+    }
+
+    fn to_bytes(&self) -> KerlabResult<Vec<u8>> {
+        let mut result = vec![];
+        result.write_u32::<LittleEndian>(self.signature_type)?;
+        result.extend_from_slice(&self.signature);
+        if let Some(rodcidentifier) = self.rodcidentifier {
+            result.write_u16::<LittleEndian>(rodcidentifier)?;
+        }
+        Ok(result)
+    }
+
     fn from(buf: &[u8]) -> KerlabResult<Self> {
         let mut cursor = Cursor::new(buf);
         let mut result = PacSignatureData::default();
@@ -207,8 +638,102 @@ impl PacSignatureData {
 /// Generic PAC structure that encompass all structe handled by kerlab
 pub enum PacStruct {
     KerbValidationInfo(KerbValidationInfo),
+    PacCredentialInfo(PacCredentialInfo),
     PacClientInfo(PacClientInfo),
     UpnDnsInfo(UpnDnsInfo),
     ServerChecksum(PacSignatureData),
-    KDCChecksum(PacSignatureData)
-}
\ No newline at end of file
+    KDCChecksum(PacSignatureData),
+    /// Buffer type not handled by kerlab
+    Unknown(u32, Vec<u8>)
+}
+
+impl PacStruct {
+    /// ulType of the PAC_INFO_BUFFER
+    pub fn ul_type(&self) -> u32 {
+        match self {
+            PacStruct::KerbValidationInfo(_) => 0x00000001,
+            PacStruct::PacCredentialInfo(_) => 0x00000002,
+            PacStruct::ServerChecksum(_) => 0x00000006,
+            PacStruct::KDCChecksum(_) => 0x00000007,
+            PacStruct::PacClientInfo(_) => 0x0000000A,
+            PacStruct::UpnDnsInfo(_) => 0x0000000C,
+            PacStruct::Unknown(ul_type, _) => *ul_type
+        }
+    }
+
+    fn to_bytes(&self) -> KerlabResult<Vec<u8>> {
+        match self {
+            PacStruct::KerbValidationInfo(e) => e.to_bytes(),
+            PacStruct::PacCredentialInfo(e) => e.to_bytes(),
+            PacStruct::ServerChecksum(e) => e.to_bytes(),
+            PacStruct::KDCChecksum(e) => e.to_bytes(),
+            PacStruct::PacClientInfo(e) => e.to_bytes(),
+            PacStruct::UpnDnsInfo(e) => e.to_bytes(),
+            PacStruct::Unknown(_, e) => Ok(e.clone())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use aescts::AesCts;
+    use byteorder::WriteBytesExt;
+
+    /// PAC_CREDENTIAL_DATA with a single NTLM credential
+    fn credential_data(nt_hash: &[u8]) -> Vec<u8> {
+        let mut ntlm = vec![];
+        ntlm.write_u32::<LittleEndian>(0).unwrap();
+        ntlm.write_u32::<LittleEndian>(NtlmSupplementalCredential::NTLM_NT_OWF).unwrap();
+        ntlm.extend_from_slice(&[0; 16]);
+        ntlm.extend_from_slice(nt_hash);
+
+        let mut body = vec![];
+        // referent, array size and credential count
+        for value in &[0x00020000, 1, 1] {
+            body.write_u32::<LittleEndian>(*value).unwrap();
+        }
+        // RPC_UNICODE_STRING "NTLM", size and pointer
+        body.write_u16::<LittleEndian>(8).unwrap();
+        body.write_u16::<LittleEndian>(8).unwrap();
+        for value in &[0x00020004, ntlm.len() as u32, 0x00020008] {
+            body.write_u32::<LittleEndian>(*value).unwrap();
+        }
+        // deferred package name
+        for value in &[4, 0, 4] {
+            body.write_u32::<LittleEndian>(*value).unwrap();
+        }
+        for c in "NTLM".encode_utf16() {
+            body.write_u16::<LittleEndian>(c).unwrap();
+        }
+        // deferred credentials
+        body.write_u32::<LittleEndian>(ntlm.len() as u32).unwrap();
+        body.extend_from_slice(&ntlm);
+
+        let mut result = vec![0x01, 0x10, 0x08, 0x00, 0xcc, 0xcc, 0xcc, 0xcc];
+        result.write_u32::<LittleEndian>(body.len() as u32).unwrap();
+        result.write_u32::<LittleEndian>(0).unwrap();
+        result.extend_from_slice(&body);
+        result
+    }
+
+    /// NT hash must be recovered from an encrypted PAC_CREDENTIAL_INFO
+    #[test]
+    fn test_pac_credential_info() {
+        let nt_hash = [0x31, 0xd6, 0xcf, 0xe0, 0xd1, 0x6a, 0xe9, 0x31, 0xb7, 0x3c, 0x59, 0xd7, 0xe0, 0xc0, 0x89, 0xc0];
+        let key = EncryptionKey::random(18).unwrap();
+
+        let mut buffer = vec![];
+        buffer.write_u32::<LittleEndian>(0).unwrap();
+        buffer.write_u32::<LittleEndian>(18).unwrap();
+        buffer.extend_from_slice(&AesCts::new(
+            key.keyvalue.inner.clone(),
+            KeyUsage::KeyUsagePacCredentials
+        ).encrypt(&credential_data(&nt_hash)).unwrap());
+
+        let credential_info = PacCredentialInfo::from(&buffer).unwrap();
+        let credentials = credential_info.decrypt(&key).unwrap();
+        assert_eq!(credentials.credentials[0].package_name, "NTLM");
+        assert_eq!(credentials.ntlm().unwrap().unwrap().nt_hash(), Some(&nt_hash[..]));
+    }
+}
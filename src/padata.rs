@@ -1,10 +1,10 @@
-use asn1::{Tag, Integer, OctetString, ASN1, to_der, GeneralString, SInteger};
-use error::{KerlabResult};
-use base::{KerberosTime, PrincipalName, Realm};
+use asn1::{Tag, Integer, OctetString, ASN1, SequenceOf, to_der, from_der, GeneralString};
+use error::{KerlabResult, Error, KerlabErrorKind};
+use base::{KerberosTime, PrincipalName, Realm, DEFAULT_MAX_SKEW_MINUTES};
 use yasna::{DERWriter, BERReader};
-use chrono::{Utc};
-use encryption::{EncryptionKey, KeyUsage};
-use checksum::{Checksum, kerberos_hmac_md5};
+use chrono::{Utc, Duration, DateTime, Timelike};
+use encryption::{EncryptionKey, KeyUsage, EncryptedData, ChecksumType, krb_fx_cf2};
+use checksum::Checksum;
 use std::str::FromStr;
 
 #[repr(u32)]
@@ -72,38 +72,174 @@ pub struct PaData {
 }
 
 impl PaData {
-    pub fn new(patype: PaDataType, pavalue: &dyn ASN1) -> Self {
-        Self {
+    pub fn new(patype: PaDataType, pavalue: &dyn ASN1) -> KerlabResult<Self> {
+        Ok(Self {
             padata_type: Tag::new(patype as Integer),
-            padata_value: Tag::new(to_der(pavalue))
-        }
+            padata_value: Tag::new(to_der(pavalue)?)
+        })
     }
 
     /// Use to format an enc timestamp use in pre authentication
     pub fn pa_enc_timestamp(key: &EncryptionKey) -> KerlabResult<Self> {
-        Ok(PaData::new(
-                PaDataType::PaEncTimestamp,
-                &key.encrypt(
-                    KeyUsage::KeyUsageAsReqTimestamp,
-                    &PaEncTsEnc::now())?
-            )
+        Self::pa_enc_timestamp_at(key, Utc::now())
+    }
+
+    /// Encrypted timestamp at a given time
+    /// use to compensate the clock skew with the KDC
+    pub fn pa_enc_timestamp_at(key: &EncryptionKey, time: DateTime<Utc>) -> KerlabResult<Self> {
+        PaData::new(
+            PaDataType::PaEncTimestamp,
+            &key.encrypt(
+                KeyUsage::KeyUsageAsReqTimestamp,
+                &PaEncTsEnc::at(time))?
+        )
+    }
+
+    /// Pre authentication use inside a FAST tunnel
+    /// timestamp is encrypted with a key derived from armor key and user key
+    /// @see https://tools.ietf.org/html/rfc6113#section-5.4.6
+    pub fn pa_encrypted_challenge(armor_key: &EncryptionKey, key: &EncryptionKey) -> KerlabResult<Self> {
+        Self::pa_encrypted_challenge_at(armor_key, key, Utc::now())
+    }
+
+    /// Encrypted challenge at a given time
+    pub fn pa_encrypted_challenge_at(armor_key: &EncryptionKey, key: &EncryptionKey, time: DateTime<Utc>) -> KerlabResult<Self> {
+        let challenge_key = krb_fx_cf2(armor_key, key, "clientchallengearmor", "challengelongterm")?;
+        PaData::new(
+            PaDataType::PaEncryptedChallenge,
+            &challenge_key.encrypt(
+                KeyUsage::KeyUsageEncChallengeClient,
+                &PaEncTsEnc::at(time))?
+        )
+    }
+
+    /// Check the encrypted challenge sent back by the KDC into the FAST response
+    /// The KDC proves it knows the user key by encrypting its own timestamp
+    pub fn verify_kdc_challenge(&self, armor_key: &EncryptionKey, key: &EncryptionKey) -> KerlabResult<PaEncTsEnc> {
+        if self.padata_type.inner != PaDataType::PaEncryptedChallenge as Integer {
+            return Err(Error::new(KerlabErrorKind::Kerberos, "Not a PA-ENCRYPTED-CHALLENGE"))
+        }
+
+        let mut challenge = EncryptedData::default();
+        from_der(&mut challenge, &self.padata_value)?;
+
+        let challenge_key = krb_fx_cf2(armor_key, key, "kdcchallengearmor", "challengelongterm")?;
+        let timestamp = challenge_key.decrypt::<PaEncTsEnc>(
+            KeyUsage::KeyUsageEncChallengeKdc,
+            &challenge
+        )?;
+
+        if (Utc::now() - timestamp.patimestamp.inner.inner).num_seconds().abs() > Duration::minutes(DEFAULT_MAX_SKEW_MINUTES).num_seconds() {
+            return Err(Error::new(KerlabErrorKind::Kerberos, "KDC encrypted challenge is out of the clock skew"))
+        }
+
+        Ok(timestamp)
+    }
+
+    /// Ask a writable DC for the long term keys of the client of a RODC TGT
+    /// @see [MS-KILE] KERB-KEY-LIST-REQ
+    pub fn kerb_key_list_req(etypes: &[Integer]) -> KerlabResult<Self> {
+        PaData::new(
+            PaDataType::KerbKeyListReq,
+            &etypes.to_vec()
+        )
+    }
+
+    /// Long term keys returned into the encrypted padata of the TGS-REP
+    pub fn key_list(&self) -> KerlabResult<KerbKeyListRep> {
+        if self.padata_type.inner != PaDataType::KerbKeyListRep as Integer {
+            return Err(Error::new(KerlabErrorKind::Kerberos, "Not a KERB-KEY-LIST-REP"))
+        }
+        let mut keys = KerbKeyListRep::default();
+        from_der(&mut keys, &self.padata_value)?;
+        Ok(keys)
+    }
+
+    /// Ask the KDC to include or to omit the PAC from the ticket
+    pub fn pa_pac_request(include_pac: bool) -> KerlabResult<Self> {
+        PaData::new(
+            PaDataType::PaPacRequest,
+            &KerbPaPacRequest::new(include_pac)
         )
     }
 
     /// use in S4u protocol extension
     pub fn pa_for_user(user_name: PrincipalName, user_realm: Realm, key: &EncryptionKey) -> KerlabResult<Self>{
-        Ok(PaData::new(
-                PaDataType::PaForUser,
-                &PaForUser::new(
-                    user_name,
-                    user_realm,
-                    key
-                )?
-            )
+        PaData::new(
+            PaDataType::PaForUser,
+            &PaForUser::new(
+                user_name,
+                user_realm,
+                key
+            )?
         )
     }
 }
 
+/// ```asn.1
+/// KERB-KEY-LIST-REQ ::= SEQUENCE OF Int32 -- encryption type --
+/// ```
+pub type KerbKeyListReq = SequenceOf<Integer>;
+
+/// ```asn.1
+/// KERB-KEY-LIST-REP ::= SEQUENCE OF EncryptionKey
+/// ```
+pub type KerbKeyListRep = SequenceOf<EncryptionKey>;
+
+/// @see [MS-KILE] 2.2.3 KERB-PA-PAC-REQUEST
+/// ```asn.1
+/// KERB-PA-PAC-REQUEST ::= SEQUENCE {
+///        include-pac[0] BOOLEAN
+///        -- If TRUE, and no pac present, include PAC.
+///        -- If FALSE, and PAC present, remove PAC
+/// }
+/// ```
+#[derive(Sequence, PartialEq, Default)]
+pub struct KerbPaPacRequest {
+    pub include_pac: Tag<0, bool>
+}
+
+impl KerbPaPacRequest {
+    pub fn new(include_pac: bool) -> Self {
+        Self {
+            include_pac: Tag::new(include_pac)
+        }
+    }
+}
+
+/// @see https://tools.ietf.org/html/rfc4120#section-5.2.7.5
+/// ```asn.1
+/// ETYPE-INFO2-ENTRY       ::= SEQUENCE {
+///         etype           [0] Int32,
+///         salt            [1] KerberosString OPTIONAL,
+///         s2kparams       [2] OCTET STRING OPTIONAL
+/// }
+/// ```
+#[derive(Sequence, PartialEq, Default, Clone)]
+pub struct EtypeInfo2Entry {
+    pub etype: Tag<0, Integer>,
+    pub salt: Option<Tag<1, GeneralString>>,
+    pub s2kparams: Option<Tag<2, OctetString>>
+}
+
+impl EtypeInfo2Entry {
+    pub fn new(etype: Integer, salt: Option<&str>) -> KerlabResult<Self> {
+        Ok(Self {
+            etype: Tag::new(etype),
+            salt: match salt {
+                Some(salt) => Some(Tag::new(GeneralString::from_str(salt)?)),
+                None => None
+            },
+            s2kparams: None
+        })
+    }
+}
+
+/// ```asn.1
+/// ETYPE-INFO2              ::= SEQUENCE SIZE (1..MAX) OF ETYPE-INFO2-ENTRY
+/// ```
+pub type EtypeInfo2 = SequenceOf<EtypeInfo2Entry>;
+
 /// @see https://www.freesoft.org/CIE/RFC/1510/55.htm
 /// ```asn1
 /// PA-ENC-TS-ENC   ::= SEQUENCE {
@@ -119,9 +255,14 @@ pub struct PaEncTsEnc {
 
 impl PaEncTsEnc {
     pub fn now() -> Self {
+        Self::at(Utc::now())
+    }
+
+    /// Timestamp at a given time, the KDC one when the clocks are skewed
+    pub fn at(time: DateTime<Utc>) -> Self {
         Self {
-            patimestamp: Tag::new(KerberosTime::new( Utc::now())),
-            pausec: None
+            patimestamp: Tag::new(KerberosTime::new(time)),
+            pausec: Some(Tag::new(time.nanosecond() / 1000 % 1000000))
         }
     }
 }
@@ -138,10 +279,10 @@ impl PaEncTsEnc {
 /// ```
 #[derive(Sequence, PartialEq, Default)]
 pub struct PaForUser {
-    user_name: Tag<0, PrincipalName>,
-    user_realm: Tag<1, Realm>,
-    cksum: Tag<2, Checksum>,
-    auth_package: Tag<3, GeneralString>
+    pub user_name: Tag<0, PrincipalName>,
+    pub user_realm: Tag<1, Realm>,
+    pub cksum: Tag<2, Checksum>,
+    pub auth_package: Tag<3, GeneralString>
 }
 
 impl PaForUser {
@@ -160,11 +301,77 @@ impl PaForUser {
         Ok(Self {
             user_name: Tag::new(user_name),
             user_realm: Tag::new(user_realm),
-            cksum: Tag::new(Checksum::new(
-                -138 as SInteger,
-                kerberos_hmac_md5(&key.keyvalue.inner, 17, &data)
-            )),
+            // always HMAC-MD5 whatever the session key type
+            cksum: Tag::new(Checksum::compute_with(
+                ChecksumType::HmacMd5,
+                key,
+                KeyUsage::KeyUsageKerbNonKerbCksumSalt,
+                &data
+            )?),
             auth_package: Tag::new(GeneralString::from_str(package)?)
         })
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// KERB-PA-PAC-REQUEST as sent by Windows clients
+    #[test]
+    fn test_pa_pac_request() {
+        let padata = PaData::pa_pac_request(true).unwrap();
+        assert_eq!(padata.padata_type.inner, PaDataType::PaPacRequest as Integer);
+        assert_eq!(padata.padata_value.inner, [0x30, 0x05, 0xa0, 0x03, 0x01, 0x01, 0xff]);
+
+        let mut request = KerbPaPacRequest::default();
+        from_der(&mut request, &PaData::pa_pac_request(false).unwrap().padata_value).unwrap();
+        assert!(!request.include_pac.inner);
+    }
+
+    /// KERB-KEY-LIST-REQ and KERB-KEY-LIST-REP
+    /// @see [MS-KILE] 2.2.11 and 2.2.12
+    #[test]
+    fn test_key_list() {
+        let request = PaData::kerb_key_list_req(&[18, 17, 23]).unwrap();
+        assert_eq!(request.padata_type.inner, PaDataType::KerbKeyListReq as Integer);
+        assert_eq!(request.padata_value.inner, [0x30, 0x09, 0x02, 0x01, 0x12, 0x02, 0x01, 0x11, 0x02, 0x01, 0x17]);
+
+        let key = EncryptionKey::new_rc4_hmac("alice").unwrap();
+        let reply = PaData::new(PaDataType::KerbKeyListRep, &vec![key.clone()]).unwrap();
+        let keys = reply.key_list().unwrap();
+        assert_eq!(keys.len(), 1);
+        assert!(keys[0] == key);
+
+        // only a KERB-KEY-LIST-REP carries keys
+        assert!(request.key_list().is_err());
+    }
+
+    /// Client and KDC challenges use their own pepper and key usage
+    /// @see https://tools.ietf.org/html/rfc6113#section-5.4.6
+    #[test]
+    fn test_encrypted_challenge() {
+        let armor_key = EncryptionKey::random(18).unwrap();
+        let key = EncryptionKey::new_rc4_hmac("alice").unwrap();
+
+        // client challenge, usage 54
+        let padata = PaData::pa_encrypted_challenge(&armor_key, &key).unwrap();
+        let mut challenge = EncryptedData::default();
+        from_der(&mut challenge, &padata.padata_value).unwrap();
+        let client_key = krb_fx_cf2(&armor_key, &key, "clientchallengearmor", "challengelongterm").unwrap();
+        assert!(client_key.decrypt::<PaEncTsEnc>(KeyUsage::KeyUsageEncChallengeClient, &challenge).is_ok());
+        assert!(client_key.decrypt::<PaEncTsEnc>(KeyUsage::KeyUsageEncChallengeKdc, &challenge).is_err());
+        // the client challenge can't be sent back as the KDC one
+        assert!(padata.verify_kdc_challenge(&armor_key, &key).is_err());
+
+        // KDC challenge, usage 55
+        let kdc_key = krb_fx_cf2(&armor_key, &key, "kdcchallengearmor", "challengelongterm").unwrap();
+        let padata = PaData::new(
+            PaDataType::PaEncryptedChallenge,
+            &kdc_key.encrypt(KeyUsage::KeyUsageEncChallengeKdc, &PaEncTsEnc::at(Utc::now())).unwrap()
+        ).unwrap();
+        assert!(padata.verify_kdc_challenge(&armor_key, &key).is_ok());
+        assert!(padata.verify_kdc_challenge(&armor_key, &EncryptionKey::new_rc4_hmac("wrong").unwrap()).is_err());
+        assert!(padata.verify_kdc_challenge(&EncryptionKey::random(18).unwrap(), &key).is_err());
+    }
+}
@@ -0,0 +1,584 @@
+//! Kerberos traffic from pcap and pcapng captures
+//!
+//! TCP streams of port 88 are reassembled and the record mark removed,
+//! UDP datagrams of port 88 carry a single message
+//! @see https://www.tcpdump.org/manpages/pcap-savefile.5.html
+//! @see draft-ietf-opsawg-pcapng PCAP Next Generation Dump File Format
+
+use std::collections::HashMap;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use chrono::{DateTime, Utc, TimeZone};
+use asn1::from_ber;
+use error::{KerlabResult, Error, KerlabErrorKind};
+use krbkdcreq::{AsReq, TgsReq};
+use krbkdcrep::{AsRep, TgsRep};
+use krberror::KrbError;
+use request::MAX_RECORD_SIZE;
+
+/// Kerberos port of the KDC
+pub const KERBEROS_PORT: u16 = 88;
+
+const PCAP_MAGIC: u32 = 0xa1b2c3d4;
+const PCAP_MAGIC_NANO: u32 = 0xa1b23c4d;
+
+/// pcapng blocks
+const PCAPNG_SECTION_HEADER: u32 = 0x0a0d0d0a;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1a2b3c4d;
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 1;
+const PCAPNG_SIMPLE_PACKET: u32 = 3;
+const PCAPNG_ENHANCED_PACKET: u32 = 6;
+const PCAPNG_OPTION_TSRESOL: u16 = 9;
+
+/// Link layer types
+/// @see https://www.tcpdump.org/linktypes.html
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+
+const IPPROTO_TCP: u8 = 6;
+const IPPROTO_UDP: u8 = 17;
+
+const TCP_SYN: u8 = 0x02;
+
+fn truncated() -> Error {
+    Error::new(KerlabErrorKind::Parsing, "Truncated capture")
+}
+
+fn read_u16(buf: &[u8], offset: usize, big_endian: bool) -> KerlabResult<u16> {
+    let bytes = buf.get(offset..offset + 2).ok_or_else(truncated)?;
+    let value = [bytes[0], bytes[1]];
+    Ok(if big_endian { u16::from_be_bytes(value) } else { u16::from_le_bytes(value) })
+}
+
+fn read_u32(buf: &[u8], offset: usize, big_endian: bool) -> KerlabResult<u32> {
+    let bytes = buf.get(offset..offset + 4).ok_or_else(truncated)?;
+    let value = [bytes[0], bytes[1], bytes[2], bytes[3]];
+    Ok(if big_endian { u32::from_be_bytes(value) } else { u32::from_le_bytes(value) })
+}
+
+fn timestamp(seconds: u64, nanoseconds: u32) -> KerlabResult<DateTime<Utc>> {
+    Utc.timestamp_opt(seconds as i64, nanoseconds).single()
+        .ok_or_else(|| Error::new(KerlabErrorKind::Parsing, "Invalid capture timestamp"))
+}
+
+/// A captured link layer frame
+struct Frame<'a> {
+    timestamp: DateTime<Utc>,
+    link_type: u32,
+    data: &'a [u8]
+}
+
+/// Frames of a pcap file
+fn pcap_frames(buf: &[u8]) -> KerlabResult<Vec<Frame<'_>>> {
+    let magic = read_u32(buf, 0, false)?;
+    let (big_endian, nano) = match magic {
+        PCAP_MAGIC => (false, false),
+        PCAP_MAGIC_NANO => (false, true),
+        _ if magic.swap_bytes() == PCAP_MAGIC => (true, false),
+        _ if magic.swap_bytes() == PCAP_MAGIC_NANO => (true, true),
+        _ => return Err(Error::new(KerlabErrorKind::Parsing, "Not a pcap or a pcapng file"))
+    };
+    let link_type = read_u32(buf, 20, big_endian)?;
+
+    let mut frames = vec![];
+    let mut offset = 24;
+    while offset < buf.len() {
+        let seconds = read_u32(buf, offset, big_endian)?;
+        let fraction = read_u32(buf, offset + 4, big_endian)?;
+        let size = read_u32(buf, offset + 8, big_endian)? as usize;
+        let data = buf.get(offset + 16..offset + 16 + size).ok_or_else(truncated)?;
+
+        frames.push(Frame {
+            timestamp: timestamp(seconds as u64, if nano { fraction } else { fraction.saturating_mul(1000) })?,
+            link_type,
+            data
+        });
+        offset += 16 + size;
+    }
+    Ok(frames)
+}
+
+/// Timestamp units per second of an interface
+fn tsresol(value: u8) -> u64 {
+    if value & 0x80 != 0 {
+        1 << (value & 0x7f) as u64
+    } else {
+        10u64.pow(value as u32)
+    }
+}
+
+/// Frames of a pcapng file
+/// Each section has its own byte order and interfaces
+fn pcapng_frames(buf: &[u8]) -> KerlabResult<Vec<Frame<'_>>> {
+    let mut frames = vec![];
+    let mut big_endian = false;
+    // link type and timestamp units of each interface
+    let mut interfaces: Vec<(u32, u64)> = vec![];
+
+    let mut offset = 0;
+    while offset < buf.len() {
+        let block_type = read_u32(buf, offset, big_endian)?;
+        if block_type == PCAPNG_SECTION_HEADER {
+            big_endian = read_u32(buf, offset + 8, false)? != PCAPNG_BYTE_ORDER_MAGIC;
+            interfaces.clear();
+        }
+
+        let size = read_u32(buf, offset + 4, big_endian)? as usize;
+        let block = buf.get(offset..offset + size).ok_or_else(truncated)?;
+        if size < 12 {
+            return Err(Error::new(KerlabErrorKind::Parsing, "Invalid pcapng block size"))
+        }
+
+        match block_type {
+            PCAPNG_INTERFACE_DESCRIPTION => {
+                let link_type = read_u16(block, 8, big_endian)? as u32;
+                let mut units = 1_000_000;
+                let mut option = 16;
+                while option + 4 <= size - 4 {
+                    let code = read_u16(block, option, big_endian)?;
+                    let length = read_u16(block, option + 2, big_endian)? as usize;
+                    if code == PCAPNG_OPTION_TSRESOL {
+                        units = tsresol(*block.get(option + 4).ok_or_else(truncated)?);
+                    }
+                    if code == 0 {
+                        break;
+                    }
+                    option += 4 + length.div_ceil(4) * 4;
+                }
+                interfaces.push((link_type, units));
+            },
+            PCAPNG_ENHANCED_PACKET => {
+                let interface = read_u32(block, 8, big_endian)? as usize;
+                let (link_type, units) = *interfaces.get(interface)
+                    .ok_or_else(|| Error::new(KerlabErrorKind::Parsing, "Packet of an unknown interface"))?;
+                let value = (read_u32(block, 12, big_endian)? as u64) << 32 | read_u32(block, 16, big_endian)? as u64;
+                let length = read_u32(block, 20, big_endian)? as usize;
+                frames.push(Frame {
+                    timestamp: timestamp(value / units, ((value % units) as u128 * 1_000_000_000 / units as u128) as u32)?,
+                    link_type,
+                    data: block.get(28..28 + length).ok_or_else(truncated)?
+                });
+            },
+            PCAPNG_SIMPLE_PACKET => {
+                let (link_type, _) = *interfaces.first()
+                    .ok_or_else(|| Error::new(KerlabErrorKind::Parsing, "Packet of an unknown interface"))?;
+                let length = (read_u32(block, 8, big_endian)? as usize).min(size.saturating_sub(16));
+                frames.push(Frame {
+                    // simple packets have no timestamp
+                    timestamp: timestamp(0, 0)?,
+                    link_type,
+                    data: block.get(12..12 + length).ok_or_else(truncated)?
+                });
+            },
+            _ => ()
+        }
+        offset += size;
+    }
+    Ok(frames)
+}
+
+/// IP packet carried by a frame
+fn ip_packet(link_type: u32, data: &[u8]) -> Option<&[u8]> {
+    let (ethertype, payload) = match link_type {
+        LINKTYPE_ETHERNET => {
+            let mut offset = 12;
+            let mut ethertype = u16::from_be_bytes([*data.get(offset)?, *data.get(offset + 1)?]);
+            while ethertype == ETHERTYPE_VLAN {
+                offset += 4;
+                ethertype = u16::from_be_bytes([*data.get(offset)?, *data.get(offset + 1)?]);
+            }
+            (Some(ethertype), data.get(offset + 2..)?)
+        },
+        LINKTYPE_LINUX_SLL => (Some(u16::from_be_bytes([*data.get(14)?, *data.get(15)?])), data.get(16..)?),
+        LINKTYPE_LINUX_SLL2 => (Some(u16::from_be_bytes([*data.first()?, *data.get(1)?])), data.get(20..)?),
+        // address family is in the byte order of the capturing host, use the IP version instead
+        LINKTYPE_NULL => (None, data.get(4..)?),
+        LINKTYPE_RAW => (None, data),
+        _ => return None
+    };
+
+    match (ethertype, payload.first()? >> 4) {
+        (Some(ETHERTYPE_IPV4), 4) | (Some(ETHERTYPE_IPV6), 6) | (None, 4) | (None, 6) => Some(payload),
+        _ => None
+    }
+}
+
+/// Transport protocol of a Kerberos message
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Transport {
+    Tcp,
+    Udp
+}
+
+/// Transport layer of an IP packet
+struct Segment<'a> {
+    source: SocketAddr,
+    destination: SocketAddr,
+    transport: Transport,
+    seq: u32,
+    flags: u8,
+    payload: &'a [u8]
+}
+
+fn segment(packet: &[u8]) -> Option<Segment<'_>> {
+    let (source, destination, protocol, payload) = if packet.first()? >> 4 == 4 {
+        let header_size = ((packet.first()? & 0x0f) as usize) * 4;
+        let total_size = u16::from_be_bytes([*packet.get(2)?, *packet.get(3)?]) as usize;
+        // fragments are ignored
+        let fragment = u16::from_be_bytes([*packet.get(6)?, *packet.get(7)?]);
+        if fragment & 0x3fff != 0 {
+            return None
+        }
+        let address = |offset: usize| packet.get(offset..offset + 4).map(|e| IpAddr::V4(Ipv4Addr::new(e[0], e[1], e[2], e[3])));
+        (address(12)?, address(16)?, *packet.get(9)?, packet.get(header_size..total_size.min(packet.len()))?)
+    } else {
+        let payload_size = u16::from_be_bytes([*packet.get(4)?, *packet.get(5)?]) as usize;
+        let address = |offset: usize| {
+            let mut bytes = [0; 16];
+            bytes.copy_from_slice(packet.get(offset..offset + 16)?);
+            Some(IpAddr::V6(Ipv6Addr::from(bytes)))
+        };
+        (address(8)?, address(24)?, *packet.get(6)?, packet.get(40..(40 + payload_size).min(packet.len()))?)
+    };
+
+    let port = |offset: usize| Some(u16::from_be_bytes([*payload.get(offset)?, *payload.get(offset + 1)?]));
+    let source = SocketAddr::new(source, port(0)?);
+    let destination = SocketAddr::new(destination, port(2)?);
+
+    match protocol {
+        IPPROTO_TCP => {
+            let header_size = ((payload.get(12)? >> 4) as usize) * 4;
+            Some(Segment {
+                source,
+                destination,
+                transport: Transport::Tcp,
+                seq: u32::from_be_bytes([*payload.get(4)?, *payload.get(5)?, *payload.get(6)?, *payload.get(7)?]),
+                flags: *payload.get(13)?,
+                payload: payload.get(header_size..)?
+            })
+        },
+        IPPROTO_UDP => Some(Segment {
+            source,
+            destination,
+            transport: Transport::Udp,
+            seq: 0,
+            flags: 0,
+            payload: payload.get(8..)?
+        }),
+        _ => None
+    }
+}
+
+/// One direction of a TCP connection
+#[derive(Default)]
+struct Stream {
+    next_seq: Option<u32>,
+    buffer: Vec<u8>,
+    /// Segments received before the missing data
+    pending: HashMap<u32, Vec<u8>>
+}
+
+impl Stream {
+    /// Add a segment to the stream
+    /// Return the records completed by this segment
+    fn push(&mut self, seq: u32, flags: u8, payload: &[u8]) -> Vec<Vec<u8>> {
+        if flags & TCP_SYN != 0 {
+            self.next_seq = Some(seq.wrapping_add(1));
+            self.buffer.clear();
+            self.pending.clear();
+        }
+        if payload.is_empty() {
+            return vec![]
+        }
+
+        let next_seq = *self.next_seq.get_or_insert(seq);
+        let offset = seq.wrapping_sub(next_seq) as i32;
+        if offset > 0 {
+            self.pending.insert(seq, payload.to_vec());
+            return vec![]
+        }
+
+        // retransmitted data is skipped
+        let skip = offset.unsigned_abs() as usize;
+        if skip >= payload.len() {
+            return vec![]
+        }
+        self.append(&payload[skip..]);
+
+        while let Some(data) = self.next_seq.and_then(|next_seq| self.pending.remove(&next_seq)) {
+            self.append(&data);
+        }
+        self.records()
+    }
+
+    fn append(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+        self.next_seq = self.next_seq.map(|next_seq| next_seq.wrapping_add(data.len() as u32));
+    }
+
+    /// Remove the record mark of complete messages
+    /// @see https://tools.ietf.org/html/rfc4120#section-7.2.2
+    fn records(&mut self) -> Vec<Vec<u8>> {
+        let mut records = vec![];
+        while self.buffer.len() >= 4 {
+            // high bit is reserved
+            let size = (u32::from_be_bytes([self.buffer[0], self.buffer[1], self.buffer[2], self.buffer[3]]) & 0x7fffffff) as usize;
+            if size > MAX_RECORD_SIZE {
+                self.buffer.clear();
+                break;
+            }
+            if self.buffer.len() < size + 4 {
+                break;
+            }
+            records.push(self.buffer[4..size + 4].to_vec());
+            self.buffer.drain(..size + 4);
+        }
+        records
+    }
+}
+
+/// Kerberos messages exchanged with a KDC
+pub enum KrbMessage {
+    AsReq(AsReq),
+    AsRep(AsRep),
+    TgsReq(TgsReq),
+    TgsRep(TgsRep),
+    KrbError(KrbError)
+}
+
+impl KrbMessage {
+    /// Decode a message using its application tag
+    pub fn from(buf: &[u8]) -> KerlabResult<Self> {
+        Ok(match buf.first() {
+            Some(0x6a) => {
+                let mut message = AsReq::default();
+                from_ber(&mut message, buf)?;
+                KrbMessage::AsReq(message)
+            },
+            Some(0x6b) => {
+                let mut message = AsRep::default();
+                from_ber(&mut message, buf)?;
+                KrbMessage::AsRep(message)
+            },
+            Some(0x6c) => {
+                let mut message = TgsReq::default();
+                from_ber(&mut message, buf)?;
+                KrbMessage::TgsReq(message)
+            },
+            Some(0x6d) => {
+                let mut message = TgsRep::default();
+                from_ber(&mut message, buf)?;
+                KrbMessage::TgsRep(message)
+            },
+            Some(0x7e) => {
+                let mut message = KrbError::default();
+                from_ber(&mut message, buf)?;
+                KrbMessage::KrbError(message)
+            },
+            _ => return Err(Error::new(KerlabErrorKind::Parsing, "Not a message of a KDC exchange"))
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            KrbMessage::AsReq(_) => "AS-REQ",
+            KrbMessage::AsRep(_) => "AS-REP",
+            KrbMessage::TgsReq(_) => "TGS-REQ",
+            KrbMessage::TgsRep(_) => "TGS-REP",
+            KrbMessage::KrbError(_) => "KRB-ERROR"
+        }
+    }
+}
+
+/// A Kerberos message seen on the wire
+pub struct KerberosPacket {
+    pub timestamp: DateTime<Utc>,
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+    pub transport: Transport,
+    /// Message without the record mark
+    pub payload: Vec<u8>
+}
+
+impl KerberosPacket {
+    pub fn decode(&self) -> KerlabResult<KrbMessage> {
+        KrbMessage::from(&self.payload)
+    }
+}
+
+/// Kerberos messages of a capture file, in capture order
+pub struct Capture {
+    pub packets: Vec<KerberosPacket>
+}
+
+impl Capture {
+    /// Parse a pcap or a pcapng capture
+    pub fn from(buf: &[u8]) -> KerlabResult<Self> {
+        let frames = if read_u32(buf, 0, false)? == PCAPNG_SECTION_HEADER {
+            pcapng_frames(buf)?
+        } else {
+            pcap_frames(buf)?
+        };
+
+        let mut streams: HashMap<(SocketAddr, SocketAddr), Stream> = HashMap::new();
+        let mut packets = vec![];
+        for frame in frames {
+            let segment = match ip_packet(frame.link_type, frame.data).and_then(segment) {
+                Some(segment) => segment,
+                None => continue
+            };
+
+            if segment.source.port() != KERBEROS_PORT && segment.destination.port() != KERBEROS_PORT {
+                continue;
+            }
+
+            let payloads = match segment.transport {
+                Transport::Udp if segment.payload.is_empty() => vec![],
+                Transport::Udp => vec![segment.payload.to_vec()],
+                Transport::Tcp => streams.entry((segment.source, segment.destination))
+                    .or_default()
+                    .push(segment.seq, segment.flags, segment.payload)
+            };
+
+            for payload in payloads {
+                packets.push(KerberosPacket {
+                    timestamp: frame.timestamp,
+                    source: segment.source,
+                    destination: segment.destination,
+                    transport: segment.transport,
+                    payload
+                });
+            }
+        }
+
+        Ok(Self {
+            packets
+        })
+    }
+
+    /// Load a capture from disk
+    pub fn from_file(path: &str) -> KerlabResult<Self> {
+        Self::from(&fs::read(path)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use asn1::to_der;
+    use base::PrincipalName;
+    use asn1::GeneralString;
+    use krberror::KrbErrorCode;
+    use std::str::FromStr;
+
+    /// Ethernet, IPv4 and TCP or UDP headers around a payload
+    fn frame(source: u16, destination: u16, protocol: u8, seq: u32, flags: u8, payload: &[u8]) -> Vec<u8> {
+        let mut transport = vec![];
+        transport.extend_from_slice(&source.to_be_bytes());
+        transport.extend_from_slice(&destination.to_be_bytes());
+        if protocol == IPPROTO_TCP {
+            transport.extend_from_slice(&seq.to_be_bytes());
+            transport.extend_from_slice(&[0, 0, 0, 0, 0x50, flags, 0xff, 0xff, 0, 0, 0, 0]);
+        } else {
+            transport.extend_from_slice(&(8 + payload.len() as u16).to_be_bytes());
+            transport.extend_from_slice(&[0, 0]);
+        }
+        transport.extend_from_slice(payload);
+
+        let mut data = vec![0; 12];
+        data.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+        data.extend_from_slice(&[0x45, 0]);
+        data.extend_from_slice(&(20 + transport.len() as u16).to_be_bytes());
+        data.extend_from_slice(&[0, 0, 0x40, 0, 64, protocol, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2]);
+        data.extend_from_slice(&transport);
+        data
+    }
+
+    fn pcap(frames: &[Vec<u8>]) -> Vec<u8> {
+        let mut buf = vec![];
+        for value in &[PCAP_MAGIC, 0x00040002, 0, 0, 0xffff, LINKTYPE_ETHERNET] {
+            buf.extend_from_slice(&value.to_le_bytes());
+        }
+        for (i, frame) in frames.iter().enumerate() {
+            for value in &[1600000000 + i as u32, 0, frame.len() as u32, frame.len() as u32] {
+                buf.extend_from_slice(&value.to_le_bytes());
+            }
+            buf.extend_from_slice(frame);
+        }
+        buf
+    }
+
+    fn pcapng(frames: &[Vec<u8>]) -> Vec<u8> {
+        let block = |block_type: u32, body: &[u8]| {
+            let mut block = vec![];
+            block.extend_from_slice(&block_type.to_be_bytes());
+            block.extend_from_slice(&(12 + body.len() as u32).to_be_bytes());
+            block.extend_from_slice(body);
+            block.extend_from_slice(&(12 + body.len() as u32).to_be_bytes());
+            block
+        };
+
+        let mut buf = block(PCAPNG_SECTION_HEADER, &[0x1a, 0x2b, 0x3c, 0x4d, 0, 1, 0, 0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+        // nanosecond resolution
+        buf.extend(block(PCAPNG_INTERFACE_DESCRIPTION, &[0, 1, 0, 0, 0, 0, 0xff, 0xff, 0, 9, 0, 1, 9, 0, 0, 0, 0, 0, 0, 0]));
+        for frame in frames {
+            let mut body = vec![0, 0, 0, 0, 0x16, 0x34, 0x57, 0x85, 0xd8, 0xa0, 0x00, 0x00];
+            body.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+            body.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+            body.extend_from_slice(frame);
+            body.resize(body.len().div_ceil(4) * 4, 0);
+            buf.extend(block(PCAPNG_ENHANCED_PACKET, &body));
+        }
+        buf
+    }
+
+    /// AS-REQ split and reordered over TCP, KRB-ERROR over UDP
+    #[test]
+    fn test_capture() {
+        let as_req = to_der(&AsReq::new("LAB.LOCAL", "alice", &[]).unwrap()).unwrap();
+        let mut record = (as_req.len() as u32).to_be_bytes().to_vec();
+        record.extend_from_slice(&as_req);
+
+        let krb_error = to_der(&KrbError::new(
+            KrbErrorCode::KdcErrPreauthRequired,
+            GeneralString::from_str("LAB.LOCAL").unwrap(),
+            PrincipalName::default()
+        )).unwrap();
+
+        let frames = vec![
+            frame(50000, 88, IPPROTO_TCP, 999, TCP_SYN, &[]),
+            frame(50000, 88, IPPROTO_TCP, 1010, 0x18, &record[10..]),
+            frame(50000, 88, IPPROTO_TCP, 1000, 0x18, &record[..10]),
+            // retransmission
+            frame(50000, 88, IPPROTO_TCP, 1000, 0x18, &record[..10]),
+            frame(50001, 443, IPPROTO_TCP, 1, 0x18, &record),
+            frame(88, 50002, IPPROTO_UDP, 0, 0, &krb_error)
+        ];
+
+        for capture in &[pcap(&frames), pcapng(&frames)] {
+            let capture = Capture::from(capture).unwrap();
+            assert_eq!(capture.packets.len(), 2);
+
+            assert_eq!(capture.packets[0].transport, Transport::Tcp);
+            assert_eq!(capture.packets[0].source, "10.0.0.1:50000".parse().unwrap());
+            assert_eq!(capture.packets[0].payload, as_req);
+            match capture.packets[0].decode().unwrap() {
+                KrbMessage::AsReq(request) => assert_eq!(request.req_body.cname.as_ref().unwrap().name(), "alice"),
+                _ => panic!("First message must be an AS-REQ")
+            }
+
+            assert_eq!(capture.packets[1].transport, Transport::Udp);
+            assert_eq!(capture.packets[1].decode().unwrap().name(), "KRB-ERROR");
+        }
+
+        assert_eq!(Capture::from(&pcap(&frames)).unwrap().packets[0].timestamp.timestamp(), 1600000002);
+        assert_eq!(Capture::from(&pcapng(&frames)).unwrap().packets[0].timestamp.timestamp(), 1600000000);
+    }
+}
@@ -0,0 +1,293 @@
+//! PKCS#12 certificate and key container
+//! Only PBES2 with AES CBC (OpenSSL 3 default) or unencrypted bags are supported
+//! @see https://tools.ietf.org/html/rfc7292
+
+use std::fs;
+use asn1::{ASN1, Tag, Implicit, Integer, OctetString, ObjectIdentifier, Any, SetOf, SequenceOf, from_ber};
+use yasna::{DERWriter, BERReader};
+use error::{KerlabResult, Error, KerlabErrorKind};
+use x509::{AlgorithmIdentifier, Certificate};
+use rsa::RsaPrivateKey;
+use cms::{ContentInfo, OID_DATA, OID_ENCRYPTED_DATA};
+use aescts::{pbkdf2, hmac_sha1, cbc_decrypt};
+use sha2::Sha256;
+use hmac::{Hmac, Mac};
+
+const OID_KEY_BAG: [u64; 9] = [1, 2, 840, 113549, 1, 12, 10, 1, 1];
+const OID_SHROUDED_KEY_BAG: [u64; 9] = [1, 2, 840, 113549, 1, 12, 10, 1, 2];
+const OID_CERT_BAG: [u64; 9] = [1, 2, 840, 113549, 1, 12, 10, 1, 3];
+const OID_X509_CERTIFICATE: [u64; 8] = [1, 2, 840, 113549, 1, 9, 22, 1];
+const OID_PBES2: [u64; 7] = [1, 2, 840, 113549, 1, 5, 13];
+const OID_PBKDF2: [u64; 7] = [1, 2, 840, 113549, 1, 5, 12];
+const OID_HMAC_WITH_SHA1: [u64; 6] = [1, 2, 840, 113549, 2, 7];
+const OID_HMAC_WITH_SHA256: [u64; 6] = [1, 2, 840, 113549, 2, 9];
+const OID_AES128_CBC: [u64; 9] = [2, 16, 840, 1, 101, 3, 4, 1, 2];
+const OID_AES256_CBC: [u64; 9] = [2, 16, 840, 1, 101, 3, 4, 1, 42];
+
+/// ```asn.1
+/// PFX ::= SEQUENCE {
+///     version     INTEGER {v3(3)}(v3,...),
+///     authSafe    ContentInfo,
+///     macData     MacData OPTIONAL
+/// }
+/// ```
+#[derive(Sequence, Default, Clone, PartialEq)]
+pub struct Pfx {
+    pub version: Integer,
+    pub auth_safe: ContentInfo,
+    pub mac_data: Option<Any>
+}
+
+/// ```asn.1
+/// EncryptedData ::= SEQUENCE {
+///     version               Version,
+///     encryptedContentInfo  EncryptedContentInfo
+/// }
+/// ```
+#[derive(Sequence, Default, Clone, PartialEq)]
+pub struct EncryptedData {
+    pub version: Integer,
+    pub encrypted_content_info: EncryptedContentInfo
+}
+
+/// ```asn.1
+/// EncryptedContentInfo ::= SEQUENCE {
+///     contentType                 ContentType,
+///     contentEncryptionAlgorithm  ContentEncryptionAlgorithmIdentifier,
+///     encryptedContent        [0] IMPLICIT EncryptedContent OPTIONAL
+/// }
+/// ```
+#[derive(Sequence, Default, Clone, PartialEq)]
+pub struct EncryptedContentInfo {
+    pub content_type: ObjectIdentifier,
+    pub content_encryption_algorithm: AlgorithmIdentifier,
+    pub encrypted_content: Option<Implicit<0, OctetString>>
+}
+
+/// ```asn.1
+/// SafeBag ::= SEQUENCE {
+///     bagId          BAG-TYPE.&id ({PKCS12BagSet}),
+///     bagValue       [0] EXPLICIT BAG-TYPE.&Type({PKCS12BagSet}{@bagId}),
+///     bagAttributes  SET OF PKCS12Attribute OPTIONAL
+/// }
+/// ```
+#[derive(Sequence, Default, Clone, PartialEq)]
+pub struct SafeBag {
+    pub bag_id: ObjectIdentifier,
+    pub bag_value: Tag<0, Any>,
+    pub bag_attributes: Option<SetOf<Any>>
+}
+
+/// ```asn.1
+/// CertBag ::= SEQUENCE {
+///     certId      BAG-TYPE.&id   ({CertTypes}),
+///     certValue   [0] EXPLICIT BAG-TYPE.&Type ({CertTypes}{@certId})
+/// }
+/// ```
+#[derive(Sequence, Default, Clone, PartialEq)]
+pub struct CertBag {
+    pub cert_id: ObjectIdentifier,
+    pub cert_value: Tag<0, OctetString>
+}
+
+/// ```asn.1
+/// EncryptedPrivateKeyInfo ::= SEQUENCE {
+///     encryptionAlgorithm  EncryptionAlgorithmIdentifier,
+///     encryptedData        EncryptedData
+/// }
+/// ```
+#[derive(Sequence, Default, Clone, PartialEq)]
+pub struct EncryptedPrivateKeyInfo {
+    pub encryption_algorithm: AlgorithmIdentifier,
+    pub encrypted_data: OctetString
+}
+
+/// ```asn.1
+/// PBES2-params ::= SEQUENCE {
+///     keyDerivationFunc AlgorithmIdentifier {{PBES2-KDFs}},
+///     encryptionScheme AlgorithmIdentifier {{PBES2-Encs}}
+/// }
+/// ```
+#[derive(Sequence, Default, Clone, PartialEq)]
+pub struct Pbes2Params {
+    pub key_derivation_func: AlgorithmIdentifier,
+    pub encryption_scheme: AlgorithmIdentifier
+}
+
+/// ```asn.1
+/// PBKDF2-params ::= SEQUENCE {
+///     salt OCTET STRING,
+///     iterationCount INTEGER (1..MAX),
+///     keyLength INTEGER (1..MAX) OPTIONAL,
+///     prf AlgorithmIdentifier {{PBKDF2-PRFs}} DEFAULT algid-hmacWithSHA1
+/// }
+/// ```
+#[derive(Sequence, Default, Clone, PartialEq)]
+pub struct Pbkdf2Params {
+    pub salt: OctetString,
+    pub iteration_count: Integer,
+    pub key_length: Option<Integer>,
+    pub prf: Option<AlgorithmIdentifier>
+}
+
+/// Compute HMAC with SHA256 hash algorithm
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut stream = Hmac::<Sha256>::new_varkey(key).unwrap();
+    stream.input(data);
+    stream.result().code().to_vec()
+}
+
+/// Parse the parameters of an algorithm identifier
+fn parameters<T: ASN1 + Default>(algorithm: &AlgorithmIdentifier) -> KerlabResult<T> {
+    let parameters = algorithm.parameters.as_ref()
+        .ok_or_else(|| Error::new(KerlabErrorKind::Parsing, "Missing algorithm parameters"))?;
+    let mut result = T::default();
+    from_ber(&mut result, &parameters.inner)?;
+    Ok(result)
+}
+
+/// Password based decryption
+///
+/// @see https://tools.ietf.org/html/rfc8018#section-6.2
+fn pbes2_decrypt(algorithm: &AlgorithmIdentifier, password: &str, data: &[u8]) -> KerlabResult<Vec<u8>> {
+    if algorithm.algorithm != ObjectIdentifier::new(&OID_PBES2) {
+        return Err(Error::new(KerlabErrorKind::Crypto, "Only PBES2 encrypted PKCS#12 are supported, use openssl to convert legacy files"))
+    }
+
+    let params: Pbes2Params = parameters(algorithm)?;
+    if params.key_derivation_func.algorithm != ObjectIdentifier::new(&OID_PBKDF2) {
+        return Err(Error::new(KerlabErrorKind::Crypto, "Unsupported key derivation function"))
+    }
+
+    let kdf: Pbkdf2Params = parameters(&params.key_derivation_func)?;
+    let prf: fn(&[u8], &[u8]) -> Vec<u8> = match &kdf.prf {
+        None => hmac_sha1,
+        Some(prf) if prf.algorithm == ObjectIdentifier::new(&OID_HMAC_WITH_SHA1) => hmac_sha1,
+        Some(prf) if prf.algorithm == ObjectIdentifier::new(&OID_HMAC_WITH_SHA256) => hmac_sha256,
+        _ => return Err(Error::new(KerlabErrorKind::Crypto, "Unsupported PBKDF2 pseudo random function"))
+    };
+
+    let key_size = if params.encryption_scheme.algorithm == ObjectIdentifier::new(&OID_AES128_CBC) {
+        16
+    } else if params.encryption_scheme.algorithm == ObjectIdentifier::new(&OID_AES256_CBC) {
+        32
+    } else {
+        return Err(Error::new(KerlabErrorKind::Crypto, "Unsupported PBES2 encryption scheme"))
+    };
+
+    let iv: OctetString = parameters(&params.encryption_scheme)?;
+    let key = pbkdf2(prf, password.as_bytes(), &kdf.salt, kdf.iteration_count, key_size);
+    let mut plaintext = cbc_decrypt(&key, &iv, data)?;
+
+    // remove PKCS#7 padding
+    let padding = *plaintext.last().unwrap_or(&0) as usize;
+    if padding == 0 || padding > 16 || padding > plaintext.len() {
+        return Err(Error::new(KerlabErrorKind::Crypto, "Invalid padding, wrong password ?"))
+    }
+    plaintext.truncate(plaintext.len() - padding);
+    Ok(plaintext)
+}
+
+/// Certificates and private key extracted from a PKCS#12 file
+pub struct Pkcs12 {
+    pub certificates: Vec<Certificate>,
+    pub key: RsaPrivateKey
+}
+
+impl Pkcs12 {
+    /// Parse a PKCS#12 file content
+    pub fn from(buf: &[u8], password: &str) -> KerlabResult<Self> {
+        let mut pfx = Pfx::default();
+        from_ber(&mut pfx, buf)?;
+
+        let mut auth_safe = SequenceOf::<ContentInfo>::default();
+        from_ber(&mut auth_safe, &data_content(&pfx.auth_safe)?)?;
+
+        let mut certificates = vec![];
+        let mut key = None;
+
+        for content_info in auth_safe {
+            let safe_contents = if content_info.content_type == ObjectIdentifier::new(&OID_DATA) {
+                data_content(&content_info)?
+            } else if content_info.content_type == ObjectIdentifier::new(&OID_ENCRYPTED_DATA) {
+                let mut encrypted_data = EncryptedData::default();
+                from_ber(&mut encrypted_data, &content_info.content.inner)?;
+                let info = encrypted_data.encrypted_content_info;
+                let encrypted_content = info.encrypted_content
+                    .ok_or_else(|| Error::new(KerlabErrorKind::Parsing, "EncryptedData without content"))?;
+                pbes2_decrypt(&info.content_encryption_algorithm, password, &encrypted_content)?
+            } else {
+                continue;
+            };
+
+            let mut bags = SequenceOf::<SafeBag>::default();
+            from_ber(&mut bags, &safe_contents)?;
+
+            for bag in bags {
+                if bag.bag_id == ObjectIdentifier::new(&OID_CERT_BAG) {
+                    let mut cert_bag = CertBag::default();
+                    from_ber(&mut cert_bag, &bag.bag_value.inner)?;
+                    if cert_bag.cert_id == ObjectIdentifier::new(&OID_X509_CERTIFICATE) {
+                        certificates.push(Certificate::from_der(&cert_bag.cert_value)?);
+                    }
+                } else if bag.bag_id == ObjectIdentifier::new(&OID_KEY_BAG) {
+                    key = Some(RsaPrivateKey::from_pkcs8(&bag.bag_value.inner)?);
+                } else if bag.bag_id == ObjectIdentifier::new(&OID_SHROUDED_KEY_BAG) {
+                    let mut encrypted_key = EncryptedPrivateKeyInfo::default();
+                    from_ber(&mut encrypted_key, &bag.bag_value.inner)?;
+                    key = Some(RsaPrivateKey::from_pkcs8(&pbes2_decrypt(
+                        &encrypted_key.encryption_algorithm,
+                        password,
+                        &encrypted_key.encrypted_data
+                    )?)?);
+                }
+            }
+        }
+
+        Ok(Self {
+            certificates,
+            key: key.ok_or_else(|| Error::new(KerlabErrorKind::Parsing, "No private key found in PKCS#12"))?
+        })
+    }
+
+    /// Load a PKCS#12 file from disk
+    pub fn from_file(path: &str, password: &str) -> KerlabResult<Self> {
+        Self::from(&fs::read(path)?, password)
+    }
+
+    /// The certificate that match the private key
+    pub fn certificate(&self) -> KerlabResult<&Certificate> {
+        let public_key = self.key.public_key();
+        for certificate in &self.certificates {
+            if certificate.public_key()? == public_key {
+                return Ok(certificate)
+            }
+        }
+        Err(Error::new(KerlabErrorKind::Parsing, "No certificate match the private key"))
+    }
+}
+
+/// Content of id-data ContentInfo is an OCTET STRING
+fn data_content(content_info: &ContentInfo) -> KerlabResult<OctetString> {
+    if content_info.content_type != ObjectIdentifier::new(&OID_DATA) {
+        return Err(Error::new(KerlabErrorKind::Parsing, "ContentInfo is not data"))
+    }
+    let mut data = OctetString::default();
+    from_ber(&mut data, &content_info.content.inner)?;
+    Ok(data)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// File generated by openssl 3 with default options
+    #[test]
+    fn test_pkcs12_pbes2() {
+        let pkcs12 = Pkcs12::from(include_bytes!("../tests/fixtures/user.p12"), "kerlab").unwrap();
+        assert_eq!(pkcs12.certificates.len(), 2);
+        let ca = &Certificate::from_pem(include_str!("../tests/fixtures/ca.pem")).unwrap()[0];
+        pkcs12.certificate().unwrap().verify_issued_by(ca).unwrap();
+        assert!(Pkcs12::from(include_bytes!("../tests/fixtures/user.p12"), "foo").is_err());
+    }
+}
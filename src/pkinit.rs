@@ -0,0 +1,430 @@
+//! Public Key Cryptography for Initial Authentication
+//! Only the Diffie-Hellman key delivery is supported
+//! @see https://tools.ietf.org/html/rfc4556
+
+use asn1::{ASN1, Tag, Implicit, Integer, OctetString, SequenceOf, BitString, BigInteger, Any, to_der, from_der, from_ber};
+use yasna::{DERWriter, BERReader};
+use error::{KerlabResult, Error, KerlabErrorKind};
+use base::KerberosTime;
+use padata::{PaData, PaDataType};
+use krbkdcreq::KdcReqBody;
+use encryption::{EncryptionKey, key_size};
+use x509::{AlgorithmIdentifier, SubjectPublicKeyInfo, Certificate};
+use rsa::{RsaPrivateKey, DigestAlgorithm};
+use cms::SignedData;
+use pkcs12::Pkcs12;
+use rnd::random;
+use chrono::{Utc, Timelike, DateTime};
+
+/// id-pkinit-authData
+pub const OID_PKINIT_AUTH_DATA: [u64; 8] = [1, 3, 6, 1, 5, 2, 3, 1];
+/// id-pkinit-DHKeyData
+pub const OID_PKINIT_DH_KEY_DATA: [u64; 8] = [1, 3, 6, 1, 5, 2, 3, 2];
+/// dhpublicnumber
+pub const OID_DH_PUBLIC_NUMBER: [u64; 6] = [1, 2, 840, 10046, 2, 1];
+
+/// Size of the Diffie-Hellman private exponent
+const DH_PRIVATE_KEY_SIZE: usize = 32;
+
+/// 2048-bit MODP group (group 14)
+/// @see https://tools.ietf.org/html/rfc3526#section-3
+const MODP_GROUP14_PRIME: [u8; 256] = [
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xc9, 0x0f, 0xda, 0xa2, 0x21, 0x68, 0xc2, 0x34,
+    0xc4, 0xc6, 0x62, 0x8b, 0x80, 0xdc, 0x1c, 0xd1, 0x29, 0x02, 0x4e, 0x08, 0x8a, 0x67, 0xcc, 0x74,
+    0x02, 0x0b, 0xbe, 0xa6, 0x3b, 0x13, 0x9b, 0x22, 0x51, 0x4a, 0x08, 0x79, 0x8e, 0x34, 0x04, 0xdd,
+    0xef, 0x95, 0x19, 0xb3, 0xcd, 0x3a, 0x43, 0x1b, 0x30, 0x2b, 0x0a, 0x6d, 0xf2, 0x5f, 0x14, 0x37,
+    0x4f, 0xe1, 0x35, 0x6d, 0x6d, 0x51, 0xc2, 0x45, 0xe4, 0x85, 0xb5, 0x76, 0x62, 0x5e, 0x7e, 0xc6,
+    0xf4, 0x4c, 0x42, 0xe9, 0xa6, 0x37, 0xed, 0x6b, 0x0b, 0xff, 0x5c, 0xb6, 0xf4, 0x06, 0xb7, 0xed,
+    0xee, 0x38, 0x6b, 0xfb, 0x5a, 0x89, 0x9f, 0xa5, 0xae, 0x9f, 0x24, 0x11, 0x7c, 0x4b, 0x1f, 0xe6,
+    0x49, 0x28, 0x66, 0x51, 0xec, 0xe4, 0x5b, 0x3d, 0xc2, 0x00, 0x7c, 0xb8, 0xa1, 0x63, 0xbf, 0x05,
+    0x98, 0xda, 0x48, 0x36, 0x1c, 0x55, 0xd3, 0x9a, 0x69, 0x16, 0x3f, 0xa8, 0xfd, 0x24, 0xcf, 0x5f,
+    0x83, 0x65, 0x5d, 0x23, 0xdc, 0xa3, 0xad, 0x96, 0x1c, 0x62, 0xf3, 0x56, 0x20, 0x85, 0x52, 0xbb,
+    0x9e, 0xd5, 0x29, 0x07, 0x70, 0x96, 0x96, 0x6d, 0x67, 0x0c, 0x35, 0x4e, 0x4a, 0xbc, 0x98, 0x04,
+    0xf1, 0x74, 0x6c, 0x08, 0xca, 0x18, 0x21, 0x7c, 0x32, 0x90, 0x5e, 0x46, 0x2e, 0x36, 0xce, 0x3b,
+    0xe3, 0x9e, 0x77, 0x2c, 0x18, 0x0e, 0x86, 0x03, 0x9b, 0x27, 0x83, 0xa2, 0xec, 0x07, 0xa2, 0x8f,
+    0xb5, 0xc5, 0x5d, 0xf0, 0x6f, 0x4c, 0x52, 0xc9, 0xde, 0x2b, 0xcb, 0xf6, 0x95, 0x58, 0x17, 0x18,
+    0x39, 0x95, 0x49, 0x7c, 0xea, 0x95, 0x6a, 0xe5, 0x15, 0xd2, 0x26, 0x18, 0x98, 0xfa, 0x05, 0x10,
+    0x15, 0x72, 0x8e, 0x5a, 0x8a, 0xac, 0xaa, 0x68, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff
+];
+
+/// ```asn.1
+/// PKAuthenticator ::= SEQUENCE {
+///     cusec                   [0] INTEGER (0..999999),
+///     ctime                   [1] KerberosTime,
+///     nonce                   [2] INTEGER (0..4294967295),
+///     paChecksum              [3] OCTET STRING OPTIONAL,
+///     ...
+/// }
+/// ```
+#[derive(Sequence, Default, Clone, PartialEq)]
+pub struct PkAuthenticator {
+    pub cusec: Tag<0, Integer>,
+    pub ctime: Tag<1, KerberosTime>,
+    pub nonce: Tag<2, Integer>,
+    pub pa_checksum: Option<Tag<3, OctetString>>
+}
+
+/// ```asn.1
+/// AuthPack ::= SEQUENCE {
+///     pkAuthenticator         [0] PKAuthenticator,
+///     clientPublicValue       [1] SubjectPublicKeyInfo OPTIONAL,
+///     supportedCMSTypes       [2] SEQUENCE OF AlgorithmIdentifier OPTIONAL,
+///     clientDHNonce           [3] DHNonce OPTIONAL,
+///     ...
+/// }
+/// ```
+#[derive(Sequence, Default, Clone, PartialEq)]
+pub struct AuthPack {
+    pub pk_authenticator: Tag<0, PkAuthenticator>,
+    pub client_public_value: Option<Tag<1, SubjectPublicKeyInfo>>,
+    pub supported_cms_types: Option<Tag<2, SequenceOf<AlgorithmIdentifier>>>,
+    pub client_dh_nonce: Option<Tag<3, OctetString>>
+}
+
+/// ```asn.1
+/// PA-PK-AS-REQ ::= SEQUENCE {
+///     signedAuthPack          [0] IMPLICIT OCTET STRING,
+///     trustedCertifiers       [1] SEQUENCE OF ExternalPrincipalIdentifier OPTIONAL,
+///     kdcPkId                 [2] IMPLICIT OCTET STRING OPTIONAL,
+///     ...
+/// }
+/// ```
+#[derive(Sequence, Default, Clone, PartialEq)]
+pub struct PaPkAsReq {
+    pub signed_auth_pack: Implicit<0, OctetString>,
+    pub trusted_certifiers: Option<Tag<1, SequenceOf<Any>>>,
+    pub kdc_pk_id: Option<Implicit<2, OctetString>>
+}
+
+/// ```asn.1
+/// DomainParameters ::= SEQUENCE {
+///     p       INTEGER, -- odd prime, p=jq +1
+///     g       INTEGER, -- generator, g
+///     q       INTEGER, -- factor of p-1
+///     j       INTEGER OPTIONAL, -- subgroup factor
+///     validationParms  ValidationParms OPTIONAL
+/// }
+/// ```
+#[derive(Sequence, Default, Clone, PartialEq)]
+pub struct DomainParameters {
+    pub p: BigInteger,
+    pub g: BigInteger,
+    pub q: BigInteger,
+    pub j: Option<BigInteger>,
+    pub validation_parms: Option<Any>
+}
+
+/// ```asn.1
+/// KDCDHKeyInfo ::= SEQUENCE {
+///     subjectPublicKey        [0] BIT STRING,
+///     nonce                   [1] INTEGER (0..4294967295),
+///     dhKeyExpiration         [2] KerberosTime OPTIONAL,
+///     ...
+/// }
+/// ```
+#[derive(Sequence, Default, Clone, PartialEq)]
+pub struct KdcDhKeyInfo {
+    pub subject_public_key: Tag<0, BitString>,
+    pub nonce: Tag<1, Integer>,
+    pub dh_key_expiration: Option<Tag<2, KerberosTime>>
+}
+
+/// ```asn.1
+/// DHRepInfo ::= SEQUENCE {
+///     dhSignedData            [0] IMPLICIT OCTET STRING,
+///     serverDHNonce           [1] DHNonce OPTIONAL,
+///     ...
+/// }
+/// ```
+#[derive(Sequence, Default, Clone, PartialEq)]
+pub struct DhRepInfo {
+    pub dh_signed_data: Implicit<0, OctetString>,
+    pub server_dh_nonce: Option<Tag<1, OctetString>>
+}
+
+/// ```asn.1
+/// PA-PK-AS-REP ::= CHOICE {
+///     dhInfo                  [0] DHRepInfo,
+///     encKeyPack              [1] IMPLICIT OCTET STRING,
+///     ...
+/// }
+/// ```
+#[derive(Choice, Clone, PartialEq)]
+pub enum PaPkAsRep {
+    DhInfo(Tag<0, DhRepInfo>),
+    EncKeyPack(Implicit<1, OctetString>)
+}
+
+/// Diffie-Hellman key agreement over MODP group 14
+pub struct DiffieHellman {
+    prime: BigInteger,
+    generator: BigInteger,
+    private_key: BigInteger
+}
+
+impl Default for DiffieHellman {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DiffieHellman {
+    /// Generate a new private key
+    pub fn new() -> Self {
+        Self {
+            prime: BigInteger::from_bytes_be(&MODP_GROUP14_PRIME),
+            generator: BigInteger::from(2u32),
+            private_key: BigInteger::from_bytes_be(&random(DH_PRIVATE_KEY_SIZE))
+        }
+    }
+
+    /// g^x mod p
+    pub fn public_key(&self) -> BigInteger {
+        self.generator.modpow(&self.private_key, &self.prime)
+    }
+
+    /// Public key with group parameters as sent by the client
+    pub fn subject_public_key_info(&self) -> KerlabResult<SubjectPublicKeyInfo> {
+        let parameters = DomainParameters {
+            p: self.prime.clone(),
+            g: self.generator.clone(),
+            q: (&self.prime - 1u32) >> 1,
+            j: None,
+            validation_parms: None
+        };
+
+        Ok(SubjectPublicKeyInfo {
+            algorithm: AlgorithmIdentifier::new(&OID_DH_PUBLIC_NUMBER, Some(Any::new(to_der(&parameters)?))),
+            subject_public_key: BitString::from_bytes(&to_der(&self.public_key())?)
+        })
+    }
+
+    /// Shared secret as a big endian octet string of the size of the prime
+    pub fn shared_secret(&self, public_key: &BigInteger) -> KerlabResult<Vec<u8>> {
+        if *public_key <= BigInteger::from(1u32) || *public_key >= &self.prime - 1u32 {
+            return Err(Error::new(KerlabErrorKind::Crypto, "Invalid Diffie-Hellman public key"))
+        }
+        let secret = public_key.modpow(&self.private_key, &self.prime).to_bytes_be();
+        let mut result = vec![0; MODP_GROUP14_PRIME.len() - secret.len()];
+        result.extend_from_slice(&secret);
+        Ok(result)
+    }
+}
+
+/// Decode a Diffie-Hellman public key carried into a BIT STRING
+pub fn dh_public_key(subject_public_key: &BitString) -> KerlabResult<BigInteger> {
+    let mut public_key = BigInteger::default();
+    from_der(&mut public_key, &subject_public_key.to_bytes())?;
+    Ok(public_key)
+}
+
+/// Derive the AS reply key from the Diffie-Hellman shared secret
+///
+/// k-truncate(SHA1(0x00 | x) | SHA1(0x01 | x) | ...)
+/// @see https://tools.ietf.org/html/rfc4556#section-3.2.3.1
+pub fn octetstring2key(x: &[u8], size: usize) -> Vec<u8> {
+    let mut result = vec![];
+    let mut counter: u8 = 0;
+    while result.len() < size {
+        let mut input = vec![counter];
+        input.extend_from_slice(x);
+        result.append(&mut DigestAlgorithm::Sha1.digest(&input));
+        counter += 1;
+    }
+    result.truncate(size);
+    result
+}
+
+/// Client side of PKINIT
+///
+/// Sign the AuthPack with the certificate of the user
+/// and derive the reply key from the KDC Diffie-Hellman public key
+/// The KDC certificate must be issued by a trusted CA, unless any KDC is accepted
+pub struct Pkinit {
+    certificate: Certificate,
+    key: RsaPrivateKey,
+    dh: DiffieHellman,
+    trusted_ca: Option<Certificate>,
+    any_kdc: bool
+}
+
+impl Pkinit {
+    /// constructor
+    pub fn new(certificate: Certificate, key: RsaPrivateKey) -> Self {
+        Self {
+            certificate,
+            key,
+            dh: DiffieHellman::new(),
+            trusted_ca: None,
+            any_kdc: false
+        }
+    }
+
+    /// Use the certificate and key of a PKCS#12 file
+    pub fn from_pkcs12(pkcs12: Pkcs12) -> KerlabResult<Self> {
+        let certificate = pkcs12.certificate()?.clone();
+        Ok(Self::new(certificate, pkcs12.key))
+    }
+
+    /// Use a PEM file that contains certificate and private key
+    pub fn from_pem(content: &str) -> KerlabResult<Self> {
+        let certificate = Certificate::from_pem(content)?.into_iter().next()
+            .ok_or_else(|| Error::new(KerlabErrorKind::Parsing, "No certificate found"))?;
+        Ok(Self::new(certificate, RsaPrivateKey::from_pem(content)?))
+    }
+
+    /// KDC certificate must be issued by this CA
+    pub fn with_trusted_ca(mut self, ca: Certificate) -> Self {
+        self.trusted_ca = Some(ca);
+        self
+    }
+
+    /// Accept a KDC certificate issued by any CA
+    /// The KDC is not authenticated, use it only in a lab
+    pub fn with_any_kdc(mut self) -> Self {
+        self.any_kdc = true;
+        self
+    }
+
+    /// Build the PA-PK-AS-REQ for an AS request
+    ///
+    /// The request body is protected by the paChecksum
+    pub fn pa_pk_as_req(&self, req_body: &KdcReqBody) -> KerlabResult<PaData> {
+        self.pa_pk_as_req_at(req_body, Utc::now())
+    }
+
+    /// PA-PK-AS-REQ with the PKAuthenticator time set to the KDC time
+    pub fn pa_pk_as_req_at(&self, req_body: &KdcReqBody, now: DateTime<Utc>) -> KerlabResult<PaData> {
+        let auth_pack = AuthPack {
+            pk_authenticator: Tag::new(PkAuthenticator {
+                cusec: Tag::new(now.nanosecond() / 1000 % 1000000),
+                ctime: Tag::new(KerberosTime::new(now)),
+                nonce: Tag::new(req_body.nonce.inner),
+                pa_checksum: Some(Tag::new(DigestAlgorithm::Sha1.digest(&to_der(req_body)?)))
+            }),
+            client_public_value: Some(Tag::new(self.dh.subject_public_key_info()?)),
+            supported_cms_types: None,
+            client_dh_nonce: None
+        };
+
+        let signed_auth_pack = SignedData::sign(
+            &OID_PKINIT_AUTH_DATA,
+            to_der(&auth_pack)?,
+            &self.certificate,
+            &self.key,
+            DigestAlgorithm::Sha1
+        )?;
+
+        PaData::new(PaDataType::PaPkAsReq, &PaPkAsReq {
+            signed_auth_pack: Implicit::new(to_der(&signed_auth_pack.to_content_info()?)?),
+            trusted_certifiers: None,
+            kdc_pk_id: None
+        })
+    }
+
+    /// Compute the key use to decrypt the AS-REP enc-part
+    ///
+    /// etype is the encryption type of the AS-REP enc-part
+    /// nonce is the nonce of the request
+    pub fn reply_key(&self, padata: &[PaData], etype: Integer, nonce: Integer) -> KerlabResult<EncryptionKey> {
+        let pa_pk_as_rep = padata.iter()
+            .find(|e| e.padata_type.inner == PaDataType::PaPkAsRep as Integer)
+            .ok_or_else(|| Error::new(KerlabErrorKind::Kerberos, "No PA-PK-AS-REP in reply"))?;
+
+        let mut reply = PaPkAsRep::default();
+        from_ber(&mut reply, &pa_pk_as_rep.padata_value)?;
+
+        let dh_info = match reply {
+            PaPkAsRep::DhInfo(dh_info) => dh_info.inner,
+            PaPkAsRep::EncKeyPack(_) => return Err(Error::new(KerlabErrorKind::Kerberos, "Public key encryption delivery is not supported"))
+        };
+
+        let signed_data = SignedData::from_content_info(&dh_info.dh_signed_data)?;
+        let kdc_certificate = signed_data.verify(&OID_PKINIT_DH_KEY_DATA)?;
+        match &self.trusted_ca {
+            Some(ca) => kdc_certificate.verify_issued_by(ca)?,
+            None if self.any_kdc => (),
+            None => return Err(Error::new(KerlabErrorKind::Crypto, "No trusted CA to check the KDC certificate"))
+        }
+
+        let mut key_info = KdcDhKeyInfo::default();
+        from_ber(&mut key_info, signed_data.content()?)?;
+        if key_info.nonce.inner != nonce {
+            return Err(Error::new(KerlabErrorKind::Kerberos, "PKINIT nonce mismatch"))
+        }
+
+        let mut x = self.dh.shared_secret(&dh_public_key(&key_info.subject_public_key)?)?;
+        if let Some(server_dh_nonce) = &dh_info.server_dh_nonce {
+            x.extend_from_slice(server_dh_nonce);
+        }
+
+        Ok(EncryptionKey {
+            keytype: Tag::new(etype),
+            keyvalue: Tag::new(octetstring2key(&x, key_size(etype)?))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use krbkdcreq::AsReq;
+
+    /// Simulate the KDC side to exercise the whole CMS path
+    #[test]
+    fn test_pkinit_exchange() {
+        let ca = Certificate::from_pem(include_str!("../tests/fixtures/ca.pem")).unwrap().remove(0);
+        let client = Pkinit::from_pkcs12(
+            Pkcs12::from(include_bytes!("../tests/fixtures/user.p12"), "kerlab").unwrap()
+        ).unwrap().with_trusted_ca(ca.clone());
+
+        let request = AsReq::new("LAB.LOCAL", "user", &[]).unwrap();
+        let padata = client.pa_pk_as_req(&request.req_body).unwrap();
+
+        // KDC side
+        let mut pa_pk_as_req = PaPkAsReq::default();
+        from_der(&mut pa_pk_as_req, &padata.padata_value).unwrap();
+        let signed_auth_pack = SignedData::from_content_info(&pa_pk_as_req.signed_auth_pack).unwrap();
+        let user_certificate = signed_auth_pack.verify(&OID_PKINIT_AUTH_DATA).unwrap();
+        user_certificate.verify_issued_by(&ca).unwrap();
+
+        let mut auth_pack = AuthPack::default();
+        from_der(&mut auth_pack, signed_auth_pack.content().unwrap()).unwrap();
+        assert_eq!(auth_pack.pk_authenticator.nonce.inner, request.req_body.nonce.inner);
+        assert!(auth_pack.pk_authenticator.pa_checksum.as_ref().unwrap().inner == DigestAlgorithm::Sha1.digest(&to_der(&request.req_body.inner).unwrap()));
+
+        let kdc_dh = DiffieHellman::new();
+        let client_public_key = dh_public_key(&auth_pack.client_public_value.unwrap().subject_public_key).unwrap();
+        let mut x = kdc_dh.shared_secret(&client_public_key).unwrap();
+        x.extend_from_slice(b"server nonce");
+        let kdc_key = octetstring2key(&x, 32);
+
+        let kdc_content = include_str!("../tests/fixtures/kdc.pem");
+        let kdc_signed_data = SignedData::sign(
+            &OID_PKINIT_DH_KEY_DATA,
+            to_der(&KdcDhKeyInfo {
+                subject_public_key: Tag::new(BitString::from_bytes(&to_der(&kdc_dh.public_key()).unwrap())),
+                nonce: Tag::new(request.req_body.nonce.inner),
+                dh_key_expiration: None
+            }).unwrap(),
+            &Certificate::from_pem(kdc_content).unwrap()[0],
+            &RsaPrivateKey::from_pem(kdc_content).unwrap(),
+            DigestAlgorithm::Sha256
+        ).unwrap();
+
+        let reply = PaData::new(PaDataType::PaPkAsRep, &PaPkAsRep::DhInfo(Tag::new(DhRepInfo {
+            dh_signed_data: Implicit::new(to_der(&kdc_signed_data.to_content_info().unwrap()).unwrap()),
+            server_dh_nonce: Some(Tag::new(b"server nonce".to_vec()))
+        }))).unwrap();
+
+        // client side
+        let key = client.reply_key(std::slice::from_ref(&reply), 18, request.req_body.nonce.inner).unwrap();
+        assert_eq!(key.keyvalue.inner, kdc_key);
+
+        // without a CA the KDC must be explicitly trusted
+        let client = Pkinit { trusted_ca: None, ..client };
+        assert!(client.reply_key(std::slice::from_ref(&reply), 18, request.req_body.nonce.inner).is_err());
+        let key = client.with_any_kdc().reply_key(&[reply], 18, request.req_body.nonce.inner).unwrap();
+        assert_eq!(key.keyvalue.inner, kdc_key);
+    }
+}
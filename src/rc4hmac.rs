@@ -3,6 +3,7 @@ use encryption::{EType, KeyUsage};
 use rnd::random;
 use md5::Md5;
 use hmac::{Hmac, Mac};
+use aescts::hmac_sha1;
 
 struct Rc4 {
     i: u8,
@@ -54,6 +55,14 @@ pub fn hmac_md5(key: &[u8], data: &[u8]) -> Vec<u8> {
     stream.result().code().to_vec()
 }
 
+/// Pseudo random function of RC4 HMAC
+///
+/// Not defined by RFC 4757 but use by FAST
+/// and implemented as an HMAC-SHA1 by both Windows and MIT
+pub fn prf(key: &[u8], data: &[u8]) -> Vec<u8> {
+    hmac_sha1(key, data)
+}
+
 pub struct Rc4Hmac {
     key: Vec<u8>,
     usage: KeyUsage,
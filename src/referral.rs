@@ -0,0 +1,192 @@
+//! Cross realm referrals
+//! Follow referral TGT until the service ticket is delivered
+//! @see https://tools.ietf.org/html/rfc6806
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use asn1::GeneralString;
+use base::{PrincipalName, PrincipalNameType, Realm, KdcOptionsType};
+use encryption::EncryptionKey;
+use error::{KerlabResult, Error, KerlabErrorKind};
+use krbkdcreq::TgsReq;
+use krbkdcrep::{TgsRep, EncTGSRepPart, EncKDCRepPart};
+use authenticator::Authenticator;
+use request::{KdcClock, KrbResponse};
+use ticket::Ticket;
+
+/// Maximum number of realms that can be crossed
+const MAX_REFERRALS: usize = 10;
+
+/// Address of the KDC of each realm
+#[derive(Default)]
+pub struct RealmMap {
+    kdcs: HashMap<String, String>
+}
+
+impl RealmMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the KDC address of a realm
+    /// Realm names are case insensitive
+    pub fn with(mut self, realm: &str, address: &str) -> Self {
+        self.kdcs.insert(realm.to_uppercase(), address.to_string());
+        self
+    }
+
+    /// Find the KDC of a realm
+    pub fn kdc(&self, realm: &str) -> KerlabResult<&str> {
+        self.kdcs.get(&realm.to_uppercase())
+            .map(|address| address.as_str())
+            .ok_or_else(|| Error::new(KerlabErrorKind::Unknown, &format!("No KDC known for realm {}", realm)))
+    }
+}
+
+/// The realm targeted by a referral TGT
+///
+/// A referral is a krbtgt/NEXT.REALM ticket delivered
+/// in place of the requested service ticket
+pub fn referral_realm(enc_part: &EncKDCRepPart, sname: &PrincipalName) -> Option<String> {
+    let service = &enc_part.sname.name_string;
+    if service.len() != 2 || service[0].as_str() != "krbtgt" || enc_part.sname.inner == *sname {
+        return None
+    }
+    if service[1].as_str().eq_ignore_ascii_case(enc_part.srealm.as_str()) {
+        return None
+    }
+    Some(service[1].as_str().to_string())
+}
+
+/// Service ticket obtained through referrals
+pub struct ServiceTicket {
+    pub response: TgsRep,
+    pub enc_part: EncTGSRepPart,
+    /// Realms crossed before the service ticket was delivered
+    pub path: Vec<String>
+}
+
+/// TGS client that follows referrals
+pub struct ReferralClient {
+    realms: RealmMap,
+    clock: KdcClock
+}
+
+impl ReferralClient {
+    pub fn new(realms: RealmMap) -> Self {
+        Self {
+            realms,
+            clock: KdcClock::default()
+        }
+    }
+
+    /// Known offsets of the KDC clocks
+    pub fn with_clock(mut self, clock: KdcClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Offsets learned while following the referrals
+    pub fn clock(&self) -> &KdcClock {
+        &self.clock
+    }
+
+    /// Ask a service ticket using a TGT of the client realm
+    ///
+    /// Canonicalize option is always set to allow the KDC to answer with a referral
+    pub fn ask_for(&mut self, tgt: Ticket, session_key: &EncryptionKey, crealm: Realm, cname: PrincipalName, sname: PrincipalName, options: &[KdcOptionsType]) -> KerlabResult<KrbResponse<ServiceTicket>> {
+        let mut options = options.to_vec();
+        options.push(KdcOptionsType::Canonicalize);
+
+        let mut ticket = tgt;
+        let mut key = session_key.clone();
+        let mut realm = crealm.as_str().to_string();
+        let mut path = vec![realm.clone()];
+
+        for _ in 0..MAX_REFERRALS {
+            let username = cname.name_string.first()
+                .ok_or_else(|| Error::new(KerlabErrorKind::Unknown, "Empty client name"))?;
+
+            let (_, response) = self.clock.ask_for::<TgsRep, _, _>(self.realms.kdc(&realm)?, |now| {
+                TgsReq::new(&realm, username.as_str(), sname.clone(), &options)?
+                    .with_authenticator(ticket.clone(), &key, Authenticator::new(crealm.clone(), cname.clone()).with_ctime(now))
+            })?;
+
+            let response = match response {
+                KrbResponse::Error(error) => return Ok(KrbResponse::Error(error)),
+                KrbResponse::Response(response) => response
+            };
+
+            let enc_part = response.decrypt(&key, None)?;
+
+            match referral_realm(&enc_part, &sname) {
+                Some(next) => {
+                    if path.iter().any(|e| e.eq_ignore_ascii_case(&next)) {
+                        return Err(Error::new(KerlabErrorKind::Kerberos, &format!("Referral loop on realm {}", next)))
+                    }
+                    ticket = response.ticket.inner.clone();
+                    key = enc_part.key.inner.clone();
+                    realm = next;
+                    path.push(realm.clone());
+                },
+                None => return Ok(KrbResponse::Response(ServiceTicket {
+                    response,
+                    enc_part,
+                    path
+                }))
+            }
+        }
+
+        Err(Error::new(KerlabErrorKind::Kerberos, "Too many referrals"))
+    }
+}
+
+/// Principal name of the TGT of a realm
+/// ```rust, ignore
+/// let sname = krbtgt("CHILD.LAB.LOCAL")?;
+/// ```
+pub fn krbtgt(realm: &str) -> KerlabResult<PrincipalName> {
+    Ok(PrincipalName::new(
+        PrincipalNameType::NtSrvInst,
+        vec![
+            GeneralString::from_str("krbtgt")?,
+            GeneralString::from_str(realm)?
+        ]
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use asn1::Tag;
+
+    fn enc_part(sname: PrincipalName, srealm: &str) -> EncKDCRepPart {
+        EncKDCRepPart {
+            sname: Tag::new(sname),
+            srealm: Tag::new(GeneralString::from_str(srealm).unwrap()),
+            ..Default::default()
+        }
+    }
+
+    /// A TGT for another realm delivered in place of the service ticket is a referral
+    #[test]
+    fn test_referral_realm() {
+        let service = PrincipalName::new(
+            PrincipalNameType::NtSrvInst,
+            vec![
+                GeneralString::from_str("cifs").unwrap(),
+                GeneralString::from_str("dc.lab.local").unwrap()
+            ]
+        );
+
+        assert_eq!(
+            referral_realm(&enc_part(krbtgt("LAB.LOCAL").unwrap(), "CHILD.LAB.LOCAL"), &service),
+            Some("LAB.LOCAL".to_string())
+        );
+        assert_eq!(referral_realm(&enc_part(service.clone(), "LAB.LOCAL"), &service), None);
+        assert_eq!(
+            referral_realm(&enc_part(krbtgt("LAB.LOCAL").unwrap(), "CHILD.LAB.LOCAL"), &krbtgt("LAB.LOCAL").unwrap()),
+            None
+        );
+    }
+}
@@ -1,9 +1,15 @@
-use error::{KerlabResult};
+use error::{KerlabResult, Error, KerlabErrorKind};
 use asn1::{to_der, from_ber, ASN1};
 use std::net::{TcpStream, UdpSocket, ToSocketAddrs};
 use std::io::{Write, Read, Cursor};
 use byteorder::{ReadBytesExt, BigEndian, WriteBytesExt};
-use krberror::{KrbError};
+use krberror::{KrbError, KrbErrorCode};
+use std::collections::HashMap;
+use chrono::{DateTime, Duration, Utc};
+
+/// Biggest Kerberos message accepted over TCP
+/// A bigger record mark means the stream is not Kerberos
+pub const MAX_RECORD_SIZE: usize = 0x100000;
 
 pub enum KrbResponse<T> {
     Error(KrbError),
@@ -17,13 +23,16 @@ impl TcpRequest{
     pub fn ask_for<T: ASN1 + Default, S: ToSocketAddrs>(request: &dyn ASN1, to: S) -> KerlabResult<KrbResponse<T>> {
 
         let mut stream = TcpStream::connect(to).unwrap();
-        let request_encoded = to_der(request);
+        let request_encoded = to_der(request)?;
 
         stream.write_u32::<BigEndian>(request_encoded.len() as u32).unwrap();
         stream.write(&request_encoded)?;
 
-        let response_length = stream.read_u32::<BigEndian>().unwrap();
-        let mut reponse_payload = vec![0; response_length as usize];
+        let response_length = stream.read_u32::<BigEndian>().unwrap() as usize;
+        if response_length > MAX_RECORD_SIZE {
+            return Err(Error::new(KerlabErrorKind::Parsing, "Reply of the KDC is too long"))
+        }
+        let mut reponse_payload = vec![0; response_length];
         stream.read_exact(&mut reponse_payload).unwrap();
 
         // Check error
@@ -38,11 +47,74 @@ impl TcpRequest{
     }
 }
 
+/// Clock of the KDCs
+///
+/// A KDC rejects a request made too far from its own time
+/// with a KRB_AP_ERR_SKEW error that gives this time.
+/// The offset is kept for each KDC and the requests are built at the KDC time
+#[derive(Default)]
+pub struct KdcClock {
+    offsets: HashMap<String, Duration>
+}
+
+impl KdcClock {
+    /// Known offset of a KDC
+    pub fn with_offset(mut self, kdc: &str, offset: Duration) -> Self {
+        self.offsets.insert(kdc.to_string(), offset);
+        self
+    }
+
+    /// Offset of the KDC clock from the local clock
+    pub fn offset(&self, kdc: &str) -> Duration {
+        self.offsets.get(kdc).cloned().unwrap_or_else(Duration::zero)
+    }
+
+    /// Current time of the KDC
+    pub fn now(&self, kdc: &str) -> DateTime<Utc> {
+        Utc::now() + self.offset(kdc)
+    }
+
+    /// Learn the offset of a KDC from a KRB_AP_ERR_SKEW error
+    pub fn learn(&mut self, kdc: &str, error: &KrbError) -> Option<Duration> {
+        if !error.is(KrbErrorCode::KrbApErrSkew) {
+            return None
+        }
+        let offset = error.server_time() - Utc::now();
+        self.offsets.insert(kdc.to_string(), offset);
+        Some(offset)
+    }
+
+    /// Send a request built at the KDC time over TCP
+    ///
+    /// When the KDC complains about the clock skew,
+    /// the request is built again with the learned offset and sent once more.
+    /// Return the request that was answered
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// let (request, response) = clock.ask_for::<AsRep, _, _>("10.0.0.1:88", |now| {
+    ///     AsReq::new("lab.local", "alice", &[])?.with_preauth_at(&key, now)
+    /// })?;
+    /// ```
+    pub fn ask_for<T, R, F>(&mut self, kdc: &str, build: F) -> KerlabResult<(R, KrbResponse<T>)>
+    where T: ASN1 + Default, R: ASN1, F: Fn(DateTime<Utc>) -> KerlabResult<R> {
+        let request = build(self.now(kdc))?;
+        match TcpRequest::ask_for::<T, &str>(&request, kdc)? {
+            KrbResponse::Error(error) if self.learn(kdc, &error).is_some() => {
+                let request = build(self.now(kdc))?;
+                let response = TcpRequest::ask_for::<T, &str>(&request, kdc)?;
+                Ok((request, response))
+            }
+            response => Ok((request, response))
+        }
+    }
+}
+
 pub struct UdpRequest;
 impl UdpRequest{
     /// But sometimes UDP is also available
     pub fn ask_for<T: ASN1 + Default, S: ToSocketAddrs>(request: &dyn ASN1, to: S) -> KerlabResult<KrbResponse<T>> {
-        let request_encoded = to_der(request);
+        let request_encoded = to_der(request)?;
         let mut stream = Cursor::new(vec![]);
         stream.write_u32::<BigEndian>(request_encoded.len() as u32).unwrap();
         stream.write(&request_encoded)?;
@@ -74,3 +146,30 @@ impl UdpRequest{
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use asn1::GeneralString;
+    use base::{KerberosTime, PrincipalName};
+    use std::str::FromStr;
+
+    /// The KDC time is learned from a KRB_AP_ERR_SKEW error only
+    #[test]
+    fn test_kdc_clock() {
+        let mut clock = KdcClock::default().with_offset("10.0.0.2:88", Duration::seconds(-30));
+        assert_eq!(clock.offset("10.0.0.1:88"), Duration::zero());
+        assert_eq!(clock.offset("10.0.0.2:88"), Duration::seconds(-30));
+
+        let mut error = KrbError::new(KrbErrorCode::KdcErrPreauthFailed, GeneralString::from_str("LAB.LOCAL").unwrap(), PrincipalName::default());
+        assert_eq!(clock.learn("10.0.0.1:88", &error), None);
+
+        error = KrbError::new(KrbErrorCode::KrbApErrSkew, GeneralString::from_str("LAB.LOCAL").unwrap(), PrincipalName::default());
+        error.stime.inner = KerberosTime::new(Utc::now() + Duration::hours(1));
+        error.susec.inner = 0;
+        let offset = clock.learn("10.0.0.1:88", &error).unwrap();
+        assert!((offset - Duration::hours(1)).num_seconds().abs() <= 1);
+        assert!((clock.now("10.0.0.1:88") - Utc::now() - Duration::hours(1)).num_seconds().abs() <= 1);
+        assert_eq!(clock.offset("10.0.0.2:88"), Duration::seconds(-30));
+    }
+}
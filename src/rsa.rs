@@ -0,0 +1,253 @@
+//! RSA PKCS#1 v1.5 signature
+//! @see https://tools.ietf.org/html/rfc8017
+//!
+//! For lab use only: the modular exponentiation of num-bigint is not constant time,
+//! so signing leaks the private key through timing
+
+use asn1::{ASN1, Implicit, Integer, OctetString, BigInteger, ObjectIdentifier, Any, to_der, from_der};
+use yasna::{DERWriter, BERReader};
+use error::{KerlabResult, Error, KerlabErrorKind};
+use x509::{AlgorithmIdentifier, pem_decode};
+use sha1::Sha1;
+use sha2::{Sha256, Digest};
+
+/// Hash algorithms use by signatures
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum DigestAlgorithm {
+    Sha1,
+    Sha256
+}
+
+impl DigestAlgorithm {
+    /// Identifier of the hash algorithm
+    pub fn oid(self) -> ObjectIdentifier {
+        match self {
+            DigestAlgorithm::Sha1 => ObjectIdentifier::new(&[1, 3, 14, 3, 2, 26]),
+            DigestAlgorithm::Sha256 => ObjectIdentifier::new(&[2, 16, 840, 1, 101, 3, 4, 2, 1])
+        }
+    }
+
+    /// Identifier of the RSA signature algorithm with this hash
+    pub fn signature_oid(self) -> ObjectIdentifier {
+        match self {
+            DigestAlgorithm::Sha1 => ObjectIdentifier::new(&[1, 2, 840, 113549, 1, 1, 5]),
+            DigestAlgorithm::Sha256 => ObjectIdentifier::new(&[1, 2, 840, 113549, 1, 1, 11])
+        }
+    }
+
+    /// Find the hash algorithm from its identifier
+    pub fn from_oid(oid: &ObjectIdentifier) -> KerlabResult<Self> {
+        for digest in &[DigestAlgorithm::Sha1, DigestAlgorithm::Sha256] {
+            if digest.oid() == *oid {
+                return Ok(*digest)
+            }
+        }
+        Err(Error::new(KerlabErrorKind::Crypto, "Unsupported digest algorithm"))
+    }
+
+    /// Find the hash algorithm from a signature algorithm identifier
+    pub fn from_signature_oid(oid: &ObjectIdentifier) -> KerlabResult<Self> {
+        for digest in &[DigestAlgorithm::Sha1, DigestAlgorithm::Sha256] {
+            if digest.signature_oid() == *oid {
+                return Ok(*digest)
+            }
+        }
+        Err(Error::new(KerlabErrorKind::Crypto, "Unsupported signature algorithm"))
+    }
+
+    /// Hash data
+    pub fn digest(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            DigestAlgorithm::Sha1 => {
+                let mut hasher = Sha1::new();
+                hasher.input(data);
+                hasher.result().to_vec()
+            },
+            DigestAlgorithm::Sha256 => {
+                let mut hasher = Sha256::new();
+                hasher.input(data);
+                hasher.result().to_vec()
+            }
+        }
+    }
+}
+
+/// ```asn.1
+/// DigestInfo ::= SEQUENCE {
+///     digestAlgorithm AlgorithmIdentifier,
+///     digest OCTET STRING
+/// }
+/// ```
+#[derive(Sequence, Default, Clone, PartialEq)]
+pub struct DigestInfo {
+    pub digest_algorithm: AlgorithmIdentifier,
+    pub digest: OctetString
+}
+
+/// EMSA-PKCS1-v1_5 encoding
+fn emsa_pkcs1_v15(digest: DigestAlgorithm, data: &[u8], size: usize) -> KerlabResult<Vec<u8>> {
+    let digest_info = to_der(&DigestInfo {
+        digest_algorithm: AlgorithmIdentifier {
+            algorithm: digest.oid(),
+            parameters: Some(Any::null())
+        },
+        digest: digest.digest(data)
+    })?;
+
+    if size < digest_info.len() + 11 {
+        return Err(Error::new(KerlabErrorKind::Crypto, "RSA key is too short"))
+    }
+
+    let mut result = vec![0x00, 0x01];
+    result.resize(size - digest_info.len() - 1, 0xff);
+    result.push(0x00);
+    result.extend_from_slice(&digest_info);
+    Ok(result)
+}
+
+/// Big endian representation padded to size
+fn to_bytes_be(value: &BigInteger, size: usize) -> Vec<u8> {
+    let bytes = value.to_bytes_be();
+    let mut result = vec![0; size.saturating_sub(bytes.len())];
+    result.extend_from_slice(&bytes);
+    result
+}
+
+/// ```asn.1
+/// RSAPublicKey ::= SEQUENCE {
+///     modulus           INTEGER,  -- n
+///     publicExponent    INTEGER   -- e
+/// }
+/// ```
+#[derive(Sequence, Default, Clone, PartialEq)]
+pub struct RsaPublicKey {
+    pub modulus: BigInteger,
+    pub public_exponent: BigInteger
+}
+
+impl RsaPublicKey {
+    /// Size of the modulus in bytes
+    pub fn size(&self) -> usize {
+        self.modulus.bits().div_ceil(8)
+    }
+
+    /// Verify a PKCS#1 v1.5 signature
+    pub fn verify(&self, digest: DigestAlgorithm, data: &[u8], signature: &[u8]) -> KerlabResult<()> {
+        let signature = BigInteger::from_bytes_be(signature);
+        if signature >= self.modulus {
+            return Err(Error::new(KerlabErrorKind::Crypto, "Invalid RSA signature"))
+        }
+
+        let encoded = signature.modpow(&self.public_exponent, &self.modulus);
+        if to_bytes_be(&encoded, self.size()) != emsa_pkcs1_v15(digest, data, self.size())? {
+            return Err(Error::new(KerlabErrorKind::Crypto, "Invalid RSA signature"))
+        }
+        Ok(())
+    }
+}
+
+/// ```asn.1
+/// RSAPrivateKey ::= SEQUENCE {
+///     version           Version,
+///     modulus           INTEGER,  -- n
+///     publicExponent    INTEGER,  -- e
+///     privateExponent   INTEGER,  -- d
+///     prime1            INTEGER,  -- p
+///     prime2            INTEGER,  -- q
+///     exponent1         INTEGER,  -- d mod (p-1)
+///     exponent2         INTEGER,  -- d mod (q-1)
+///     coefficient       INTEGER   -- (inverse of q) mod p
+/// }
+/// ```
+#[derive(Sequence, Default, Clone, PartialEq)]
+pub struct RsaPrivateKey {
+    pub version: Integer,
+    pub modulus: BigInteger,
+    pub public_exponent: BigInteger,
+    pub private_exponent: BigInteger,
+    pub prime1: BigInteger,
+    pub prime2: BigInteger,
+    pub exponent1: BigInteger,
+    pub exponent2: BigInteger,
+    pub coefficient: BigInteger
+}
+
+/// ```asn.1
+/// PrivateKeyInfo ::= SEQUENCE {
+///     version                   Version,
+///     privateKeyAlgorithm       AlgorithmIdentifier,
+///     privateKey                OCTET STRING,
+///     attributes           [0]  IMPLICIT Attributes OPTIONAL
+/// }
+/// ```
+#[derive(Sequence, Default, Clone, PartialEq)]
+pub struct PrivateKeyInfo {
+    pub version: Integer,
+    pub private_key_algorithm: AlgorithmIdentifier,
+    pub private_key: OctetString,
+    pub attributes: Option<Implicit<0, Any>>
+}
+
+impl RsaPrivateKey {
+    /// Parse a PKCS#1 encoded key
+    pub fn from_pkcs1(der: &[u8]) -> KerlabResult<Self> {
+        let mut key = RsaPrivateKey::default();
+        from_der(&mut key, der)?;
+        Ok(key)
+    }
+
+    /// Parse a PKCS#8 encoded key
+    pub fn from_pkcs8(der: &[u8]) -> KerlabResult<Self> {
+        let mut info = PrivateKeyInfo::default();
+        from_der(&mut info, der)?;
+        Self::from_pkcs1(&info.private_key)
+    }
+
+    /// Load the first private key of a PEM file
+    /// Both PKCS#8 and PKCS#1 format are supported
+    pub fn from_pem(content: &str) -> KerlabResult<Self> {
+        if let Some(der) = pem_decode(content, "PRIVATE KEY")?.first() {
+            return Self::from_pkcs8(der)
+        }
+        if let Some(der) = pem_decode(content, "RSA PRIVATE KEY")?.first() {
+            return Self::from_pkcs1(der)
+        }
+        Err(Error::new(KerlabErrorKind::Parsing, "No private key found"))
+    }
+
+    /// Public part of the key
+    pub fn public_key(&self) -> RsaPublicKey {
+        RsaPublicKey {
+            modulus: self.modulus.clone(),
+            public_exponent: self.public_exponent.clone()
+        }
+    }
+
+    /// PKCS#1 v1.5 signature
+    /// Use the chinese remainder theorem
+    pub fn sign(&self, digest: DigestAlgorithm, data: &[u8]) -> KerlabResult<Vec<u8>> {
+        let size = self.public_key().size();
+        let message = BigInteger::from_bytes_be(&emsa_pkcs1_v15(digest, data, size)?);
+
+        let s1 = message.modpow(&self.exponent1, &self.prime1);
+        let s2 = message.modpow(&self.exponent2, &self.prime2);
+        let h = (&self.coefficient * (&s1 + &self.prime1 - (&s2 % &self.prime1))) % &self.prime1;
+        let signature = s2 + h * &self.prime2;
+
+        Ok(to_bytes_be(&signature, size))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A signature must be verified by the public key
+    #[test]
+    fn test_sign_verify() {
+        let key = RsaPrivateKey::from_pem(include_str!("../tests/fixtures/kdc.pem")).unwrap();
+        let signature = key.sign(DigestAlgorithm::Sha256, b"kerlab").unwrap();
+        key.public_key().verify(DigestAlgorithm::Sha256, b"kerlab", &signature).unwrap();
+        assert!(key.public_key().verify(DigestAlgorithm::Sha256, b"kerlaB", &signature).is_err());
+    }
+}
@@ -2,7 +2,7 @@ use asn1::{Tag, Integer, ASN1, Application, OctetString};
 use base::{Realm, PrincipalName, TicketFlags, KerberosTime, AuthorizationData, HostAddresses};
 use encryption::{EncryptedData, EncryptionKey};
 use yasna::{DERWriter, BERReader};
-use error::{KerlabResult};
+use error::{KerlabResult, Error, KerlabErrorKind};
 
 /// @see pub type Ticket = Application<1, TicketBody>;
 /// ```asn.1
@@ -36,6 +36,79 @@ pub struct TransitedEncoding {
     pub contents: Tag<1, OctetString>
 }
 
+/// Registered encoding of the transited field
+#[repr(u32)]
+pub enum TransitedType {
+    DomainX500Compress = 1
+}
+
+impl TransitedEncoding {
+    /// List of realms transited by the ticket
+    ///
+    /// Only DOMAIN-X500-COMPRESS is supported
+    /// An empty sub field is kept as an empty entry, it means that all realms
+    /// between the previous and the next realm were transited
+    /// @see https://tools.ietf.org/html/rfc4120#section-3.3.3.2
+    pub fn realms(&self) -> KerlabResult<Vec<String>> {
+        if self.tr_type.inner != TransitedType::DomainX500Compress as Integer {
+            return Err(Error::new(KerlabErrorKind::Parsing, "Unsupported transited encoding"))
+        }
+
+        let contents = String::from_utf8(self.contents.inner.clone())
+            .map_err(|_| Error::new(KerlabErrorKind::Parsing, "Invalid transited encoding"))?;
+
+        // no realm transited
+        if contents.is_empty() {
+            return Ok(vec![])
+        }
+
+        // split on unescaped comma, keep track of unescaped trailing dot
+        let mut fields = vec![];
+        let mut field = String::new();
+        let mut trailing_dot = false;
+        let mut chars = contents.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => {
+                    field.push(chars.next().ok_or_else(|| Error::new(KerlabErrorKind::Parsing, "Invalid escape in transited encoding"))?);
+                    trailing_dot = false;
+                },
+                ',' => {
+                    fields.push((field, trailing_dot));
+                    field = String::new();
+                    trailing_dot = false;
+                },
+                _ => {
+                    field.push(c);
+                    trailing_dot = c == '.';
+                }
+            }
+        }
+        fields.push((field, trailing_dot));
+
+        let mut result: Vec<String> = vec![];
+        for (field, trailing_dot) in fields {
+            if field.is_empty() {
+                result.push(field);
+                continue;
+            }
+
+            // compression is relative to the last named realm
+            let realm = match result.iter().rev().find(|realm| !realm.is_empty()) {
+                // domain style name is a prefix of the previous realm
+                Some(previous) if trailing_dot => format!("{}{}", field, previous),
+                None if trailing_dot => return Err(Error::new(KerlabErrorKind::Parsing, "First transited realm can't be compressed")),
+                // X.500 style name is a suffix of the previous realm
+                Some(previous) if field.starts_with('/') && previous.starts_with('/') => format!("{}{}", previous, field),
+                // leading space means not compressed
+                _ => field.trim_start_matches(' ').to_string()
+            };
+            result.push(realm);
+        }
+        Ok(result)
+    }
+}
+
 /// See https://www.freesoft.org/CIE/RFC/1510/52.htm
 /// ```asn1
 /// -- Encrypted part of ticket
@@ -93,3 +166,38 @@ pub enum AdDataType {
 /// ```
 pub type AdIfRelevant = AuthorizationData;
 
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn transited(contents: &str) -> TransitedEncoding {
+        TransitedEncoding {
+            tr_type: Tag::new(TransitedType::DomainX500Compress as Integer),
+            contents: Tag::new(contents.as_bytes().to_vec())
+        }
+    }
+
+    /// Examples of RFC 4120
+    #[test]
+    fn test_transited_realms() {
+        assert_eq!(
+            transited("EDU,MIT.,ATHENA.,WASHINGTON.EDU,CS.").realms().unwrap(),
+            vec!["EDU", "MIT.EDU", "ATHENA.MIT.EDU", "WASHINGTON.EDU", "CS.WASHINGTON.EDU"]
+        );
+        assert_eq!(
+            transited("/COM,/HP,/APOLLO, /COM/DEC").realms().unwrap(),
+            vec!["/COM", "/COM/HP", "/COM/HP/APOLLO", "/COM/DEC"]
+        );
+        assert_eq!(
+            transited("EDU,,CS.WASHINGTON.EDU").realms().unwrap(),
+            vec!["EDU", "", "CS.WASHINGTON.EDU"]
+        );
+        assert_eq!(
+            transited(",EDU,MIT.,").realms().unwrap(),
+            vec!["", "EDU", "MIT.EDU", ""]
+        );
+        assert!(transited("").realms().unwrap().is_empty());
+        assert!(transited("CS.").realms().is_err());
+    }
+}
@@ -0,0 +1,389 @@
+//! Validation of a KDC reply against its request
+//!
+//! The plain part of a reply can be changed by anyone on the path,
+//! the client must check that the encrypted part answers its own request.
+//! Failed checks are errors, suspicious but legitimate answers are warnings
+//!
+//! @see https://datatracker.ietf.org/doc/html/rfc4120#section-3.1.5
+
+use std::fmt;
+use chrono::{DateTime, Duration, Utc};
+use asn1::Integer;
+use base::{KdcOptionsType, MessageType, PrincipalName, TicketFlagsType, DEFAULT_MAX_SKEW_MINUTES};
+use encryption::EType;
+use json::{Json, ToJson, etype_name};
+use krbkdcrep::{KdcRep, EncKDCRepPart};
+use krbkdcreq::KdcReq;
+use padata::PaDataType;
+
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Severity {
+    Warning,
+    Error
+}
+
+/// Checks made on a reply
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Check {
+    /// AS-REP for an AS-REQ, TGS-REP for a TGS-REQ
+    MessageType,
+    /// Nonce of the encrypted part, defeats replayed replies
+    Nonce,
+    /// Client name and realm of the reply
+    Client,
+    /// Server name and realm of the encrypted part, defeats sname substitution
+    Server,
+    /// Server name of the ticket against the encrypted part
+    Ticket,
+    /// Encryption types chosen by the KDC
+    Etype,
+    /// Ticket times
+    Times,
+    /// Ticket flags against requested options
+    Flags
+}
+
+impl Check {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Check::MessageType => "message-type",
+            Check::Nonce => "nonce",
+            Check::Client => "client",
+            Check::Server => "server",
+            Check::Ticket => "ticket",
+            Check::Etype => "etype",
+            Check::Times => "times",
+            Check::Flags => "flags"
+        }
+    }
+}
+
+/// A failed check
+pub struct Issue {
+    pub check: Check,
+    pub severity: Severity,
+    pub description: String
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error"
+        };
+        write!(f, "{} {}: {}", severity, self.check.name(), self.description)
+    }
+}
+
+impl ToJson for Issue {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("check", Json::string(self.check.name())),
+            ("severity", Json::string(if self.severity == Severity::Error { "error" } else { "warning" })),
+            ("description", Json::string(&self.description))
+        ])
+    }
+}
+
+/// Result of the validation of a reply
+#[derive(Default)]
+pub struct Validation {
+    pub issues: Vec<Issue>
+}
+
+impl Validation {
+    fn add(&mut self, check: Check, severity: Severity, description: String) {
+        self.issues.push(Issue {
+            check,
+            severity,
+            description
+        })
+    }
+
+    /// No error, warnings are allowed
+    pub fn is_valid(&self) -> bool {
+        !self.issues.iter().any(|issue| issue.severity == Severity::Error)
+    }
+
+    pub fn errors(&self) -> Vec<&Issue> {
+        self.issues.iter().filter(|issue| issue.severity == Severity::Error).collect()
+    }
+
+    pub fn warnings(&self) -> Vec<&Issue> {
+        self.issues.iter().filter(|issue| issue.severity == Severity::Warning).collect()
+    }
+}
+
+fn is_strong(etype: Integer) -> bool {
+    etype == EType::Aes128CtsHmacSha196 as Integer || etype == EType::Aes256CtsHmacSha196 as Integer
+}
+
+fn etype(etype: Integer) -> String {
+    etype_name(etype).map_or(etype.to_string(), |name| name.to_string())
+}
+
+/// Names are compared as Windows does, without case and name type
+fn same_name(a: &PrincipalName, b: &PrincipalName) -> bool {
+    a.name().eq_ignore_ascii_case(&b.name())
+}
+
+fn has_padata(request: &KdcReq, padata_type: PaDataType) -> bool {
+    let padata_type = padata_type as Integer;
+    request.padata.as_ref().is_some_and(|padata| padata.iter().any(|e| e.padata_type.inner == padata_type))
+}
+
+/// Validate a reply with its decrypted part
+///
+/// # Example
+/// ```rust, ignore
+/// let validation = validate_reply(&request, &reply, &enc_part);
+/// if !validation.is_valid() {
+///     ...
+/// }
+/// ```
+pub fn validate_reply(request: &KdcReq, reply: &KdcRep, enc_part: &EncKDCRepPart) -> Validation {
+    validate_reply_at(request, reply, enc_part, Utc::now(), Duration::minutes(DEFAULT_MAX_SKEW_MINUTES))
+}
+
+/// Validate a reply at a given time and with the accepted clock skew
+pub fn validate_reply_at(request: &KdcReq, reply: &KdcRep, enc_part: &EncKDCRepPart, now: DateTime<Utc>, max_skew: Duration) -> Validation {
+    let mut validation = Validation::default();
+    let body = &request.req_body;
+    let options = body.kdc_options.inner;
+    let canonicalize = options.contains(KdcOptionsType::Canonicalize);
+    let is_as = request.msg_type.inner == MessageType::KrbAsReq as Integer;
+
+    let expected_type = if is_as { MessageType::KrbAsRep } else { MessageType::KrbTgsRep } as Integer;
+    if reply.msg_type.inner != expected_type {
+        validation.add(Check::MessageType, Severity::Error, format!("message type {} doesn't answer message type {}", reply.msg_type.inner, request.msg_type.inner));
+    }
+
+    if enc_part.nonce.inner != body.nonce.inner {
+        validation.add(Check::Nonce, Severity::Error, format!("reply nonce {} doesn't match request nonce {}", enc_part.nonce.inner, body.nonce.inner));
+    }
+
+    // S4U2Self and S4U2Proxy change the client of a TGS reply
+    let s4u = !is_as && (has_padata(request, PaDataType::PaForUser) || options.contains(KdcOptionsType::CNameInAddLTkt));
+    if !s4u {
+        if let Some(cname) = &body.cname {
+            if !same_name(&reply.cname, cname) {
+                validation.add(Check::Client, if canonicalize { Severity::Warning } else { Severity::Error }, format!("client {} was asked, reply is for {}", cname.name(), reply.cname.name()));
+            }
+        }
+    }
+    // the realm of a TGS-REQ is the one of the server
+    if is_as && !reply.crealm.as_str().eq_ignore_ascii_case(body.realm.as_str()) {
+        validation.add(Check::Client, if canonicalize { Severity::Warning } else { Severity::Error }, format!("client realm {} was asked, reply is for {}", body.realm.as_str(), reply.crealm.as_str()));
+    }
+
+    // canonicalization and referrals change the server
+    if let Some(sname) = &body.sname {
+        if !same_name(&enc_part.sname, sname) {
+            validation.add(Check::Server, if canonicalize { Severity::Warning } else { Severity::Error }, format!("service {} was asked, ticket is for {}", sname.name(), enc_part.sname.name()));
+        }
+    }
+    if !enc_part.srealm.as_str().eq_ignore_ascii_case(body.realm.as_str()) {
+        validation.add(Check::Server, if canonicalize { Severity::Warning } else { Severity::Error }, format!("realm {} was asked, ticket is for {}", body.realm.as_str(), enc_part.srealm.as_str()));
+    }
+
+    // the ticket is not protected by the reply key
+    if !same_name(&reply.ticket.sname, &enc_part.sname) || !reply.ticket.realm.as_str().eq_ignore_ascii_case(enc_part.srealm.as_str()) {
+        validation.add(Check::Ticket, Severity::Error, format!("ticket is for {}@{} but encrypted part for {}@{}",
+            reply.ticket.sname.name(), reply.ticket.realm.as_str(), enc_part.sname.name(), enc_part.srealm.as_str()));
+    }
+
+    validate_etype(&mut validation, &body.etype, enc_part.key.keytype.inner, "session key");
+    if is_as {
+        validate_etype(&mut validation, &body.etype, reply.enc_part.etype.inner, "reply key");
+    }
+
+    validate_times(&mut validation, request, enc_part, now, max_skew);
+
+    if options.contains(KdcOptionsType::Forwardable) && !enc_part.flags.contains(TicketFlagsType::Forwardable) {
+        validation.add(Check::Flags, Severity::Warning, "forwardable ticket was asked but not delivered".to_string());
+    }
+    if enc_part.flags.contains(TicketFlagsType::Invalid) && !options.contains(KdcOptionsType::AllowPostDate) {
+        validation.add(Check::Flags, Severity::Error, "invalid ticket was not asked".to_string());
+    }
+
+    validation
+}
+
+/// The etype must be one of the request,
+/// a weak one is suspicious when a strong one was preferred
+fn validate_etype(validation: &mut Validation, requested: &[Integer], chosen: Integer, usage: &str) {
+    match requested.iter().position(|e| *e == chosen) {
+        None => validation.add(Check::Etype, Severity::Error, format!("{} etype {} was not requested", usage, etype(chosen))),
+        Some(index) => {
+            if let Some(preferred) = requested[..index].iter().find(|e| is_strong(**e)) {
+                if !is_strong(chosen) {
+                    validation.add(Check::Etype, Severity::Warning, format!("{} etype {} was chosen but {} was preferred", usage, etype(chosen), etype(*preferred)));
+                }
+            }
+        }
+    }
+}
+
+fn validate_times(validation: &mut Validation, request: &KdcReq, enc_part: &EncKDCRepPart, now: DateTime<Utc>, max_skew: Duration) {
+    let body = &request.req_body;
+    let authtime = enc_part.authtime.inner.inner;
+    let starttime = enc_part.starttime.as_ref().map_or(authtime, |e| e.inner.inner);
+    let endtime = enc_part.endtime.inner.inner;
+
+    if endtime <= starttime {
+        validation.add(Check::Times, Severity::Error, format!("ticket ends at {} before it starts at {}", endtime, starttime));
+    }
+
+    if endtime > body.till.inner.inner {
+        validation.add(Check::Times, Severity::Error, format!("ticket ends at {} after the requested {}", endtime, body.till.inner.inner));
+    }
+
+    if endtime < now {
+        validation.add(Check::Times, Severity::Error, format!("ticket expired at {}", endtime));
+    }
+
+    let postdated = body.from.as_ref().is_some_and(|from| from.inner.inner > now + max_skew);
+    if !postdated && starttime > now + max_skew {
+        validation.add(Check::Times, Severity::Warning, format!("ticket starts at {} into the future", starttime));
+    }
+
+    // a TGS reply keeps the authtime of the TGT
+    if request.msg_type.inner == MessageType::KrbAsReq as Integer && (authtime - now).num_seconds().abs() > max_skew.num_seconds() {
+        validation.add(Check::Times, Severity::Warning, format!("authentication time {} is out of the clock skew", authtime));
+    }
+
+    if let Some(renew_till) = &enc_part.renew_till {
+        if !enc_part.flags.contains(TicketFlagsType::Renewable) {
+            validation.add(Check::Times, Severity::Warning, "renew time of a ticket that is not renewable".to_string());
+        }
+        if let Some(rtime) = &body.rtime {
+            if renew_till.inner.inner > rtime.inner.inner {
+                validation.add(Check::Times, Severity::Error, format!("ticket renews until {} after the requested {}", renew_till.inner.inner, rtime.inner.inner));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use asn1::{Tag, GeneralString};
+    use base::{PrincipalNameType, KerberosTime, TicketFlags, KDCOptions};
+    use encryption::{EncryptionKey, EncryptedData};
+    use krbkdcreq::{AsReq, TgsReq};
+    use padata::PaData;
+    use ticket::{Ticket, TicketBody};
+    use std::str::FromStr;
+
+    /// Reply as a KDC would send it
+    fn kdc_reply(request: &KdcReq, etype: EType) -> (KdcRep, EncKDCRepPart) {
+        let body = &request.req_body;
+        let now = Utc::now();
+        let sname = body.sname.as_ref().unwrap().inner.clone();
+        let reply = KdcRep {
+            pvno: Tag::new(5),
+            msg_type: Tag::new(if request.msg_type.inner == MessageType::KrbAsReq as Integer { MessageType::KrbAsRep } else { MessageType::KrbTgsRep } as Integer),
+            padata: None,
+            crealm: Tag::new(GeneralString::from_str("LAB.LOCAL").unwrap()),
+            cname: Tag::new(body.cname.as_ref().unwrap().inner.clone()),
+            ticket: Tag::new(Ticket {
+                inner: TicketBody {
+                    tkt_vno: Tag::new(5),
+                    realm: Tag::new(GeneralString::from_str("LAB.LOCAL").unwrap()),
+                    sname: Tag::new(sname.clone()),
+                    enc_part: Tag::new(EncryptedData::new(18, vec![]))
+                }
+            }),
+            enc_part: Tag::new(EncryptedData::new(etype as Integer, vec![]))
+        };
+        let enc_part = EncKDCRepPart {
+            key: Tag::new(EncryptionKey::random(etype as Integer).unwrap()),
+            last_req: Tag::new(vec![]),
+            nonce: Tag::new(body.nonce.inner),
+            key_expiration: None,
+            flags: Tag::new(TicketFlags::new(&[TicketFlagsType::Initial])),
+            authtime: Tag::new(KerberosTime::new(now)),
+            starttime: None,
+            endtime: Tag::new(KerberosTime::new(now + Duration::hours(10))),
+            renew_till: None,
+            srealm: Tag::new(GeneralString::from_str("LAB.LOCAL").unwrap()),
+            sname: Tag::new(sname),
+            caddr: None,
+            encrypted_pa_data: None
+        };
+        (reply, enc_part)
+    }
+
+    fn checks(validation: &Validation) -> Vec<(Check, Severity)> {
+        validation.issues.iter().map(|issue| (issue.check, issue.severity)).collect()
+    }
+
+    /// Tampered replies are rejected
+    #[test]
+    fn test_validate_reply() {
+        let mut request = AsReq::new("lab.local", "alice", &[]).unwrap();
+        request.req_body.etype.inner = vec![EType::Aes256CtsHmacSha196 as Integer, EType::Rc4Hmac as Integer];
+
+        let (reply, enc_part) = kdc_reply(&request, EType::Aes256CtsHmacSha196);
+        assert!(validate_reply(&request, &reply, &enc_part).issues.is_empty());
+
+        // replayed reply
+        let mut replayed = enc_part.clone();
+        replayed.nonce.inner ^= 1;
+        assert_eq!(checks(&validate_reply(&request, &reply, &replayed)), [(Check::Nonce, Severity::Error)]);
+
+        // ticket of another service
+        let mut substituted = reply.clone();
+        substituted.ticket.inner.sname.inner = PrincipalName::new(PrincipalNameType::NtSrvInst, vec![
+            GeneralString::from_str("cifs").unwrap(),
+            GeneralString::from_str("dc.lab.local").unwrap()
+        ]);
+        let validation = validate_reply(&request, &substituted, &enc_part);
+        assert!(!validation.is_valid());
+        assert_eq!(checks(&validation), [(Check::Ticket, Severity::Error)]);
+
+        // downgrade to a requested but weaker etype
+        let (reply, enc_part) = kdc_reply(&request, EType::Rc4Hmac);
+        let validation = validate_reply(&request, &reply, &enc_part);
+        assert!(validation.is_valid());
+        assert_eq!(validation.warnings().len(), 2);
+        assert_eq!(validation.warnings()[0].to_string(), "warning etype: session key etype rc4-hmac was chosen but aes256-cts-hmac-sha1-96 was preferred");
+
+        // etype that was not requested and ticket longer than requested
+        let mut enc_part = enc_part;
+        enc_part.key.inner = EncryptionKey::random(EType::Aes128CtsHmacSha196 as Integer).unwrap();
+        enc_part.endtime.inner = KerberosTime::new(request.req_body.till.inner.inner + Duration::hours(1));
+        assert_eq!(checks(&validate_reply(&request, &reply, &enc_part)), [
+            (Check::Etype, Severity::Error),
+            (Check::Etype, Severity::Warning),
+            (Check::Times, Severity::Error)
+        ]);
+    }
+
+    /// The client of a TGS reply is checked unless S4U changes it
+    #[test]
+    fn test_validate_tgs_client() {
+        let sname = PrincipalName::new(PrincipalNameType::NtSrvInst, vec![
+            GeneralString::from_str("cifs").unwrap(),
+            GeneralString::from_str("dc.lab.local").unwrap()
+        ]);
+        let request = TgsReq::new("LAB.LOCAL", "alice", sname, &[]).unwrap();
+        let (mut reply, enc_part) = kdc_reply(&request, EType::Rc4Hmac);
+        assert!(validate_reply(&request, &reply, &enc_part).is_valid());
+
+        reply.cname.inner = PrincipalName::new(PrincipalNameType::NtPrincipal, vec![GeneralString::from_str("administrator").unwrap()]);
+        assert_eq!(checks(&validate_reply(&request, &reply, &enc_part)), [(Check::Client, Severity::Error)]);
+
+        // S4U2Self
+        let key = EncryptionKey::random(EType::Rc4Hmac as Integer).unwrap();
+        let mut s4u2self = request.clone();
+        s4u2self.padata = Some(Tag::new(vec![PaData::pa_for_user(reply.cname.inner.clone(), GeneralString::from_str("LAB.LOCAL").unwrap(), &key).unwrap()]));
+        assert!(validate_reply(&s4u2self, &reply, &enc_part).is_valid());
+
+        // S4U2Proxy
+        let mut s4u2proxy = request.clone();
+        s4u2proxy.req_body.kdc_options.inner = KDCOptions::new(&[KdcOptionsType::CNameInAddLTkt]);
+        assert!(validate_reply(&s4u2proxy, &reply, &enc_part).is_valid());
+    }
+}
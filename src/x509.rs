@@ -0,0 +1,175 @@
+//! Minimal X.509 support use by PKINIT
+//! @see https://tools.ietf.org/html/rfc5280
+
+use asn1::{ASN1, Tag, Implicit, Integer, BitString, BigInteger, ObjectIdentifier, Any, to_der, from_der};
+use yasna::{DERWriter, BERReader};
+use error::{KerlabResult, Error, KerlabErrorKind};
+use rsa::{RsaPublicKey, DigestAlgorithm};
+
+/// rsaEncryption
+pub const OID_RSA_ENCRYPTION: [u64; 7] = [1, 2, 840, 113549, 1, 1, 1];
+
+/// ```asn.1
+/// AlgorithmIdentifier ::= SEQUENCE {
+///     algorithm   OBJECT IDENTIFIER,
+///     parameters  ANY DEFINED BY algorithm OPTIONAL
+/// }
+/// ```
+#[derive(Sequence, Default, Clone, PartialEq, Debug)]
+pub struct AlgorithmIdentifier {
+    pub algorithm: ObjectIdentifier,
+    pub parameters: Option<Any>
+}
+
+impl AlgorithmIdentifier {
+    /// constructor
+    pub fn new(algorithm: &[u64], parameters: Option<Any>) -> Self {
+        Self {
+            algorithm: ObjectIdentifier::new(algorithm),
+            parameters
+        }
+    }
+}
+
+/// ```asn.1
+/// SubjectPublicKeyInfo ::= SEQUENCE {
+///     algorithm         AlgorithmIdentifier,
+///     subjectPublicKey  BIT STRING
+/// }
+/// ```
+#[derive(Sequence, Default, Clone, PartialEq)]
+pub struct SubjectPublicKeyInfo {
+    pub algorithm: AlgorithmIdentifier,
+    pub subject_public_key: BitString
+}
+
+/// ```asn.1
+/// TBSCertificate ::= SEQUENCE {
+///     version         [0] EXPLICIT Version DEFAULT v1,
+///     serialNumber         CertificateSerialNumber,
+///     signature            AlgorithmIdentifier,
+///     issuer               Name,
+///     validity             Validity,
+///     subject              Name,
+///     subjectPublicKeyInfo SubjectPublicKeyInfo,
+///     issuerUniqueID  [1]  IMPLICIT UniqueIdentifier OPTIONAL,
+///     subjectUniqueID [2]  IMPLICIT UniqueIdentifier OPTIONAL,
+///     extensions      [3]  EXPLICIT Extensions OPTIONAL
+/// }
+/// ```
+#[derive(Sequence, Default, Clone, PartialEq)]
+pub struct TbsCertificate {
+    pub version: Option<Tag<0, Integer>>,
+    pub serial_number: BigInteger,
+    pub signature: AlgorithmIdentifier,
+    pub issuer: Any,
+    pub validity: Any,
+    pub subject: Any,
+    pub subject_public_key_info: SubjectPublicKeyInfo,
+    pub issuer_unique_id: Option<Implicit<1, BitString>>,
+    pub subject_unique_id: Option<Implicit<2, BitString>>,
+    pub extensions: Option<Tag<3, Any>>
+}
+
+/// ```asn.1
+/// Certificate ::= SEQUENCE {
+///     tbsCertificate       TBSCertificate,
+///     signatureAlgorithm   AlgorithmIdentifier,
+///     signatureValue       BIT STRING
+/// }
+/// ```
+#[derive(Sequence, Default, Clone, PartialEq)]
+pub struct Certificate {
+    pub tbs_certificate: TbsCertificate,
+    pub signature_algorithm: AlgorithmIdentifier,
+    pub signature_value: BitString
+}
+
+/// ```asn.1
+/// IssuerAndSerialNumber ::= SEQUENCE {
+///     issuer        Name,
+///     serialNumber  CertificateSerialNumber
+/// }
+/// ```
+#[derive(Sequence, Default, Clone, PartialEq)]
+pub struct IssuerAndSerialNumber {
+    pub issuer: Any,
+    pub serial_number: BigInteger
+}
+
+impl Certificate {
+    /// Parse a DER encoded certificate
+    pub fn from_der(der: &[u8]) -> KerlabResult<Self> {
+        let mut certificate = Certificate::default();
+        from_der(&mut certificate, der)?;
+        Ok(certificate)
+    }
+
+    /// Parse all certificates of a PEM file
+    pub fn from_pem(content: &str) -> KerlabResult<Vec<Self>> {
+        pem_decode(content, "CERTIFICATE")?.iter()
+            .map(|der| Self::from_der(der))
+            .collect()
+    }
+
+    /// RSA public key of the subject
+    pub fn public_key(&self) -> KerlabResult<RsaPublicKey> {
+        let spki = &self.tbs_certificate.subject_public_key_info;
+        if spki.algorithm.algorithm != ObjectIdentifier::new(&OID_RSA_ENCRYPTION) {
+            return Err(Error::new(KerlabErrorKind::Crypto, "Only RSA certificates are supported"))
+        }
+        let mut public_key = RsaPublicKey::default();
+        from_der(&mut public_key, &spki.subject_public_key.to_bytes())?;
+        Ok(public_key)
+    }
+
+    /// Identifier of the certificate use by CMS
+    pub fn issuer_and_serial_number(&self) -> IssuerAndSerialNumber {
+        IssuerAndSerialNumber {
+            issuer: self.tbs_certificate.issuer.clone(),
+            serial_number: self.tbs_certificate.serial_number.clone()
+        }
+    }
+
+    /// Check that the certificate was signed by the issuer certificate
+    pub fn verify_issued_by(&self, issuer: &Certificate) -> KerlabResult<()> {
+        if self.tbs_certificate.issuer != issuer.tbs_certificate.subject {
+            return Err(Error::new(KerlabErrorKind::Crypto, "Certificate issuer mismatch"))
+        }
+        issuer.public_key()?.verify(
+            DigestAlgorithm::from_signature_oid(&self.signature_algorithm.algorithm)?,
+            &to_der(&self.tbs_certificate)?,
+            &self.signature_value.to_bytes()
+        )
+    }
+}
+
+/// Extract all blocks of a PEM file that match the label
+///
+/// # Example
+/// ```rust, ignore
+/// let certificates = pem_decode(content, "CERTIFICATE")?;
+/// ```
+pub fn pem_decode(content: &str, label: &str) -> KerlabResult<Vec<Vec<u8>>> {
+    let begin = format!("-----BEGIN {}-----", label);
+    let end = format!("-----END {}-----", label);
+
+    let mut result = vec![];
+    let mut block: Option<String> = None;
+    for line in content.lines() {
+        let line = line.trim();
+        if line == begin {
+            block = Some(String::new());
+        } else if line == end {
+            if let Some(encoded) = block.take() {
+                result.push(
+                    base64::decode(&encoded)
+                        .map_err(|_| Error::new(KerlabErrorKind::Parsing, "Invalid PEM encoding"))?
+                );
+            }
+        } else if let Some(encoded) = &mut block {
+            encoded.push_str(line);
+        }
+    }
+    Ok(result)
+}